@@ -0,0 +1,831 @@
+//! Assembler pipeline — the two assembly passes and the [`Assembler`] builder.
+//!
+//! `get_pass1` assigns program counters, `get_pass2` resolves opcodes, registers
+//! and arguments.  [`Assembler`] strings the whole pipeline together (read with
+//! `!include` → block comments → macros → pass 1 → labels → pass 2 → flat image)
+//! so the CLI, the test runners and external build scripts all assemble through
+//! one path and get back one structured [`Assembly`].
+
+use crate::files::{code_listing, read_file_to_vector, read_file_with_includes, read_text_to_vector, remove_block_comments, LineType};
+use crate::helper::{
+    build_ddr_image, data_as_bytes, data_name_from_string, is_valid_line, line_type, num_data_bytes, strip_comments, HEAP_HEADER_WORDS,
+};
+use crate::labels::{find_duplicate_label, get_labels, label_name_from_string, Label};
+use crate::macros::{expand_embedded_macros, expand_macros, Macro};
+use crate::messages::{Message, MessageType, MsgList};
+use crate::opcodes::{add_arguments, add_registers, num_arguments, parse_vh_file, InputData, Opcode, Pass0, Pass1, Pass2};
+use std::path::{Path, PathBuf};
+
+/// Auto-upgrade `SETR R value` to `SETR64 R value` when the immediate doesn't fit in 32 bits.
+///
+/// Returns the (possibly rewritten) line string unchanged for all other mnemonics or
+/// when the value fits in the 32-bit range accepted by `convert_argument`
+/// (i.e. `i32::MIN ..= 0xFFFF_FFFF`).
+fn upgrade_setr_to_setr64(line: &str) -> String {
+    let stripped = strip_comments(line);
+    let mut words = stripped.split_whitespace();
+    if !words.next().unwrap_or("").eq_ignore_ascii_case("setr") {
+        return line.to_owned();
+    }
+    let _reg = words.next(); // skip register name
+    let Some(val_str) = words.next() else {
+        return line.to_owned();
+    };
+    // Parse as signed 64-bit so we handle negative decimal and full-width hex.
+    let val: i64 = if val_str.len() >= 2 && val_str.get(..2).is_some_and(|s| s.eq_ignore_ascii_case("0x")) {
+        let hex = &val_str[2..].replace('_', "");
+        u64::from_str_radix(hex, 16).map_or(0, |v| v as i64)
+    } else {
+        val_str.parse::<i64>().unwrap_or(0)
+    };
+    // Same bounds as convert_argument: [i32::MIN, 0xFFFF_FFFF] fits in 32 bits.
+    if val >= i64::from(i32::MIN) && val <= 0xFFFF_FFFF_i64 {
+        return line.to_owned();
+    }
+    // Upgrade: replace the leading "SETR" (any case) with "SETR64".
+    let trimmed = line.trim_start();
+    let leading_ws = &line[..line.len() - trimmed.len()];
+    // SAFETY: trimmed starts with "setr" (4 ASCII chars), confirmed by eq_ignore_ascii_case above.
+    format!("{leading_ws}SETR64{}", &trimmed[4..])
+}
+
+/// Returns pass1 from pass0.
+///
+/// Takes the macro expanded pass0 and returns vector of pass1, with the program counters.
+#[inline]
+pub fn get_pass1(msg_list: &mut MsgList, pass0: Vec<Pass0>, mut oplist: Vec<Opcode>) -> Vec<Pass1> {
+    let mut pass1: Vec<Pass1> = Vec::new();
+    let mut program_counter: u32 = HEAP_HEADER_WORDS * 8; // Byte address: 4 header words × 8 bytes each (64-bit words)
+    let mut data_pass0: Vec<Pass0> = Vec::new();
+    let mut in_data_section = false;
+
+    for pass in pass0 {
+        let stripped = strip_comments(&pass.input_text_line);
+        let first_word = stripped.split_whitespace().next().unwrap_or("").to_owned();
+
+        // Track section context for C compiler directives
+        match first_word.as_str() {
+            ".text" => {
+                in_data_section = false;
+            }
+            ".data" | ".rodata" | ".bss" => {
+                in_data_section = true;
+            }
+            _ => {}
+        }
+
+        // Expand .comm/.lcomm NAME SIZE into label + .space, defer to data section
+        if first_word == ".comm" || first_word == ".lcomm" {
+            let parts: Vec<&str> = stripped
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|s| !s.is_empty())
+                .collect();
+            if parts.len() >= 3 {
+                let name = parts[1];
+                let size_str = parts[2];
+                let size: u32 = size_str.parse().unwrap_or(0);
+                data_pass0.push(Pass0 {
+                    input_text_line: format!("{name}:"),
+                    file_name: pass.file_name.clone(),
+                    line_counter: pass.line_counter,
+                });
+                if size > 0 {
+                    data_pass0.push(Pass0 {
+                        input_text_line: format!(".space {size}"),
+                        file_name: pass.file_name.clone(),
+                        line_counter: pass.line_counter,
+                    });
+                }
+            }
+            continue;
+        }
+
+        // Rewrite "SETR R val" → "SETR64 R val" before line_type/num_arguments when val > 32 bits.
+        let upgraded_line = upgrade_setr_to_setr64(&pass.input_text_line);
+        let lt = line_type(&mut oplist, &upgraded_line);
+
+        // Defer labels in data section to end of program with data
+        if in_data_section && lt == LineType::Label {
+            data_pass0.push(pass);
+            continue;
+        }
+
+        pass1.push(Pass1 {
+            input_text_line: upgraded_line.clone(),
+            file_name: pass.file_name.clone(),
+            line_counter: pass.line_counter,
+            program_counter,
+            line_type: lt.clone(),
+        });
+        if !is_valid_line(&mut oplist, strip_comments(&upgraded_line)) {
+            msg_list.push(
+                format!("Error {upgraded_line}"),
+                Some(pass.line_counter),
+                Some(pass.file_name.clone()),
+                MessageType::Error,
+            );
+        }
+        if lt == LineType::Opcode {
+            let num_args = num_arguments(&mut oplist, &strip_comments(&upgraded_line));
+            if let Some(arguments) = num_args {
+                program_counter += (arguments + 1) * 4; // Each word = 4 bytes
+            }
+        }
+
+        if lt == LineType::Data {
+            if in_data_section {
+                data_pass0.push(pass);
+                pass1.pop();
+            } else {
+                // Keep inline data when no explicit .data section is active.
+                // This preserves label semantics for C compiler output data blocks.
+                //
+                program_counter += num_data_bytes(&pass.input_text_line, msg_list, pass.line_counter, pass.file_name.clone()) / 2;
+            }
+        }
+    }
+    for data_pass in data_pass0 {
+        let lt = line_type(&mut oplist, &data_pass.input_text_line);
+        pass1.push(Pass1 {
+            input_text_line: data_pass.input_text_line.clone(),
+            file_name: data_pass.file_name.clone(),
+            line_counter: data_pass.line_counter,
+            program_counter,
+            line_type: lt.clone(),
+        });
+
+        if lt == LineType::Data {
+            program_counter += num_data_bytes(&data_pass.input_text_line, msg_list, data_pass.line_counter, data_pass.file_name) / 2;
+        }
+    }
+    pass1
+}
+
+/// Returns pass2 from pass1.
+///
+/// Pass1 with program counters and returns vector of pass2, with final values.
+#[inline]
+pub fn get_pass2(msg_list: &mut MsgList, pass1: Vec<Pass1>, mut oplist: Vec<Opcode>, mut labels: Vec<Label>) -> Vec<Pass2> {
+    let mut pass2: Vec<Pass2> = Vec::new();
+    for line in pass1 {
+        let new_opcode = if line.line_type == LineType::Opcode {
+            let mut opcode = add_registers(
+                &mut oplist,
+                &strip_comments(&line.input_text_line.clone()),
+                line.file_name.clone(),
+                msg_list,
+                line.line_counter,
+            );
+            opcode.push_str(&add_arguments(
+                &mut oplist,
+                &strip_comments(&line.input_text_line.clone()),
+                msg_list,
+                line.line_counter,
+                &line.file_name,
+                &mut labels,
+            ));
+            opcode
+        } else if line.line_type == LineType::Data {
+            data_as_bytes(line.input_text_line.as_str()).unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        pass2.push(Pass2 {
+            input_text_line: line.input_text_line,
+            file_name: line.file_name.clone(),
+            line_counter: line.line_counter,
+            program_counter: line.program_counter,
+            line_type: if new_opcode.contains("ERR") { LineType::Error } else { line.line_type },
+            opcode: new_opcode,
+        });
+    }
+    pass2
+}
+
+/// Build a flat little-endian code byte image from an assembled `Pass2` vector.
+///
+/// Each opcode/data entry's hex string is placed at its `program_counter`
+/// offset relative to the code base (`HEAP_HEADER_WORDS * 8`).  Opcode words
+/// are 8 hex chars (one 32-bit word) emitted little-endian so the emulator's
+/// `read32` reconstructs the natural value; data entries are byte sequences
+/// already in natural order and are emitted as 64-bit little-endian words.
+/// Returns `(code_bytes, entry_pc)` or `None` if there is no `_start`.
+#[must_use]
+pub fn build_flat_code(pass2: &[Pass2]) -> Option<(Vec<u8>, u32)> {
+    let code_base: u32 = HEAP_HEADER_WORDS * 8;
+    let mut code: Vec<u8> = Vec::new();
+    let mut entry: Option<u32> = None;
+
+    for line in pass2 {
+        if line.line_type == LineType::Start {
+            entry = Some(line.program_counter);
+            continue;
+        }
+        if line.opcode.is_empty() {
+            continue;
+        }
+        let offset = line.program_counter.saturating_sub(code_base) as usize;
+        // Convert the hex string into bytes: each 8-char (32-bit) group → LE bytes.
+        let hex = &line.opcode;
+        let mut bytes: Vec<u8> = Vec::with_capacity(hex.len() / 2);
+        let chunk: Vec<char> = hex.chars().collect();
+        let mut i = 0;
+        while i + 8 <= chunk.len() {
+            let s: String = chunk[i..i + 8].iter().collect();
+            if let Ok(w) = u32::from_str_radix(&s, 16) {
+                bytes.extend_from_slice(&w.to_le_bytes());
+            }
+            i += 8;
+        }
+        // Any trailing < 8-char group (test artefacts) — pad pairwise as raw bytes.
+        while i + 2 <= chunk.len() {
+            let s: String = chunk[i..i + 2].iter().collect();
+            if let Ok(b) = u8::from_str_radix(&s, 16) {
+                bytes.push(b);
+            }
+            i += 2;
+        }
+        if offset + bytes.len() > code.len() {
+            code.resize(offset + bytes.len(), 0);
+        }
+        code[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+
+    entry.map(|e| (code, e))
+}
+
+/// An instruction set: the opcode table and the (embedded-expanded) macros
+/// parsed from a Verilog `opcode_select.vh` file.
+#[derive(Clone, Debug, Default)]
+pub struct Isa {
+    /// Opcodes, in `.vh` file order.
+    pub opcodes: Vec<Opcode>,
+    /// Macros with embedded macros already expanded.
+    pub macros: Vec<Macro>,
+}
+
+impl Isa {
+    /// Load the ISA from a `.vh` opcode file.
+    ///
+    /// Returns `None` (with the reason in `msg_list`) if the file cannot be read
+    /// or parsed.
+    pub fn from_vh_file(path: &str, msg_list: &mut MsgList) -> Option<Self> {
+        let mut opened_files: Vec<String> = Vec::new();
+        let vh_list = read_file_to_vector(path, msg_list, &mut opened_files)?;
+        Self::from_lines(vh_list, msg_list)
+    }
+
+    /// Parse the ISA from `.vh` text already in memory.
+    pub fn from_vh_text(text: &str, msg_list: &mut MsgList) -> Option<Self> {
+        let mut opened_files: Vec<String> = Vec::new();
+        let vh_list = read_text_to_vector("<isa>", text, &[], msg_list, &mut opened_files)?;
+        Self::from_lines(vh_list, msg_list)
+    }
+
+    /// Build opcode and macro lists from `.vh` lines.
+    fn from_lines(lines: Vec<InputData>, msg_list: &mut MsgList) -> Option<Self> {
+        let (opt_oplist, opt_macro_list) = parse_vh_file(lines, msg_list);
+        let opcodes = opt_oplist?;
+        let macros = expand_embedded_macros(opt_macro_list?, msg_list);
+        Some(Self { opcodes, macros })
+    }
+}
+
+/// Where the assembler reads its top-level source from.
+#[derive(Clone, Debug)]
+enum Source {
+    /// A `.kla` file on disk.
+    File(String),
+    /// Source text held in memory, with a display name for diagnostics.
+    Text {
+        /// Name used in messages and as the base for relative `!include`s.
+        name: String,
+        /// The source text itself.
+        text: String,
+    },
+}
+
+/// A named address in the assembled program (code label or `#DATA` object).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    /// Name without the trailing `:` of a code label (`#` kept for data).
+    pub name: String,
+    /// Byte address.
+    pub address: u32,
+    /// Source file defining the symbol (after `!include` resolution).
+    pub file_name: String,
+    /// Line number in `file_name`.
+    pub line_number: u32,
+    /// True for `#DATA` objects, false for code labels.
+    pub is_data: bool,
+}
+
+/// Structured result of one assembly run.
+#[derive(Debug)]
+pub struct Assembly {
+    /// Final pass, one entry per source line.
+    pub pass2: Vec<Pass2>,
+    /// Flat DDR image (heap header + code), `None` if there were errors or no `_start`.
+    pub image: Option<Vec<u8>>,
+    /// Entry PC from `_start`, if present.
+    pub entry: Option<u32>,
+    /// Code labels and data objects in address order.
+    pub symbols: Vec<Symbol>,
+    /// The `.code` listing text.
+    pub listing: String,
+    /// Every source file read, top-level first, then `!include`s in the order reached.
+    pub source_files: Vec<String>,
+    /// Messages raised while assembling this program.
+    pub diagnostics: Vec<Message>,
+}
+
+impl Assembly {
+    /// Number of diagnostics of the given level.
+    #[must_use]
+    pub fn number_by_type(&self, msg_type: &MessageType) -> usize {
+        self.diagnostics.iter().filter(|x| x.level == *msg_type).count()
+    }
+
+    /// True when assembly produced no errors.
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.number_by_type(&MessageType::Error) == 0
+    }
+}
+
+/// Builder for a single assembly run.
+///
+/// ```ignore
+/// let isa = Isa::from_vh_file("opcode_select.vh", &mut msgs)?;
+/// let assembly = Assembler::new(&isa).source_file("hello.kla").include_path("lib").assemble();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Assembler<'isa> {
+    /// Instruction set to assemble against.
+    isa: &'isa Isa,
+    /// Top-level source.
+    source: Option<Source>,
+    /// Extra directories searched for `!include` files.
+    include_paths: Vec<PathBuf>,
+}
+
+impl<'isa> Assembler<'isa> {
+    /// Create an assembler for the given ISA.
+    #[must_use]
+    pub const fn new(isa: &'isa Isa) -> Self {
+        Self {
+            isa,
+            source: None,
+            include_paths: Vec::new(),
+        }
+    }
+
+    /// Assemble the `.kla` file at `path`.
+    #[must_use]
+    pub fn source_file(mut self, path: impl Into<String>) -> Self {
+        self.source = Some(Source::File(path.into()));
+        self
+    }
+
+    /// Assemble source text held in memory; `name` is used in diagnostics.
+    #[must_use]
+    pub fn source_text(mut self, name: impl Into<String>, text: impl Into<String>) -> Self {
+        self.source = Some(Source::Text {
+            name: name.into(),
+            text: text.into(),
+        });
+        self
+    }
+
+    /// Add a directory searched for `!include` files after the including file's own directory.
+    #[must_use]
+    pub fn include_path(mut self, dir: impl AsRef<Path>) -> Self {
+        self.include_paths.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add several `!include` search directories, in order.
+    #[must_use]
+    pub fn include_paths(mut self, dirs: impl IntoIterator<Item = impl AsRef<Path>>) -> Self {
+        self.include_paths.extend(dirs.into_iter().map(|dir| dir.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Run the assembler with a private message list.
+    #[must_use]
+    pub fn assemble(&self) -> Assembly {
+        let mut msg_list = MsgList::new();
+        self.assemble_into(&mut msg_list)
+    }
+
+    /// Run the assembler, pushing messages to `msg_list` as they are raised.
+    ///
+    /// The messages raised by this run are also copied into
+    /// [`Assembly::diagnostics`].
+    pub fn assemble_into(&self, msg_list: &mut MsgList) -> Assembly {
+        let first_message = msg_list.list.len();
+        let mut opened_files: Vec<String> = Vec::new();
+        let input_list = match &self.source {
+            Some(Source::File(path)) => read_file_with_includes(path, &self.include_paths, msg_list, &mut opened_files),
+            Some(Source::Text { name, text }) => read_text_to_vector(name, text, &self.include_paths, msg_list, &mut opened_files),
+            None => {
+                msg_list.push("No source given to assembler".to_owned(), None, None, MessageType::Error);
+                None
+            }
+        };
+
+        let mut source_files: Vec<String> = Vec::new();
+        if let Some(Source::File(path)) = &self.source {
+            source_files.push(path.clone());
+        }
+        let text_name = match &self.source {
+            Some(Source::Text { name, .. }) => Some(name.as_str()),
+            _ => None,
+        };
+        let pass2 = input_list.map_or_else(Vec::new, |lines| {
+            for line in &lines {
+                if Some(line.file_name.as_str()) != text_name && !source_files.contains(&line.file_name) {
+                    source_files.push(line.file_name.clone());
+                }
+            }
+            let input_list = remove_block_comments(lines, msg_list);
+            let mut macro_list = self.isa.macros.clone();
+            let pass0 = expand_macros(msg_list, input_list, &mut macro_list);
+            let pass1: Vec<Pass1> = get_pass1(msg_list, pass0, self.isa.opcodes.clone());
+            let mut labels = get_labels(&pass1, msg_list);
+            find_duplicate_label(&mut labels, msg_list);
+            get_pass2(msg_list, pass1, self.isa.opcodes.clone(), labels)
+        });
+
+        let diagnostics: Vec<Message> = msg_list.list.get(first_message..).unwrap_or_default().to_vec();
+        let has_errors = diagnostics.iter().any(|m| m.level == MessageType::Error);
+        let flat = build_flat_code(&pass2);
+        Assembly {
+            image: flat.as_ref().filter(|_| !has_errors).map(|(code, _)| build_ddr_image(code)),
+            entry: flat.map(|(_, entry)| entry),
+            symbols: collect_symbols(&pass2),
+            listing: code_listing(&pass2),
+            source_files,
+            pass2,
+            diagnostics,
+        }
+    }
+}
+
+/// Collect code labels and `#DATA` names from pass 2, sorted by address.
+fn collect_symbols(pass2: &[Pass2]) -> Vec<Symbol> {
+    let mut symbols: Vec<Symbol> = pass2
+        .iter()
+        .filter_map(|line| {
+            let (name, is_data) = if line.line_type == LineType::Label {
+                (label_name_from_string(&line.input_text_line)?.trim_end_matches(':').to_owned(), false)
+            } else if line.line_type == LineType::Data {
+                (data_name_from_string(&line.input_text_line)?, true)
+            } else {
+                return None;
+            };
+            Some(Symbol {
+                name,
+                address: line.program_counter,
+                file_name: line.file_name.clone(),
+                line_number: line.line_counter,
+                is_data,
+            })
+        })
+        .collect();
+    symbols.sort_by_key(|s| s.address);
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+    use crate::test_support::corpus_isa;
+
+    #[test]
+    // Test get_pass1 for correct vector returned, with correct program counters
+    fn test_get_pass1_1() {
+        let mut msg_list = MsgList::new();
+        let opcodes = &mut Vec::<Opcode>::new();
+        opcodes.push(Opcode {
+            text_name: String::from("PUSH"),
+            hex_code: String::from("0000001X"),
+            comment: String::new(),
+            variables: 0,
+            registers: 1,
+            section: String::new(),
+        });
+        opcodes.push(Opcode {
+            text_name: String::from("MOV"),
+            hex_code: String::from("00000020"),
+            comment: String::new(),
+            variables: 2,
+            registers: 0,
+            section: String::new(),
+        });
+        opcodes.push(Opcode {
+            text_name: String::from("RET"),
+            hex_code: String::from("00000030"),
+            comment: String::new(),
+            variables: 0,
+            registers: 0,
+            section: String::new(),
+        });
+
+        let pass0 = vec![
+            Pass0 {
+                input_text_line: "MOV A B".to_owned(),
+                file_name: String::new(),
+                line_counter: 1,
+            },
+            Pass0 {
+                input_text_line: "PUSH A".to_owned(),
+                file_name: String::new(),
+                line_counter: 2,
+            },
+            Pass0 {
+                input_text_line: "RET".to_owned(),
+                file_name: String::new(),
+                line_counter: 3,
+            },
+            Pass0 {
+                input_text_line: "#DATA1 0x2".to_owned(), // Should be moved to end
+                file_name: String::new(),
+                line_counter: 4,
+            },
+            Pass0 {
+                input_text_line: "RET".to_owned(),
+                file_name: String::new(),
+                line_counter: 5,
+            },
+            Pass0 {
+                input_text_line: "#DATA1 \"HELLO\"".to_owned(), // Should be moved to end
+                file_name: String::new(),
+                line_counter: 6,
+            },
+            Pass0 {
+                input_text_line: "RET".to_owned(),
+                file_name: String::new(),
+                line_counter: 7,
+            },
+        ];
+        let pass1 = get_pass1(&mut msg_list, pass0, opcodes.clone());
+        // Byte addressing: PC starts at 32 (4 header words × 8 bytes each in 64-bit).
+        // Each instruction word = 4 bytes (opcode encoding unchanged).
+        // Each 64-bit data word = 8 bytes (16 hex chars).
+        assert_eq!(pass1.first().unwrap_or_default().program_counter, 32); // MOV
+        assert_eq!(pass1.get(1).unwrap_or_default().program_counter, 44); // PUSH (MOV=3 words×4=12)
+        assert_eq!(pass1.get(2).unwrap_or_default().program_counter, 48); // RET  (+4)
+        assert_eq!(pass1.get(3).unwrap_or_default().program_counter, 52); // #DATA1 0x2 inline (+4)
+        assert_eq!(pass1.get(4).unwrap_or_default().program_counter, 68); // RET  (2 data words×8=16)
+        assert_eq!(pass1.get(5).unwrap_or_default().program_counter, 72); // #DATA1 "HELLO" inline (+4)
+        assert_eq!(pass1.get(6).unwrap_or_default().program_counter, 84); // RET  (string=12 bytes)
+    }
+
+    #[test]
+    // Test get_pass1 for correct vector returned, with correct program counters
+    fn test_get_pass1_2() {
+        let mut msg_list = MsgList::new();
+        let opcodes = &mut Vec::<Opcode>::new();
+        opcodes.push(Opcode {
+            text_name: String::from("PUSH"),
+            hex_code: String::from("0000001X"),
+            comment: String::new(),
+            variables: 0,
+            registers: 1,
+            section: String::new(),
+        });
+
+        let pass0 = vec![Pass0 {
+            input_text_line: "Test_not_code_line".to_owned(),
+            file_name: String::new(),
+            line_counter: 1,
+        }];
+        let _pass1 = get_pass1(&mut msg_list, pass0, opcodes.clone());
+        assert_eq!(msg_list.list.first().unwrap_or_default().text, "Error Test_not_code_line");
+    }
+
+    #[test]
+    // Test get_pass2 for correct vector returned, with correct opcodes, registers and variables
+    fn test_get_pass2_1() {
+        let mut msg_list = MsgList::new();
+        let labels = Vec::<Label>::new();
+        let opcodes = &mut Vec::<Opcode>::new();
+        opcodes.push(Opcode {
+            text_name: String::from("PUSH"),
+            hex_code: String::from("0000001X"),
+            comment: String::new(),
+            variables: 0,
+            registers: 1,
+            section: String::new(),
+        });
+        opcodes.push(Opcode {
+            text_name: String::from("MOVR"),
+            hex_code: String::from("0000007X"),
+            comment: String::new(),
+            variables: 1,
+            registers: 1,
+            section: String::new(),
+        });
+        opcodes.push(Opcode {
+            text_name: String::from("MOV"),
+            hex_code: String::from("00000020"),
+            comment: String::new(),
+            variables: 2,
+            registers: 0,
+            section: String::new(),
+        });
+        opcodes.push(Opcode {
+            text_name: String::from("RET"),
+            hex_code: String::from("00000030"),
+            comment: String::new(),
+            variables: 0,
+            registers: 0,
+            section: String::new(),
+        });
+        opcodes.push(Opcode {
+            text_name: String::from("DELAY"),
+            hex_code: String::from("00000040"),
+            comment: String::new(),
+            variables: 1,
+            registers: 0,
+            section: String::new(),
+        });
+
+        opcodes.push(Opcode {
+            text_name: String::from("DMOV"),
+            hex_code: String::from("00000AXX"),
+            comment: String::new(),
+            variables: 2,
+            registers: 2,
+            section: String::new(),
+        });
+
+        let pass2 = get_pass2(
+            &mut msg_list,
+            vec![
+                Pass1 {
+                    input_text_line: "MOV 0xEEEEEEEE 0xFFFFFFFF".to_owned(),
+                    file_name: String::from("test"),
+                    line_counter: 1,
+                    program_counter: 0,
+                    line_type: LineType::Opcode,
+                },
+                Pass1 {
+                    input_text_line: "DELAY 0x7".to_owned(),
+                    file_name: String::from("test"),
+                    line_counter: 1,
+                    program_counter: 1,
+                    line_type: LineType::Opcode,
+                },
+                Pass1 {
+                    input_text_line: "PUSH A".to_owned(),
+                    file_name: String::from("test"),
+                    line_counter: 2,
+                    program_counter: 3,
+                    line_type: LineType::Opcode,
+                },
+                Pass1 {
+                    input_text_line: "RET".to_owned(),
+                    file_name: String::from("test"),
+                    line_counter: 3,
+                    program_counter: 4,
+                    line_type: LineType::Opcode,
+                },
+                Pass1 {
+                    input_text_line: "RET".to_owned(),
+                    file_name: String::from("test"),
+                    line_counter: 3,
+                    program_counter: 5,
+                    line_type: LineType::Opcode,
+                },
+                Pass1 {
+                    input_text_line: "MOVR C 0xAAAA".to_owned(),
+                    file_name: String::from("test"),
+                    line_counter: 3,
+                    program_counter: 5,
+                    line_type: LineType::Opcode,
+                },
+                Pass1 {
+                    input_text_line: "DMOV D E 0xA 0xB".to_owned(),
+                    file_name: String::from("test"),
+                    line_counter: 3,
+                    program_counter: 5,
+                    line_type: LineType::Opcode,
+                },
+                Pass1 {
+                    input_text_line: "#DATA1 \"HELLO\"".to_owned(),
+                    file_name: String::from("test"),
+                    line_counter: 3,
+                    program_counter: 5,
+                    line_type: LineType::Data,
+                },
+                Pass1 {
+                    input_text_line: "xxx".to_owned(),
+                    file_name: String::from("test"),
+                    line_counter: 3,
+                    program_counter: 5,
+                    line_type: LineType::Error,
+                },
+            ],
+            opcodes.clone(),
+            labels,
+        );
+        assert_eq!(pass2.first().unwrap_or_default().opcode, "00000020EEEEEEEEFFFFFFFF");
+        assert_eq!(pass2.get(1).unwrap_or_default().opcode, "0000004000000007");
+        assert_eq!(pass2.get(2).unwrap_or_default().opcode, "00000010");
+        assert_eq!(pass2.get(3).unwrap_or_default().opcode, "00000030");
+        assert_eq!(pass2.get(4).unwrap_or_default().opcode, "00000030");
+        assert_eq!(pass2.get(5).unwrap_or_default().opcode, "000000720000AAAA");
+        assert_eq!(pass2.get(6).unwrap_or_default().opcode, "00000A340000000A0000000B");
+        assert_eq!(pass2.get(7).unwrap_or_default().opcode, "0000000248454C4C4F000000");
+        assert_eq!(pass2.get(8).unwrap_or_default().opcode, "");
+    }
+
+    #[test]
+    // Test get_pass2 for invalid opcode
+    fn test_get_pass2_2() {
+        let mut msg_list = MsgList::new();
+        let labels = Vec::<Label>::new();
+        let opcodes = &mut Vec::<Opcode>::new();
+        opcodes.push(Opcode {
+            text_name: String::from("PUSH"),
+            hex_code: String::from("0000001X"),
+            comment: String::new(),
+            variables: 0,
+            registers: 1,
+            section: String::new(),
+        });
+        let pass2 = get_pass2(
+            &mut msg_list,
+            vec![Pass1 {
+                input_text_line: "TEST".to_owned(),
+                file_name: String::from("test"),
+                line_counter: 1,
+                program_counter: 0,
+                line_type: LineType::Opcode,
+            }],
+            opcodes.clone(),
+            labels,
+        );
+        assert_eq!(pass2.first().unwrap_or_default().opcode, "ERR     ");
+    }
+
+    #[test]
+    // Test Assembler builds an image, entry and symbols from in-memory source
+    fn test_assembler_source_text() {
+        let isa = corpus_isa();
+        let assembly = Assembler::new(&isa)
+            .source_text("inline.kla", "_start\nCALL print:\nHALT\nprint:\nSETR A 0x41\nTXR A\nRET\n")
+            .assemble();
+        assert!(assembly.is_ok(), "{:?}", assembly.diagnostics);
+        assert_eq!(assembly.entry, Some(0x20));
+        assert!(assembly.image.is_some());
+        assert_eq!(assembly.symbols.len(), 1);
+        assert_eq!(assembly.symbols[0].name, "print");
+        assert_eq!(assembly.symbols[0].address, 0x2C);
+        assert_eq!(assembly.symbols[0].file_name, "inline.kla");
+        assert!(assembly.listing.contains("print:"));
+    }
+
+    #[test]
+    // Test Assembler records the top-level file and every include it reads
+    fn test_assembler_source_files() {
+        let isa = corpus_isa();
+        let dir = tempfile::TempDir::new().unwrap();
+        let lib_dir = dir.path().join("lib");
+        std::fs::create_dir(&lib_dir).unwrap();
+        std::fs::write(lib_dir.join("print.kla"), "print:\nTXR A\nRET\n").unwrap();
+        let main_path = dir.path().join("main.kla");
+        std::fs::write(&main_path, "_start\nCALL print:\nHALT\n!include print.kla\n").unwrap();
+        let main_name = main_path.to_string_lossy().into_owned();
+
+        let assembly = Assembler::new(&isa).source_file(main_name.as_str()).include_path(&lib_dir).assemble();
+        assert!(assembly.is_ok(), "{:?}", assembly.diagnostics);
+        assert_eq!(
+            assembly.source_files,
+            vec![main_name, lib_dir.join("print.kla").to_string_lossy().into_owned()]
+        );
+    }
+
+    #[test]
+    // Test Assembler reports errors as diagnostics and withholds the image
+    fn test_assembler_error_diagnostics() {
+        let isa = corpus_isa();
+        let assembly = Assembler::new(&isa).source_text("bad.kla", "_start\nNOTANOPCODE A\nHALT\n").assemble();
+        assert!(!assembly.is_ok());
+        assert!(assembly.image.is_none());
+        assert!(assembly.number_by_type(&MessageType::Error) > 0);
+    }
+
+    #[test]
+    // Test Assembler errors when no source is given
+    fn test_assembler_no_source() {
+        let isa = Isa::default();
+        let assembly = Assembler::new(&isa).assemble();
+        assert!(!assembly.is_ok());
+        assert!(assembly.pass2.is_empty());
+    }
+}
@@ -0,0 +1,448 @@
+//! Instruction and data cache simulator for the emulator.
+//!
+//! A [`Cache`] sees every access the emulator makes — one 4- or 8-byte fetch
+//! per instruction (its immediate word too, if it has one) for the I-cache,
+//! every DDR read and write for the D-cache — and counts hits and misses overall and per program counter,
+//! so reports can attribute them to labels.  It holds tags only; the data
+//! stays in the emulator's memory.  Configured from a spec such as
+//! `size=8K,line=32,ways=2,write=back,replace=lru,penalty=20` (every key
+//! optional).
+
+use crate::symbols::SymbolMap;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+/// How writes reach memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Writes allocate and dirty the line; it is written back when evicted.
+    WriteBack,
+    /// Writes go straight to memory; a write miss does not allocate.
+    WriteThrough,
+}
+
+/// Which way of a full set is evicted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Replacement {
+    /// Least recently used.
+    Lru,
+    /// Oldest fill.
+    Fifo,
+    /// Pseudo-random (a fixed seed, so runs repeat).
+    Random,
+}
+
+/// Cache geometry, policies and miss cost.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Total size in bytes.
+    pub size: u32,
+    /// Line size in bytes.
+    pub line_size: u32,
+    /// Ways per set.
+    pub ways: u32,
+    /// Write policy (ignored by an I-cache).
+    pub write_policy: WritePolicy,
+    /// Replacement policy.
+    pub replacement: Replacement,
+    /// Cycles a miss, or a dirty line's write-back, adds to a timing estimate.
+    pub miss_penalty: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            size: 8 * 1024,
+            line_size: 32,
+            ways: 2,
+            write_policy: WritePolicy::WriteBack,
+            replacement: Replacement::Lru,
+            miss_penalty: 20,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Parse a `key=value,…` spec over the defaults.  Keys: `size` (bytes, `K`
+    /// or `M` suffix), `line`, `ways`, `write` (`back`/`through`), `replace`
+    /// (`lru`/`fifo`/`random`) and `penalty` (cycles).
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut config = Self::default();
+        for item in spec.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let (key, value) = item.split_once('=').ok_or_else(|| format!("expected key=value, got \"{item}\""))?;
+            let (key, value) = (key.trim().to_ascii_lowercase(), value.trim().to_ascii_lowercase());
+            let bad = || format!("bad {key} \"{value}\"");
+            match key.as_str() {
+                "size" => config.size = parse_size(&value).ok_or_else(bad)?,
+                "line" => config.line_size = parse_size(&value).ok_or_else(bad)?,
+                "ways" => config.ways = value.parse().map_err(|_| bad())?,
+                "write" => {
+                    config.write_policy = match value.as_str() {
+                        "back" => WritePolicy::WriteBack,
+                        "through" => WritePolicy::WriteThrough,
+                        _ => return Err(bad()),
+                    }
+                }
+                "replace" => {
+                    config.replacement = match value.as_str() {
+                        "lru" => Replacement::Lru,
+                        "fifo" => Replacement::Fifo,
+                        "random" => Replacement::Random,
+                        _ => return Err(bad()),
+                    }
+                }
+                "penalty" => config.miss_penalty = value.parse().map_err(|_| bad())?,
+                _ => return Err(format!("unknown cache setting \"{key}\"")),
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the geometry: power-of-two line size (at least 8) and set count.
+    fn validate(&self) -> Result<(), String> {
+        if self.line_size < 8 || !self.line_size.is_power_of_two() {
+            return Err(format!("line size {} is not a power of two of at least 8", self.line_size));
+        }
+        if self.ways == 0 {
+            return Err("ways must be at least 1".to_owned());
+        }
+        let sets = u64::from(self.size) / (u64::from(self.line_size) * u64::from(self.ways));
+        if sets == 0 || !sets.is_power_of_two() || sets * u64::from(self.line_size) * u64::from(self.ways) != u64::from(self.size) {
+            return Err(format!(
+                "size {} is not a power-of-two number of {}-way sets of {}-byte lines",
+                self.size, self.ways, self.line_size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Number of sets.
+    #[must_use]
+    pub const fn sets(&self) -> u32 {
+        self.size / (self.line_size * self.ways)
+    }
+
+    /// One-line description, e.g. `8 KiB, 32 B lines, 2-way, LRU`.
+    #[must_use]
+    pub fn describe(&self) -> String {
+        let size = if self.size.is_multiple_of(1024) {
+            format!("{} KiB", self.size / 1024)
+        } else {
+            format!("{} B", self.size)
+        };
+        let replacement = match self.replacement {
+            Replacement::Lru => "LRU",
+            Replacement::Fifo => "FIFO",
+            Replacement::Random => "random",
+        };
+        format!("{size}, {} B lines, {}-way, {replacement}", self.line_size, self.ways)
+    }
+}
+
+/// Bytes with an optional `K` / `M` suffix.
+fn parse_size(text: &str) -> Option<u32> {
+    let (digits, scale) = match text.strip_suffix('k') {
+        Some(digits) => (digits, 1024),
+        None => text.strip_suffix('m').map_or((text, 1), |digits| (digits, 1024 * 1024)),
+    };
+    digits.parse::<u32>().ok()?.checked_mul(scale)
+}
+
+/// Hit and miss counts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Reads (fetches for an I-cache).
+    pub reads: u64,
+    /// Reads that missed.
+    pub read_misses: u64,
+    /// Writes.
+    pub writes: u64,
+    /// Writes that missed.
+    pub write_misses: u64,
+    /// Dirty lines written back on eviction.
+    pub writebacks: u64,
+}
+
+impl CacheStats {
+    /// Reads and writes.
+    #[must_use]
+    pub const fn accesses(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    /// Read and write misses.
+    #[must_use]
+    pub const fn misses(&self) -> u64 {
+        self.read_misses + self.write_misses
+    }
+
+    /// Hits as a percentage of accesses (100 with no accesses).
+    #[must_use]
+    pub fn hit_rate(&self) -> f64 {
+        if self.accesses() == 0 {
+            100.0
+        } else {
+            100.0 * (self.accesses() - self.misses()) as f64 / self.accesses() as f64
+        }
+    }
+
+    /// Add `other`'s counts.
+    fn add(&mut self, other: &Self) {
+        self.reads += other.reads;
+        self.read_misses += other.read_misses;
+        self.writes += other.writes;
+        self.write_misses += other.write_misses;
+        self.writebacks += other.writebacks;
+    }
+}
+
+/// One cache line's tag state.
+#[derive(Clone, Copy, Debug, Default)]
+struct Line {
+    /// Holds a line.
+    valid: bool,
+    /// Written since it was filled (write-back only).
+    dirty: bool,
+    /// Line address (`address / line_size`).
+    tag: u32,
+    /// Access count when last used (LRU).
+    used: u64,
+    /// Access count when filled (FIFO).
+    filled: u64,
+}
+
+/// A set-associative cache model.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    /// Geometry and policies.
+    config: CacheConfig,
+    /// `sets * ways` lines, set by set.
+    lines: Vec<Line>,
+    /// Accesses so far (the LRU / FIFO clock).
+    clock: u64,
+    /// Random replacement state (xorshift).
+    seed: u64,
+    /// Totals.
+    stats: CacheStats,
+    /// Counts by the program counter of the accessing instruction.
+    by_pc: HashMap<u32, CacheStats>,
+}
+
+impl Cache {
+    /// An empty (all-invalid) cache.
+    #[must_use]
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            lines: vec![Line::default(); (config.sets() * config.ways) as usize],
+            clock: 0,
+            seed: 0x2545_F491_4F6C_DD1D,
+            stats: CacheStats::default(),
+            by_pc: HashMap::new(),
+        }
+    }
+
+    /// Geometry and policies.
+    #[must_use]
+    pub const fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// Totals.
+    #[must_use]
+    pub const fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    /// Record an access of `len` bytes at `address` by the instruction at `pc`.
+    ///
+    /// Every line the bytes touch counts as one access.  Returns the penalty
+    /// cycles it costs: [`CacheConfig::miss_penalty`] per miss and per dirty
+    /// line written back.
+    pub fn access(&mut self, pc: u32, address: u32, len: u32, write: bool) -> u64 {
+        let first = address / self.config.line_size;
+        let last = address.saturating_add(len.max(1) - 1) / self.config.line_size;
+        let mut penalty = 0;
+        for tag in first..=last {
+            let mut counts = CacheStats::default();
+            self.access_line(tag, write, &mut counts);
+            penalty += (counts.misses() + counts.writebacks) * self.config.miss_penalty;
+            self.stats.add(&counts);
+            self.by_pc.entry(pc).or_default().add(&counts);
+        }
+        penalty
+    }
+
+    /// Look up one line, filling or updating it; counts go to `counts`.
+    fn access_line(&mut self, tag: u32, write: bool, counts: &mut CacheStats) {
+        self.clock += 1;
+        let ways = self.config.ways as usize;
+        let set = (tag % self.config.sets()) as usize;
+        let write_back = self.config.write_policy == WritePolicy::WriteBack;
+        if write {
+            counts.writes = 1;
+        } else {
+            counts.reads = 1;
+        }
+        let clock = self.clock;
+        let lines = &mut self.lines[set * ways..(set + 1) * ways];
+        if let Some(line) = lines.iter_mut().find(|line| line.valid && line.tag == tag) {
+            line.used = clock;
+            line.dirty |= write && write_back;
+            return;
+        }
+        if write {
+            counts.write_misses = 1;
+            if !write_back {
+                return; // no write-allocate
+            }
+        } else {
+            counts.read_misses = 1;
+        }
+        let victim = match lines.iter().position(|line| !line.valid) {
+            Some(free) => free,
+            None => match self.config.replacement {
+                Replacement::Lru => (0..ways).min_by_key(|&way| lines[way].used).unwrap_or(0),
+                Replacement::Fifo => (0..ways).min_by_key(|&way| lines[way].filled).unwrap_or(0),
+                Replacement::Random => {
+                    self.seed ^= self.seed << 13;
+                    self.seed ^= self.seed >> 7;
+                    self.seed ^= self.seed << 17;
+                    (self.seed % ways as u64) as usize
+                }
+            },
+        };
+        if lines[victim].valid && lines[victim].dirty {
+            counts.writebacks = 1;
+        }
+        lines[victim] = Line {
+            valid: true,
+            dirty: write,
+            tag,
+            used: clock,
+            filled: clock,
+        };
+    }
+
+    /// Counts grouped by the label each accessing instruction falls under,
+    /// most misses first.
+    #[must_use]
+    pub fn by_label<'a>(&self, symbols: &'a SymbolMap) -> Vec<(&'a str, CacheStats)> {
+        let mut labels: HashMap<&str, CacheStats> = HashMap::new();
+        for (pc, counts) in &self.by_pc {
+            labels.entry(symbols.name(*pc)).or_default().add(counts);
+        }
+        let mut labels: Vec<(&str, CacheStats)> = labels.into_iter().collect();
+        labels.sort_by(|(a_name, a), (b_name, b)| b.misses().cmp(&a.misses()).then(b.accesses().cmp(&a.accesses())).then(a_name.cmp(b_name)));
+        labels
+    }
+
+    /// Summary line plus a per-label table of at most `limit` rows.
+    #[must_use]
+    pub fn report(&self, name: &str, symbols: &SymbolMap, limit: usize) -> String {
+        let stats = &self.stats;
+        // The write policy only matters to a cache that saw writes (not an I-cache).
+        let write = match self.config.write_policy {
+            _ if stats.writes == 0 => "",
+            WritePolicy::WriteBack => ", write-back",
+            WritePolicy::WriteThrough => ", write-through",
+        };
+        let mut text = format!(
+            "{name} ({}{write}): {} accesses, {} misses, {:.2}% hits",
+            self.config.describe(),
+            stats.accesses(),
+            stats.misses(),
+            stats.hit_rate()
+        );
+        if stats.writebacks > 0 {
+            let _ = write!(text, ", {} write-backs", stats.writebacks);
+        }
+        text.push('\n');
+        let labels = self.by_label(symbols);
+        if labels.is_empty() {
+            return text;
+        }
+        let _ = writeln!(text, "  {:<24} {:>12} {:>10} {:>8}", "label", "accesses", "misses", "hit %");
+        for (label, counts) in labels.iter().take(limit) {
+            let _ = writeln!(
+                text,
+                "  {label:<24} {:>12} {:>10} {:>8.2}",
+                counts.accesses(),
+                counts.misses(),
+                counts.hit_rate()
+            );
+        }
+        if labels.len() > limit {
+            let _ = writeln!(text, "  … {} more labels", labels.len() - limit);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+
+    #[test]
+    // Test spec parsing, defaults and geometry checks
+    fn test_parse_config() {
+        let config = CacheConfig::parse("size=1K, line=16, ways=4, write=through, replace=fifo, penalty=7").unwrap();
+        assert_eq!(config.sets(), 16);
+        assert_eq!(config.describe(), "1 KiB, 16 B lines, 4-way, FIFO");
+        assert_eq!(config.write_policy, WritePolicy::WriteThrough);
+        assert_eq!(config.miss_penalty, 7);
+        assert_eq!(CacheConfig::parse("").unwrap(), CacheConfig::default());
+        assert!(CacheConfig::parse("line=24").unwrap_err().contains("power of two"));
+        assert!(CacheConfig::parse("size=3K").unwrap_err().contains("sets"));
+        assert!(CacheConfig::parse("colour=red").unwrap_err().contains("unknown"));
+        assert!(CacheConfig::parse("write=sideways").is_err());
+    }
+
+    #[test]
+    // Test LRU eviction, dirty write-backs and penalties in a 2-way set
+    fn test_lru_write_back() {
+        // 2 sets of 2 ways, 16-byte lines: addresses 0x00, 0x20 and 0x40 share set 0.
+        let mut cache = Cache::new(CacheConfig::parse("size=64,line=16,ways=2,penalty=10").unwrap());
+        assert_eq!(cache.access(0x100, 0x00, 8, true), 10, "write miss allocates");
+        assert_eq!(cache.access(0x100, 0x08, 8, false), 0, "same line hits");
+        assert_eq!(cache.access(0x104, 0x20, 8, false), 10);
+        assert_eq!(cache.access(0x104, 0x00, 8, false), 0, "0x00 becomes most recent");
+        assert_eq!(cache.access(0x108, 0x40, 8, false), 10, "evicts clean 0x20");
+        assert_eq!(cache.access(0x108, 0x20, 8, false), 20, "evicts dirty 0x00: miss plus write-back");
+        assert_eq!(cache.access(0x10C, 0x5C, 8, false), 20, "straddles two lines, both miss");
+        let stats = cache.stats();
+        assert_eq!(
+            (stats.reads, stats.read_misses, stats.writes, stats.write_misses, stats.writebacks),
+            (7, 5, 1, 1, 1)
+        );
+
+        let symbols = SymbolMap::from_symbols(vec![(0x100, "init".to_owned()), (0x108, "walk".to_owned())]);
+        let labels = cache.by_label(&symbols);
+        assert_eq!(labels[0].0, "walk");
+        assert_eq!(labels[0].1.misses(), 4);
+        assert_eq!(labels[1].1.accesses(), 4);
+        let report = cache.report("D-cache", &symbols, 1);
+        assert!(report.starts_with("D-cache (64 B, 16 B lines, 2-way, LRU, write-back): 8 accesses, 6 misses, 25.00% hits, 1 write-backs\n"));
+        assert!(report.ends_with("… 1 more labels\n"), "{report}");
+    }
+
+    #[test]
+    // Test write-through does not allocate on a write miss, and FIFO ignores reuse
+    fn test_write_through_fifo() {
+        let mut cache = Cache::new(CacheConfig::parse("size=32,line=16,ways=2,write=through,replace=fifo").unwrap());
+        let _ = cache.access(0, 0x00, 8, true);
+        assert_eq!(cache.stats().write_misses, 1);
+        let _ = cache.access(0, 0x00, 8, false);
+        assert_eq!(cache.stats().read_misses, 1, "the write did not allocate");
+        let _ = cache.access(0, 0x10, 8, false);
+        let _ = cache.access(0, 0x00, 8, false);
+        let _ = cache.access(0, 0x20, 8, false); // evicts 0x00, the oldest fill, despite its reuse
+        let _ = cache.access(0, 0x10, 8, false);
+        assert_eq!(cache.stats().read_misses, 3);
+        let _ = cache.access(0, 0x00, 8, false);
+        assert_eq!(cache.stats().read_misses, 4);
+        assert_eq!(cache.stats().writebacks, 0);
+    }
+}
@@ -1,7 +1,19 @@
 //! Command-line interface definition (clap).
 
-use crate::serial::AUTO_SERIAL;
-use clap::{Arg, ArgAction, Command};
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use klausscc::cache_sim::CacheConfig;
+use klausscc::discover::DEFAULT_DISCOVERY_ADDRESS;
+use klausscc::emulate::UartInput;
+use klausscc::expect::{unescape, Script};
+use klausscc::image_format::{ImageFormat, MemLayout};
+use klausscc::manifest::{find_manifest, Manifest, MANIFEST_FILE_NAME};
+use klausscc::messages::{MessageType, MsgList};
+use klausscc::netload::{NetLoadOptions, NetbootProtocol};
+use klausscc::peripherals::parse_switches;
+use klausscc::serial::{MonitorOptions, AUTO_SERIAL};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// Builds the clap `Command` describing every CLI argument and subcommand mode.
 #[must_use]
@@ -14,7 +26,9 @@ pub fn set_matches() -> Command {
         .override_usage(
             "klausscc [OPTIONS] \
              <--input <input> | --textmate | --opcodes | --test-list <test_list> \
-             | --net-load <file> | --mem-out <file> | --monitor>",
+             | --net-load <file> | --mem-out <file> | --monitor | --disasm <file> \
+             | --inspect <kbt> | --size-diff <old> <new> | --fake-board <endpoint> \
+             | --netboot-server [address] | --discover>",
         )
         .arg(
             Arg::new("opcode_file")
@@ -35,7 +49,7 @@ pub fn set_matches() -> Command {
             Arg::new("ip")
                 .long("ip")
                 .num_args(1)
-                .help("Board IP address for --net-load (e.g. 192.168.68.50)"),
+                .help("Board host name or IP address for --net-load, IPv4 or IPv6 (e.g. 192.168.68.50, fe80::1), or \"auto\" to use the only board found by discovery"),
         )
         .arg(
             Arg::new("port")
@@ -43,6 +57,65 @@ pub fn set_matches() -> Command {
                 .num_args(1)
                 .help("Board TCP port for --net-load (default 5000)"),
         )
+        .arg(
+            Arg::new("console_port")
+                .long("console-port")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u16))
+                .help("Monitor the board's network console on this TCP port for -m after --net-load without -s (default: the console discovery reports, else the serial port)"),
+        )
+        .arg(
+            Arg::new("board")
+                .long("board")
+                .num_args(1)
+                .help("Board name: --net-load finds it by discovery instead of --ip; --netboot-server announces itself under it (default emulator)"),
+        )
+        .arg(
+            Arg::new("discover")
+                .long("discover")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["input", "test_list", "net_load", "mem_out", "target", "suite", "netboot_server"])
+                .help("Broadcast a discovery query and list the netboot boards that answer (name, IP, port, MAC, firmware, ISA)"),
+        )
+        .arg(
+            Arg::new("discover_address")
+                .long("discover-address")
+                .num_args(1)
+                .default_value(DEFAULT_DISCOVERY_ADDRESS)
+                .help("Where discovery queries go (use 127.0.0.1:5001 for a local --netboot-server)"),
+        )
+        .arg(
+            Arg::new("net_protocol")
+                .long("net-protocol")
+                .num_args(1)
+                .value_parser(NetbootProtocol::NAMES)
+                .default_value("auto")
+                .help("Netboot protocol for --net-load: 2 (handshake, acknowledged chunks), 1 (single image + ack) or auto (2, falling back to 1)"),
+        )
+        .arg(
+            Arg::new("net_timeout")
+                .long("net-timeout")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64))
+                .default_value("15")
+                .help("Seconds to wait for the board to connect or acknowledge during --net-load"),
+        )
+        .arg(
+            Arg::new("net_retries")
+                .long("net-retries")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u32))
+                .default_value("3")
+                .help("Reconnect attempts after a transient --net-load failure"),
+        )
+        .arg(
+            Arg::new("net_chunk")
+                .long("net-chunk")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u32).range(4..))
+                .default_value("65536")
+                .help("Bytes per acknowledged chunk with netboot protocol 2"),
+        )
         .arg(
             Arg::new("mem_out")
                 .long("mem-out")
@@ -66,12 +139,40 @@ pub fn set_matches() -> Command {
             Arg::new("input")
                 .short('i')
                 .long("input")
-                .required_unless_present_any(["textmate", "opcodes", "test_list", "net_load", "mem_out", "monitor", "emulate_test"])
+                .required_unless_present_any(["textmate", "opcodes", "test_list", "net_load", "mem_out", "monitor", "emulate_test", "target", "suite", "disasm", "round_trip", "inspect", "size_diff", "fake_board", "netboot_server", "discover"])
                 .conflicts_with("textmate")
                 .conflicts_with("opcodes")
                 .num_args(1)
                 .help("Input file. Type is detected from the extension: .kla assembles (needs --opcode), .kbt sends a pre-built image, anything else (.elf or flat binary) converts to the board wire format"),
         )
+        .arg(
+            Arg::new("include")
+                .short('I')
+                .long("include")
+                .num_args(1)
+                .action(ArgAction::Append)
+                .help("Directory searched for !include files (repeatable; searched after the including file's own directory)"),
+        )
+        .arg(
+            Arg::new("manifest")
+                .long("manifest")
+                .num_args(1)
+                .help("Project manifest to use instead of the klauss.toml found from the current directory upward"),
+        )
+        .arg(
+            Arg::new("target")
+                .long("target")
+                .num_args(1)
+                .conflicts_with("input")
+                .help("Build the named [target.<name>] from the project manifest"),
+        )
+        .arg(
+            Arg::new("suite")
+                .long("suite")
+                .num_args(1)
+                .conflicts_with_all(["input", "target", "test_list", "emulate_test"])
+                .help("Run the named [suite.<name>] test suite from the project manifest"),
+        )
         .arg(
             Arg::new("output")
                 .short('o')
@@ -105,7 +206,7 @@ pub fn set_matches() -> Command {
                 .long("serial")
                 .num_args(0..=1)
                 .default_missing_value(AUTO_SERIAL)
-                .help("Serial port for output"),
+                .help("Serial port for output: a device, serial:<device>, pty:<path> or tcp://host:port"),
         )
         .arg(
             Arg::new("monitor")
@@ -113,7 +214,7 @@ pub fn set_matches() -> Command {
                 .long("monitor")
                 .action(ArgAction::SetTrue)
                 .conflicts_with("test")
-                .help("Monitor serial port for UART output after sending (Ctrl+C to stop)"),
+                .help("Monitor serial port for UART output after sending (Ctrl+C to stop); after --net-load without -s, the board's network console if --console-port is given or discovery reports one"),
         )
         .arg(
             Arg::new("test")
@@ -151,7 +252,49 @@ pub fn set_matches() -> Command {
                 .short('d')
                 .long("debug")
                 .action(ArgAction::SetTrue)
-                .help("Print each received UART byte as hex alongside normal output"),
+                .help("Print each received UART byte as hex alongside normal output; with --emulate, open the emulator debugger instead of running"),
+        )
+        .arg(
+            Arg::new("log")
+                .long("log")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Monitor: also write the decoded UART output to this file and the raw bytes to <file>.raw"),
+        )
+        .arg(
+            Arg::new("timestamps")
+                .long("timestamps")
+                .action(ArgAction::SetTrue)
+                .help("Monitor: prefix each UART line with the host time"),
+        )
+        .arg(
+            Arg::new("until")
+                .long("until")
+                .num_args(1)
+                .value_parser(|pattern: &str| Regex::new(pattern))
+                .help("Monitor: stop and exit 0 when a UART line matches this regex (exit 1 if it never does)"),
+        )
+        .arg(
+            Arg::new("fail_on")
+                .long("fail-on")
+                .num_args(1)
+                .value_parser(|pattern: &str| Regex::new(pattern))
+                .help("Monitor: stop and exit 1 when a UART line matches this regex"),
+        )
+        .arg(
+            Arg::new("script")
+                .long("script")
+                .num_args(1)
+                .value_parser(|path: &str| Script::load(Path::new(path)))
+                .conflicts_with_all(["test", "test_list", "watch"])
+                .help("Drive the program with an expect script (send/expect/halt steps) over the monitor or, with --emulate, in the emulator; exit 1 if a step fails. On the board, --until or --fail-on ends the script with its result and --monitor-timeout fails it"),
+        )
+        .arg(
+            Arg::new("monitor_timeout")
+                .long("monitor-timeout")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64))
+                .help("Monitor: stop after this many seconds (a failure when --until is given)"),
         )
         .arg(
             Arg::new("emulate")
@@ -159,6 +302,140 @@ pub fn set_matches() -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Assemble the input and run it on the built-in ISA emulator (golden model); prints captured UART output"),
         )
+        .arg(
+            Arg::new("uart_input")
+                .long("uart-input")
+                .num_args(1)
+                .value_parser(|text: &str| unescape(text))
+                .conflicts_with_all(["uart_input_file", "uart_stdin", "script"])
+                .help("With --emulate, UART input for the program (RXRB/RXRNB); escapes such as \\r, \\n and \\xHH are allowed"),
+        )
+        .arg(
+            Arg::new("uart_input_file")
+                .long("uart-input-file")
+                .num_args(1)
+                .value_parser(|path: &str| std::fs::read(path).map_err(|err| format!("cannot read {path}: {err}")))
+                .conflicts_with_all(["uart_stdin", "script"])
+                .help("With --emulate, UART input for the program read from this file"),
+        )
+        .arg(
+            Arg::new("uart_stdin")
+                .long("uart-stdin")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["script", "watch"])
+                .help("With --emulate, feed standard input to the program's UART as it arrives, streaming its output"),
+        )
+        .arg(
+            Arg::new("interactive")
+                .long("interactive")
+                .action(ArgAction::SetTrue)
+                .requires("emulate")
+                .conflicts_with_all(["uart_input", "uart_input_file", "uart_stdin", "script", "watch"])
+                .help("With --emulate, stream UART output live and forward keystrokes to the program (Ctrl+C to stop), as -m does for the board"),
+        )
+        .arg(
+            Arg::new("gdb")
+                .long("gdb")
+                .num_args(1)
+                .value_name("port")
+                .value_parser(|value: &str| -> Result<String, String> {
+                    // A bare port listens on loopback only.
+                    Ok(if value.parse::<u16>().is_ok() { format!("127.0.0.1:{value}") } else { value.to_owned() })
+                })
+                .requires("emulate")
+                .conflicts_with_all(["debug", "uart_stdin", "interactive", "script", "watch"])
+                .help("With --emulate, wait for GDB on this port (or address:port) and let it drive the emulator"),
+        )
+        .arg(
+            Arg::new("switches")
+                .long("switches")
+                .num_args(1)
+                .value_name("value")
+                .value_parser(parse_switches)
+                .requires("emulate")
+                .help("With --emulate, the board switch word SWR reads (0x hex, 0b binary or decimal; default 0)"),
+        )
+        .arg(
+            Arg::new("panel")
+                .long("panel")
+                .action(ArgAction::SetTrue)
+                .requires("emulate")
+                .conflicts_with_all(["debug", "gdb", "uart_stdin", "interactive", "script", "watch"])
+                .help("With --emulate, show a live front panel (LEDs, 7-seg displays, RGB LEDs, switches and LCD) while the program runs"),
+        )
+        .arg(
+            Arg::new("peripheral_log")
+                .long("peripheral-log")
+                .num_args(1)
+                .value_name("file")
+                .requires("emulate")
+                .conflicts_with_all(["debug", "gdb", "watch"])
+                .help("With --emulate, write every LED, 7-seg, RGB and LCD write to this file, numbered by retired instruction"),
+        )
+        .arg(
+            Arg::new("timing")
+                .long("timing")
+                .num_args(0..=1)
+                .value_name("table")
+                .default_missing_value("")
+                .requires("emulate")
+                .conflicts_with_all(["debug", "gdb", "script", "watch"])
+                .help("With --emulate, estimate cycles and run time, using a cycle table file if given (mnemonic = cycles, section <name> = cycles, default, ddr_latency, clock_mhz)"),
+        )
+        .arg(
+            Arg::new("clock_mhz")
+                .long("clock-mhz")
+                .num_args(1)
+                .value_name("MHz")
+                .value_parser(|value: &str| -> Result<f64, String> {
+                    value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|mhz| mhz.is_finite() && *mhz > 0.0)
+                        .ok_or_else(|| format!("bad clock frequency \"{value}\""))
+                })
+                .requires("timing")
+                .help("Clock frequency for the --timing run time estimate (default 50 MHz)"),
+        )
+        .arg(
+            Arg::new("icache")
+                .long("icache")
+                .num_args(0..=1)
+                .value_name("spec")
+                .default_missing_value("")
+                .value_parser(CacheConfig::parse)
+                .requires("emulate")
+                .conflicts_with_all(["debug", "gdb", "script", "watch"])
+                .help("With --emulate, simulate an instruction cache and report hits and misses per label; spec is size=8K,line=32,ways=2,replace=lru|fifo|random,penalty=20 (all optional)"),
+        )
+        .arg(
+            Arg::new("dcache")
+                .long("dcache")
+                .num_args(0..=1)
+                .value_name("spec")
+                .default_missing_value("")
+                .value_parser(CacheConfig::parse)
+                .requires("emulate")
+                .conflicts_with_all(["debug", "gdb", "script", "watch"])
+                .help("With --emulate, simulate a data cache as --icache does; its spec also takes write=back|through. With --timing, miss penalties replace the flat DDR latency"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .action(ArgAction::SetTrue)
+                .requires("emulate")
+                .conflicts_with_all(["debug", "gdb", "script", "watch"])
+                .help("With --emulate, profile the run: a flat profile of instructions per label and a call graph built from CALL/RET"),
+        )
+        .arg(
+            Arg::new("profile_folded")
+                .long("profile-folded")
+                .num_args(1)
+                .value_name("file")
+                .requires("emulate")
+                .conflicts_with_all(["debug", "gdb", "script", "watch"])
+                .help("With --emulate, write the profile's folded call stacks to this file, for flame graph tools"),
+        )
         .arg(
             Arg::new("trace")
                 .long("trace")
@@ -171,10 +448,227 @@ pub fn set_matches() -> Command {
                 .num_args(1)
                 .help("Run the emulator over a test list (or a directory of .kla files) and verify captured UART vs expected // values"),
         )
+        .arg(
+            Arg::new("watch")
+                .long("watch")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["test_list", "net_load", "mem_out", "textmate", "opcodes", "monitor", "test"])
+                .requires("watch_source")
+                .help("Re-assemble whenever the .kla input, its !include files or the opcode file change; re-runs --emulate, --emulate-test or the -s board load each time"),
+        )
+        // --watch needs something to rebuild: a .kla input (perhaps from --target) or --emulate-test.
+        .group(ArgGroup::new("watch_source").args(["input", "target", "emulate_test"]).multiple(true))
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .num_args(1)
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(ImageFormat::NAMES)
+                .help("Also write the assembled image as ihex, srec, bin, mem or coe next to the .kbt (repeat or comma-separate for several)"),
+        )
+        .arg(
+            Arg::new("mem_width")
+                .long("mem-width")
+                .num_args(1)
+                .value_parser(["32", "64"])
+                .default_value("64")
+                .help("Bits per $readmemh line for --mem-out and --format mem"),
+        )
+        .arg(
+            Arg::new("mem_lanes")
+                .long("mem-lanes")
+                .action(ArgAction::SetTrue)
+                .help("Split $readmemh output into one file per byte lane (<stem>_lane<n>.mem)"),
+        )
+        .arg(
+            Arg::new("mem_depth")
+                .long("mem-depth")
+                .num_args(1)
+                .value_parser(clap::value_parser!(usize))
+                .help("Fixed ROM depth in words: pad $readmemh output to it, error if the image does not fit"),
+        )
+        .arg(
+            Arg::new("mem_address")
+                .long("mem-address")
+                .action(ArgAction::SetTrue)
+                .help("Write @address records in $readmemh output, skipping runs of zero words"),
+        )
+        .arg(
+            Arg::new("size_report")
+                .long("size-report")
+                .action(ArgAction::SetTrue)
+                .help("Print bytes per function and data object after assembly, and save them to <bitcode>.size.json"),
+        )
+        .arg(
+            Arg::new("size_diff")
+                .long("size-diff")
+                .num_args(2)
+                .value_names(["OLD", "NEW"])
+                .conflicts_with_all(["input", "test_list", "net_load", "mem_out", "target", "suite"])
+                .help("Compare two --size-report JSON files and show growth per symbol"),
+        )
+        .arg(
+            Arg::new("inspect")
+                .long("inspect")
+                .num_args(1)
+                .conflicts_with_all(["input", "test_list", "net_load", "mem_out", "disasm", "target", "suite"])
+                .help("Decode and validate a .kbt file: header, size, entry PC and checksum (non-zero exit if malformed)"),
+        )
+        .arg(
+            Arg::new("inspect_disasm")
+                .long("inspect-disasm")
+                .action(ArgAction::SetTrue)
+                .requires("inspect")
+                .help("With --inspect, also print a disassembly of the program (needs --opcode)"),
+        )
+        .arg(
+            Arg::new("disasm")
+                .long("disasm")
+                .num_args(1)
+                .conflicts_with_all(["input", "test_list", "net_load", "mem_out", "target", "suite"])
+                .help("Disassemble a .kbt, .mem, ELF or flat binary to re-assemblable .kla source (needs --opcode; --entry for flat/.mem input)"),
+        )
+        .arg(
+            Arg::new("disasm_out")
+                .long("disasm-out")
+                .num_args(1)
+                .help("Output .kla file for --disasm (default <input>_disasm.kla)"),
+        )
+        .arg(
+            Arg::new("round_trip")
+                .long("round-trip")
+                .action(ArgAction::SetTrue)
+                .help("Self-check that every opcode in the opcode file survives disassemble → reassemble byte-identically"),
+        )
+        .arg(
+            Arg::new("fake_board")
+                .long("fake-board")
+                .num_args(1)
+                .conflicts_with_all(["input", "test_list", "net_load", "mem_out", "target", "suite", "monitor"])
+                .help("Act as a board: serve the UART kbt loader from the emulator on \"pty\" or tcp://host:port, for -s without hardware"),
+        )
+        .arg(
+            Arg::new("netboot_server")
+                .long("netboot-server")
+                .num_args(0..=1)
+                .default_missing_value("127.0.0.1:5000")
+                .conflicts_with_all(["input", "test_list", "net_load", "mem_out", "target", "suite", "monitor", "fake_board"])
+                .help("Act as a netboot board: accept --net-load uploads on host:port (default 127.0.0.1:5000) and run them in the emulator"),
+        )
+        .arg(
+            Arg::new("uart_tcp")
+                .long("uart-tcp")
+                .num_args(0..=1)
+                .default_missing_value("")
+                .requires("netboot_server")
+                .help("With --netboot-server, serve the program's UART as a network console on host:port (default: the netboot port + 1) instead of stdout"),
+        )
         .arg(
             Arg::new("max_instructions")
                 .long("max-instructions")
                 .num_args(1)
-                .help("Instruction-count cap for the emulator (default 50000000)"),
+                .help("Instruction-count cap for the emulator (default 50000000; none for --interactive)"),
         )
 }
+
+/// Load the project manifest for this invocation.
+///
+/// Uses `--manifest` if given, otherwise the nearest `klauss.toml` from the
+/// current directory upward; no manifest gives the empty default.  Returns
+/// `None` (with the reason in `msg_list`) if the manifest cannot be loaded.
+#[cfg(not(tarpaulin_include))] // Depends on the process working directory
+pub fn project_manifest(matches: &ArgMatches, msg_list: &mut MsgList) -> Option<Manifest> {
+    let path = match matches.get_one::<String>("manifest") {
+        Some(path) => Some(Path::new(path).to_path_buf()),
+        None => std::env::current_dir().ok().and_then(|dir| find_manifest(&dir)),
+    };
+    let Some(path) = path else {
+        return Some(Manifest::default());
+    };
+    if !path.is_file() {
+        msg_list.push(
+            format!("Manifest {} not found (expected a {MANIFEST_FILE_NAME} file)", path.display()),
+            None,
+            None,
+            MessageType::Error,
+        );
+        return None;
+    }
+    Manifest::load(&path, msg_list)
+}
+
+/// `$readmemh` layout from the `--mem-*` options.
+#[must_use]
+pub fn mem_layout(matches: &ArgMatches) -> MemLayout {
+    MemLayout {
+        word_bytes: if matches.get_one::<String>("mem_width").is_some_and(|width| width == "32") {
+            4
+        } else {
+            8
+        },
+        split_lanes: matches.get_flag("mem_lanes"),
+        depth: matches.get_one::<usize>("mem_depth").copied(),
+        address_records: matches.get_flag("mem_address"),
+    }
+}
+
+/// Netboot settings from the `--net-*` options.
+#[must_use]
+pub fn net_load_options(matches: &ArgMatches) -> NetLoadOptions {
+    let defaults = NetLoadOptions::default();
+    let timeout = matches.get_one::<u64>("net_timeout").map(|secs| Duration::from_secs(*secs));
+    NetLoadOptions {
+        protocol: matches
+            .get_one::<String>("net_protocol")
+            .and_then(|name| NetbootProtocol::from_name(name))
+            .unwrap_or(defaults.protocol),
+        connect_timeout: timeout.unwrap_or(defaults.connect_timeout),
+        ack_timeout: timeout.unwrap_or(defaults.ack_timeout),
+        retries: matches.get_one::<u32>("net_retries").copied().unwrap_or(defaults.retries),
+        chunk_size: matches.get_one::<u32>("net_chunk").copied().unwrap_or(defaults.chunk_size),
+        progress: defaults.progress,
+    }
+}
+
+/// Serial monitor settings from `--debug`, `--log`, `--timestamps`, `--until`,
+/// `--fail-on`, `--monitor-timeout` and `--script`.
+#[must_use]
+pub fn monitor_options(matches: &ArgMatches) -> MonitorOptions {
+    MonitorOptions {
+        debug: matches.get_flag("debug"),
+        timestamps: matches.get_flag("timestamps"),
+        log: matches.get_one::<PathBuf>("log").cloned(),
+        until: matches.get_one::<Regex>("until").cloned(),
+        fail_on: matches.get_one::<Regex>("fail_on").cloned(),
+        timeout: matches.get_one::<u64>("monitor_timeout").map(|secs| Duration::from_secs(*secs)),
+        script: matches.get_one::<Script>("script").cloned(),
+    }
+}
+
+/// Emulated UART input from `--uart-input`, `--uart-input-file`, `--uart-stdin` or `--interactive`.
+#[must_use]
+pub fn uart_input(matches: &ArgMatches) -> UartInput {
+    if matches.get_flag("interactive") {
+        return UartInput::Terminal;
+    }
+    if matches.get_flag("uart_stdin") {
+        return UartInput::Stdin;
+    }
+    let bytes = matches
+        .get_one::<Vec<u8>>("uart_input")
+        .or_else(|| matches.get_one::<Vec<u8>>("uart_input_file"))
+        .cloned()
+        .unwrap_or_default();
+    UartInput::Bytes(bytes)
+}
+
+/// Value of a CLI option only if it was given on the command line (ignores clap defaults).
+#[must_use]
+pub fn cli_value(matches: &ArgMatches, id: &str) -> Option<String> {
+    if matches.value_source(id) == Some(clap::parser::ValueSource::CommandLine) {
+        matches.get_one::<String>(id).cloned()
+    } else {
+        None
+    }
+}
@@ -1,17 +1,61 @@
 //! Subcommand handlers — each `run_*` function drives one CLI mode end to end
 //! (net-load, mem-out, elf2serial, kbt send, emulate, and the test runners).
 
-use crate::files::{filename_stem, read_file_to_vector, write_code_output_file};
-use crate::helper::{build_ddr_image, disassemble_flat_to_pass2, encode_word_kbt, human_bytes, parse_expected_uart_values, HEAP_HEADER_WORDS};
-use crate::messages::{print_messages, MessageType, MsgList};
-use crate::netload::net_load;
-use crate::opcodes::{parse_vh_file, Opcode, Pass2};
-use crate::serial::{monitor_serial_port, run_test_monitor, write_to_board_keep_port, AUTO_SERIAL};
-use crate::{assemble_file, assemble_to_image, build_flat_code, print_results, write_binary_file, write_to_device, ELF_MAGIC};
-use crate::{emulate, helper, macros};
+use crate::{assemble_file, assemble_to_image, print_results, write_binary_file, write_to_device};
 use chrono::NaiveTime;
+use klausscc::assembler::build_flat_code;
+use klausscc::cache_sim::{Cache, CacheConfig};
+use klausscc::debugger::Debugger;
+use klausscc::disasm::{disassemble_to_source, round_trip_opcodes};
+use klausscc::discover::{discover, select_board, BoardAnnouncement, DISCOVERY_WAIT};
+use klausscc::emulate::{Cpu, EmulateResult, StopReason, UartInput};
+use klausscc::expect::{run_script, EmulatorTarget, Script, ScriptTarget as _};
+use klausscc::fake_board::uart_bytes;
+use klausscc::files::{code_listing, filename_stem, read_file_to_vector, write_code_output_file};
+use klausscc::gdb_stub::GdbStub;
+use klausscc::helper::{
+    build_ddr_image, disassemble_flat_to_pass2, encode_word_kbt, human_bytes, parse_expected_uart_values, parse_mem_image, parse_mem_lanes,
+    HEAP_HEADER_WORDS,
+};
+use klausscc::image_format::{mem_files, suffixed_file_name, MemLayout};
+use klausscc::kbt::{parse_kbt, KbtImage};
+use klausscc::messages::{print_messages, MessageType, MsgList};
+use klausscc::netload::{net_load_with, NetLoadOptions};
+use klausscc::opcodes::{parse_vh_file, Opcode, Pass2};
+use klausscc::peripherals::{parse_expected_peripherals, Peripherals};
+use klausscc::serial::{
+    forward_keystrokes, monitor_serial_port, run_test_monitor, start_raw_terminal, write_to_board_keep_port, MonitorOptions, MonitorOutcome,
+    AUTO_SERIAL,
+};
+use klausscc::size_report::{format_size_diff, SizeReport};
+use klausscc::symbols::SymbolMap;
+use klausscc::timing::TimingModel;
+use klausscc::{emulate, helper, Assembler, Isa, ELF_MAGIC};
 use std::fmt::Write as _;
 use std::fs;
+use std::io::{Read as _, Write as _};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Instructions run between front panel redraws (`--panel`).
+const PANEL_SLICE: u64 = 20_000;
+
+/// Shortest time between front panel redraws.
+const PANEL_REFRESH: Duration = Duration::from_millis(50);
+
+/// Characters of the last UART line shown under the front panel.
+const PANEL_UART_WIDTH: usize = 60;
+
+/// Labels listed in each cache report (`--icache` / `--dcache`).
+const CACHE_REPORT_LABELS: usize = 10;
+
+/// Rows in the flat profile and functions in the call graph (`--profile`).
+const PROFILE_REPORT_ROWS: usize = 20;
 
 /// Result of a single test in a batch run.
 struct BatchTestResult {
@@ -164,6 +208,7 @@ pub(crate) fn run_netload(
     entry_override: Option<u32>,
     board_ip: &str,
     board_port: u16,
+    options: &NetLoadOptions,
     msg_list: &mut MsgList,
     start_time: NaiveTime,
 ) -> Result<(), i32> {
@@ -210,7 +255,7 @@ pub(crate) fn run_netload(
 
     let image = build_ddr_image(&binary_data);
 
-    if let Err(err) = net_load(board_ip, board_port, &image, entry_addr, msg_list) {
+    if let Err(err) = net_load_with(board_ip, board_port, &image, entry_addr, options, msg_list) {
         msg_list.push(format!("netboot failed: \"{err}\""), None, None, MessageType::Error);
         print_results(msg_list, start_time);
         return Err(1);
@@ -227,7 +272,13 @@ pub(crate) fn run_netload(
 /// `boot_rom.v` (`DEPTH_DW` × 64-bit, `$readmemh`).  `boot_rom`'s copy FSM reads
 /// word 0 (`heap_start` = image byte length) to know how much to copy to DDR.
 #[cfg(not(tarpaulin_include))]
-pub(crate) fn run_mem_out(binary_path: &str, mem_file_name: &str, msg_list: &mut MsgList, start_time: NaiveTime) -> Result<(), i32> {
+pub(crate) fn run_mem_out(
+    binary_path: &str,
+    mem_file_name: &str,
+    layout: &MemLayout,
+    msg_list: &mut MsgList,
+    start_time: NaiveTime,
+) -> Result<(), i32> {
     let file_data = fs::read(binary_path).map_err(|e| {
         msg_list.push(format!("Cannot read binary file {binary_path}: {e}"), None, None, MessageType::Error);
         1
@@ -264,29 +315,12 @@ pub(crate) fn run_mem_out(binary_path: &str, mem_file_name: &str, msg_list: &mut
     }
     let image = build_ddr_image(&binary_data);
 
-    // One 64-bit little-endian doubleword per line. image is 8-byte aligned.
-    let mut out = String::with_capacity(image.len() / 8 * 17);
-    for chunk in image.chunks(8) {
-        let dw = u64::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3], chunk[4], chunk[5], chunk[6], chunk[7]]);
-        let _ = writeln!(out, "{dw:016X}");
-    }
-
-    if let Err(e) = fs::write(mem_file_name, &out) {
-        msg_list.push(
-            format!("Failed to write boot ROM image {mem_file_name}: {e}"),
-            None,
-            None,
-            MessageType::Error,
-        );
+    if !write_mem_image(&image, mem_file_name, layout, msg_list) {
         print_results(msg_list, start_time);
         return Err(1);
     }
     msg_list.push(
-        format!(
-            "mem-out: {binary_path} → {mem_file_name} ({} doublewords, {} bytes)",
-            image.len() / 8,
-            image.len()
-        ),
+        format!("mem-out: {binary_path} → {mem_file_name} ({} bytes)", image.len()),
         None,
         None,
         MessageType::Information,
@@ -295,6 +329,34 @@ pub(crate) fn run_mem_out(binary_path: &str, mem_file_name: &str, msg_list: &mut
     Ok(())
 }
 
+/// Write a DDR image as `$readmemh` file(s) in `layout`; false (with the reason in `msg_list`) on failure.
+///
+/// Byte-lane layouts write one file per lane, named `<stem>_lane<n>.<ext>`.
+#[cfg(not(tarpaulin_include))]
+pub(crate) fn write_mem_image(image: &[u8], mem_file_name: &str, layout: &MemLayout, msg_list: &mut MsgList) -> bool {
+    let files = match mem_files(image, layout) {
+        Ok(files) => files,
+        Err(e) => {
+            msg_list.push(
+                format!("Cannot lay out boot ROM image {mem_file_name}: {e}"),
+                None,
+                None,
+                MessageType::Error,
+            );
+            return false;
+        }
+    };
+    for (suffix, text) in files {
+        let file_name = suffixed_file_name(mem_file_name, &suffix);
+        if let Err(e) = fs::write(&file_name, text) {
+            msg_list.push(format!("Failed to write boot ROM image {file_name}: {e}"), None, None, MessageType::Error);
+            return false;
+        }
+        msg_list.push(format!("Writing boot ROM image to {file_name}"), None, None, MessageType::Information);
+    }
+    true
+}
+
 /// Convert an LLVM ELF or flat binary to the board wire format and optionally send it.
 ///
 /// Detects ELF magic automatically:
@@ -314,7 +376,7 @@ pub(crate) fn run_elf2serial(
     output_serial_port: &str,
     kbt_file_name: &str,
     monitor_flag: bool,
-    debug_flag: bool,
+    monitor_options: &MonitorOptions,
     no_break_flag: bool,
     msg_list: &mut MsgList,
     start_time: NaiveTime,
@@ -449,10 +511,7 @@ pub(crate) fn run_elf2serial(
                 Ok(port) => {
                     msg_list.push("Wrote to serial port".to_owned(), None, None, MessageType::Information);
                     print_results(msg_list, start_time);
-                    if let Err(err) = monitor_serial_port(port, debug_flag, msg_list) {
-                        msg_list.push(format!("Serial monitor stopped: \"{err}\""), None, None, MessageType::Warning);
-                    }
-                    return Ok(());
+                    return monitor_exit(monitor_serial_port(port, monitor_options, msg_list), monitor_options, msg_list);
                 }
                 Err(err) => {
                     msg_list.push(format!("Failed to write to serial port, error \"{err}\""), None, None, MessageType::Error);
@@ -468,6 +527,33 @@ pub(crate) fn run_elf2serial(
     Ok(())
 }
 
+/// Turn a serial monitor run into the process result.
+///
+/// A monitor error (a dropped or refused connection, a failed read, a `--log`
+/// file that cannot be opened), a `--fail-on` match, a failed `--script` or
+/// `--until` never matching is a failure, so board runs can gate CI.
+pub(crate) fn monitor_exit(result: Result<MonitorOutcome, std::io::Error>, options: &MonitorOptions, msg_list: &mut MsgList) -> Result<(), i32> {
+    match result {
+        Ok(outcome) if !outcome.is_success(options) => {
+            let reason = match outcome {
+                MonitorOutcome::Failed(line) => format!("Failure pattern matched: \"{line}\""),
+                MonitorOutcome::ScriptFailed(reason) => reason,
+                MonitorOutcome::TimedOut => "Monitor timed out before --until matched".to_owned(),
+                MonitorOutcome::Halted | MonitorOutcome::Stopped | MonitorOutcome::Matched(_) | MonitorOutcome::ScriptPassed(_) => {
+                    "Monitor stopped before --until matched".to_owned()
+                }
+            };
+            msg_list.push(reason, None, None, MessageType::Error);
+            Err(1)
+        }
+        Ok(_) => Ok(()),
+        Err(err) => {
+            msg_list.push(format!("Serial monitor stopped: \"{err}\""), None, None, MessageType::Error);
+            Err(1)
+        }
+    }
+}
+
 /// Send a pre-built `.kbt` board wire-format image to the board and/or monitor it.
 ///
 /// A `.kbt` file already holds the complete wire-format string (`S…Z…X`) produced
@@ -479,7 +565,7 @@ pub(crate) fn run_kbt_send(
     kbt_path: &str,
     output_serial_port: &str,
     monitor_flag: bool,
-    debug_flag: bool,
+    monitor_options: &MonitorOptions,
     no_break_flag: bool,
     msg_list: &mut MsgList,
     start_time: NaiveTime,
@@ -524,10 +610,7 @@ pub(crate) fn run_kbt_send(
             Ok(port) => {
                 msg_list.push("Wrote to serial port".to_owned(), None, None, MessageType::Information);
                 print_results(msg_list, start_time);
-                if let Err(err) = monitor_serial_port(port, debug_flag, msg_list) {
-                    msg_list.push(format!("Serial monitor stopped: \"{err}\""), None, None, MessageType::Warning);
-                }
-                return Ok(());
+                return monitor_exit(monitor_serial_port(port, monitor_options, msg_list), monitor_options, msg_list);
             }
             Err(err) => {
                 msg_list.push(format!("Failed to write to serial port, error \"{err}\""), None, None, MessageType::Error);
@@ -619,6 +702,65 @@ pub fn run_test_mode(
     }
 }
 
+/// `--emulate` settings shared by the `.kla` and binary paths.
+pub(crate) struct EmulateOptions<'a> {
+    /// Where to write the instruction trace (`None`: no trace).
+    pub trace_file: Option<&'a str>,
+    /// Expect script to drive the program with, instead of a plain run.
+    pub script: Option<&'a Script>,
+    /// The program's UART input.
+    pub input: UartInput,
+    /// Instruction cap.
+    pub max_instructions: u64,
+    /// Open the debugger instead of running (`--emulate --debug`).
+    pub debug: bool,
+    /// Opcode list for the debugger's disassembly (empty if unknown).
+    pub opcodes: &'a [Opcode],
+    /// Address to wait for GDB on (`--gdb`).
+    pub gdb: Option<&'a str>,
+    /// Switch word SWR reads (`--switches`).
+    pub switches: u64,
+    /// Show the live front panel (`--panel`).
+    pub panel: bool,
+    /// Where to write the peripheral change history (`--peripheral-log`).
+    pub peripheral_log: Option<&'a str>,
+    /// Estimate run time (`--timing`): the cycle table file, or empty for the built-in model.
+    pub timing: Option<&'a str>,
+    /// Clock frequency for the estimate (`--clock-mhz`).
+    pub clock_mhz: Option<f64>,
+    /// Instruction cache to simulate (`--icache`).
+    pub icache: Option<CacheConfig>,
+    /// Data cache to simulate (`--dcache`).
+    pub dcache: Option<CacheConfig>,
+    /// Print a flat profile and call graph (`--profile`).
+    pub profile: bool,
+    /// Where to write the profile's folded stacks (`--profile-folded`).
+    pub profile_folded: Option<&'a str>,
+}
+
+impl Default for EmulateOptions<'_> {
+    fn default() -> Self {
+        Self {
+            trace_file: None,
+            script: None,
+            input: UartInput::default(),
+            max_instructions: emulate::DEFAULT_MAX_INSTRUCTIONS,
+            debug: false,
+            opcodes: &[],
+            gdb: None,
+            switches: 0,
+            panel: false,
+            peripheral_log: None,
+            timing: None,
+            clock_mhz: None,
+            icache: None,
+            dcache: None,
+            profile: false,
+            profile_folded: None,
+        }
+    }
+}
+
 /// Run the emulator on a single assembled program (`--emulate`).
 ///
 /// Builds the flat DDR image, executes the golden model, prints captured UART
@@ -627,8 +769,7 @@ pub fn run_test_mode(
 pub(crate) fn run_emulate(
     pass2: &[Pass2],
     input_file_name: &str,
-    trace_file: Option<&str>,
-    max_instructions: u64,
+    options: &EmulateOptions<'_>,
     msg_list: &mut MsgList,
     start_time: NaiveTime,
 ) -> Result<(), i32> {
@@ -649,10 +790,110 @@ pub(crate) fn run_emulate(
         None,
         MessageType::Information,
     );
+    emulate_and_report(&image, entry, pass2, &SymbolMap::from_pass2(pass2), options, msg_list, start_time)
+}
 
-    let (result, trace) = emulate::emulate_image(&image, entry, max_instructions, trace_file.is_some());
+/// Run a DDR image in the emulator and print the outcome, UART output and trace.
+///
+/// With `--uart-stdin` or `--interactive` the UART output is streamed while the
+/// program runs (`--interactive` forwards keystrokes like the serial monitor);
+/// otherwise it is printed once the run stops.  `--panel` shows the board
+/// outputs live while it runs.
+#[cfg(not(tarpaulin_include))]
+fn emulate_and_report(
+    image: &[u8],
+    entry: u32,
+    pass2: &[Pass2],
+    symbols: &SymbolMap,
+    options: &EmulateOptions<'_>,
+    msg_list: &mut MsgList,
+    start_time: NaiveTime,
+) -> Result<(), i32> {
+    if options.debug {
+        return debug_emulator(image, entry, pass2, symbols, options, msg_list, start_time);
+    }
+    if let Some(address) = options.gdb {
+        return serve_gdb(image, entry, address, options, msg_list, start_time);
+    }
+    if let Some(script) = options.script {
+        return emulate_script(image, entry, script, options, msg_list, start_time);
+    }
+    let mut trace = options.trace_file.is_some().then(String::new);
+    let mut cpu = Cpu::new(image, entry);
+    cpu.set_switches(options.switches);
+    if let Some(table) = options.timing {
+        let Some(timing) = timing_model(table, options, msg_list) else {
+            print_messages(msg_list);
+            return Err(1);
+        };
+        cpu.set_timing(timing);
+    }
+    if let Some(config) = options.icache {
+        cpu.set_icache(Cache::new(config));
+    }
+    if let Some(config) = options.dcache {
+        cpu.set_dcache(Cache::new(config));
+    }
+    if options.profile || options.profile_folded.is_some() {
+        cpu.start_profile();
+    }
+    let result = match &options.input {
+        UartInput::Bytes(bytes) => {
+            cpu.push_uart_input(bytes);
+            cpu.close_uart_input();
+            if options.panel {
+                print_results(msg_list, start_time);
+                run_with_panel(&mut cpu, options.max_instructions, trace.as_mut())
+            } else {
+                cpu.run(options.max_instructions, trace.as_mut())
+            }
+        }
+        UartInput::Stdin | UartInput::Terminal => {
+            print_results(msg_list, start_time);
+            let interactive = options.input == UartInput::Terminal;
+            let running = Arc::new(AtomicBool::new(true));
+            let input = if interactive {
+                println!("--- Interactive UART (Ctrl+C to stop) ---");
+                start_raw_terminal(msg_list);
+                let (sender, receiver) = mpsc::channel();
+                forward_keystrokes(Arc::clone(&running), move |byte| drop(sender.send(vec![byte])));
+                receiver
+            } else {
+                println!("--- UART output (input from stdin) ---");
+                stdin_chunks()
+            };
+            let mut stdout = std::io::stdout();
+            let result = emulate::run_live(
+                &mut cpu,
+                options.max_instructions,
+                &input,
+                &running,
+                |text| {
+                    let _ = stdout.write_all(&uart_bytes(text));
+                    let _ = stdout.flush();
+                },
+                trace.as_mut(),
+            );
+            // Stop the keystroke thread before handing the terminal back.
+            running.store(false, Ordering::Relaxed);
+            if interactive {
+                let _ = crossterm::terminal::disable_raw_mode();
+            }
+            println!("--- end UART ---");
+            result
+        }
+    };
+    if let Some(path) = options.peripheral_log {
+        write_peripheral_log(path, cpu.peripherals(), msg_list);
+    }
+    if let (Some(path), Some(profiler)) = (options.profile_folded, cpu.profiler()) {
+        match fs::write(path, profiler.folded(symbols)) {
+            Ok(()) => msg_list.push(format!("Wrote folded profile stacks to {path}"), None, None, MessageType::Information),
+            Err(err) => msg_list.push(format!("Failed to write folded profile {path}: {err}"), None, None, MessageType::Error),
+        }
+    }
 
-    if let (Some(path), Some(text)) = (trace_file, trace.as_ref()) {
+    if let (Some(path), Some(text)) = (options.trace_file, trace.as_ref()) {
         if let Err(e) = fs::write(path, text) {
             msg_list.push(format!("Failed to write trace file {path}: {e}"), None, None, MessageType::Error);
         } else {
@@ -670,17 +911,296 @@ pub(crate) fn run_emulate(
         "--- Emulator finished: {} instructions, stop = {:?} ---",
         result.instructions, result.stop
     );
-    if trace_file.is_none() {
-        if let Some(text) = trace.as_ref() {
-            print!("{text}");
+    if let Some(timing) = cpu.timing() {
+        println!("--- Estimated {} ---", timing.describe(cpu.cycles()));
+    }
+    for (name, cache) in [("I-cache", cpu.icache()), ("D-cache", cpu.dcache())] {
+        if let Some(cache) = cache {
+            print!("{}", cache.report(name, symbols, CACHE_REPORT_LABELS));
+        }
+    }
+    if let Some(profiler) = cpu.profiler().filter(|_| options.profile) {
+        print!("{}", profiler.flat_report(symbols, PROFILE_REPORT_ROWS));
+        print!("{}", profiler.call_graph_report(symbols, PROFILE_REPORT_ROWS));
+    }
+    if matches!(options.input, UartInput::Bytes(_)) {
+        println!("--- Captured UART output ---");
+        print!("{}", result.uart);
+        println!("--- end UART ---");
+    }
+    Ok(())
+}
+
+/// The `--timing` model: the built-in one for an empty `table`, else the table
+/// file, whose opcode names need the opcode file.  `--clock-mhz` overrides its clock.
+#[cfg(not(tarpaulin_include))]
+fn timing_model(table: &str, options: &EmulateOptions<'_>, msg_list: &mut MsgList) -> Option<TimingModel> {
+    let mut timing = if table.is_empty() {
+        TimingModel::new()
+    } else {
+        match TimingModel::load(Path::new(table), options.opcodes) {
+            Ok(timing) => timing,
+            Err(err) => {
+                let hint = if options.opcodes.is_empty() {
+                    " (opcode names need -c <opcode file>)"
+                } else {
+                    ""
+                };
+                msg_list.push(format!("{err}{hint}"), None, None, MessageType::Error);
+                return None;
+            }
+        }
+    };
+    if let Some(mhz) = options.clock_mhz {
+        timing.set_clock_mhz(mhz);
+    }
+    Some(timing)
+}
+
+/// Run to a stop with the front panel redrawn in place (`--emulate --panel`).
+///
+/// The panel is drawn at most every [`PANEL_REFRESH`] and once more at the end,
+/// with the last line of UART output under it.
+#[cfg(not(tarpaulin_include))]
+fn run_with_panel(cpu: &mut Cpu, max_instructions: u64, mut trace: Option<&mut String>) -> EmulateResult {
+    let mut stdout = std::io::stdout();
+    let mut uart = String::new();
+    let mut retired = 0_u64;
+    let mut drawn = 0_u16;
+    let mut last_draw: Option<Instant> = None;
+    loop {
+        let result = cpu.run(PANEL_SLICE.min(max_instructions - retired), trace.as_deref_mut());
+        retired += result.instructions;
+        uart.push_str(&result.uart);
+        let running = result.stop == StopReason::InstructionCap && retired < max_instructions;
+        if !running || last_draw.is_none_or(|drawn_at| drawn_at.elapsed() >= PANEL_REFRESH) {
+            drawn = draw_panel(&mut stdout, cpu.peripherals(), &uart, drawn);
+            last_draw = Some(Instant::now());
+        }
+        if !running {
+            return EmulateResult {
+                uart,
+                instructions: retired,
+                stop: result.stop,
+            };
+        }
+    }
+}
+
+/// Draw the front panel over the `drawn` lines of the previous one; returns
+/// the number of lines drawn.
+#[cfg(not(tarpaulin_include))]
+fn draw_panel(out: &mut impl std::io::Write, panel: &Peripherals, uart: &str, drawn: u16) -> u16 {
+    use crossterm::cursor::MoveUp;
+    use crossterm::terminal::{Clear, ClearType};
+    if drawn > 0 {
+        let _ = crossterm::queue!(out, MoveUp(drawn), Clear(ClearType::FromCursorDown));
+    }
+    let mut lines = panel.render();
+    let last = uart.lines().map(str::trim).rfind(|line| !line.is_empty()).unwrap_or("");
+    lines.push(format!("UART  {}", last.chars().take(PANEL_UART_WIDTH).collect::<String>()));
+    for line in &lines {
+        let _ = writeln!(out, "{line}");
+    }
+    let _ = out.flush();
+    u16::try_from(lines.len()).unwrap_or(u16::MAX)
+}
+
+/// Write the peripheral change history to `path` (`--peripheral-log`).
+#[cfg(not(tarpaulin_include))]
+fn write_peripheral_log(path: &str, panel: &Peripherals, msg_list: &mut MsgList) {
+    let mut text = String::new();
+    if panel.dropped() > 0 {
+        let _ = writeln!(text, "# {} earlier changes dropped", panel.dropped());
+    }
+    for (instruction, event) in panel.history() {
+        let _ = writeln!(text, "{instruction:>10}  {event}");
+    }
+    match fs::write(path, text) {
+        Ok(()) => msg_list.push(
+            format!("Wrote {} peripheral changes to {path}", panel.history().len()),
+            None,
+            None,
+            MessageType::Information,
+        ),
+        Err(err) => msg_list.push(format!("Failed to write peripheral log {path}: {err}"), None, None, MessageType::Error),
+    }
+}
+
+/// Debug a DDR image interactively (`--emulate --debug`): read commands from
+/// standard input until `quit` or end of file.
+///
+/// `--uart-input` bytes are queued for the program; live input cannot share
+/// the terminal with the command prompt.
+#[cfg(not(tarpaulin_include))]
+fn debug_emulator(
+    image: &[u8],
+    entry: u32,
+    pass2: &[Pass2],
+    symbols: &SymbolMap,
+    options: &EmulateOptions<'_>,
+    msg_list: &mut MsgList,
+    start_time: NaiveTime,
+) -> Result<(), i32> {
+    let UartInput::Bytes(input) = &options.input else {
+        msg_list.push(
+            "--debug cannot be combined with --uart-stdin or --interactive".to_owned(),
+            None,
+            None,
+            MessageType::Error,
+        );
+        print_messages(msg_list);
+        return Err(1);
+    };
+    if options.script.is_some() {
+        msg_list.push("--debug cannot be combined with --script".to_owned(), None, None, MessageType::Error);
+        print_messages(msg_list);
+        return Err(1);
+    }
+    let mut cpu = Cpu::new(image, entry);
+    cpu.set_switches(options.switches);
+    cpu.push_uart_input(input);
+    cpu.close_uart_input();
+    let mut debugger = Debugger::new(cpu, pass2, options.opcodes, options.max_instructions).with_symbols(symbols);
+    print_results(msg_list, start_time);
+    println!("--- Emulator debugger (help for commands, quit to leave) ---");
+    println!("{}", debugger.location());
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+    loop {
+        print!("(kdb) ");
+        let _ = std::io::stdout().flush();
+        line.clear();
+        if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+            println!();
+            break;
+        }
+        let (output, quit) = debugger.execute(&line);
+        print!("{output}");
+        if quit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Let one GDB session drive the emulator (`--emulate --gdb`).
+///
+/// `--uart-input` bytes are queued for the program; its UART output goes to
+/// the GDB console.
+#[cfg(not(tarpaulin_include))]
+fn serve_gdb(
+    image: &[u8],
+    entry: u32,
+    address: &str,
+    options: &EmulateOptions<'_>,
+    msg_list: &mut MsgList,
+    start_time: NaiveTime,
+) -> Result<(), i32> {
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(err) => {
+            msg_list.push(format!("Unable to listen on {address}: \"{err}\""), None, None, MessageType::Error);
+            print_messages(msg_list);
+            return Err(1);
+        }
+    };
+    let local = listener.local_addr().map_or_else(|_| address.to_owned(), |local| local.to_string());
+    msg_list.push(
+        format!("Waiting for GDB on {local}, connect with: target remote {local}"),
+        None,
+        None,
+        MessageType::Information,
+    );
+    print_results(msg_list, start_time);
+    msg_list.live = true;
+    let (stream, peer) = match listener.accept() {
+        Ok(connection) => connection,
+        Err(err) => {
+            msg_list.push(format!("GDB accept failed: \"{err}\""), None, None, MessageType::Error);
+            return Err(1);
         }
+    };
+    msg_list.push(format!("GDB connected from {peer}"), None, None, MessageType::Information);
+    let mut cpu = Cpu::new(image, entry);
+    cpu.set_switches(options.switches);
+    if let UartInput::Bytes(input) = &options.input {
+        cpu.push_uart_input(input);
+    }
+    cpu.close_uart_input();
+    let mut stub = GdbStub::new(cpu, options.max_instructions);
+    if let Err(err) = stub.serve(stream) {
+        msg_list.push(format!("GDB connection dropped: \"{err}\""), None, None, MessageType::Warning);
     }
-    println!("--- Captured UART output ---");
-    print!("{}", result.uart);
-    println!("--- end UART ---");
+    msg_list.push(
+        format!("GDB session ended after {} instructions", stub.cpu().retired()),
+        None,
+        None,
+        MessageType::Information,
+    );
     Ok(())
 }
 
+/// Standard input in chunks as it arrives; the sender hangs up at end of file.
+#[cfg(not(tarpaulin_include))]
+fn stdin_chunks() -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+        let mut buffer = [0_u8; 1024];
+        while let Ok(count @ 1..) = stdin.read(&mut buffer) {
+            if sender.send(buffer[..count].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Drive an emulated program with an expect script (`--emulate --script`).
+///
+/// UART output is shown as it is produced; a failing step is an error.
+#[cfg(not(tarpaulin_include))]
+fn emulate_script(
+    image: &[u8],
+    entry: u32,
+    script: &Script,
+    options: &EmulateOptions<'_>,
+    msg_list: &mut MsgList,
+    start_time: NaiveTime,
+) -> Result<(), i32> {
+    print_results(msg_list, start_time);
+    let mut stdout = std::io::stdout();
+    let mut target = EmulatorTarget::new(image, entry, options.max_instructions);
+    // Switches set before the first step, so a script's own `switches` steps override them.
+    let _ = target.set_switches(options.switches);
+    let result = run_script(script, &mut target, |data| {
+        let _ = stdout.write_all(data);
+        let _ = stdout.flush();
+    });
+    println!();
+    if let Some(path) = options.peripheral_log {
+        write_peripheral_log(path, target.cpu().peripherals(), msg_list);
+    }
+    match result {
+        Ok(steps) => {
+            msg_list.push(
+                format!("Script {} passed: {steps} steps", script.name),
+                None,
+                None,
+                MessageType::Information,
+            );
+            print_messages(msg_list);
+            Ok(())
+        }
+        Err(failure) => {
+            msg_list.push(failure.describe(script), None, None, MessageType::Error);
+            print_messages(msg_list);
+            Err(1)
+        }
+    }
+}
+
 /// Run the emulator on an ELF or flat binary input (`--emulate` with binary input).
 ///
 /// Mirrors `run_elf2serial`'s ELF → flat → board-address conversion, then builds
@@ -690,8 +1210,7 @@ pub(crate) fn run_emulate(
 pub(crate) fn run_emulate_elf(
     binary_path: &str,
     entry_override: Option<u32>,
-    trace_file: Option<&str>,
-    max_instructions: u64,
+    options: &EmulateOptions<'_>,
     msg_list: &mut MsgList,
     start_time: NaiveTime,
 ) -> Result<(), i32> {
@@ -700,7 +1219,7 @@ pub(crate) fn run_emulate_elf(
         1
     })?;
 
-    let (binary_data, entry_addr, elf) = flatten_input(file_data, entry_override).ok_or_else(|| {
+    let (binary_data, entry_addr, elf) = flatten_input(file_data.clone(), entry_override).ok_or_else(|| {
         msg_list.push(
             format!("Failed to extract LOAD segments from ELF file {binary_path}"),
             None,
@@ -709,6 +1228,10 @@ pub(crate) fn run_emulate_elf(
         );
         1
     })?;
+    let symbols = elf
+        .as_ref()
+        .map(|elf| SymbolMap::from_elf(&file_data, elf.base, HEAP_HEADER_WORDS * 8))
+        .unwrap_or_default();
     if let Some(elf) = elf {
         msg_list.push(
             format!(
@@ -724,55 +1247,573 @@ pub(crate) fn run_emulate_elf(
         msg_list.push(format!("Emulating flat binary {binary_path}"), None, None, MessageType::Information);
     }
 
-    let image = build_ddr_image(&binary_data);
-    let (result, trace) = emulate::emulate_image(&image, entry_addr, max_instructions, trace_file.is_some());
+    emulate_and_report(&build_ddr_image(&binary_data), entry_addr, &[], &symbols, options, msg_list, start_time)
+}
 
-    if let (Some(path), Some(text)) = (trace_file, trace.as_ref()) {
-        if let Err(e) = fs::write(path, text) {
-            msg_list.push(format!("Failed to write trace file {path}: {e}"), None, None, MessageType::Error);
+/// Consistency problems in a decoded `.kbt` beyond framing and checksum.
+///
+/// `heap_start` must lie at or after the end of the image, and the entry PC
+/// must point into the program.
+fn kbt_layout_problems(image: &KbtImage) -> Vec<String> {
+    let code_start = HEAP_HEADER_WORDS * 8;
+    let code_end = code_start + image.code.len() as u32;
+    let mut problems: Vec<String> = Vec::new();
+    if image.heap_start() < code_end {
+        problems.push(format!(
+            "heap_start 0x{:08X} is inside the program (image ends at 0x{code_end:08X})",
+            image.heap_start()
+        ));
+    }
+    if image.entry < code_start || image.entry >= code_end {
+        problems.push(format!(
+            "entry PC 0x{:08X} is outside the program (0x{code_start:08X}..0x{code_end:08X})",
+            image.entry
+        ));
+    }
+    problems
+}
+
+/// Decode and validate a `.kbt` file, optionally printing its disassembly.
+///
+/// Fails on malformed framing, odd lengths or a checksum mismatch; header
+/// and entry inconsistencies are reported as warnings.
+#[cfg(not(tarpaulin_include))]
+pub(crate) fn run_inspect(
+    kbt_path: &str,
+    opcode_file_name: &str,
+    disassemble: bool,
+    msg_list: &mut MsgList,
+    start_time: NaiveTime,
+) -> Result<(), i32> {
+    let text = fs::read_to_string(kbt_path).map_err(|e| {
+        msg_list.push(format!("Cannot read file {kbt_path}: {e}"), None, None, MessageType::Error);
+        print_results(msg_list, start_time);
+        1
+    })?;
+    let image = parse_kbt(&text).map_err(|e| {
+        msg_list.push(format!("Malformed kbt file {kbt_path}: {e}"), None, None, MessageType::Error);
+        print_results(msg_list, start_time);
+        1
+    })?;
+
+    msg_list.push(
+        format!(
+            "{kbt_path}: program {} ({} words) at 0x{:08X}, entry 0x{:08X}, heap_start 0x{:08X}",
+            human_bytes(image.code.len()),
+            image.code.len() / 4,
+            HEAP_HEADER_WORDS * 8,
+            image.entry,
+            image.heap_start()
+        ),
+        None,
+        None,
+        MessageType::Information,
+    );
+    for (index, word) in image.header.iter().enumerate().skip(1).filter(|(_, word)| **word != 0) {
+        msg_list.push(
+            format!("Reserved header word {index} is 0x{word:016X}, expected 0"),
+            None,
+            None,
+            MessageType::Warning,
+        );
+    }
+    for problem in kbt_layout_problems(&image) {
+        msg_list.push(problem, None, None, MessageType::Warning);
+    }
+    if image.checksum_ok() {
+        msg_list.push(format!("Checksum 0x{:08X} OK", image.checksum), None, None, MessageType::Information);
+    } else {
+        msg_list.push(
+            format!(
+                "Checksum mismatch: file has 0x{:08X}, computed 0x{:08X}",
+                image.checksum, image.computed_checksum
+            ),
+            None,
+            None,
+            MessageType::Error,
+        );
+    }
+
+    if disassemble {
+        if let Some(isa) = Isa::from_vh_file(opcode_file_name, msg_list) {
+            print!(
+                "{}",
+                code_listing(&disassemble_flat_to_pass2(&image.code, HEAP_HEADER_WORDS * 8, &isa.opcodes))
+            );
         } else {
             msg_list.push(
-                format!("Wrote {} instruction trace lines to {path}", result.instructions),
+                format!("Cannot disassemble without a valid opcode file ({opcode_file_name})"),
                 None,
                 None,
-                MessageType::Information,
+                MessageType::Error,
             );
         }
     }
 
     print_results(msg_list, start_time);
-    println!(
-        "--- Emulator finished: {} instructions, stop = {:?} ---",
-        result.instructions, result.stop
-    );
-    if trace_file.is_none() {
-        if let Some(text) = trace.as_ref() {
-            print!("{text}");
-        }
+    if msg_list.number_by_type(&MessageType::Error) > 0 {
+        return Err(1);
     }
-    println!("--- Captured UART output ---");
-    print!("{}", result.uart);
-    println!("--- end UART ---");
     Ok(())
 }
 
-/// Batch-verify the emulator against `.kla` files with expected `// ` UART values.
-///
-/// `test_path` may be a single `.kla` file, a directory (all `*.kla` inside),
-/// or a test-list file (one path per line).  For each file with expected
-/// values, assemble + emulate and compare captured UART tokens in order.
+/// Print the per-symbol size changes between two `--size-report` JSON files.
 #[cfg(not(tarpaulin_include))]
-pub(crate) fn run_emulate_test(
-    oplist: &[Opcode],
-    macro_list: &[macros::Macro],
-    test_path: &str,
-    max_instructions: u64,
+pub(crate) fn run_size_diff(old_path: &str, new_path: &str, msg_list: &mut MsgList, start_time: NaiveTime) -> Result<(), i32> {
+    let mut reports: Vec<SizeReport> = Vec::new();
+    for path in [old_path, new_path] {
+        match fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+        {
+            Ok(report) => reports.push(report),
+            Err(e) => {
+                msg_list.push(format!("Cannot read size report {path}: {e}"), None, None, MessageType::Error);
+                print_results(msg_list, start_time);
+                return Err(1);
+            }
+        }
+    }
+    if let [old, new] = reports.as_slice() {
+        print!("{}", format_size_diff(old, new));
+    }
+    print_results(msg_list, start_time);
+    Ok(())
+}
+
+/// Broadcast a discovery query and list the boards that answer.
+#[cfg(not(tarpaulin_include))]
+pub(crate) fn run_discover(discover_address: &str, msg_list: &mut MsgList, start_time: NaiveTime) -> Result<(), i32> {
+    let boards = match discover(discover_address, DISCOVERY_WAIT) {
+        Ok(boards) => boards,
+        Err(err) => {
+            msg_list.push(format!("Discovery failed: \"{err}\""), None, None, MessageType::Error);
+            print_results(msg_list, start_time);
+            return Err(1);
+        }
+    };
+    if boards.is_empty() {
+        msg_list.push(
+            format!("No boards answered discovery on {discover_address}"),
+            None,
+            None,
+            MessageType::Warning,
+        );
+    } else {
+        println!("{:<16} {:<40} {:>5}  {:<17}  {:<28} ISA", "NAME", "IP", "PORT", "MAC", "FIRMWARE");
+        for board in &boards {
+            println!(
+                "{:<16} {:<40} {:>5}  {:<17}  {:<28} {}",
+                board.name,
+                board.ip.map(|ip| ip.to_string()).unwrap_or_default(),
+                board.port,
+                board.mac,
+                board.firmware,
+                board.isa
+            );
+        }
+    }
+    print_results(msg_list, start_time);
+    Ok(())
+}
+
+/// Find a board by discovery for `--ip auto` / `--board <name>`.
+#[cfg(not(tarpaulin_include))]
+pub(crate) fn find_board(discover_address: &str, name: Option<&str>, msg_list: &mut MsgList) -> Option<BoardAnnouncement> {
+    let boards = discover(discover_address, DISCOVERY_WAIT)
+        .map_err(|err| msg_list.push(format!("Discovery failed: \"{err}\""), None, None, MessageType::Error))
+        .ok()?;
+    match select_board(&boards, name) {
+        Ok(board) => {
+            msg_list.push(
+                format!(
+                    "Found board \"{}\" at {} port {} ({})",
+                    board.name,
+                    board.ip.map(|ip| ip.to_string()).unwrap_or_default(),
+                    board.port,
+                    board.firmware
+                ),
+                None,
+                None,
+                MessageType::Information,
+            );
+            Some(board.clone())
+        }
+        Err(err) => {
+            msg_list.push(err, None, None, MessageType::Error);
+            None
+        }
+    }
+}
+
+/// Load a program for disassembly: `(code bytes from 0x20, entry PC)`.
+///
+/// `.kbt` files are decoded from the wire format, `.mem` files (or a set of
+/// byte-lane files) from any `--mem-*` `$readmemh` layout, anything else as an
+/// ELF or flat binary.
+#[cfg(not(tarpaulin_include))]
+fn load_program_image(path: &str, entry_override: Option<u32>, msg_list: &mut MsgList) -> Option<(Vec<u8>, u32)> {
+    let lower = path.to_ascii_lowercase();
+    let header_bytes = HEAP_HEADER_WORDS as usize * 8;
+    if lower.ends_with(".kbt") || lower.ends_with(".mem") {
+        let text = fs::read_to_string(path)
+            .map_err(|e| msg_list.push(format!("Cannot read file {path}: {e}"), None, None, MessageType::Error))
+            .ok()?;
+        if lower.ends_with(".kbt") {
+            let image = parse_kbt(&text)
+                .map_err(|e| msg_list.push(format!("Malformed kbt file {path}: {e}"), None, None, MessageType::Error))
+                .ok()?;
+            if !image.checksum_ok() {
+                msg_list.push(format!("Checksum mismatch in {path}"), None, None, MessageType::Warning);
+            }
+            return Some((image.code, entry_override.unwrap_or(image.entry)));
+        }
+        let image = match mem_lane_files(path) {
+            Some(lane_files) => read_mem_lanes(&lane_files, msg_list)?,
+            None => parse_mem_image(&text)
+                .map_err(|e| msg_list.push(format!("Malformed mem file {path}: {e}"), None, None, MessageType::Error))
+                .ok()?,
+        };
+        return Some((
+            image.get(header_bytes..).unwrap_or_default().to_vec(),
+            entry_override.unwrap_or(HEAP_HEADER_WORDS * 8),
+        ));
+    }
+    let file_data = fs::read(path)
+        .map_err(|e| msg_list.push(format!("Cannot read binary file {path}: {e}"), None, None, MessageType::Error))
+        .ok()?;
+    let Some((code, entry, _)) = flatten_input(file_data, entry_override) else {
+        msg_list.push(
+            format!("Failed to extract LOAD segments from ELF file {path}"),
+            None,
+            None,
+            MessageType::Error,
+        );
+        return None;
+    };
+    Some((code, entry))
+}
+
+/// The set of byte-lane files `path` belongs to (`<stem>_lane<n>.<ext>`, from
+/// lane 0 up to the first missing one), or `None` if it is not a lane file.
+fn mem_lane_files(path: &str) -> Option<Vec<String>> {
+    let (stem, extension) = path.rsplit_once('.')?;
+    let (base, lane) = stem.rsplit_once("_lane")?;
+    if lane.is_empty() || !lane.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let file_name = format!("{base}.{extension}");
+    Some(
+        (0..=8)
+            .map(|lane| suffixed_file_name(&file_name, &format!("_lane{lane}")))
+            .take_while(|lane_file| Path::new(lane_file).exists())
+            .collect(),
+    )
+}
+
+/// Read and merge a `--mem-lanes` image's lane files.
+#[cfg(not(tarpaulin_include))]
+fn read_mem_lanes(lane_files: &[String], msg_list: &mut MsgList) -> Option<Vec<u8>> {
+    let mut lanes: Vec<String> = Vec::with_capacity(lane_files.len());
+    for lane_file in lane_files {
+        lanes.push(
+            fs::read_to_string(lane_file)
+                .map_err(|e| msg_list.push(format!("Cannot read file {lane_file}: {e}"), None, None, MessageType::Error))
+                .ok()?,
+        );
+    }
+    parse_mem_lanes(&lanes)
+        .map_err(|e| {
+            msg_list.push(
+                format!("Malformed mem lane files {}: {e}", lane_files.join(", ")),
+                None,
+                None,
+                MessageType::Error,
+            );
+        })
+        .ok()
+}
+
+/// Disassemble a `.kbt`, `.mem`, ELF or flat binary to re-assemblable `.kla` source.
+///
+/// The source is reassembled straight away and must give a byte-identical
+/// DDR image and the same entry PC; any difference is an error.
+#[cfg(not(tarpaulin_include))]
+pub(crate) fn run_disasm(
+    isa: &Isa,
+    input_path: &str,
+    output_path: &str,
+    entry_override: Option<u32>,
     msg_list: &mut MsgList,
     start_time: NaiveTime,
 ) -> Result<(), i32> {
+    let Some((code, entry)) = load_program_image(input_path, entry_override, msg_list) else {
+        print_results(msg_list, start_time);
+        return Err(1);
+    };
+    let disassembly = match disassemble_to_source(&code, entry, &isa.opcodes, input_path) {
+        Ok(disassembly) => disassembly,
+        Err(err) => {
+            msg_list.push(format!("Cannot disassemble {input_path}: {err}"), None, None, MessageType::Error);
+            print_results(msg_list, start_time);
+            return Err(1);
+        }
+    };
+    msg_list.push(
+        format!(
+            "Disassembled {input_path}: {} instructions, {} data bytes, {} labels",
+            disassembly.instructions, disassembly.data_bytes, disassembly.labels
+        ),
+        None,
+        None,
+        MessageType::Information,
+    );
+    if let Err(e) = fs::write(output_path, &disassembly.source) {
+        msg_list.push(format!("Failed to write {output_path}: {e}"), None, None, MessageType::Error);
+        print_results(msg_list, start_time);
+        return Err(1);
+    }
+    msg_list.push(format!("Writing disassembly to {output_path}"), None, None, MessageType::Information);
+
+    let assembly = Assembler::new(isa).source_text(output_path, disassembly.source.as_str()).assemble();
+    let expected = build_ddr_image(&code);
+    match build_flat_code(&assembly.pass2).filter(|_| assembly.is_ok()) {
+        Some((reassembled, reassembled_entry)) if build_ddr_image(&reassembled) == expected && reassembled_entry == entry => {
+            msg_list.push(
+                format!("Round trip: reassembled image is byte-identical ({} bytes)", expected.len()),
+                None,
+                None,
+                MessageType::Information,
+            );
+        }
+        Some((reassembled, reassembled_entry)) => {
+            let image = build_ddr_image(&reassembled);
+            let first_diff = expected
+                .iter()
+                .zip(&image)
+                .position(|(a, b)| a != b)
+                .unwrap_or(expected.len().min(image.len()));
+            msg_list.push(
+                format!(
+                    "Round trip: reassembled image differs from byte 0x{first_diff:X} ({} vs {} bytes, entry 0x{reassembled_entry:08X} vs 0x{entry:08X})",
+                    image.len(),
+                    expected.len()
+                ),
+                None,
+                None,
+                MessageType::Error,
+            );
+        }
+        None => {
+            msg_list.push(
+                format!("Round trip: {output_path} does not reassemble cleanly"),
+                None,
+                None,
+                MessageType::Error,
+            );
+            msg_list.list.extend(assembly.diagnostics);
+        }
+    }
+
+    print_results(msg_list, start_time);
+    if msg_list.number_by_type(&MessageType::Error) > 0 {
+        return Err(1);
+    }
+    Ok(())
+}
+
+/// Check every opcode in the ISA survives disassemble → reassemble unchanged.
+#[cfg(not(tarpaulin_include))]
+pub(crate) fn run_round_trip_check(isa: &Isa, msg_list: &mut MsgList, start_time: NaiveTime) -> Result<(), i32> {
+    let failures = round_trip_opcodes(isa);
+    for failure in &failures {
+        msg_list.push(format!("Round trip failed for {failure}"), None, None, MessageType::Error);
+    }
+    msg_list.push(
+        format!(
+            "Round trip self-check: {}/{} opcodes reassemble byte-identically",
+            isa.opcodes.len() - failures.len(),
+            isa.opcodes.len()
+        ),
+        None,
+        None,
+        MessageType::Information,
+    );
+    print_results(msg_list, start_time);
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(1)
+    }
+}
+
+/// Serve an emulator-backed fake board until interrupted.
+///
+/// `endpoint` is `pty` (a new pseudo-terminal; its slave path is printed for
+/// `-s pty:<path>`) or `tcp://host:port` to accept connections one at a time.
+#[cfg(not(tarpaulin_include))]
+pub(crate) fn run_fake_board(endpoint: &str, max_instructions: u64, msg_list: &mut MsgList) -> Result<(), i32> {
+    use klausscc::fake_board::serve_board;
+    use klausscc::transport::{SerialTransport, TcpTransport, Transport as _};
+    use serialport::SerialPort as _;
+    use std::net::TcpListener;
+
+    msg_list.live = true;
+    if endpoint == "pty" {
+        let (master, slave) = match serialport::TTYPort::pair() {
+            Ok(pair) => pair,
+            Err(err) => {
+                msg_list.push(format!("Unable to create a pty pair: \"{err}\""), None, None, MessageType::Error);
+                return Err(1);
+            }
+        };
+        // Keep the slave open so the master does not see a hang-up between host sessions.
+        let slave_name = slave.name().unwrap_or_default();
+        let mut transport = match SerialTransport::new(Box::new(master)) {
+            Ok(transport) => transport,
+            Err(err) => {
+                msg_list.push(format!("Unable to configure the pty: \"{err}\""), None, None, MessageType::Error);
+                return Err(1);
+            }
+        };
+        msg_list.push(
+            format!("Fake board ready, connect with -s pty:{slave_name} (Ctrl+C to stop)"),
+            None,
+            None,
+            MessageType::Information,
+        );
+        if let Err(err) = serve_board(&mut transport, max_instructions, msg_list) {
+            msg_list.push(format!("Fake board stopped: \"{err}\""), None, None, MessageType::Error);
+            return Err(1);
+        }
+        drop(slave);
+        return Ok(());
+    }
+
+    let Some(address) = endpoint.strip_prefix("tcp://") else {
+        msg_list.push(
+            format!("Fake board endpoint \"{endpoint}\" must be pty or tcp://host:port"),
+            None,
+            None,
+            MessageType::Error,
+        );
+        return Err(1);
+    };
+    let listener = match TcpListener::bind(address.trim_end_matches('/')) {
+        Ok(listener) => listener,
+        Err(err) => {
+            msg_list.push(format!("Unable to listen on {address}: \"{err}\""), None, None, MessageType::Error);
+            return Err(1);
+        }
+    };
+    let local = listener.local_addr().map_or_else(|_| address.to_owned(), |local| local.to_string());
+    msg_list.push(
+        format!("Fake board listening, connect with -s tcp://{local} (Ctrl+C to stop)"),
+        None,
+        None,
+        MessageType::Information,
+    );
+    for stream in listener.incoming() {
+        let mut transport = match stream.and_then(TcpTransport::from_stream) {
+            Ok(transport) => transport,
+            Err(err) => {
+                msg_list.push(format!("Fake board accept failed: \"{err}\""), None, None, MessageType::Warning);
+                continue;
+            }
+        };
+        msg_list.push(format!("Host connected from {}", transport.name()), None, None, MessageType::Information);
+        if let Err(err) = serve_board(&mut transport, max_instructions, msg_list) {
+            msg_list.push(format!("Connection dropped: \"{err}\""), None, None, MessageType::Warning);
+        }
+        msg_list.push("Host disconnected".to_owned(), None, None, MessageType::Information);
+    }
+    Ok(())
+}
+
+/// Serve the board side of network boot from the emulator until interrupted.
+///
+/// Uploads are accepted on `address`; the running program's UART goes to
+/// stdout, or to `uart_address` for a `-m -s tcp://…` monitor.  `discovery`
+/// is the `--discover-address` and the board name to announce.
+#[cfg(not(tarpaulin_include))]
+pub(crate) fn run_netboot_server(
+    address: &str,
+    uart_address: Option<&str>,
+    discovery: (&str, &str),
+    max_instructions: u64,
+    msg_list: &mut MsgList,
+) -> Result<(), i32> {
+    use klausscc::netboot_server::{NetbootServer, UartOutput};
+
+    msg_list.live = true;
+    let uart = match uart_address.map_or(Ok(UartOutput::Stdout), UartOutput::listen) {
+        Ok(uart) => uart,
+        Err(err) => {
+            msg_list.push(
+                format!("Unable to listen for UART monitors on {}: \"{err}\"", uart_address.unwrap_or_default()),
+                None,
+                None,
+                MessageType::Error,
+            );
+            return Err(1);
+        }
+    };
+    let mut server = match NetbootServer::bind(address, uart, max_instructions) {
+        Ok(server) => server,
+        Err(err) => {
+            msg_list.push(format!("Unable to listen on {address}: \"{err}\""), None, None, MessageType::Error);
+            return Err(1);
+        }
+    };
+    let local = server.local_addr().map_or_else(|_| address.to_owned(), |local| local.to_string());
+    msg_list.push(
+        format!("Netboot server listening on {local} (Ctrl+C to stop)"),
+        None,
+        None,
+        MessageType::Information,
+    );
+    // Queries arrive as broadcasts, so listen on every interface unless a unicast address was given.
+    let (discover_address, board_name) = discovery;
+    let discovery_bind = match discover_address.parse::<std::net::SocketAddr>() {
+        Ok(std::net::SocketAddr::V4(v4)) if v4.ip().is_broadcast() || v4.ip().is_unspecified() => format!("0.0.0.0:{}", v4.port()),
+        _ => discover_address.to_owned(),
+    };
+    match server.enable_discovery(&discovery_bind, board_name) {
+        Ok(()) => msg_list.push(
+            format!("Answering discovery on {discovery_bind} as \"{board_name}\""),
+            None,
+            None,
+            MessageType::Information,
+        ),
+        Err(err) => msg_list.push(
+            format!("Not answering discovery, cannot listen on {discovery_bind}: \"{err}\""),
+            None,
+            None,
+            MessageType::Warning,
+        ),
+    }
+    if let Some(uart_local) = server.uart_addr() {
+        msg_list.push(
+            format!("UART available, monitor with -m -s tcp://{uart_local}"),
+            None,
+            None,
+            MessageType::Information,
+        );
+    }
+    if let Err(err) = server.serve(msg_list) {
+        msg_list.push(format!("Netboot server stopped: \"{err}\""), None, None, MessageType::Error);
+        return Err(1);
+    }
+    Ok(())
+}
+
+/// Resolve the `.kla` files named by an `--emulate-test` path.
+///
+/// `test_path` may be a single `.kla` file, a directory (all `*.kla` inside,
+/// sorted) or a test-list file (one path per line).
+pub(crate) fn emulate_test_files(test_path: &str, msg_list: &mut MsgList) -> Vec<String> {
     use std::path::Path;
 
-    // Resolve the list of .kla files to test.
     let path = Path::new(test_path);
     let mut files: Vec<String> = Vec::new();
     if path.is_dir() {
@@ -791,7 +1832,41 @@ pub(crate) fn run_emulate_test(
         // treat as a test-list file
         files = read_test_list(test_path, msg_list);
     }
+    files
+}
+
+/// Batch-verify the emulator against `.kla` files with expected `// ` UART values.
+///
+/// `test_path` may be a single `.kla` file, a directory (all `*.kla` inside),
+/// or a test-list file (one path per line).  For each file with expected
+/// values, assemble + emulate and compare captured UART tokens in order.
+#[cfg(not(tarpaulin_include))]
+pub(crate) fn run_emulate_test(
+    isa: &Isa,
+    include_paths: &[PathBuf],
+    test_path: &str,
+    max_instructions: u64,
+    msg_list: &mut MsgList,
+    start_time: NaiveTime,
+) -> Result<(), i32> {
+    run_emulate_test_with(test_path, max_instructions, msg_list, start_time, |file, test_msgs| {
+        assemble_to_image(file, isa, include_paths, test_msgs)
+    })
+}
 
+/// [`run_emulate_test`] with each file turned into a DDR image and entry point
+/// by `assemble`, so `--watch` can also see which files each test includes.
+///
+/// Assembly warnings and errors of the tests are added to `msg_list`.
+#[cfg(not(tarpaulin_include))]
+pub(crate) fn run_emulate_test_with(
+    test_path: &str,
+    max_instructions: u64,
+    msg_list: &mut MsgList,
+    start_time: NaiveTime,
+    mut assemble: impl FnMut(&str, &mut MsgList) -> Option<(Vec<u8>, u32)>,
+) -> Result<(), i32> {
+    let files = emulate_test_files(test_path, msg_list);
     if files.is_empty() {
         msg_list.push(
             format!("No .kla files found for emulate-test at {test_path}"),
@@ -812,19 +1887,25 @@ pub(crate) fn run_emulate_test(
         // Expected values from source comments.
         let raw_lines: Vec<String> = fs::read_to_string(file).unwrap_or_default().lines().map(String::from).collect();
         let expected = parse_expected_uart_values(&raw_lines);
-        if expected.is_empty() {
+        let expected_panel = parse_expected_peripherals(&raw_lines);
+        if expected.is_empty() && expected_panel.is_empty() {
             continue; // skip non-validatable files silently
         }
         total_files += 1;
 
         let mut test_msgs = MsgList::new();
-        let Some((image, entry)) = assemble_to_image(file, oplist, macro_list, &mut test_msgs) else {
+        let Some((image, entry)) = assemble(file, &mut test_msgs) else {
             println!("  FAIL {file}: assembly error");
             failed_files.push(format!("{file} (assembly error)"));
+            msg_list
+                .list
+                .extend(test_msgs.list.into_iter().filter(|msg| msg.level != MessageType::Information));
             continue;
         };
 
-        let (result, _) = emulate::emulate_image(&image, entry, max_instructions, false);
+        let mut cpu = Cpu::new(&image, entry);
+        cpu.close_uart_input();
+        let result = cpu.run(max_instructions, None);
         // Extract 8-hex-digit tokens from each UART line (mirrors serial.rs).
         let got: Vec<String> = result
             .uart
@@ -858,10 +1939,20 @@ pub(crate) fn run_emulate_test(
             }
         }
 
+        // Final LEDs / 7-seg / RGB values, checked once the UART values all match.
+        if first_diff.is_none() {
+            first_diff = cpu.peripherals().check(&expected_panel).into_iter().next();
+        }
+
         let stem = filename_stem(&file.clone());
-        if matched == expected.len() {
+        if first_diff.is_none() {
             total_pass += 1;
-            println!("  PASS {stem}: {matched}/{} ({} instrs)", expected.len(), result.instructions);
+            let panel = if expected_panel.is_empty() {
+                String::new()
+            } else {
+                format!(" + {} panel", expected_panel.len())
+            };
+            println!("  PASS {stem}: {matched}/{}{panel} ({} instrs)", expected.len(), result.instructions);
         } else {
             let diff = first_diff.unwrap_or_else(|| "unknown".to_owned());
             println!("  FAIL {stem}: {matched}/{} — {diff}", expected.len());
@@ -927,8 +2018,8 @@ fn read_test_list(list_file: &str, msg_list: &mut MsgList) -> Vec<String> {
 /// and prints per-test and aggregate results.
 #[cfg(not(tarpaulin_include))]
 pub fn run_test_list(
-    oplist: &[Opcode],
-    macro_list: &[macros::Macro],
+    isa: &Isa,
+    include_paths: &[PathBuf],
     list_file: &str,
     output_serial_port: &str,
     test_timeout: u64,
@@ -959,7 +2050,7 @@ pub fn run_test_list(
         let mut test_msg_list = MsgList::new();
 
         // Assemble the test file
-        let Some(bin_string) = assemble_file(test_file, oplist, macro_list, &mut test_msg_list) else {
+        let Some(bin_string) = assemble_file(test_file, isa, include_paths, &mut test_msg_list) else {
             println!("  SKIP: assembly failed");
             print_messages(&test_msg_list);
             results.push(BatchTestResult {
@@ -1136,6 +2227,35 @@ mod tests {
         assert!(parse_elf_to_flat(&bad).is_none());
     }
 
+    // ---- kbt_layout_problems -------------------------------------------------
+
+    #[test]
+    fn kbt_layout_accepts_consistent_image() {
+        let image = KbtImage {
+            header: vec![0x30, 0, 0, 0],
+            code: vec![0; 12],
+            entry: 0x20,
+            checksum: 0,
+            computed_checksum: 0,
+        };
+        assert!(kbt_layout_problems(&image).is_empty());
+    }
+
+    #[test]
+    fn kbt_layout_flags_heap_and_entry() {
+        let image = KbtImage {
+            header: vec![0x24, 0, 0, 0],
+            code: vec![0; 12],
+            entry: 0x2C,
+            checksum: 0,
+            computed_checksum: 0,
+        };
+        let problems = kbt_layout_problems(&image);
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].contains("heap_start"));
+        assert!(problems[1].contains("entry PC"));
+    }
+
     // ---- flatten_input -------------------------------------------------------
 
     #[test]
@@ -1184,4 +2304,42 @@ mod tests {
         bad.extend_from_slice(&[0_u8; 64]);
         assert!(flatten_input(bad, None).is_none());
     }
+
+    // ---- mem_lane_files ------------------------------------------------------
+
+    #[test]
+    // Test a lane file finds its whole set, and other names are not lane files
+    fn test_mem_lane_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let base = dir.path().join("boot.mem").to_string_lossy().into_owned();
+        for lane in 0..4 {
+            fs::write(suffixed_file_name(&base, &format!("_lane{lane}")), "00\n").unwrap();
+        }
+        let third = suffixed_file_name(&base, "_lane2");
+        let lanes = mem_lane_files(&third).unwrap();
+        assert_eq!(lanes.len(), 4);
+        assert_eq!(lanes[0], suffixed_file_name(&base, "_lane0"));
+        assert_eq!(mem_lane_files(&base), None);
+        assert_eq!(mem_lane_files("boot_lanex.mem"), None);
+    }
+
+    // ---- monitor_exit --------------------------------------------------------
+
+    #[test]
+    // Test a monitor error fails the run, with or without pass/fail conditions
+    fn test_monitor_exit_error() {
+        let refused = || Err(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused"));
+        let mut msg_list = MsgList::new();
+        assert_eq!(monitor_exit(refused(), &MonitorOptions::default(), &mut msg_list), Err(1));
+        assert_eq!(msg_list.number_by_type(&MessageType::Error), 1);
+        let options = MonitorOptions {
+            until: Some(regex::Regex::new("PASS").unwrap()),
+            ..MonitorOptions::default()
+        };
+        assert_eq!(monitor_exit(refused(), &options, &mut MsgList::new()), Err(1));
+        assert_eq!(
+            monitor_exit(Ok(MonitorOutcome::Halted), &MonitorOptions::default(), &mut MsgList::new()),
+            Ok(())
+        );
+    }
 }
@@ -0,0 +1,721 @@
+//! Interactive debugger for the emulator (`--emulate --debug`).
+//!
+//! [`Debugger::execute`] runs one command line against a [`Cpu`] and returns
+//! the text to show, so the REPL around it is only a read/print loop and the
+//! commands can be tested without a terminal.  Labels and source lines come
+//! from the assembler's `Pass2` list, labels also from an ELF's symbols;
+//! instructions are shown with [`disassemble_word`] when the opcode list is
+//! known.
+
+use crate::emulate::{Cpu, Flags, StopReason};
+use crate::files::LineType;
+use crate::opcodes::{disassemble_word, Opcode, Pass2};
+use crate::symbols::SymbolMap;
+use std::fmt::Write as _;
+
+/// Text shown by `help`.
+const HELP: &str = "\
+break|b <label|0xADDR|file:line>  set a breakpoint
+delete [n]                       delete breakpoint n, or all of them
+breaks                           list breakpoints
+step|s [n]                       execute n instructions (default 1)
+next|n [n]                       like step, but run CALLs through to their return
+continue|c                       run until a breakpoint or the program stops
+finish                           run until the current routine or handler returns
+regs|r                           registers, PC, SP and flags
+x[/NF] <addr|label|reg>          examine N units of memory; F is d (64-bit, default),
+                                 w (32-bit), b (bytes), s (string) or i (instructions)
+set <A-P|pc|sp|flag> <value>     set a register (pc also takes a label), or a flag
+                                 (zero equal carry overflow sign less ult) to 0 or 1
+set [addr][/b|/w|/d] <value>     write memory (64-bit unless /b or /w)
+list|l [addr|label] [n]          disassemble n instructions around the PC or from an address
+quit|q                           leave the debugger
+An empty line repeats the previous command.
+";
+
+/// RET: pops the return address.
+const RET_WORD: u32 = 0x0000_1012;
+
+/// IRET: pops the saved interrupt context.
+const IRET_WORD: u32 = 0x0000_6011;
+
+/// Known instructions `list` shows before the PC.
+const LIST_BEFORE: usize = 3;
+
+/// Instructions `list` shows by default.
+const LIST_COUNT: usize = 8;
+
+/// Register names, indexed like [`Cpu::reg`].
+const REGISTER_NAMES: [&str; 16] = ["A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P"];
+
+/// Flag names, in [`Flags`] order.
+const FLAG_NAMES: [&str; 7] = ["zero", "equal", "carry", "overflow", "sign", "less", "ult"];
+
+/// A source line that assembled to code.
+struct SourceLine {
+    /// Source file name.
+    file: String,
+    /// Line number in `file`.
+    line: u32,
+    /// Address of the line's instruction.
+    pc: u32,
+}
+
+/// Debugger state: the machine, what is known about the program, and breakpoints.
+pub struct Debugger<'a> {
+    /// The machine being debugged.
+    cpu: Cpu,
+    /// Opcode list for disassembly (empty if unknown).
+    opcodes: &'a [Opcode],
+    /// Labels and their addresses, sorted by address.
+    labels: Vec<(String, u32)>,
+    /// Code lines, sorted by address.
+    lines: Vec<SourceLine>,
+    /// Breakpoint addresses, in the order they were set.
+    breakpoints: Vec<u32>,
+    /// Most instructions one command may run.
+    max_instructions: u64,
+    /// Command an empty line repeats.
+    last_command: String,
+}
+
+impl<'a> Debugger<'a> {
+    /// Debug `cpu`, using `pass2` for labels and source lines and `opcodes` for disassembly.
+    #[must_use]
+    pub fn new(cpu: Cpu, pass2: &[Pass2], opcodes: &'a [Opcode], max_instructions: u64) -> Self {
+        let mut labels: Vec<(String, u32)> = Vec::new();
+        let mut lines: Vec<SourceLine> = Vec::new();
+        for line in pass2 {
+            match line.line_type {
+                LineType::Label | LineType::Start => {
+                    if let Some(name) = line.input_text_line.split_whitespace().next() {
+                        labels.push((name.trim_end_matches(':').to_owned(), line.program_counter));
+                    }
+                }
+                LineType::Opcode => lines.push(SourceLine {
+                    file: line.file_name.clone(),
+                    line: line.line_counter,
+                    pc: line.program_counter,
+                }),
+                _ => {}
+            }
+        }
+        labels.sort_by_key(|(_, address)| *address);
+        lines.sort_by_key(|line| line.pc);
+        Self {
+            cpu,
+            opcodes,
+            labels,
+            lines,
+            breakpoints: Vec::new(),
+            max_instructions,
+            last_command: String::new(),
+        }
+    }
+
+    /// Add the labels of `symbols` (such as an ELF's) that are not known yet.
+    #[must_use]
+    pub fn with_symbols(mut self, symbols: &SymbolMap) -> Self {
+        for (address, name) in symbols.iter() {
+            if !self.labels.iter().any(|(label, _)| label == name) {
+                self.labels.push((name.to_owned(), address));
+            }
+        }
+        self.labels.sort_by_key(|(_, address)| *address);
+        self
+    }
+
+    /// The machine being debugged.
+    #[must_use]
+    pub const fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// Run one command line; returns its output and whether to quit.
+    pub fn execute(&mut self, line: &str) -> (String, bool) {
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            line.trim().to_owned()
+        };
+        self.last_command.clone_from(&line);
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return (String::new(), false);
+        };
+        let args: Vec<&str> = words.collect();
+        let (base, suffix) = command.split_once('/').unwrap_or((command, ""));
+        let result = match base {
+            "help" | "h" | "?" => Ok(HELP.to_owned()),
+            "break" | "b" => self.command_break(&args),
+            "delete" => self.command_delete(&args),
+            "breaks" | "info" => Ok(self.list_breakpoints()),
+            "step" | "s" => count_arg(args.first()).map(|count| self.step(count, false)),
+            "next" | "n" => count_arg(args.first()).map(|count| self.step(count, true)),
+            "continue" | "c" => {
+                let reason = self.advance(|_, _| false);
+                Ok(self.report(reason))
+            }
+            "finish" => Ok(self.finish()),
+            "regs" | "r" => Ok(self.registers()),
+            "x" => self.examine(suffix, &args),
+            "set" => self.command_set(&args),
+            "list" | "l" | "disas" => self.command_list(&args),
+            "quit" | "q" => return (String::new(), true),
+            _ => Err(format!("Unknown command \"{command}\" (try help)")),
+        };
+        let mut output = result.unwrap_or_else(|message| message + "\n");
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        (output, false)
+    }
+
+    /// Where the PC is, as a one-line description with its instruction.
+    #[must_use]
+    pub fn location(&self) -> String {
+        let pc = self.cpu.pc();
+        let text = self.instruction_at(pc).map_or_else(|| "(outside memory)".to_owned(), |(text, _)| text);
+        format!("=> {}: {text}", self.describe(pc))
+    }
+
+    /// `0xADDR <label+off> (file:line)`, with the parts that are known.
+    fn describe(&self, address: u32) -> String {
+        let mut text = format!("0x{address:08X}");
+        if let Some((name, base)) = self.labels.iter().rev().find(|(_, base)| *base <= address) {
+            let _ = write!(text, " <{name}+{}>", address - base);
+        }
+        if let Some(line) = self.lines.iter().find(|line| line.pc == address) {
+            let _ = write!(text, " ({}:{})", line.file, line.line);
+        }
+        text
+    }
+
+    /// Instruction text and length in bytes at `address`.
+    ///
+    /// Words that do not decode (or any word, without an opcode list) are shown as hex.
+    fn instruction_at(&self, address: u32) -> Option<(String, u32)> {
+        let word = self.word(address)?;
+        let decoded = disassemble_word(word, self.opcodes).and_then(|(mut text, vars)| {
+            let args: Option<Vec<u32>> = (0..vars).map(|i| self.word(address + 4 + i * 4)).collect();
+            match args?.as_slice() {
+                [value] => {
+                    let _ = write!(text, " 0x{value:X}");
+                    if let Some((name, _)) = self.labels.iter().find(|(_, base)| base == value) {
+                        let _ = write!(text, " <{name}>");
+                    }
+                }
+                [lo, hi] => {
+                    let _ = write!(text, " 0x{:X}", (u64::from(*hi) << 32) | u64::from(*lo));
+                }
+                _ => {}
+            }
+            Some((text, 4 + vars * 4))
+        });
+        Some(decoded.unwrap_or_else(|| (format!("(0x{word:08X})"), 4)))
+    }
+
+    /// The 32-bit word at `address`, if it is in memory.
+    fn word(&self, address: u32) -> Option<u32> {
+        let bytes = self.cpu.memory(address, 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Address for a breakpoint, `x` or `list`: `0xADDR`, a label, `file:line` or a register.
+    fn resolve(&self, text: &str) -> Result<u32, String> {
+        if let Some(index) = register_index(text) {
+            return Ok(self.cpu.reg(index) as u32);
+        }
+        match text.to_ascii_lowercase().as_str() {
+            "pc" => return Ok(self.cpu.pc()),
+            "sp" => return Ok(self.cpu.sp()),
+            _ => {}
+        }
+        if let Some(value) = parse_number(text) {
+            return u32::try_from(value).map_err(|_| format!("Address {text} is out of range"));
+        }
+        if let Some((file, line)) = text.rsplit_once(':') {
+            if let Ok(line) = line.parse::<u32>() {
+                return self
+                    .lines
+                    .iter()
+                    .filter(|source| same_file(&source.file, file) && source.line >= line)
+                    .min_by_key(|source| (source.line, source.pc))
+                    .map(|source| source.pc)
+                    .ok_or_else(|| format!("No code at or after {file}:{line}"));
+            }
+        }
+        let name = text.trim_end_matches(':');
+        self.labels
+            .iter()
+            .find(|(label, _)| label == name)
+            .map(|(_, address)| *address)
+            .ok_or_else(|| format!("Unknown label or address \"{text}\""))
+    }
+
+    /// `break <where>`.
+    fn command_break(&mut self, args: &[&str]) -> Result<String, String> {
+        let [target] = args else {
+            return Err("Usage: break <label|0xADDR|file:line>".to_owned());
+        };
+        let address = self.resolve(target)?;
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+        let number = self.breakpoints.iter().position(|&b| b == address).unwrap_or(0) + 1;
+        Ok(format!("Breakpoint {number} at {}", self.describe(address)))
+    }
+
+    /// `delete [n]`.
+    fn command_delete(&mut self, args: &[&str]) -> Result<String, String> {
+        match args {
+            [] => {
+                self.breakpoints.clear();
+                Ok("Deleted all breakpoints".to_owned())
+            }
+            [number] => {
+                let index = number
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| n.checked_sub(1))
+                    .filter(|&i| i < self.breakpoints.len())
+                    .ok_or_else(|| format!("No breakpoint {number}"))?;
+                let address = self.breakpoints.remove(index);
+                Ok(format!("Deleted breakpoint {number} at 0x{address:08X}"))
+            }
+            _ => Err("Usage: delete [n]".to_owned()),
+        }
+    }
+
+    /// `breaks`.
+    fn list_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return "No breakpoints".to_owned();
+        }
+        let mut text = String::new();
+        for (index, address) in self.breakpoints.iter().enumerate() {
+            let _ = writeln!(text, "{}: {}", index + 1, self.describe(*address));
+        }
+        text
+    }
+
+    /// `step n`, or `next n` with `over_calls`.
+    fn step(&mut self, count: u64, over_calls: bool) -> String {
+        let mut reason = None;
+        for _ in 0..count {
+            let pc = self.cpu.pc();
+            reason = match self.word(pc).and_then(call_length) {
+                Some(length) if over_calls => {
+                    let (return_address, sp) = (pc + length, self.cpu.sp());
+                    self.advance(|cpu, _| cpu.pc() == return_address && cpu.sp() >= sp)
+                }
+                _ => self.advance(|_, _| true),
+            };
+            if reason.is_some() {
+                break;
+            }
+        }
+        self.report(reason)
+    }
+
+    /// `finish`: run until a RET or IRET leaves the current routine.
+    fn finish(&mut self) -> String {
+        let sp = self.cpu.sp();
+        let reason = self.advance(|cpu, word| matches!(word, RET_WORD | IRET_WORD) && cpu.sp() > sp);
+        self.report(reason)
+    }
+
+    /// Execute instructions until a breakpoint is reached, `done(cpu, word just
+    /// executed)` is true, the machine stops or the instruction budget runs out.
+    ///
+    /// Returns why it stopped, unless it was `done`.
+    fn advance(&mut self, mut done: impl FnMut(&Cpu, u32) -> bool) -> Option<String> {
+        let mut executed: u64 = 0;
+        loop {
+            let word = self.word(self.cpu.pc()).unwrap_or(0);
+            if let Some(stop) = self.cpu.step_instruction(None) {
+                return Some(describe_stop(&stop));
+            }
+            executed += 1;
+            if let Some(index) = self.breakpoints.iter().position(|&b| b == self.cpu.pc()) {
+                return Some(format!("Breakpoint {}", index + 1));
+            }
+            if done(&self.cpu, word) {
+                return None;
+            }
+            if executed >= self.max_instructions {
+                return Some(format!("Stopped after {executed} instructions (--max-instructions)"));
+            }
+        }
+    }
+
+    /// The UART output since the last report, why execution stopped and the new location.
+    fn report(&mut self, reason: Option<String>) -> String {
+        let mut output = self.cpu.take_uart();
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        if let Some(reason) = reason {
+            let _ = writeln!(output, "{reason}");
+        }
+        output.push_str(&self.location());
+        output.push('\n');
+        output
+    }
+
+    /// `regs`.
+    fn registers(&self) -> String {
+        let mut text = String::new();
+        for (index, name) in REGISTER_NAMES.iter().enumerate() {
+            let _ = write!(text, "{name} 0x{:016X}", self.cpu.reg(index));
+            text.push_str(if index % 4 == 3 { "\n" } else { "  " });
+        }
+        let flags = flag_values(self.cpu.flags());
+        let set: Vec<&str> = FLAG_NAMES.iter().zip(flags).filter(|(_, on)| *on).map(|(name, _)| *name).collect();
+        let _ = writeln!(
+            text,
+            "PC 0x{:08X}  SP 0x{:08X}  flags: {}",
+            self.cpu.pc(),
+            self.cpu.sp(),
+            if set.is_empty() { "none".to_owned() } else { set.join(" ") }
+        );
+        text
+    }
+
+    /// `x[/NF] <where>`.
+    fn examine(&self, suffix: &str, args: &[&str]) -> Result<String, String> {
+        let [target] = args else {
+            return Err("Usage: x[/NF] <addr|label|reg>".to_owned());
+        };
+        let digits = suffix.chars().take_while(char::is_ascii_digit).count();
+        let count = if digits == 0 {
+            0
+        } else {
+            suffix[..digits].parse::<usize>().map_err(|e| e.to_string())?
+        };
+        let format = suffix[digits..].chars().next().unwrap_or('d');
+        let address = self.resolve(target)?;
+        let unit = match format {
+            'd' => 8,
+            'w' => 4,
+            'b' => 1,
+            's' => return Ok(self.examine_string(address, count.max(1))),
+            'i' => return Ok(self.disassemble(address, if count == 0 { LIST_COUNT } else { count })),
+            _ => return Err(format!("Unknown format \"{format}\" (use d, w, b, s or i)")),
+        };
+        let count = if count == 0 { 8 } else { count };
+        let per_line = 16 / unit;
+        let length = count.checked_mul(unit).ok_or_else(|| format!("Count {count} is too large"))?;
+        let bytes = self
+            .cpu
+            .memory(address, length)
+            .ok_or_else(|| format!("0x{address:08X} is outside memory"))?;
+        let mut text = String::new();
+        for (line, chunk) in bytes.chunks(unit * per_line).enumerate() {
+            let _ = write!(text, "0x{:08X}:", address as usize + line * unit * per_line);
+            for value in chunk.chunks(unit) {
+                let mut raw = [0_u8; 8];
+                raw[..unit].copy_from_slice(value);
+                let _ = write!(text, " {:0width$X}", u64::from_le_bytes(raw), width = unit * 2);
+            }
+            text.push('\n');
+        }
+        Ok(text)
+    }
+
+    /// `x/Ns`: `count` NUL-terminated strings from `address`.
+    fn examine_string(&self, address: u32, count: usize) -> String {
+        let mut text = String::new();
+        let mut address = address;
+        for _ in 0..count {
+            let mut bytes: Vec<u8> = Vec::new();
+            while let Some(&[byte]) = self.cpu.memory(address + bytes.len() as u32, 1) {
+                if byte == 0 || bytes.len() >= 256 {
+                    break;
+                }
+                bytes.push(byte);
+            }
+            let _ = writeln!(text, "0x{address:08X}: {:?}", String::from_utf8_lossy(&bytes));
+            address += bytes.len() as u32 + 1;
+        }
+        text
+    }
+
+    /// `count` instructions from `address`, marking the PC and breakpoints.
+    fn disassemble(&self, address: u32, count: usize) -> String {
+        let mut text = String::new();
+        let mut address = address;
+        for _ in 0..count {
+            let Some((instruction, length)) = self.instruction_at(address) else {
+                break;
+            };
+            let marker = if address == self.cpu.pc() {
+                "=>"
+            } else if self.breakpoints.contains(&address) {
+                " *"
+            } else {
+                "  "
+            };
+            let _ = writeln!(text, "{marker} {}: {instruction}", self.describe(address));
+            address += length;
+        }
+        text
+    }
+
+    /// `list [where] [n]`.
+    fn command_list(&self, args: &[&str]) -> Result<String, String> {
+        let (start, count) = match args {
+            [] => (self.list_start(), LIST_COUNT),
+            [target] => (self.resolve(target)?, LIST_COUNT),
+            [target, count] => (self.resolve(target)?, count_arg(Some(count))? as usize),
+            _ => return Err("Usage: list [addr|label] [n]".to_owned()),
+        };
+        Ok(self.disassemble(start, count))
+    }
+
+    /// Where `list` starts by default: a few known instructions before the PC.
+    fn list_start(&self) -> u32 {
+        let pc = self.cpu.pc();
+        let before = self.lines.iter().filter(|line| line.pc < pc).count();
+        self.lines.get(before.saturating_sub(LIST_BEFORE)).map_or(pc, |line| line.pc.min(pc))
+    }
+
+    /// `set <target> <value>`.
+    fn command_set(&mut self, args: &[&str]) -> Result<String, String> {
+        let [target, value] = args else {
+            return Err("Usage: set <A-P|pc|sp|flag|[addr][/b|/w|/d]> <value>".to_owned());
+        };
+        if target.eq_ignore_ascii_case("pc") {
+            let address = self.resolve(value)?;
+            self.cpu.set_pc(address);
+            return Ok(self.location());
+        }
+        let value = parse_number(value).ok_or_else(|| format!("Bad value \"{value}\""))?;
+        if let Some(index) = register_index(target) {
+            self.cpu.set_reg(index, value);
+            return Ok(format!("{} = 0x{value:016X}", REGISTER_NAMES[index]));
+        }
+        let lower = target.to_ascii_lowercase();
+        if let Some(index) = FLAG_NAMES.iter().position(|name| *name == lower) {
+            let mut flags = flag_values(self.cpu.flags());
+            flags[index] = value != 0;
+            self.cpu.set_flags(Flags {
+                zero: flags[0],
+                equal: flags[1],
+                carry: flags[2],
+                overflow: flags[3],
+                sign: flags[4],
+                less: flags[5],
+                ult: flags[6],
+            });
+            return Ok(format!("{lower} = {}", u8::from(value != 0)));
+        }
+        if lower == "sp" {
+            let sp = u32::try_from(value).map_err(|_| format!("Address 0x{value:X} is out of range"))?;
+            self.cpu.set_sp(sp);
+            return Ok(format!("SP = 0x{sp:08X}"));
+        }
+        let Some((address, size)) = target.strip_prefix('[').and_then(|rest| rest.split_once(']')) else {
+            return Err(format!("Cannot set \"{target}\""));
+        };
+        let address = self.resolve(address)?;
+        let width = match size {
+            "" | "/d" => 8,
+            "/w" => 4,
+            "/b" => 1,
+            _ => return Err(format!("Unknown size \"{size}\" (use /b, /w or /d)")),
+        };
+        if !self.cpu.write_memory(address, &value.to_le_bytes()[..width]) {
+            return Err(format!("0x{address:08X} is outside memory"));
+        }
+        Ok(format!("[0x{address:08X}] = 0x{value:0digits$X}", digits = width * 2))
+    }
+}
+
+/// Length of the CALL at `word`, or `None` if it is not one.
+const fn call_length(word: u32) -> Option<u32> {
+    match word {
+        0x1009..=0x1011 | 0x1041 => Some(8),
+        0x4070..=0x407F => Some(4),
+        _ => None,
+    }
+}
+
+/// Index of register `name` (A..P, any case).
+fn register_index(name: &str) -> Option<usize> {
+    REGISTER_NAMES.iter().position(|reg| reg.eq_ignore_ascii_case(name))
+}
+
+/// `0x` hex, decimal, or negative decimal (two's complement).
+fn parse_number(text: &str) -> Option<u64> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        return u64::from_str_radix(&hex.replace('_', ""), 16).ok();
+    }
+    if text.starts_with('-') {
+        return text.parse::<i64>().ok().map(|value| value as u64);
+    }
+    text.parse().ok()
+}
+
+/// Optional repeat count (default 1, must be positive).
+fn count_arg(arg: Option<&&str>) -> Result<u64, String> {
+    arg.map_or(Ok(1), |text| {
+        text.parse::<u64>().ok().filter(|&n| n > 0).ok_or_else(|| format!("Bad count \"{text}\""))
+    })
+}
+
+/// True if `name` is `file`, or ends with it as a path component.
+fn same_file(name: &str, file: &str) -> bool {
+    name == file || name.ends_with(&format!("/{file}"))
+}
+
+/// Flags in [`FLAG_NAMES`] order.
+const fn flag_values(flags: Flags) -> [bool; 7] {
+    [flags.zero, flags.equal, flags.carry, flags.overflow, flags.sign, flags.less, flags.ult]
+}
+
+/// Message for a machine stop.
+fn describe_stop(stop: &StopReason) -> String {
+    match stop {
+        StopReason::Halt => "Program halted".to_owned(),
+        StopReason::InputExhausted => "Program is waiting for UART input (none left)".to_owned(),
+        StopReason::WaitingForInput => "Program is waiting for UART input".to_owned(),
+        other => format!("Program stopped: {other:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+    use crate::assembler::build_flat_code;
+    use crate::helper::build_ddr_image;
+    use crate::test_support::corpus_isa;
+    use crate::{Assembler, Isa};
+
+    /// Assemble `source` as `test.kla` and start a debugger on it.
+    fn debugger<'a>(isa: &'a Isa, source: &str) -> Debugger<'a> {
+        let assembly = Assembler::new(isa).source_text("test.kla", source).assemble();
+        assert!(assembly.is_ok(), "{:?}\n{source}", assembly.diagnostics);
+        let (code, entry) = build_flat_code(&assembly.pass2).unwrap();
+        Debugger::new(Cpu::new(&build_ddr_image(&code), entry), &assembly.pass2, &isa.opcodes, 1000)
+    }
+
+    const PROGRAM: &str = "_start\nSETR A 0x41\nCALL print:\nSETR A 0x42\nCALL print:\nHALT\nprint:\nTXR A\nRET\n";
+
+    #[test]
+    // Test breakpoints by label and file:line, and continue to them
+    fn test_breakpoints() {
+        let isa = corpus_isa();
+        let mut dbg = debugger(&isa, PROGRAM);
+        let (out, _) = dbg.execute("break print");
+        assert!(out.starts_with("Breakpoint 1 at"), "{out}");
+        let (out, _) = dbg.execute("c");
+        assert!(
+            out.contains("Breakpoint 1") && out.contains("<print+0>") && out.contains("TXR A"),
+            "{out}"
+        );
+        let (out, _) = dbg.execute("");
+        assert!(out.starts_with("0000000000000041\nBreakpoint 1"), "{out}");
+        dbg.execute("delete 1");
+        let (out, _) = dbg.execute("b test.kla:6");
+        assert!(out.contains("(test.kla:6)"), "{out}");
+        assert!(
+            dbg.execute("b test.kla:7").0.contains("(test.kla:8)"),
+            "label lines move to the next code line"
+        );
+        assert!(dbg.execute("breaks").0.starts_with("1: 0x00000040 <_start+32> (test.kla:6)"));
+        let (out, _) = dbg.execute("continue");
+        assert!(out.starts_with("0000000000000042\nBreakpoint 1") && out.contains("HALT"), "{out}");
+        dbg.execute("delete");
+        assert!(dbg.execute("continue").0.contains("Program halted"));
+        assert!(dbg.execute("b nowhere").0.contains("Unknown label"));
+        assert!(dbg.execute("quit").1);
+    }
+
+    #[test]
+    // Test step, next over a CALL and finish out of a routine
+    fn test_step_next_finish() {
+        let isa = corpus_isa();
+        let mut dbg = debugger(&isa, PROGRAM);
+        let (out, _) = dbg.execute("s");
+        assert!(out.contains("CALL 0x") && out.contains("<print>"), "{out}");
+        let (out, _) = dbg.execute("next");
+        assert!(out.starts_with("0000000000000041\n=>") && out.contains("(test.kla:4)"), "{out}");
+        dbg.execute("b print");
+        let (out, _) = dbg.execute("step 5");
+        assert!(out.starts_with("Breakpoint 1\n=> 0x00000044 <print+0>"), "{out}");
+        dbg.execute("delete");
+        let (out, _) = dbg.execute("finish");
+        assert!(out.starts_with("0000000000000042\n=> 0x00000040") && out.contains("HALT"), "{out}");
+        assert_eq!(dbg.cpu().reg(0), 0x42);
+    }
+
+    #[test]
+    // Test a breakpoint on an interrupt handler stops before its first instruction
+    fn test_break_on_interrupt_vector() {
+        let isa = corpus_isa();
+        let source = "_start\nSETR A 2\nSETR B handler:\nINTSETRR A B\nSETR C 0xFFFF0000\nSETR D 4\nMEMSET64RR D C\n\
+                      SETR C 0xFFFF0010\nMEMSET64RR D C\nHALT\nhandler:\nINCR E\nIRET\n";
+        let mut dbg = debugger(&isa, source);
+        dbg.execute("b handler");
+        let (out, _) = dbg.execute("c");
+        assert!(out.starts_with("Breakpoint 1\n=>") && out.contains("<handler+0>"), "{out}");
+        assert_eq!(dbg.cpu().reg(4), 0, "the handler has not run yet");
+        assert!(dbg.execute("c").0.contains("Program halted"));
+        assert_eq!(dbg.cpu().reg(4), 1);
+    }
+
+    #[test]
+    // Test finish out of an interrupt handler stops after its IRET
+    fn test_finish_from_handler() {
+        let isa = corpus_isa();
+        let source = "_start\nSETR A 2\nSETR B handler:\nINTSETRR A B\nSETR C 0xFFFF0000\nSETR D 4\nMEMSET64RR D C\n\
+                      SETR C 0xFFFF0010\nMEMSET64RR D C\nHALT\nhandler:\nINCR E\nIRET\n";
+        let mut dbg = debugger(&isa, source);
+        dbg.execute("b handler");
+        dbg.execute("c");
+        dbg.execute("delete");
+        let (out, _) = dbg.execute("finish");
+        assert!(out.starts_with("=>") && out.contains("HALT"), "{out}");
+        assert_eq!(dbg.cpu().reg(4), 1);
+    }
+
+    #[test]
+    // Test labels from a symbol map (as for ELF input, which has no Pass2)
+    fn test_with_symbols() {
+        let isa = corpus_isa();
+        let assembly = Assembler::new(&isa).source_text("test.kla", PROGRAM).assemble();
+        let (code, entry) = build_flat_code(&assembly.pass2).unwrap();
+        let symbols = SymbolMap::from_symbols(vec![(0x44, "print".to_owned())]);
+        let mut dbg = Debugger::new(Cpu::new(&build_ddr_image(&code), entry), &[], &isa.opcodes, 1000).with_symbols(&symbols);
+        assert!(dbg.execute("break print").0.starts_with("Breakpoint 1 at 0x00000044"));
+        let (out, _) = dbg.execute("c");
+        assert!(out.contains("<print+0>") && out.contains("TXR A"), "{out}");
+    }
+
+    #[test]
+    // Test regs, x, set and list
+    fn test_state_commands() {
+        let isa = corpus_isa();
+        let mut dbg = debugger(&isa, PROGRAM);
+        assert!(dbg.execute("set b -1").0.contains("B = 0xFFFFFFFFFFFFFFFF"));
+        dbg.execute("set carry 1");
+        let (out, _) = dbg.execute("regs");
+        assert!(out.contains("B 0xFFFFFFFFFFFFFFFF") && out.contains("flags: carry"), "{out}");
+
+        assert!(dbg.execute("set [0x1000] 0x6968").0.contains("[0x00001000] = 0x0000000000006968"));
+        assert_eq!(dbg.execute("x/s 0x1000").0, "0x00001000: \"hi\"\n");
+        assert_eq!(dbg.execute("x/4b 0x1000").0, "0x00001000: 68 69 00 00\n");
+        dbg.execute("set [0x1004]/w 0x12345678");
+        assert_eq!(dbg.execute("x/2w 0x1000").0, "0x00001000: 00006968 12345678\n");
+        assert!(dbg.execute("x/2305843009213693952d 0x1000").0.contains("too large"));
+        assert!(dbg.execute("x/2i print").0.contains("TXR A\n") && dbg.execute("x/2i print").0.contains("RET"));
+
+        let (out, _) = dbg.execute("list");
+        assert!(out.starts_with("=> 0x00000020 <_start+0> (test.kla:2): SETR A 0x41"), "{out}");
+        assert!(dbg.execute("set pc print").0.starts_with("=> 0x00000044 <print+0>"));
+        assert!(dbg.execute("set a x").0.contains("Bad value"));
+        assert!(dbg.execute("bogus").0.contains("Unknown command"));
+    }
+}
@@ -0,0 +1,293 @@
+//! Disassembler producing re-assemblable `.kla` source.
+//!
+//! Unlike the `.code` listing from `disassemble_flat_to_pass2`, the output
+//! here must assemble back to the same bytes: every word that does not decode
+//! to an instruction (or whose operands run past the end) becomes `.word` /
+//! `.long` data, jump and call targets get synthetic labels, and `_start` is
+//! placed at the entry PC.  [`round_trip_opcodes`] checks the disassembler
+//! against the assembler for every opcode in an ISA.
+
+use crate::assembler::{build_flat_code, Assembler, Isa};
+use crate::helper::HEAP_HEADER_WORDS;
+use crate::opcodes::{disassemble_word, Opcode};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// Board address of the first program byte (just after the heap header).
+const CODE_BASE: u32 = HEAP_HEADER_WORDS * 8;
+
+/// One decoded unit of the program image.
+#[derive(Clone, Debug)]
+enum Item {
+    /// An instruction and its operand words.
+    Instruction {
+        /// Mnemonic with register operands, as from `disassemble_word`.
+        text: String,
+        /// Operand words following the instruction word.
+        args: Vec<u32>,
+    },
+    /// A 32-bit word that is not (part of) an instruction.
+    Data(u32),
+}
+
+impl Item {
+    /// Size in bytes.
+    const fn len(&self) -> u32 {
+        match self {
+            Self::Instruction { args, .. } => 4 + 4 * args.len() as u32,
+            Self::Data(_) => 4,
+        }
+    }
+
+    /// Absolute code address taken by a jump or call, if this is one.
+    fn branch_target(&self) -> Option<(u32, bool)> {
+        let Self::Instruction { text, args } = self else {
+            return None;
+        };
+        let mnemonic = text.split_whitespace().next().unwrap_or("");
+        let is_call = mnemonic.starts_with("CALL");
+        if (is_call || mnemonic.starts_with("JMP")) && args.len() == 1 {
+            args.first().map(|target| (*target, is_call))
+        } else {
+            None
+        }
+    }
+}
+
+/// Result of disassembling an image.
+#[derive(Clone, Debug, Default)]
+pub struct Disassembly {
+    /// `.kla` source text.
+    pub source: String,
+    /// Number of instructions emitted.
+    pub instructions: usize,
+    /// Number of program bytes emitted as `.word`/`.long` data.
+    pub data_bytes: usize,
+    /// Number of synthetic labels created.
+    pub labels: usize,
+}
+
+/// Split `code` (starting at the code base) into instructions and data words.
+fn decode_items(code: &[u8], opcodes: &[Opcode]) -> Vec<(u32, Item)> {
+    let word_at = |offset: usize| -> Option<u32> { code.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])) };
+    let mut items: Vec<(u32, Item)> = Vec::new();
+    let mut offset = 0_usize;
+    while let Some(word) = word_at(offset) {
+        let address = CODE_BASE + offset as u32;
+        let item = disassemble_word(word, opcodes)
+            .and_then(|(text, vars)| {
+                let args: Option<Vec<u32>> = (0..vars as usize).map(|i| word_at(offset + 4 + i * 4)).collect();
+                args.map(|args| Item::Instruction { text, args })
+            })
+            .unwrap_or(Item::Data(word));
+        offset += item.len() as usize;
+        items.push((address, item));
+    }
+    items
+}
+
+/// Render a data run as `.word` (64-bit) lines, with `.long` for a leftover 32-bit word.
+fn render_data(source: &mut String, address: u32, words: &[u32]) {
+    let mut address = address;
+    for pair in words.chunks(2) {
+        if let [lo, hi] = pair {
+            let value = (u64::from(*hi) << 32) | u64::from(*lo);
+            // `.word` parses through i64, so the top half of the range is written as a negative decimal.
+            let text = i64::try_from(value).map_or_else(|_| format!("{}", value as i64), |v| format!("0x{v:X}"));
+            let _ = writeln!(source, ".word {text:<24}// 0x{address:08X}");
+            address += 8;
+        } else {
+            let _ = writeln!(source, ".long 0x{:<21X}// 0x{address:08X}", pair[0]);
+            address += 4;
+        }
+    }
+}
+
+/// Disassemble a program image into `.kla` source that reassembles to the same bytes.
+///
+/// `code` holds the bytes loaded from the code base (`0x20`) onward; a trailing
+/// partial word is zero-padded.  Fails if `entry` is not at an instruction or
+/// data boundary inside the image.
+pub fn disassemble_to_source(code: &[u8], entry: u32, opcodes: &[Opcode], title: &str) -> Result<Disassembly, String> {
+    let mut padded = code.to_vec();
+    padded.resize(code.len().next_multiple_of(4), 0);
+    let items = decode_items(&padded, opcodes);
+    let end = CODE_BASE + padded.len() as u32;
+
+    if entry != end && items.binary_search_by_key(&entry, |(address, _)| *address).is_err() {
+        return Err(format!("entry 0x{entry:08X} is not on an instruction boundary inside the image"));
+    }
+
+    // Synthetic labels for every branch target that lands on an item boundary.
+    let mut labels: BTreeMap<u32, String> = BTreeMap::new();
+    for (_, item) in &items {
+        if let Some((target, is_call)) = item.branch_target() {
+            if items.binary_search_by_key(&target, |(address, _)| *address).is_ok() {
+                // A call target is named as a subroutine even if it is also jumped to.
+                if is_call {
+                    labels.insert(target, format!("sub_{target:08X}:"));
+                } else {
+                    labels.entry(target).or_insert_with(|| format!("L_{target:08X}:"));
+                }
+            }
+        }
+    }
+
+    let mut result = Disassembly {
+        labels: labels.len(),
+        ..Disassembly::default()
+    };
+    let mut source = format!(
+        "// Disassembled from {title} by klausscc\n// {} program bytes, entry 0x{entry:08X}\n\n",
+        padded.len()
+    );
+    let mut data_run: Vec<u32> = Vec::new();
+    let mut data_start = CODE_BASE;
+    for (address, item) in &items {
+        let boundary = *address == entry || labels.contains_key(address);
+        if !data_run.is_empty() && (boundary || !matches!(item, Item::Data(_))) {
+            render_data(&mut source, data_start, &data_run);
+            data_run.clear();
+        }
+        if *address == entry {
+            source.push_str("_start\n");
+        }
+        if let Some(label) = labels.get(address) {
+            let _ = writeln!(source, "{label}");
+        }
+        match item {
+            Item::Data(word) => {
+                if data_run.is_empty() {
+                    data_start = *address;
+                }
+                data_run.push(*word);
+                result.data_bytes += 4;
+            }
+            Item::Instruction { text, args } => {
+                let mut line = text.clone();
+                match item.branch_target().and_then(|(target, _)| labels.get(&target)) {
+                    Some(label) => {
+                        let _ = write!(line, " {label}");
+                    }
+                    None => match args.as_slice() {
+                        [value] => {
+                            let _ = write!(line, " 0x{value:X}");
+                        }
+                        [lo, hi] => {
+                            let _ = write!(line, " 0x{:X}", (u64::from(*hi) << 32) | u64::from(*lo));
+                        }
+                        _ => {}
+                    },
+                }
+                let _ = writeln!(source, "{line:<30}// 0x{address:08X}");
+                result.instructions += 1;
+            }
+        }
+    }
+    if !data_run.is_empty() {
+        render_data(&mut source, data_start, &data_run);
+    }
+    if entry == end {
+        source.push_str("_start\n");
+    }
+    result.source = source;
+    Ok(result)
+}
+
+/// Check that disassembling and reassembling reproduces every opcode in `isa`.
+///
+/// Each opcode is encoded with its wildcard nibbles filled and sample operand
+/// words, disassembled to source and assembled again.  Returns one message per
+/// opcode whose bytes do not survive the round trip.
+#[must_use]
+pub fn round_trip_opcodes(isa: &Isa) -> Vec<String> {
+    let mut failures: Vec<String> = Vec::new();
+    for (index, opcode) in isa.opcodes.iter().enumerate() {
+        let mut word: u32 = 0;
+        for (nibble, ch) in opcode.hex_code.chars().enumerate() {
+            let value = ch.to_digit(16).unwrap_or(((index + nibble * 5) & 0xF) as u32);
+            word = (word << 4) | value;
+        }
+        let mut code: Vec<u8> = word.to_le_bytes().to_vec();
+        for arg in [0x1234_5678_u32, 0x0ABC_DEF0].iter().take(opcode.variables as usize) {
+            code.extend_from_slice(&arg.to_le_bytes());
+        }
+
+        let source = match disassemble_to_source(&code, CODE_BASE, &isa.opcodes, &opcode.text_name) {
+            Ok(disassembly) => disassembly.source,
+            Err(err) => {
+                failures.push(format!("{}: {err}", opcode.text_name));
+                continue;
+            }
+        };
+        let assembly = Assembler::new(isa).source_text(opcode.text_name.as_str(), source.as_str()).assemble();
+        let reassembled = build_flat_code(&assembly.pass2).map(|(bytes, _)| bytes);
+        if !assembly.is_ok() || reassembled.as_deref() != Some(code.as_slice()) {
+            let line = source
+                .lines()
+                .find(|l| !l.starts_with("//") && !l.is_empty() && *l != "_start")
+                .unwrap_or("")
+                .to_owned();
+            failures.push(format!(
+                "{}: 0x{word:08X} disassembles to \"{}\" which reassembles to {}",
+                opcode.text_name,
+                line.split("//").next().unwrap_or("").trim(),
+                reassembled.map_or_else(|| "errors".to_owned(), |bytes| format!("{bytes:02X?}")),
+            ));
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+    use crate::helper::build_ddr_image;
+    use crate::test_support::corpus_isa;
+
+    fn assemble(isa: &Isa, source: &str) -> (Vec<u8>, u32) {
+        let assembly = Assembler::new(isa).source_text("test.kla", source).assemble();
+        assert!(assembly.is_ok(), "{:?}\n{source}", assembly.diagnostics);
+        build_flat_code(&assembly.pass2).unwrap()
+    }
+
+    #[test]
+    // Test a program with calls, jumps and trailing data reassembles byte-identically
+    fn test_disassemble_round_trip() {
+        let isa = corpus_isa();
+        let (code, entry) = assemble(
+            &isa,
+            "JMP main:\nprint:\nTXR A\nRET\n_start\nmain:\nSETR A 0x41\nCALL print:\nJMPZ main:\nHALT\n.word -1\n.long 0x12\n",
+        );
+        let disassembly = disassemble_to_source(&code, entry, &isa.opcodes, "test").unwrap();
+        assert!(disassembly.source.contains("CALL sub_00000028:"), "{}", disassembly.source);
+        assert!(disassembly.source.contains("JMPZ L_00000030:"), "{}", disassembly.source);
+        assert_eq!(disassembly.labels, 2);
+        assert_eq!(disassembly.data_bytes, 12);
+
+        let (again, again_entry) = assemble(&isa, &disassembly.source);
+        assert_eq!(again_entry, entry);
+        assert_eq!(build_ddr_image(&again), build_ddr_image(&code));
+    }
+
+    #[test]
+    // Test undecodable words become data and a bad entry is rejected
+    fn test_disassemble_data_and_entry() {
+        let isa = corpus_isa();
+        let code: Vec<u8> = [0xFFFF_FFFF_u32, 0xEEEE_EEEE, 0x0000_F011].iter().flat_map(|w| w.to_le_bytes()).collect();
+        let disassembly = disassemble_to_source(&code, 0x28, &isa.opcodes, "test").unwrap();
+        assert!(disassembly.source.contains(".word -"), "{}", disassembly.source);
+        assert_eq!(disassembly.instructions, 1);
+        let (again, entry) = assemble(&isa, &disassembly.source);
+        assert_eq!((again, entry), (code.clone(), 0x28));
+        assert!(disassemble_to_source(&code, 0x22, &isa.opcodes, "test").is_err());
+    }
+
+    #[test]
+    // Test every opcode in the corpus ISA survives disassemble → assemble
+    fn test_round_trip_opcodes() {
+        let failures = round_trip_opcodes(&corpus_isa());
+        assert!(failures.is_empty(), "{failures:#?}");
+    }
+}
@@ -0,0 +1,245 @@
+//! Find netboot boards on the local network.
+//!
+//! The host broadcasts an 8-byte query (`magic` = `KDSQ`, `version`, both
+//! little-endian u32) to UDP port [`DISCOVERY_PORT`].  Each board running
+//! netboot answers its sender with `KDSR` followed by UTF-8 `key=value`
+//! lines:
+//!
+//! ```text
+//! name=lab-a7
+//! mac=00:0a:35:01:02:03
+//! port=5000
+//! console=5001
+//! firmware=netboot 1.4
+//! isa=2
+//! ```
+//!
+//! The board's IP is the reply's source address, so it is right even when the
+//! board does not know it yet (link-local or just after DHCP).  Unknown keys
+//! are ignored so boards can add fields.
+
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// UDP port boards listen on for discovery queries.
+pub const DISCOVERY_PORT: u16 = 5001;
+
+/// Where `--discover` sends its query by default (limited broadcast).
+pub const DEFAULT_DISCOVERY_ADDRESS: &str = "255.255.255.255:5001";
+
+/// How long to collect replies.
+pub const DISCOVERY_WAIT: Duration = Duration::from_secs(1);
+
+/// Query magic — `b"KDSQ"` read as a little-endian u32.
+const QUERY_MAGIC: u32 = 0x5153_444B;
+
+/// Reply prefix.
+const REPLY_MAGIC: &[u8] = b"KDSR";
+
+/// Discovery protocol version.
+const DISCOVERY_VERSION: u32 = 1;
+
+/// One board's answer to a discovery query.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BoardAnnouncement {
+    /// Board name, as used by `--board`.
+    pub name: String,
+    /// Ethernet MAC address.
+    pub mac: String,
+    /// Address the reply came from (`None` until received).
+    pub ip: Option<IpAddr>,
+    /// Netboot TCP port.
+    pub port: u16,
+    /// Network console TCP port, if the board has one.
+    pub console: Option<u16>,
+    /// Firmware (netboot) version.
+    pub firmware: String,
+    /// ISA revision the board's CPU implements.
+    pub isa: String,
+}
+
+impl BoardAnnouncement {
+    /// Encode as a reply datagram (the IP is not sent).
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let console = self.console.map(|port| format!("console={port}\n")).unwrap_or_default();
+        let text = format!(
+            "name={}\nmac={}\nport={}\n{console}firmware={}\nisa={}\n",
+            self.name, self.mac, self.port, self.firmware, self.isa
+        );
+        [REPLY_MAGIC, text.as_bytes()].concat()
+    }
+
+    /// Decode a reply datagram received from `source`.
+    #[must_use]
+    pub fn decode(datagram: &[u8], source: IpAddr) -> Option<Self> {
+        let text = std::str::from_utf8(datagram.strip_prefix(REPLY_MAGIC)?).ok()?;
+        let mut board = Self {
+            ip: Some(source),
+            ..Self::default()
+        };
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().to_owned();
+            match key.trim() {
+                "name" => board.name = value,
+                "mac" => board.mac = value,
+                "port" => board.port = value.parse().ok()?,
+                "console" => board.console = value.parse().ok(),
+                "firmware" => board.firmware = value,
+                "isa" => board.isa = value,
+                _ => {}
+            }
+        }
+        (board.port != 0).then_some(board)
+    }
+}
+
+/// The query datagram.
+fn query() -> Vec<u8> {
+    [QUERY_MAGIC.to_le_bytes(), DISCOVERY_VERSION.to_le_bytes()].concat()
+}
+
+/// True if `datagram` is a discovery query.
+#[must_use]
+pub fn is_query(datagram: &[u8]) -> bool {
+    datagram.len() >= 8 && datagram[..4] == QUERY_MAGIC.to_le_bytes()
+}
+
+/// Send a query to `address` and collect the distinct replies that arrive within `wait`.
+pub fn discover(address: &str, wait: Duration) -> io::Result<Vec<BoardAnnouncement>> {
+    let target: SocketAddr = address
+        .parse()
+        .map_err(|err| io::Error::new(ErrorKind::InvalidInput, format!("bad discovery address {address}: {err}")))?;
+    let socket = UdpSocket::bind(if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&query(), target)?;
+
+    let deadline = Instant::now() + wait;
+    let mut boards: Vec<BoardAnnouncement> = Vec::new();
+    let mut buffer = [0_u8; 1024];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        match socket.recv_from(&mut buffer) {
+            Ok((count, source)) => {
+                if let Some(board) = BoardAnnouncement::decode(&buffer[..count], source.ip()) {
+                    if !boards.contains(&board) {
+                        boards.push(board);
+                    }
+                }
+            }
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    boards.sort_by(|a, b| a.name.cmp(&b.name).then(a.ip.cmp(&b.ip)));
+    Ok(boards)
+}
+
+/// Pick a board: the one named `name`, or the only one found.
+pub fn select_board<'a>(boards: &'a [BoardAnnouncement], name: Option<&str>) -> Result<&'a BoardAnnouncement, String> {
+    let candidates: Vec<&BoardAnnouncement> = boards.iter().filter(|board| name.is_none_or(|name| board.name == name)).collect();
+    match (candidates.as_slice(), name) {
+        ([board], _) => Ok(board),
+        ([], Some(name)) => Err(format!("No board named \"{name}\" answered discovery")),
+        ([], None) => Err("No boards answered discovery".to_owned()),
+        (_, Some(name)) => Err(format!("{} boards are named \"{name}\"", candidates.len())),
+        (_, None) => Err(format!(
+            "{} boards answered discovery ({}); choose one with --board <name>",
+            candidates.len(),
+            candidates.iter().map(|board| board.name.as_str()).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+/// Answer any queries waiting on a non-blocking `socket` with `board`.
+pub fn answer_queries(socket: &UdpSocket, board: &BoardAnnouncement) -> io::Result<()> {
+    let mut buffer = [0_u8; 64];
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((count, source)) if is_query(&buffer[..count]) => {
+                socket.send_to(&board.encode(), source)?;
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn board(name: &str) -> BoardAnnouncement {
+        BoardAnnouncement {
+            name: name.to_owned(),
+            mac: "00:0a:35:01:02:03".to_owned(),
+            ip: None,
+            port: 5000,
+            console: Some(5001),
+            firmware: "netboot 1.4".to_owned(),
+            isa: "2".to_owned(),
+        }
+    }
+
+    #[test]
+    // Test a reply round-trips, takes its IP from the sender and ignores unknown keys
+    fn test_announcement_round_trip() {
+        let source = IpAddr::V4(Ipv4Addr::new(192, 168, 68, 50));
+        let decoded = BoardAnnouncement::decode(&board("lab-a7").encode(), source).unwrap();
+        assert_eq!(
+            decoded,
+            BoardAnnouncement {
+                ip: Some(source),
+                ..board("lab-a7")
+            }
+        );
+
+        let extra = b"KDSRname=x\nuptime=5\nport=6000\n";
+        assert_eq!(BoardAnnouncement::decode(extra, source).unwrap().port, 6000);
+        assert!(BoardAnnouncement::decode(b"KDSRname=x\n", source).is_none(), "port is required");
+        assert!(BoardAnnouncement::decode(b"XXXXport=1", source).is_none());
+        assert!(is_query(&query()));
+    }
+
+    #[test]
+    // Test selection by name and by being the only board
+    fn test_select_board() {
+        let boards = vec![board("a"), board("b")];
+        assert_eq!(select_board(&boards, Some("b")).unwrap().name, "b");
+        assert!(select_board(&boards, None).unwrap_err().contains("a, b"));
+        assert!(select_board(&boards, Some("c")).unwrap_err().contains("\"c\""));
+        assert_eq!(select_board(&boards[..1], None).unwrap().name, "a");
+        assert!(select_board(&[], None).is_err());
+    }
+
+    #[test]
+    // Test a responder on loopback answers a discovery query
+    fn test_discover_loopback() {
+        let responder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        responder.set_nonblocking(true).unwrap();
+        let address = responder.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let deadline = Instant::now() + Duration::from_millis(700);
+            while Instant::now() < deadline {
+                answer_queries(&responder, &board("sim")).unwrap();
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        });
+        let boards = discover(&address, Duration::from_millis(500)).unwrap();
+        assert_eq!(boards.len(), 1);
+        assert_eq!(boards[0].name, "sim");
+        assert_eq!(boards[0].ip, Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        server.join().unwrap();
+    }
+}
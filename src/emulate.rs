@@ -8,16 +8,30 @@
 //!
 //! The model is intentionally cycle-agnostic: each instruction commits
 //! atomically.  Cache, IFB, timing and the DDR multi-cycle pipeline are not
-//! modelled (they have no architectural effect).  Interrupts / timer MMIO /
-//! WAIT are stubbed (documented in the summary); the validation corpus does
-//! not exercise them.
+//! modelled (they have no architectural effect); an optional [`TimingModel`]
+//! estimates run time from per-instruction costs instead, and optional
+//! [`Cache`] models count I-cache and D-cache hits and misses, and an optional
+//! [`Profiler`] counts instructions per PC and call stack.  Interrupts are modelled:
+//! four vectored lines (timer, UART receive and two spare), dispatched between
+//! instructions, with the timer and interrupt controls in an MMIO page at
+//! [`IO_BASE`] — see "Interrupts and timer" in `EMULATOR_ISA_SEMANTICS.md`.
+//! Board LEDs, 7-seg displays, RGB LEDs, LCD and switches are kept in
+//! [`Peripherals`].  WAIT is stubbed; the validation corpus does not exercise it.
 //!
 //! Decoding is done directly from the 32-bit instruction word's opcode bit
 //! fields (CPU_ARCHITECTURE.md §15), independent of the assembler's opcode
 //! table — so the emulator is a genuine second implementation, not a re-run of
 //! the assembler.
 
+use crate::cache_sim::Cache;
+use crate::peripherals::{Event, Peripherals};
+use crate::profiler::Profiler;
+use crate::timing::TimingModel;
+use std::collections::VecDeque;
 use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::time::Duration;
 
 /// Heap-header byte size (4 doublewords) — code starts here (0x20).
 #[allow(dead_code, reason = "used by default_entry() / tests; documents the code base")]
@@ -25,12 +39,44 @@ const CODE_BASE: u32 = 32;
 /// Default instruction-count cap to guard against runaway / infinite loops.
 pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 50_000_000;
 /// Size of the modelled address space (128 MiB DDR2).
-const MEM_SIZE: usize = 128 * 1024 * 1024;
+pub(crate) const MEM_SIZE: usize = 128 * 1024 * 1024;
 /// Initial stack pointer — top of DDR2, grows down. The loader sets SP near the
 /// top of memory; we use a generous value below the 128 MiB ceiling so PUSH
 /// never wraps. Matches the board's full-descending stack convention.
 const STACK_TOP: u32 = 0x0800_0000;
 
+/// Start of the MMIO page (interrupt controller and timer); reads and writes of
+/// 64-bit registers here do not reach DDR.
+pub const IO_BASE: u32 = 0xFFFF_0000;
+/// Interrupt lines enabled, bit n = line n (`INT_MASK`).
+pub const IO_INT_MASK: u32 = IO_BASE;
+/// Pending interrupt lines; writing 1s clears latched requests, 0s are ignored.
+pub const IO_INT_PENDING: u32 = IO_BASE + 0x08;
+/// Setting bits raises those lines from software.
+pub const IO_INT_RAISE: u32 = IO_BASE + 0x10;
+/// Timer count: +1 per retired instruction while enabled.
+pub const IO_TIMER_COUNT: u32 = IO_BASE + 0x18;
+/// Timer compare: the count reaching it raises [`INT_TIMER`].
+pub const IO_TIMER_COMPARE: u32 = IO_BASE + 0x20;
+/// Timer control: [`TIMER_ENABLE`] | [`TIMER_PERIODIC`].
+pub const IO_TIMER_CONTROL: u32 = IO_BASE + 0x28;
+/// Timer control bit: count.
+pub const TIMER_ENABLE: u64 = 1;
+/// Timer control bit: restart from 0 on reaching the compare value.
+pub const TIMER_PERIODIC: u64 = 2;
+/// Interrupt line of the timer.
+pub const INT_TIMER: usize = 0;
+/// Interrupt line of the UART receiver (requests while the FIFO holds a byte).
+pub const INT_UART_RX: usize = 1;
+/// Number of interrupt lines (`INTSETRR` takes rs1[1:0]).
+const INT_LINES: usize = 4;
+
+/// Instructions [`run_live`] runs between output flushes and input checks.
+const LIVE_SLICE: u64 = 10_000;
+
+/// How long [`run_live`] waits for input before checking `running` again.
+const LIVE_INPUT_POLL: Duration = Duration::from_millis(50);
+
 /// Reason the emulator stopped executing.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum StopReason {
@@ -44,6 +90,79 @@ pub enum StopReason {
     InvalidOpcode(u32),
     /// PC left the modelled address space.
     PcOutOfRange(u32),
+    /// RXRB found the receive FIFO empty; the PC stays on the RXRB so `run`
+    /// can resume once input is pushed with [`Cpu::push_uart_input`].
+    WaitingForInput,
+    /// RXRB found the receive FIFO empty after [`Cpu::close_uart_input`]: the
+    /// program is blocked on input that will never come.
+    InputExhausted,
+    /// [`run_live`] was told to stop (Ctrl+C in `--interactive`).
+    Interrupted,
+}
+
+/// Where an emulated program's UART input comes from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UartInput {
+    /// Fixed bytes (`--uart-input`, `--uart-input-file`); empty for none.
+    Bytes(Vec<u8>),
+    /// Live standard input (`--uart-stdin`).
+    Stdin,
+    /// Keystrokes from the terminal in raw mode (`--interactive`).
+    Terminal,
+}
+
+impl Default for UartInput {
+    fn default() -> Self {
+        Self::Bytes(Vec::new())
+    }
+}
+
+/// The condition flags, for debuggers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools, reason = "each bool is a distinct hardware condition flag")]
+pub struct Flags {
+    /// Zero flag.
+    pub zero: bool,
+    /// Equal flag.
+    pub equal: bool,
+    /// Carry / borrow flag.
+    pub carry: bool,
+    /// Signed overflow flag.
+    pub overflow: bool,
+    /// Sign flag.
+    pub sign: bool,
+    /// Signed less-than flag.
+    pub less: bool,
+    /// Unsigned less-than flag.
+    pub ult: bool,
+}
+
+impl Flags {
+    /// Packed as in the interrupt context slot's bits [38:32]: bit 6 zero … bit 0 ult.
+    #[must_use]
+    pub const fn bits(self) -> u8 {
+        (self.zero as u8) << 6
+            | (self.equal as u8) << 5
+            | (self.carry as u8) << 4
+            | (self.overflow as u8) << 3
+            | (self.sign as u8) << 2
+            | (self.less as u8) << 1
+            | self.ult as u8
+    }
+
+    /// Unpack [`Flags::bits`]; higher bits are ignored.
+    #[must_use]
+    pub const fn from_bits(bits: u8) -> Self {
+        Self {
+            zero: bits & 0x40 != 0,
+            equal: bits & 0x20 != 0,
+            carry: bits & 0x10 != 0,
+            overflow: bits & 0x08 != 0,
+            sign: bits & 0x04 != 0,
+            less: bits & 0x02 != 0,
+            ult: bits & 0x01 != 0,
+        }
+    }
 }
 
 /// Result of an emulation run.
@@ -89,6 +208,44 @@ pub struct Cpu {
     mem: Vec<u8>,
     /// Captured UART output.
     uart: String,
+    /// UART receive FIFO (RXRB / RXRNB).
+    uart_rx: VecDeque<u8>,
+    /// No more input will be pushed.
+    uart_rx_closed: bool,
+    /// Instructions retired over all `run` calls (numbers trace lines).
+    retired: u64,
+    /// Interrupt handler addresses set by INTSETRR (0: not set, never dispatched).
+    int_vectors: [u32; INT_LINES],
+    /// Enabled interrupt lines (`INT_MASK`); cleared on dispatch, restored by IRET.
+    int_mask: u8,
+    /// Latched interrupt requests (the timer and software raises).
+    int_pending: u8,
+    /// Timer count register.
+    timer_count: u64,
+    /// Timer compare register.
+    timer_compare: u64,
+    /// Timer control register.
+    timer_control: u64,
+    /// LEDs, 7-seg displays, RGB LEDs, LCD and switches.
+    peripherals: Peripherals,
+    /// DDR data reads and writes (not instruction fetches) since the CPU was created.
+    ddr_accesses: u64,
+    /// DDR accesses of the last interrupt entry, charged with the handler's first instruction.
+    entry_accesses: u64,
+    /// Cycle costs, when run time is being estimated.
+    timing: Option<TimingModel>,
+    /// Estimated cycles since the CPU was created (0 without a timing model).
+    cycles: u64,
+    /// Instruction cache, when simulated.
+    icache: Option<Cache>,
+    /// Data cache, when simulated.
+    dcache: Option<Cache>,
+    /// Address of the instruction being executed (cache attribution).
+    instruction_pc: u32,
+    /// Cache penalty cycles of the instruction being executed.
+    penalty: u64,
+    /// Instruction counts per PC and call stack, when profiling.
+    profiler: Option<Profiler>,
     /// True once a HALT (or other terminator) is reached.
     halted: bool,
     /// Set when an unrecoverable stop condition occurs.
@@ -130,6 +287,25 @@ impl Cpu {
             ult: false,
             mem,
             uart: String::new(),
+            uart_rx: VecDeque::new(),
+            uart_rx_closed: false,
+            retired: 0,
+            int_vectors: [0; INT_LINES],
+            int_mask: 0,
+            int_pending: 0,
+            timer_count: 0,
+            timer_compare: 0,
+            timer_control: 0,
+            peripherals: Peripherals::new(),
+            ddr_accesses: 0,
+            entry_accesses: 0,
+            timing: None,
+            cycles: 0,
+            icache: None,
+            dcache: None,
+            instruction_pc: entry,
+            penalty: 0,
+            profiler: None,
             halted: false,
             stop: None,
             last_write: None,
@@ -147,8 +323,17 @@ impl Cpu {
         u32::from_le_bytes([self.mem[a], self.mem[a + 1], self.mem[a + 2], self.mem[a + 3]])
     }
 
-    /// Read a 64-bit little-endian doubleword.
-    fn read64(&self, addr: u32) -> u64 {
+    /// Read a 64-bit little-endian doubleword (a data access).
+    fn read64(&mut self, addr: u32) -> u64 {
+        self.observe_data(addr, 8, false);
+        self.peek64(addr)
+    }
+
+    /// Read a 64-bit little-endian doubleword without counting an access.
+    fn peek64(&self, addr: u32) -> u64 {
+        if addr >= IO_BASE {
+            return self.io_read(addr & !7);
+        }
         let a = addr as usize;
         if a + 8 > self.mem.len() {
             return 0;
@@ -159,7 +344,12 @@ impl Cpu {
     }
 
     /// Read `n` bytes (1/2/4) zero-extended into a u64, little-endian.
-    fn read_sub(&self, addr: u32, n: usize) -> u64 {
+    fn read_sub(&mut self, addr: u32, n: usize) -> u64 {
+        self.observe_data(addr, n as u32, false);
+        if addr >= IO_BASE {
+            let value = self.io_read(addr & !7) >> (8 * (addr & 7));
+            return value & (u64::MAX >> (64 - 8 * n));
+        }
         let a = addr as usize;
         let mut v: u64 = 0;
         for i in 0..n {
@@ -172,6 +362,10 @@ impl Cpu {
 
     /// Write a 64-bit doubleword, record the write for the trace.
     fn write64(&mut self, addr: u32, val: u64) {
+        self.observe_data(addr, 8, true);
+        if addr >= IO_BASE {
+            self.io_write(addr & !7, val);
+        }
         let a = addr as usize;
         if a + 8 <= self.mem.len() {
             self.mem[a..a + 8].copy_from_slice(&val.to_le_bytes());
@@ -181,6 +375,14 @@ impl Cpu {
 
     /// Write the low `n` bytes (1/2/4) at `addr`, little-endian; record write.
     fn write_sub(&mut self, addr: u32, val: u64, n: usize) {
+        self.observe_data(addr, n as u32, true);
+        if addr >= IO_BASE {
+            // Merge the written lanes into the register's current value.
+            let shift = 8 * (addr & 7);
+            let lanes = (u64::MAX >> (64 - 8 * n)) << shift;
+            let old = self.io_read(addr & !7);
+            self.io_write(addr & !7, (old & !lanes) | ((val << shift) & lanes));
+        }
         let a = addr as usize;
         for i in 0..n {
             if a + i < self.mem.len() {
@@ -191,7 +393,97 @@ impl Cpu {
         // the RTL's byte-enable semantics for the trace `be` field.
         let lane = (addr & 7) as usize;
         let be_bits: u8 = ((1_u16 << n) - 1).rotate_left(lane as u32) as u8;
-        self.last_write = Some((addr & !7, be_bits, self.read64(addr & !7)));
+        self.last_write = Some((addr & !7, be_bits, self.peek64(addr & !7)));
+    }
+
+    /// Count a data access of `len` bytes that reaches DDR (MMIO registers do
+    /// not) and show it to the D-cache.
+    fn observe_data(&mut self, addr: u32, len: u32, write: bool) {
+        if addr >= IO_BASE {
+            return;
+        }
+        self.ddr_accesses += 1;
+        if let Some(dcache) = self.dcache.as_mut() {
+            self.penalty += dcache.access(self.instruction_pc, addr, len, write);
+        }
+    }
+
+    // ---- interrupt controller and timer ---------------------------------------
+
+    /// Read an MMIO register (unmapped addresses read 0).
+    fn io_read(&self, addr: u32) -> u64 {
+        match addr {
+            IO_INT_MASK => u64::from(self.int_mask),
+            IO_INT_PENDING => u64::from(self.pending_interrupts()),
+            IO_TIMER_COUNT => self.timer_count,
+            IO_TIMER_COMPARE => self.timer_compare,
+            IO_TIMER_CONTROL => self.timer_control,
+            _ => 0,
+        }
+    }
+
+    /// Write an MMIO register (unmapped addresses ignore writes).
+    fn io_write(&mut self, addr: u32, val: u64) {
+        let lines = (val & ((1 << INT_LINES) - 1)) as u8;
+        match addr {
+            IO_INT_MASK => self.int_mask = lines,
+            IO_INT_PENDING => self.int_pending &= !lines,
+            IO_INT_RAISE => self.int_pending |= lines,
+            IO_TIMER_COUNT => self.timer_count = val,
+            IO_TIMER_COMPARE => self.timer_compare = val,
+            IO_TIMER_CONTROL => self.timer_control = val & (TIMER_ENABLE | TIMER_PERIODIC),
+            _ => {}
+        }
+    }
+
+    /// Requesting lines: the latched ones plus UART receive while input waits.
+    fn pending_interrupts(&self) -> u8 {
+        self.int_pending | (u8::from(!self.uart_rx.is_empty()) << INT_UART_RX)
+    }
+
+    /// Raise interrupt `line` (0..4) as an external source would.
+    pub fn raise_interrupt(&mut self, line: usize) {
+        if line < INT_LINES {
+            self.int_pending |= 1 << line;
+        }
+    }
+
+    /// Advance the timer by one retired instruction.
+    fn tick_timer(&mut self) {
+        if self.timer_control & TIMER_ENABLE == 0 {
+            return;
+        }
+        self.timer_count = self.timer_count.wrapping_add(1);
+        if self.timer_count == self.timer_compare {
+            self.int_pending |= 1 << INT_TIMER;
+            if self.timer_control & TIMER_PERIODIC != 0 {
+                self.timer_count = 0;
+            }
+        }
+    }
+
+    /// Dispatch the lowest pending, enabled line that has a handler, if any.
+    ///
+    /// Pushes the saved-context slot (PC, flags, `INT_MASK`), disables all
+    /// lines and jumps to the handler.  A latched request is acknowledged; the
+    /// UART receive request lasts until the FIFO is drained.  The push's memory
+    /// cost is left for the handler's first instruction to be charged with.
+    fn dispatch_interrupt(&mut self) {
+        let ready = self.pending_interrupts() & self.int_mask;
+        let Some(line) = (0..INT_LINES).find(|&line| ready & (1 << line) != 0 && self.int_vectors[line] != 0) else {
+            return;
+        };
+        let context = u64::from(self.pc) | u64::from(self.flags().bits()) << 32 | u64::from(self.int_mask) << 39;
+        let accesses = self.ddr_accesses;
+        self.sp = self.sp.wrapping_sub(8);
+        self.write64(self.sp, context);
+        self.entry_accesses = self.ddr_accesses - accesses;
+        self.int_pending &= !(1 << line);
+        self.int_mask = 0;
+        self.pc = self.int_vectors[line];
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.call(self.pc, self.sp);
+        }
     }
 
     // ---- flag helpers --------------------------------------------------------
@@ -250,6 +542,175 @@ impl Cpu {
         self.uart.push(b as char);
     }
 
+    /// Queue bytes on the UART receive FIFO.
+    pub fn push_uart_input(&mut self, bytes: &[u8]) {
+        self.uart_rx.extend(bytes);
+    }
+
+    /// Mark the end of UART input: once the FIFO drains, RXRB stops the run
+    /// with [`StopReason::InputExhausted`] instead of waiting.
+    pub fn close_uart_input(&mut self) {
+        self.uart_rx_closed = true;
+    }
+
+    // ---- state access for debuggers ------------------------------------------
+
+    /// Program counter.
+    #[must_use]
+    pub const fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    /// Move the program counter; clears a fault stop so execution can resume.
+    pub fn set_pc(&mut self, pc: u32) {
+        self.pc = pc;
+        if matches!(self.stop, Some(StopReason::PcOutOfRange(_) | StopReason::InvalidOpcode(_))) {
+            self.stop = None;
+        }
+    }
+
+    /// Stack pointer.
+    #[must_use]
+    pub const fn sp(&self) -> u32 {
+        self.sp
+    }
+
+    /// Set the stack pointer.
+    pub const fn set_sp(&mut self, sp: u32) {
+        self.sp = sp;
+    }
+
+    /// General-purpose register `index` (0..16, A..P).
+    #[must_use]
+    pub fn reg(&self, index: usize) -> u64 {
+        self.regs.get(index).copied().unwrap_or(0)
+    }
+
+    /// Set general-purpose register `index` (0..16); other indices are ignored.
+    pub fn set_reg(&mut self, index: usize, value: u64) {
+        if let Some(reg) = self.regs.get_mut(index) {
+            *reg = value;
+        }
+    }
+
+    /// The seven condition flags.
+    #[must_use]
+    pub const fn flags(&self) -> Flags {
+        Flags {
+            zero: self.zero,
+            equal: self.equal,
+            carry: self.carry,
+            overflow: self.overflow,
+            sign: self.sign,
+            less: self.less,
+            ult: self.ult,
+        }
+    }
+
+    /// Set the seven condition flags.
+    pub const fn set_flags(&mut self, flags: Flags) {
+        self.zero = flags.zero;
+        self.equal = flags.equal;
+        self.carry = flags.carry;
+        self.overflow = flags.overflow;
+        self.sign = flags.sign;
+        self.less = flags.less;
+        self.ult = flags.ult;
+    }
+
+    /// `len` bytes of memory at `addr`, or `None` if any lie outside it.
+    #[must_use]
+    pub fn memory(&self, addr: u32, len: usize) -> Option<&[u8]> {
+        self.mem.get(addr as usize..(addr as usize).checked_add(len)?)
+    }
+
+    /// Write `bytes` at `addr`; false (and nothing written) if they do not fit.
+    pub fn write_memory(&mut self, addr: u32, bytes: &[u8]) -> bool {
+        let Some(end) = (addr as usize).checked_add(bytes.len()) else {
+            return false;
+        };
+        match self.mem.get_mut(addr as usize..end) {
+            Some(target) => {
+                target.copy_from_slice(bytes);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Instructions retired since the CPU was created.
+    #[must_use]
+    pub const fn retired(&self) -> u64 {
+        self.retired
+    }
+
+    /// Estimate run time with `timing` from now on.
+    pub fn set_timing(&mut self, timing: TimingModel) {
+        self.timing = Some(timing);
+    }
+
+    /// The timing model, if run time is being estimated.
+    #[must_use]
+    pub const fn timing(&self) -> Option<&TimingModel> {
+        self.timing.as_ref()
+    }
+
+    /// Estimated cycles since the CPU was created (0 without a timing model).
+    #[must_use]
+    pub const fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Simulate an instruction cache from now on.
+    pub fn set_icache(&mut self, cache: Cache) {
+        self.icache = Some(cache);
+    }
+
+    /// Simulate a data cache from now on; with timing, its miss penalties
+    /// replace the flat DDR latency.
+    pub fn set_dcache(&mut self, cache: Cache) {
+        self.dcache = Some(cache);
+    }
+
+    /// The instruction cache, if simulated.
+    #[must_use]
+    pub const fn icache(&self) -> Option<&Cache> {
+        self.icache.as_ref()
+    }
+
+    /// The data cache, if simulated.
+    #[must_use]
+    pub const fn dcache(&self) -> Option<&Cache> {
+        self.dcache.as_ref()
+    }
+
+    /// Profile execution from now on, with the current PC as the outermost frame.
+    pub fn start_profile(&mut self) {
+        self.profiler = Some(Profiler::new(self.pc));
+    }
+
+    /// The execution profile, if profiling.
+    #[must_use]
+    pub const fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Board peripheral state (LEDs, 7-seg, RGB, LCD, switches).
+    #[must_use]
+    pub const fn peripherals(&self) -> &Peripherals {
+        &self.peripherals
+    }
+
+    /// Set the switch status word that SWR reads.
+    pub const fn set_switches(&mut self, value: u64) {
+        self.peripherals.set_switches(value);
+    }
+
+    /// UART output produced since the last call (or the last `run`).
+    pub fn take_uart(&mut self) -> String {
+        std::mem::take(&mut self.uart)
+    }
+
     // ---- main execute loop ---------------------------------------------------
 
     /// Run until HALT / TRAP / cap / fault. Returns the result + trace (if any).
@@ -257,35 +718,105 @@ impl Cpu {
     /// When `trace` is `Some`, one line per retired instruction is appended in
     /// the `EMULATOR_ISA_SEMANTICS.md` "Trace format" layout.
     pub fn run(&mut self, max_instructions: u64, mut trace: Option<&mut String>) -> EmulateResult {
-        let mut count: u64 = 0;
-        while !self.halted && count < max_instructions {
-            if self.stop.is_some() {
+        let start = self.retired;
+        while self.retired - start < max_instructions {
+            if self.step_instruction(trace.as_deref_mut()).is_some() {
                 break;
             }
-            let pc = self.pc;
-            if (pc as usize) + 4 > self.mem.len() {
-                self.stop = Some(StopReason::PcOutOfRange(pc));
-                break;
-            }
-            let word = self.read32(pc);
-            self.last_write = None;
-            self.step(word);
-            count += 1;
-            if let Some(t) = trace.as_deref_mut() {
-                self.trace_line(t, count, pc, word);
-            }
         }
-        let stop = self
-            .stop
-            .clone()
-            .unwrap_or(if self.halted { StopReason::Halt } else { StopReason::InstructionCap });
         EmulateResult {
             uart: std::mem::take(&mut self.uart),
-            instructions: count,
-            stop,
+            instructions: self.retired - start,
+            stop: self.stop_reason().unwrap_or(StopReason::InstructionCap),
         }
     }
 
+    /// Execute one instruction, appending its trace line to `trace` if given.
+    ///
+    /// Returns why the machine is stopped, if it is — including when it already
+    /// was, so callers can step in a loop.  An RXRB that stalls on input is not
+    /// retired; stepping again retries it.  A pending interrupt is taken once
+    /// the instruction is traced and charged, so the next step starts at the
+    /// handler and is charged with the context push.
+    pub fn step_instruction(&mut self, trace: Option<&mut String>) -> Option<StopReason> {
+        if self.stop == Some(StopReason::WaitingForInput) {
+            self.stop = None;
+        }
+        if let Some(stop) = self.stop_reason() {
+            return Some(stop);
+        }
+        let pc = self.pc;
+        if (pc as usize) + 4 > self.mem.len() {
+            self.stop = Some(StopReason::PcOutOfRange(pc));
+            return self.stop.clone();
+        }
+        let word = self.read32(pc);
+        self.last_write = None;
+        self.instruction_pc = pc;
+        let accesses = self.ddr_accesses - std::mem::take(&mut self.entry_accesses);
+        let sp = self.sp;
+        self.step(word);
+        if matches!(self.stop, Some(StopReason::WaitingForInput | StopReason::InputExhausted)) {
+            return self.stop.clone();
+        }
+        if let Some(icache) = self.icache.as_mut() {
+            self.penalty += icache.access(pc, pc, fetch_length(word, pc, self.pc), false);
+        }
+        self.retired += 1;
+        self.tick_timer();
+        if self.profiler.is_some() {
+            self.profile(pc, word, sp);
+        }
+        let penalty = std::mem::take(&mut self.penalty);
+        if self.timing.is_some() {
+            self.add_cycles(pc, word, self.ddr_accesses - accesses, penalty);
+        }
+        if let Some(t) = trace {
+            self.trace_line(t, self.retired, pc, word);
+        }
+        if self.stop_reason().is_none() {
+            self.dispatch_interrupt();
+        }
+        self.stop_reason()
+    }
+
+    /// Charge the instruction `word` at `pc` to the timing model: its table
+    /// cycles, a DELAY's spin count and its memory cost — the cache `penalty`
+    /// cycles, plus DDR latency for each data access when no D-cache is
+    /// simulated.
+    fn add_cycles(&mut self, pc: u32, word: u32, accesses: u64, penalty: u64) {
+        let delay = match word {
+            0xF013 => u64::from(self.read32(pc.wrapping_add(4))),
+            0xF000..=0xF00F => self.regs[(word & 0xF) as usize],
+            _ => 0,
+        };
+        let uncached = if self.dcache.is_some() { 0 } else { accesses };
+        if let Some(timing) = self.timing.as_mut() {
+            let cost = timing.instruction_cycles(word) + uncached * timing.ddr_latency() + penalty;
+            self.cycles = self.cycles.saturating_add(cost).saturating_add(delay);
+        }
+    }
+
+    /// Count the instruction `word` at `pc` in the profile, and follow a taken
+    /// call (it pushed a return address below `sp`) or a return (it popped one from `sp`).
+    fn profile(&mut self, pc: u32, word: u32, sp: u32) {
+        let Some(profiler) = self.profiler.as_mut() else {
+            return;
+        };
+        profiler.record(pc);
+        let is_call = matches!(word, 0x1009..=0x1011 | 0x1041) || word & 0xFFF0 == 0x4070;
+        if is_call && self.sp == sp.wrapping_sub(8) {
+            profiler.call(self.pc, self.sp);
+        } else if matches!(word, 0x1012 | 0x6011) {
+            profiler.ret(sp);
+        }
+    }
+
+    /// Why the machine is stopped, or `None` if it can run.
+    fn stop_reason(&self) -> Option<StopReason> {
+        self.stop.clone().or_else(|| self.halted.then_some(StopReason::Halt))
+    }
+
     /// Append one trace line for the just-retired instruction.
     fn trace_line(&self, out: &mut String, i: u64, pc: u32, word: u32) {
         let _ = write!(out, "i={i} pc={pc:08x} op={word:08x}");
@@ -353,8 +884,8 @@ impl Cpu {
                 self.pc = self.pc.wrapping_add(4);
             }
             0x10..=0x1C => self.exec_flow(full, &fields),
-            0x20..=0x21 => self.exec_lcd(full, &fields),
-            0x30 => self.exec_io(full, &fields),
+            0x20..=0x21 => self.exec_lcd(full, &fields, imm()),
+            0x30 => self.exec_io(full, &fields, imm()),
             0x40 => self.exec_stack(full, &fields),
             0x50 => self.exec_uart(full, &fields),
             0x60 => self.exec_interrupt(full, &fields),
@@ -876,15 +1407,22 @@ impl Cpu {
                 return;
             }
             0x5050 => {
-                // RXRB R: blocking receive — no input source modelled; return 0.
-                self.regs[f.rs2] = 0;
-                self.pc = self.pc.wrapping_add(4);
+                // RXRB R: blocking receive — stall (without retiring) until a byte is queued.
+                if let Some(byte) = self.uart_rx.pop_front() {
+                    self.regs[f.rs2] = u64::from(byte);
+                    self.pc = self.pc.wrapping_add(4);
+                } else if self.uart_rx_closed {
+                    self.stop = Some(StopReason::InputExhausted);
+                } else {
+                    self.stop = Some(StopReason::WaitingForInput);
+                }
                 return;
             }
             0x5060 => {
-                // RXRNB R: non-blocking receive — FIFO empty: rd=0, zero_flag=1.
-                self.regs[f.rs2] = 0;
-                self.zero = true;
+                // RXRNB R: non-blocking receive — rd=byte, or FIFO empty: rd=0, zero_flag=1.
+                let byte = self.uart_rx.pop_front();
+                self.regs[f.rs2] = byte.map_or(0, u64::from);
+                self.zero = byte.is_none();
                 self.pc = self.pc.wrapping_add(4);
                 return;
             }
@@ -931,54 +1469,72 @@ impl Cpu {
         }
     }
 
-    /// LCD ops (0x20xx) — modelled as no-ops (no architectural state).
-    fn exec_lcd(&mut self, full: u32, _f: &Fields) {
+    /// LCD ops (0x20xx) — command / data bytes and the reset line, recorded in
+    /// [`Peripherals`].
+    fn exec_lcd(&mut self, full: u32, f: &Fields, imm: u32) {
+        let event = match full {
+            0x2021 => Event::LcdCommand(imm as u8),
+            0x2022 => Event::LcdData(imm as u8),
+            0x2023 => Event::LcdReset(imm & 1 != 0),
+            _ if full & 0xFFF0 == 0x2000 => Event::LcdCommand(self.regs[f.rs2] as u8),
+            _ => Event::LcdData(self.regs[f.rs2] as u8),
+        };
+        self.peripherals.apply(self.retired + 1, event);
         // 0x2021/0x2022/0x2023 are V (2-word); 0x200?/0x201? are R (1-word).
         let two_word = matches!(full, 0x2021..=0x2023);
         self.pc = self.pc.wrapping_add(if two_word { 8 } else { 4 });
     }
 
-    /// Board I/O (0x30xx) — LEDs / 7-seg / switches. No architectural effect,
-    /// except SWR reads switches (modelled as 0).
-    fn exec_io(&mut self, full: u32, f: &Fields) {
-        match full & 0xFFF0 {
+    /// Board I/O (0x30xx) — LEDs / 7-seg / RGB writes go to [`Peripherals`];
+    /// SWR reads the switch word set by [`Cpu::set_switches`].
+    fn exec_io(&mut self, full: u32, f: &Fields, imm: u32) {
+        let reg = self.regs[f.rs2] as u32;
+        let events = match full & 0xFFF0 {
             0x3010 => {
-                // SWR R: read switch status (modelled as 0).
-                self.regs[f.rs2] = 0;
-                self.pc = self.pc.wrapping_add(4);
-                return;
-            }
-            0x3000 | 0x3020 | 0x3030 | 0x3040 | 0x3050 | 0x3060 => {
-                // LEDR / 7SEG*R / RGB*R — R form, no effect.
+                // SWR R: read switch status.
+                self.regs[f.rs2] = self.peripherals.switches();
                 self.pc = self.pc.wrapping_add(4);
                 return;
             }
-            _ => {}
+            0x3000 => [Some(Event::Leds(reg)), None],
+            0x3020 => [Some(Event::Seg1(Some(reg))), None],
+            0x3030 => [Some(Event::Seg2(Some(reg))), None],
+            // 7SEGR: the same value on both displays.
+            0x3040 => [Some(Event::Seg1(Some(reg))), Some(Event::Seg2(Some(reg)))],
+            0x3050 => [Some(Event::Rgb1(reg)), None],
+            0x3060 => [Some(Event::Rgb2(reg)), None],
+            _ => match full {
+                0x3070 => [Some(Event::Leds(imm)), None],
+                0x3071 => [Some(Event::Seg1(Some(imm))), None],
+                0x3072 => [Some(Event::Seg2(Some(imm))), None],
+                0x3073 => [Some(Event::Seg1(None)), Some(Event::Seg2(None))],
+                0x3074 => [Some(Event::Rgb1(imm)), None],
+                0x3075 => [Some(Event::Rgb2(imm)), None],
+                _ => [None, None],
+            },
+        };
+        for event in events.into_iter().flatten() {
+            self.peripherals.apply(self.retired + 1, event);
         }
-        // 0x3070..0x3075 are V (2-word); 0x3073 (7SEGBLANK) is 1-word.
+        // 0x3070..0x3075 are V (2-word); 0x3073 (7SEGBLANK) and the R forms are 1-word.
         let two_word = matches!(full, 0x3070 | 0x3071 | 0x3072 | 0x3074 | 0x3075);
         self.pc = self.pc.wrapping_add(if two_word { 8 } else { 4 });
     }
 
-    /// Interrupt ops (0x60xx) — INTSETRR / IRET. Stubbed: no interrupt model.
-    fn exec_interrupt(&mut self, full: u32, _f: &Fields) {
+    /// Interrupt ops (0x60xx) — INTSETRR / IRET.
+    fn exec_interrupt(&mut self, full: u32, f: &Fields) {
         if full == 0x6011 {
-            // IRET: pop saved context, restore PC[31:0] and flags. With no
-            // interrupt dispatch modelled this still correctly unwinds an
-            // explicitly-pushed context if one exists.
+            // IRET: pop the saved context; restore PC[31:0], flags[38:32] and
+            // INT_MASK[42:39].
             let ctx = self.read64(self.sp);
             self.sp = self.sp.wrapping_add(8);
             self.pc = ctx as u32;
-            self.zero = (ctx >> 38) & 1 == 1;
-            self.equal = (ctx >> 37) & 1 == 1;
-            self.carry = (ctx >> 36) & 1 == 1;
-            self.overflow = (ctx >> 35) & 1 == 1;
-            self.sign = (ctx >> 34) & 1 == 1;
-            self.less = (ctx >> 33) & 1 == 1;
-            self.ult = (ctx >> 32) & 1 == 1;
+            self.set_flags(Flags::from_bits((ctx >> 32) as u8));
+            self.int_mask = ((ctx >> 39) & 0xF) as u8;
             return;
         }
-        // INTSETRR RR: configure handler — no architectural register effect here.
+        // INTSETRR RR: handler of interrupt rs1[1:0] = rs2[31:0].
+        self.int_vectors[(self.regs[f.rs1] & 3) as usize] = self.regs[f.rs2] as u32;
         self.pc = self.pc.wrapping_add(4);
     }
 
@@ -1139,15 +1695,83 @@ impl Cpu {
 
 /// Emulate a flat DDR image starting at `entry`. Convenience wrapper.
 ///
-/// Returns the result and, if `want_trace`, the full trace text.
+/// Returns the result and, if `want_trace`, the full trace text.  There is no
+/// UART input, so an RXRB stops the run with [`StopReason::InputExhausted`].
 #[must_use]
 pub fn emulate_image(image: &[u8], entry: u32, max_instructions: u64, want_trace: bool) -> (EmulateResult, Option<String>) {
+    emulate_image_with_input(image, entry, &[], max_instructions, want_trace)
+}
+
+/// [`emulate_image`] with `input` queued on the UART receive FIFO.
+///
+/// The input is all there is: RXRB after it is consumed stops the run with
+/// [`StopReason::InputExhausted`].
+#[must_use]
+pub fn emulate_image_with_input(image: &[u8], entry: u32, input: &[u8], max_instructions: u64, want_trace: bool) -> (EmulateResult, Option<String>) {
     let mut cpu = Cpu::new(image, entry);
+    cpu.push_uart_input(input);
+    cpu.close_uart_input();
     let mut trace = want_trace.then(String::new);
     let result = cpu.run(max_instructions, trace.as_mut());
     (result, trace)
 }
 
+/// Run with UART input arriving on `input` while the program runs (live stdin
+/// or keystrokes).
+///
+/// UART output is handed to `on_output` as each slice of instructions
+/// completes, so the returned result's `uart` is empty.  A stalled RXRB waits
+/// for the next chunk; when the sender hangs up the FIFO is closed, so a
+/// further RXRB stops with [`StopReason::InputExhausted`].  Clearing `running`
+/// stops the run with [`StopReason::Interrupted`] (checked between slices).
+pub fn run_live(
+    cpu: &mut Cpu,
+    max_instructions: u64,
+    input: &Receiver<Vec<u8>>,
+    running: &AtomicBool,
+    mut on_output: impl FnMut(&str),
+    mut trace: Option<&mut String>,
+) -> EmulateResult {
+    let mut retired: u64 = 0;
+    loop {
+        if !running.load(Ordering::Relaxed) {
+            return EmulateResult {
+                uart: String::new(),
+                instructions: retired,
+                stop: StopReason::Interrupted,
+            };
+        }
+        let result = cpu.run(LIVE_SLICE.min(max_instructions - retired), trace.as_deref_mut());
+        retired += result.instructions;
+        on_output(&result.uart);
+        loop {
+            match input.try_recv() {
+                Ok(bytes) => cpu.push_uart_input(&bytes),
+                Err(TryRecvError::Disconnected) => {
+                    cpu.close_uart_input();
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        match result.stop {
+            StopReason::InstructionCap if retired < max_instructions => {}
+            StopReason::WaitingForInput => match input.recv_timeout(LIVE_INPUT_POLL) {
+                Ok(bytes) => cpu.push_uart_input(&bytes),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => cpu.close_uart_input(),
+            },
+            stop => {
+                return EmulateResult {
+                    uart: String::new(),
+                    instructions: retired,
+                    stop,
+                }
+            }
+        }
+    }
+}
+
 /// The default entry point for an assembled `.kla` program (code base 0x20).
 #[must_use]
 #[allow(dead_code, reason = "public golden-model API; used by tests and external callers")]
@@ -1155,11 +1779,26 @@ pub const fn default_entry() -> u32 {
     CODE_BASE
 }
 
+/// Bytes fetched for the instruction `word` at `pc`, given the PC after it
+/// ran: its immediate word too when it has one.
+const fn fetch_length(word: u32, pc: u32, next: u32) -> u32 {
+    match word {
+        // JMPR, RET, CALLR, IRET and RESET: one word, wherever they go.
+        0x1012 | 0x1020..=0x102F | 0x4070..=0x407F | 0x6011 | 0xF012 => 4,
+        // The other jumps, branches and calls carry a target word.
+        0x1000..=0x1CFF => 8,
+        _ if next == pc.wrapping_add(8) => 8,
+        _ => 4,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
     use super::*;
+    use crate::cache_sim::CacheConfig;
     use crate::helper::build_ddr_image;
+    use crate::symbols::SymbolMap;
 
     /// Assemble a tiny program from raw 32-bit words (already in board order) into
     /// a flat code byte vector (little-endian), then wrap in a DDR image.
@@ -1374,4 +2013,324 @@ mod tests {
         // SETFR B: rd=B=1; top bit (zero) set.
         assert_eq!(cpu.regs[1] >> 63, 1);
     }
+
+    #[test]
+    // Test RXRB stalls on an empty FIFO without retiring and resumes once input is pushed
+    fn test_rxrb_waits_for_input() {
+        // RXRB A ; TXR A ; RXRNB B ; HALT
+        let mut cpu = Cpu::new(&image_from_words(&[0x5050, 0x5010, 0x5061, 0xF011]), 0x20);
+        let r = cpu.run(100, None);
+        assert_eq!(r.stop, StopReason::WaitingForInput);
+        assert_eq!(r.instructions, 0);
+        cpu.push_uart_input(b"K");
+        let r = cpu.run(100, None);
+        assert_eq!(r.stop, StopReason::Halt);
+        assert_eq!(r.instructions, 4);
+        assert_eq!(r.uart, "000000000000004B");
+        assert!(cpu.zero, "RXRNB on an empty FIFO sets zero");
+    }
+
+    #[test]
+    // Test queued input is consumed in order and RXRB on exhausted input stops the run
+    fn test_rxrb_input_exhausted() {
+        // RXRB A ; TXR A ; RXRB A ; TXR A ; HALT
+        let image = image_from_words(&[0x5050, 0x5010, 0x5050, 0x5010, 0xF011]);
+        let (r, _) = emulate_image_with_input(&image, 0x20, b"xy", 100, false);
+        assert_eq!(r.stop, StopReason::Halt);
+        assert_eq!(r.uart, "00000000000000780000000000000079");
+        let (r, trace) = emulate_image_with_input(&image, 0x20, b"x", 100, true);
+        assert_eq!(r.stop, StopReason::InputExhausted);
+        assert_eq!(r.instructions, 2, "the stalled RXRB does not retire");
+        assert_eq!(trace.unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    // Test run_live waits for input chunks, streams output, closes input on hang-up and stops when told
+    fn test_run_live() {
+        let image = image_from_words(&[0x5050, 0x5010, 0x5050, 0x5010, 0xF011]);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let feeder = std::thread::spawn(move || {
+            sender.send(b"a".to_vec()).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            sender.send(b"b".to_vec()).unwrap();
+        });
+        let mut cpu = Cpu::new(&image, 0x20);
+        let mut output = String::new();
+        let running = AtomicBool::new(true);
+        let r = run_live(&mut cpu, 100, &receiver, &running, |text| output.push_str(text), None);
+        feeder.join().unwrap();
+        assert_eq!(r.stop, StopReason::Halt);
+        assert_eq!(output, "00000000000000610000000000000062");
+
+        let (sender, receiver) = std::sync::mpsc::channel::<Vec<u8>>();
+        drop(sender);
+        let r = run_live(&mut Cpu::new(&image, 0x20), 100, &receiver, &running, |_| {}, None);
+        assert_eq!(r.stop, StopReason::InputExhausted);
+
+        running.store(false, Ordering::Relaxed);
+        let (_sender, receiver) = std::sync::mpsc::channel::<Vec<u8>>();
+        let r = run_live(&mut Cpu::new(&image, 0x20), 100, &receiver, &running, |_| {}, None);
+        assert_eq!(r.stop, StopReason::Interrupted);
+    }
+
+    #[test]
+    // Test a one-shot timer interrupt is dispatched once the line is enabled and IRET resumes the program
+    fn test_timer_interrupt() {
+        let words = [
+            0x0000_0800,
+            0, // SETR A 0 (timer line)
+            0x0000_0801,
+            0x7C,        // SETR B handler
+            0x0000_6001, // INTSETRR A B
+            0x0000_0802,
+            IO_TIMER_COMPARE, // SETR C compare
+            0x0000_0803,
+            3,           // SETR D 3
+            0x0000_7032, // MEMSET64RR D C
+            0x0000_0802,
+            IO_TIMER_CONTROL, // SETR C control
+            0x0000_0803,
+            1,           // SETR D enable (count 1 once retired)
+            0x0000_7032, // MEMSET64RR D C
+            0x0000_0802,
+            IO_INT_MASK, // SETR C mask (count 2)
+            0x0000_0803,
+            1,           // SETR D 1 (count 3: timer pending, still masked)
+            0x0000_7032, // MEMSET64RR D C — line 0 enabled
+            0x0000_0844, // 0x70: INCR E
+            0x0000_0844, // INCR E
+            0x0000_F011, // HALT
+            0x0000_0845, // 0x7C handler: INCR F
+            0x0000_6011, // IRET
+        ];
+        let mut cpu = Cpu::new(&image_from_words(&words), default_entry());
+        let r = cpu.run(100, None);
+        assert_eq!(r.stop, StopReason::Halt);
+        assert_eq!((cpu.regs[4], cpu.regs[5]), (2, 1), "E counts the main line, F the handler");
+        assert_eq!(cpu.sp, STACK_TOP);
+        assert_eq!(cpu.read64(STACK_TOP - 8), 0x70 | 1 << 39, "saved PC and INT_MASK");
+        assert_eq!(cpu.int_mask, 1, "IRET restores the mask");
+        assert_eq!(cpu.read64(IO_TIMER_COUNT), 9, "a one-shot timer keeps counting past the compare value");
+    }
+
+    #[test]
+    // Test the UART receive line requests while bytes wait, and software raise / clear through MMIO
+    fn test_uart_rx_interrupt() {
+        let words = [
+            0x0000_0800,
+            1, // SETR A 1 (UART receive line)
+            0x0000_0801,
+            0x50,        // SETR B handler
+            0x0000_6001, // INTSETRR A B
+            0x0000_0802,
+            IO_INT_MASK, // SETR C mask
+            0x0000_0803,
+            2,           // SETR D 2
+            0x0000_7032, // MEMSET64RR D C
+            0x0000_0844, // INCR E
+            0x0000_F011, // HALT
+            0x0000_5056, // 0x50 handler: RXRB G
+            0x0000_5016, // TXR G
+            0x0000_6011, // IRET
+        ];
+        let mut cpu = Cpu::new(&image_from_words(&words), default_entry());
+        cpu.push_uart_input(b"AB");
+        let r = cpu.run(100, None);
+        assert_eq!(r.stop, StopReason::Halt);
+        assert_eq!(
+            r.uart, "00000000000000410000000000000042",
+            "one dispatch per byte before the main line resumes"
+        );
+        assert_eq!(cpu.regs[4], 1);
+
+        cpu.write64(IO_INT_RAISE, 0b1100);
+        assert_eq!(cpu.read_sub(IO_INT_PENDING, 1), 0b1100);
+        cpu.write_sub(IO_INT_PENDING, 0b0100, 1);
+        assert_eq!(cpu.read64(IO_INT_PENDING), 0b1000);
+        cpu.push_uart_input(b"C");
+        assert_eq!(cpu.read64(IO_INT_PENDING), 0b1010);
+    }
+
+    #[test]
+    // Test LED, 7-seg, RGB and LCD writes reach the peripheral model and SWR reads the switches
+    fn test_board_io() {
+        use crate::peripherals::{Event, Output};
+        let words = [
+            0x0000_3070,
+            0xAA,        // LEDV 0xAA
+            0x0000_3010, // SWR A
+            0x0000_3040, // 7SEGR A
+            0x0000_3000, // LEDR A
+            0x0000_3074,
+            0xFF_0000, // RGB1V 0xFF0000
+            0x0000_2021,
+            0xC1, // LCDCMDV 0xC1 (row 2, column 1)
+            0x0000_2022,
+            u32::from(b'K'), // LCDDATAV 'K'
+            0x0000_3073,     // 7SEGBLANK
+            0x0000_3071,
+            0x14,        // 7SEG1V 0x14
+            0x0000_F011, // HALT
+        ];
+        let mut cpu = Cpu::new(&image_from_words(&words), default_entry());
+        cpu.set_switches(0x5A);
+        let r = cpu.run(100, None);
+        assert_eq!(r.stop, StopReason::Halt);
+        let panel = cpu.peripherals();
+        assert_eq!(cpu.regs[0], 0x5A);
+        assert_eq!(panel.value(Output::Leds), 0x5A);
+        assert_eq!(panel.value(Output::Seg1), 0x14);
+        assert_eq!(panel.value(Output::Seg2), 0, "blanked after 7SEGR");
+        assert_eq!(panel.value(Output::Rgb1), 0xFF_0000);
+        assert_eq!(panel.lcd_text()[1], " K              ");
+        let history: Vec<(u64, Event)> = panel.history().iter().copied().collect();
+        assert_eq!(history[0], (1, Event::Leds(0xAA)));
+        assert_eq!(history[1..3], [(3, Event::Seg1(Some(0x5A))), (3, Event::Seg2(Some(0x5A)))]);
+        assert_eq!(history.len(), 10);
+    }
+
+    #[test]
+    // Test the timing model charges table cycles, DDR latency per data access and DELAY counts
+    fn test_timing_cycles() {
+        let words = [
+            0x0000_0800,
+            0x200,       // SETR A 0x200
+            0x0000_7010, // MEMSET64RR B A (one DDR write)
+            0x0000_F013,
+            100,         // DELAYV 100
+            0x0000_0841, // INCR B
+            0x0000_F001, // DELAYR B (B = 1)
+            0x0000_F011, // HALT
+        ];
+        let mut cpu = Cpu::new(&image_from_words(&words), default_entry());
+        cpu.set_timing(TimingModel::new());
+        let r = cpu.run(100, None);
+        assert_eq!(r.stop, StopReason::Halt);
+        let per = crate::timing::DEFAULT_CYCLES;
+        assert_eq!(cpu.cycles(), 6 * per + crate::timing::DEFAULT_DDR_LATENCY + 100 + 1);
+
+        let mut untimed = Cpu::new(&image_from_words(&words), default_entry());
+        let _ = untimed.run(100, None);
+        assert_eq!(untimed.cycles(), 0);
+    }
+
+    #[test]
+    // Test a store that raises an interrupt keeps its trace line and cycles; the handler pays for the push
+    fn test_interrupt_after_store() {
+        let words = [
+            0x0000_0800,
+            0, // SETR A 0 (timer line)
+            0x0000_0801,
+            0x80,        // SETR B handler
+            0x0000_6001, // INTSETRR A B
+            0x0000_0802,
+            IO_INT_MASK, // SETR C mask
+            0x0000_0803,
+            1,           // SETR D 1
+            0x0000_7032, // MEMSET64RR D C — line 0 enabled
+            0x0000_0802,
+            IO_TIMER_COMPARE, // SETR C compare
+            0x0000_0803,
+            3,           // SETR D 3
+            0x0000_7032, // MEMSET64RR D C
+            0x0000_0802,
+            IO_TIMER_CONTROL, // SETR C control
+            0x0000_0803,
+            1,           // SETR D enable
+            0x0000_7032, // MEMSET64RR D C (count 1)
+            0x0000_0802,
+            0x200,       // SETR C 0x200 (count 2)
+            0x0000_7032, // 0x78: MEMSET64RR D C (count 3: timer fires)
+            0x0000_F011, // HALT
+            0x0000_6011, // 0x80 handler: IRET
+        ];
+        let mut cpu = Cpu::new(&image_from_words(&words), default_entry());
+        cpu.set_timing(TimingModel::new());
+        while cpu.pc() != 0x78 {
+            assert_eq!(cpu.step_instruction(None), None);
+        }
+        let (per, latency) = (crate::timing::DEFAULT_CYCLES, crate::timing::DEFAULT_DDR_LATENCY);
+        let mut trace = String::new();
+        let before = cpu.cycles();
+        cpu.step_instruction(Some(&mut trace));
+        assert!(trace.starts_with("i=14 pc=00000078") && trace.contains(" wr=00000200/"), "{trace}");
+        assert_eq!(cpu.cycles() - before, per + latency, "the store alone");
+        assert_eq!(cpu.pc(), 0x80);
+
+        let before = cpu.cycles();
+        trace.clear();
+        cpu.step_instruction(Some(&mut trace));
+        assert!(trace.starts_with("i=15 pc=00000080"), "{trace}");
+        assert_eq!(cpu.cycles() - before, per + 2 * latency, "IRET's pop and the entry push");
+        assert_eq!(cpu.run(10, None).stop, StopReason::Halt);
+    }
+
+    #[test]
+    // Test I-cache fetches and D-cache misses, whose penalties replace the flat DDR latency
+    fn test_cache_simulation() {
+        let words = [
+            0x0000_0800,
+            0x200,       // SETR A 0x200
+            0x0000_7010, // MEMSET64RR B A (one D-cache write miss)
+            0x0000_F011, // HALT
+        ];
+        let mut cpu = Cpu::new(&image_from_words(&words), default_entry());
+        cpu.set_timing(TimingModel::new());
+        cpu.set_icache(Cache::new(CacheConfig::default()));
+        cpu.set_dcache(Cache::new(CacheConfig::default()));
+        let r = cpu.run(100, None);
+        assert_eq!(r.stop, StopReason::Halt);
+        let icache = cpu.icache().unwrap().stats();
+        assert_eq!(icache.reads, 3, "one fetch per instruction");
+        assert_eq!(icache.writes, 0);
+        let dcache = cpu.dcache().unwrap().stats();
+        assert_eq!((dcache.writes, dcache.write_misses, dcache.reads), (1, 1, 0));
+        let penalty = CacheConfig::default().miss_penalty;
+        assert_eq!(
+            cpu.cycles(),
+            3 * crate::timing::DEFAULT_CYCLES + (icache.misses() + dcache.misses()) * penalty
+        );
+    }
+
+    #[test]
+    // Test an instruction's immediate word is fetched through the I-cache
+    fn test_icache_immediate_fetch() {
+        let mut words = vec![0x0000_F010; 7]; // NOPs up to 0x3C
+        words.extend([
+            0x0000_0800,
+            0x41, // 0x3C: SETR A 0x41 (its immediate starts the next line)
+            0x0000_1000,
+            0x4C,        // JMP 0x4C
+            0x0000_F011, // HALT
+        ]);
+        let mut cpu = Cpu::new(&image_from_words(&words), default_entry());
+        cpu.set_icache(Cache::new(CacheConfig::default()));
+        let r = cpu.run(100, None);
+        assert_eq!(r.stop, StopReason::Halt);
+        let icache = cpu.icache().unwrap().stats();
+        assert_eq!((icache.reads, icache.read_misses), (11, 2), "SETR touches two lines, JMP one");
+    }
+
+    #[test]
+    // Test the profiler follows a taken CALL and its RET, and ignores a call not taken
+    fn test_profile_calls() {
+        let words = [
+            0x0000_1009,
+            0x34, // 0x20: CALL sub
+            0x0000_100A,
+            0x34,        // 0x28: CALLZ sub (zero clear: not taken)
+            0x0000_F011, // 0x30: HALT
+            0x0000_0840, // 0x34: sub: INCR A
+            0x0000_1012, // 0x38: RET
+        ];
+        let mut cpu = Cpu::new(&image_from_words(&words), default_entry());
+        cpu.start_profile();
+        let r = cpu.run(100, None);
+        assert_eq!(r.stop, StopReason::Halt);
+        let profiler = cpu.profiler().unwrap();
+        assert_eq!(profiler.total(), 5);
+        assert_eq!(profiler.unmatched_returns(), 0);
+        let symbols = SymbolMap::from_symbols(vec![(0x20, "_start".to_owned()), (0x34, "sub".to_owned())]);
+        assert_eq!(profiler.folded(&symbols), "_start 3\n_start;sub 2\n");
+    }
 }
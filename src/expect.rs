@@ -0,0 +1,607 @@
+//! Expect-style scripts for interactive programs.
+//!
+//! A script drives a running program over its UART: it sends input and waits
+//! for output, so programs that read with `getchar` get regression tests.  One
+//! step per line; `#` starts a comment:
+//!
+//! ```text
+//! timeout 10                  # default wait for later expects (seconds)
+//! expect "Type a line"        # literal text
+//! sendline "hello"            # text then CR, as the Enter key sends
+//! expect /HELLO\s*$/ 2        # regex, with its own timeout
+//! send "q"                    # text as-is
+//! ctrl C                      # a control byte (0x03)
+//! byte 0x1B                   # any byte
+//! switches 0x0F               # set the board switches (emulator only)
+//! expect halt                 # the program halts
+//! ```
+//!
+//! Strings take `\n`, `\r`, `\t`, `\0`, `\\`, `\"` and `\xHH` escapes.
+//! Output is matched from where the previous expect matched, so each expect
+//! consumes what it saw.  The same script runs against the board (any
+//! [`Transport`], with the `0x00` halt marker) or the emulator.
+
+use crate::emulate::{Cpu, StopReason};
+use crate::fake_board::uart_bytes;
+use crate::peripherals::parse_switches;
+use crate::transport::Transport;
+use regex::Regex;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Wait for an expect with no `timeout` directive in force.
+pub const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Instructions the emulator runs between output checks.
+const EMULATOR_SLICE: u64 = 100_000;
+
+/// How long one read from a board waits before the deadline is checked again.
+const READ_POLL: Duration = Duration::from_millis(100);
+
+/// What an expect waits for.
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    /// Exact text.
+    Literal(String),
+    /// A regular expression.
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Byte range of the first match in `text`.
+    fn find(&self, text: &str) -> Option<(usize, usize)> {
+        match self {
+            Self::Literal(literal) => text.find(literal.as_str()).map(|start| (start, start + literal.len())),
+            Self::Regex(regex) => regex.find(text).map(|found| (found.start(), found.end())),
+        }
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Literal(literal) => write!(f, "{literal:?}"),
+            Self::Regex(regex) => write!(f, "/{}/", regex.as_str()),
+        }
+    }
+}
+
+/// One script step.
+#[derive(Clone, Debug)]
+pub enum Step {
+    /// Send bytes to the program.
+    Send(Vec<u8>),
+    /// Wait for output matching a pattern.
+    Expect {
+        /// What to wait for.
+        pattern: Pattern,
+        /// How long to wait.
+        timeout: Duration,
+    },
+    /// Wait for the program to halt.
+    ExpectHalt {
+        /// How long to wait.
+        timeout: Duration,
+    },
+    /// Set the switch word SWR reads.
+    Switches(u64),
+}
+
+/// A parsed script: steps with their source line numbers.
+#[derive(Clone, Debug, Default)]
+pub struct Script {
+    /// Script file name, for messages.
+    pub name: String,
+    /// Steps in order, each with its 1-based line number.
+    pub steps: Vec<(usize, Step)>,
+}
+
+/// Why a script failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptFailure {
+    /// Line of the failing step (0 if the target failed outside a step).
+    pub line: usize,
+    /// What went wrong.
+    pub message: String,
+    /// Output received but not consumed by an expect.
+    pub unmatched: String,
+}
+
+impl ScriptFailure {
+    /// One line naming the script and step, with the end of the unmatched output.
+    #[must_use]
+    pub fn describe(&self, script: &Script) -> String {
+        let tail: String = self.unmatched.chars().rev().take(80).collect::<Vec<char>>().into_iter().rev().collect();
+        if tail.is_empty() {
+            format!("Script {}:{}: {}", script.name, self.line, self.message)
+        } else {
+            format!("Script {}:{}: {} (last output {tail:?})", script.name, self.line, self.message)
+        }
+    }
+}
+
+impl Script {
+    /// Read and parse a script file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("Cannot read script {}: {err}", path.display()))?;
+        Self::parse(&path.display().to_string(), &text)
+    }
+
+    /// Parse script text; `name` is used in error messages.
+    pub fn parse(name: &str, text: &str) -> Result<Self, String> {
+        let mut script = Self {
+            name: name.to_owned(),
+            steps: Vec::new(),
+        };
+        let mut default_timeout = DEFAULT_EXPECT_TIMEOUT;
+        for (index, raw) in text.lines().enumerate() {
+            let line = index + 1;
+            let content = strip_comment(raw).trim();
+            if content.is_empty() {
+                continue;
+            }
+            let (keyword, rest) = content.split_once(char::is_whitespace).unwrap_or((content, ""));
+            let rest = rest.trim();
+            let error = |message: String| format!("{name}:{line}: {message}");
+            let step = match keyword {
+                "timeout" => {
+                    default_timeout = parse_seconds(rest).ok_or_else(|| error(format!("bad timeout \"{rest}\"")))?;
+                    continue;
+                }
+                "send" | "sendline" => {
+                    let (mut bytes, tail) = parse_string(rest).map_err(&error)?;
+                    if !tail.trim().is_empty() {
+                        return Err(error(format!("unexpected \"{}\" after string", tail.trim())));
+                    }
+                    if keyword == "sendline" {
+                        bytes.push(b'\r');
+                    }
+                    Step::Send(bytes)
+                }
+                "ctrl" => match rest.as_bytes() {
+                    [letter] if letter.is_ascii_alphabetic() || b"@[\\]^_".contains(letter) => Step::Send(vec![letter.to_ascii_uppercase() & 0x1F]),
+                    _ => return Err(error(format!("ctrl needs one letter, got \"{rest}\""))),
+                },
+                "byte" => Step::Send(vec![parse_byte(rest).ok_or_else(|| error(format!("bad byte \"{rest}\"")))?]),
+                "switches" => Step::Switches(parse_switches(rest).map_err(error)?),
+                "expect" => {
+                    let (target, tail) = if let Some(tail) = rest.strip_prefix("halt") {
+                        (None, tail)
+                    } else if rest.starts_with('"') {
+                        let (bytes, tail) = parse_string(rest).map_err(&error)?;
+                        (Some(Pattern::Literal(String::from_utf8_lossy(&bytes).into_owned())), tail)
+                    } else if let Some(body) = rest.strip_prefix('/') {
+                        let end = body.rfind('/').ok_or_else(|| error("regex needs a closing /".to_owned()))?;
+                        let regex = Regex::new(&body[..end]).map_err(|err| error(format!("bad regex: {err}")))?;
+                        (Some(Pattern::Regex(regex)), &body[end + 1..])
+                    } else {
+                        return Err(error(format!("expect needs \"text\", /regex/ or halt, got \"{rest}\"")));
+                    };
+                    let tail = tail.trim();
+                    let timeout = if tail.is_empty() {
+                        default_timeout
+                    } else {
+                        parse_seconds(tail).ok_or_else(|| error(format!("bad timeout \"{tail}\"")))?
+                    };
+                    match target {
+                        Some(pattern) => Step::Expect { pattern, timeout },
+                        None => Step::ExpectHalt { timeout },
+                    }
+                }
+                _ => return Err(error(format!("unknown step \"{keyword}\""))),
+            };
+            script.steps.push((line, step));
+        }
+        Ok(script)
+    }
+}
+
+/// `line` without a trailing `#` comment (a `#` inside quotes or a regex is kept).
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (index, ch) in line.char_indices() {
+        match (quote, ch) {
+            _ if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(open), _) if ch == open => quote = None,
+            (None, '"' | '/') => quote = Some(ch),
+            (None, '#') => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Seconds (fractions allowed) as a duration.
+fn parse_seconds(text: &str) -> Option<Duration> {
+    text.parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+/// `0xNN` or decimal byte.
+fn parse_byte(text: &str) -> Option<u8> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Parse a leading `"…"` string with escapes; returns its bytes and the rest of the line.
+fn parse_string(text: &str) -> Result<(Vec<u8>, &str), String> {
+    let body = text.strip_prefix('"').ok_or_else(|| format!("expected a \"string\", got \"{text}\""))?;
+    match unescape_until(body, Some('"'))? {
+        (bytes, Some(end)) => Ok((bytes, &body[end + 1..])),
+        (_, None) => Err("unterminated string".to_owned()),
+    }
+}
+
+/// Bytes of `text` with the script string escapes (`\n`, `\xHH`, …) applied.
+pub fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    unescape_until(text, None).map(|(bytes, _)| bytes)
+}
+
+/// Unescape up to an unescaped `terminator`; returns the bytes and the terminator's index.
+fn unescape_until(text: &str, terminator: Option<char>) -> Result<(Vec<u8>, Option<usize>), String> {
+    let mut bytes = Vec::new();
+    let mut chars = text.char_indices();
+    while let Some((index, ch)) = chars.next() {
+        match ch {
+            _ if Some(ch) == terminator => return Ok((bytes, Some(index))),
+            '\\' => {
+                let escape = match chars.next().map(|(_, escape)| escape) {
+                    Some('n') => b'\n',
+                    Some('r') => b'\r',
+                    Some('t') => b'\t',
+                    Some('0') => 0,
+                    Some('\\') => b'\\',
+                    Some('"') => b'"',
+                    Some('x') => {
+                        let hex: String = chars.by_ref().take(2).map(|(_, digit)| digit).collect();
+                        u8::from_str_radix(&hex, 16).map_err(|_| format!("bad \\x escape \"\\x{hex}\""))?
+                    }
+                    other => return Err(format!("unknown escape \"\\{}\"", other.unwrap_or(' '))),
+                };
+                bytes.push(escape);
+            }
+            _ => bytes.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Ok((bytes, None))
+}
+
+/// What a target produced while the runner waited.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TargetEvent {
+    /// UART output.
+    Output(Vec<u8>),
+    /// Nothing yet.
+    Idle,
+    /// The program is blocked reading input (emulator only).
+    WaitingForInput,
+    /// The program halted.
+    Halted,
+    /// The program stopped for another reason and will not produce more output.
+    Stopped(String),
+}
+
+/// A running program a script can talk to.
+pub trait ScriptTarget {
+    /// Send bytes to the program's UART.
+    fn send(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// Wait up to `wait` for something to happen.
+    fn receive(&mut self, wait: Duration) -> io::Result<TargetEvent>;
+
+    /// Set the switch word the program reads with SWR.
+    fn set_switches(&mut self, _value: u64) -> io::Result<()> {
+        Err(io::Error::new(ErrorKind::Unsupported, "switches can only be set in the emulator"))
+    }
+}
+
+/// A board (or anything speaking its UART) behind a [`Transport`].
+pub struct TransportTarget {
+    /// The connection.
+    port: Box<dyn Transport>,
+    /// The halt marker has been seen.
+    halted: bool,
+}
+
+impl TransportTarget {
+    /// Talk to the program behind `port`.
+    #[must_use]
+    pub fn new(port: Box<dyn Transport>) -> Self {
+        Self { port, halted: false }
+    }
+}
+
+impl ScriptTarget for TransportTarget {
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.port.write_all(data)?;
+        self.port.flush()
+    }
+
+    fn receive(&mut self, wait: Duration) -> io::Result<TargetEvent> {
+        if self.halted {
+            return Ok(TargetEvent::Halted);
+        }
+        self.port.set_timeout(wait.clamp(Duration::from_millis(1), READ_POLL))?;
+        let mut buffer = [0_u8; 1024];
+        match self.port.read(&mut buffer) {
+            Ok(0) => Ok(TargetEvent::Stopped("connection closed".to_owned())),
+            Ok(count) => {
+                let data = &buffer[..count];
+                // 0x00 never appears in program output: it is the halt marker.
+                let end = data.iter().position(|&byte| byte == 0x00).unwrap_or(count);
+                self.halted = end < count;
+                if end == 0 {
+                    return Ok(TargetEvent::Halted);
+                }
+                Ok(TargetEvent::Output(data[..end].to_vec()))
+            }
+            Err(err) if matches!(err.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => Ok(TargetEvent::Idle),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// A program running in the emulator.
+pub struct EmulatorTarget {
+    /// The machine.
+    cpu: Cpu,
+    /// Instructions left before the run counts as stopped.
+    remaining: u64,
+    /// Final event once the program has stopped.
+    ended: Option<TargetEvent>,
+}
+
+impl EmulatorTarget {
+    /// Run `image` from `entry` for at most `max_instructions`.
+    #[must_use]
+    pub fn new(image: &[u8], entry: u32, max_instructions: u64) -> Self {
+        Self {
+            cpu: Cpu::new(image, entry),
+            remaining: max_instructions,
+            ended: None,
+        }
+    }
+
+    /// The machine, for its state once the script has run.
+    #[must_use]
+    pub const fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+}
+
+impl ScriptTarget for EmulatorTarget {
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.cpu.push_uart_input(data);
+        Ok(())
+    }
+
+    fn set_switches(&mut self, value: u64) -> io::Result<()> {
+        self.cpu.set_switches(value);
+        Ok(())
+    }
+
+    fn receive(&mut self, wait: Duration) -> io::Result<TargetEvent> {
+        let deadline = Instant::now() + wait;
+        while self.ended.is_none() {
+            let result = self.cpu.run(EMULATOR_SLICE.min(self.remaining), None);
+            self.remaining -= result.instructions;
+            let waiting = match result.stop {
+                StopReason::InstructionCap if self.remaining > 0 => false,
+                StopReason::WaitingForInput => true,
+                StopReason::Halt => {
+                    self.ended = Some(TargetEvent::Halted);
+                    false
+                }
+                stop => {
+                    self.ended = Some(TargetEvent::Stopped(format!("emulator stopped: {stop:?}")));
+                    false
+                }
+            };
+            if !result.uart.is_empty() {
+                return Ok(TargetEvent::Output(uart_bytes(&result.uart)));
+            }
+            if waiting {
+                return Ok(TargetEvent::WaitingForInput);
+            }
+            if Instant::now() >= deadline {
+                return Ok(TargetEvent::Idle);
+            }
+        }
+        Ok(self.ended.clone().unwrap_or(TargetEvent::Idle))
+    }
+}
+
+/// Run `script` against `target`, passing all output to `on_output` as it arrives.
+///
+/// Returns the number of steps run, or the first failure.
+pub fn run_script(script: &Script, target: &mut dyn ScriptTarget, mut on_output: impl FnMut(&[u8])) -> Result<usize, ScriptFailure> {
+    let mut unmatched = String::new();
+    for (line, step) in &script.steps {
+        let line = *line;
+        let fail = |message: String, unmatched: &str| ScriptFailure {
+            line,
+            message,
+            unmatched: unmatched.to_owned(),
+        };
+        let (pattern, timeout) = match step {
+            Step::Send(bytes) => {
+                target.send(bytes).map_err(|err| fail(format!("send failed: {err}"), &unmatched))?;
+                continue;
+            }
+            Step::Switches(value) => {
+                target
+                    .set_switches(*value)
+                    .map_err(|err| fail(format!("switches failed: {err}"), &unmatched))?;
+                continue;
+            }
+            Step::Expect { pattern, timeout } => (Some(pattern), *timeout),
+            Step::ExpectHalt { timeout } => (None, *timeout),
+        };
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some((_, end)) = pattern.and_then(|pattern| pattern.find(&unmatched)) {
+                unmatched.drain(..end);
+                break;
+            }
+            let wait = deadline.saturating_duration_since(Instant::now());
+            let what = pattern.map_or_else(|| "halt".to_owned(), ToString::to_string);
+            if wait.is_zero() {
+                return Err(fail(
+                    format!("timed out after {:.1}s waiting for {what}", timeout.as_secs_f64()),
+                    &unmatched,
+                ));
+            }
+            match target.receive(wait).map_err(|err| fail(format!("receive failed: {err}"), &unmatched))? {
+                TargetEvent::Output(data) => {
+                    on_output(&data);
+                    unmatched.push_str(&String::from_utf8_lossy(&data));
+                }
+                TargetEvent::Idle => {}
+                TargetEvent::Halted if pattern.is_none() => break,
+                TargetEvent::Halted => return Err(fail(format!("program halted while waiting for {what}"), &unmatched)),
+                TargetEvent::WaitingForInput => return Err(fail(format!("program is waiting for input while waiting for {what}"), &unmatched)),
+                TargetEvent::Stopped(reason) => return Err(fail(format!("{reason} while waiting for {what}"), &unmatched)),
+            }
+        }
+    }
+    Ok(script.steps.len())
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+    use crate::helper::build_ddr_image;
+    use std::collections::VecDeque;
+
+    /// Echo two bytes as hex: RXRB A ; TXR A ; NEWLINE ; RXRB B ; TXR B ; HALT.
+    fn echo_image() -> Vec<u8> {
+        let words: [u32; 6] = [0x5050, 0x5010, 0x5001, 0x5051, 0x5011, 0xF011];
+        build_ddr_image(&words.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>())
+    }
+
+    /// Replays canned events and records what the script sent.
+    struct CannedTarget {
+        /// Events still to report; `Idle` once they run out.
+        events: VecDeque<TargetEvent>,
+        /// Everything the script sent.
+        sent: Vec<u8>,
+    }
+
+    impl ScriptTarget for CannedTarget {
+        fn send(&mut self, data: &[u8]) -> io::Result<()> {
+            self.sent.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn receive(&mut self, wait: Duration) -> io::Result<TargetEvent> {
+            Ok(self.events.pop_front().unwrap_or_else(|| {
+                std::thread::sleep(wait.min(Duration::from_millis(10)));
+                TargetEvent::Idle
+            }))
+        }
+    }
+
+    #[test]
+    // Test the runner matches output split across reads, sends input and sees the halt, or times out
+    fn test_script_on_canned_target() {
+        let events = [
+            TargetEvent::Output(b"Na".to_vec()),
+            TargetEvent::Output(b"me? ".to_vec()),
+            TargetEvent::Halted,
+        ];
+        let mut target = CannedTarget {
+            events: events.into(),
+            sent: Vec::new(),
+        };
+        let script = Script::parse("t", "expect \"Name?\"\nsendline \"Bo\"\nexpect halt").unwrap();
+        assert_eq!(run_script(&script, &mut target, |_| {}).unwrap(), 3);
+        assert_eq!(target.sent, b"Bo\r");
+
+        let mut target = CannedTarget {
+            events: VecDeque::new(),
+            sent: Vec::new(),
+        };
+        let script = Script::parse("t", "expect \"never\" 0.05").unwrap();
+        let failure = run_script(&script, &mut target, |_| {}).unwrap_err();
+        assert!(failure.message.contains("timed out"), "{}", failure.message);
+    }
+
+    #[test]
+    // Test every step kind parses, with escapes, comments and timeouts
+    fn test_parse_script() {
+        let script = Script::parse(
+            "t.kexp",
+            "# header\ntimeout 2\nexpect \"a # b\"  # comment\nsendline \"hi\\x41\\n\"\nctrl c\nbyte 0x1b\nexpect /^ok\\s+$/ 0.5\nexpect halt\n",
+        )
+        .unwrap();
+        assert_eq!(script.steps.len(), 6);
+        assert!(
+            matches!(&script.steps[0], (3, Step::Expect { pattern: Pattern::Literal(text), timeout }) if text == "a # b" && timeout.as_secs() == 2)
+        );
+        assert!(matches!(&script.steps[1], (4, Step::Send(bytes)) if bytes == b"hiA\n\r"));
+        assert!(matches!(&script.steps[2], (5, Step::Send(bytes)) if bytes == &[0x03]));
+        assert!(matches!(&script.steps[3], (6, Step::Send(bytes)) if bytes == &[0x1B]));
+        assert!(matches!(&script.steps[4], (7, Step::Expect { pattern: Pattern::Regex(_), timeout }) if timeout.as_millis() == 500));
+        assert!(matches!(&script.steps[5], (8, Step::ExpectHalt { timeout }) if timeout.as_secs() == 2));
+    }
+
+    #[test]
+    // Test parse errors name the file and line
+    fn test_parse_errors() {
+        assert_eq!(Script::parse("t", "\nfrobnicate").unwrap_err(), "t:2: unknown step \"frobnicate\"");
+        assert!(Script::parse("t", "send \"open").unwrap_err().contains("unterminated"));
+        assert!(Script::parse("t", "expect /(/").unwrap_err().contains("bad regex"));
+        assert!(Script::parse("t", "ctrl CC").is_err());
+        assert!(Script::parse("t", "byte 0x100").is_err());
+        assert_eq!(unescape("a\\r\\x41\"").unwrap(), b"a\rA\"");
+    }
+
+    #[test]
+    // Test a script drives the emulator through input, output and halt
+    fn test_script_on_emulator() {
+        let script = Script::parse("t", "send \"A\"\nexpect /0+41\\n/\nsend \"B\"\nexpect \"42\"\nexpect halt").unwrap();
+        let mut output = Vec::new();
+        let steps = run_script(&script, &mut EmulatorTarget::new(&echo_image(), 0x20, 1000), |data| {
+            output.extend_from_slice(data);
+        })
+        .unwrap();
+        assert_eq!(steps, 5);
+        assert_eq!(output, b"0000000000000041\n\r0000000000000042");
+    }
+
+    #[test]
+    // Test an expect fails fast when the emulated program is blocked on input, and on halt
+    fn test_script_failures_on_emulator() {
+        let script = Script::parse("t", "expect \"x\" 60").unwrap();
+        let failure = run_script(&script, &mut EmulatorTarget::new(&echo_image(), 0x20, 1000), |_| {}).unwrap_err();
+        assert_eq!(failure.line, 1);
+        assert!(failure.message.contains("waiting for input"), "{}", failure.message);
+
+        let script = Script::parse("t", "send \"AB\"\nexpect \"nope\"").unwrap();
+        let failure = run_script(&script, &mut EmulatorTarget::new(&echo_image(), 0x20, 1000), |_| {}).unwrap_err();
+        assert!(failure.message.contains("halted"), "{}", failure.message);
+        assert!(failure.unmatched.ends_with("0000000000000042"));
+    }
+
+    #[test]
+    // Test a switches step sets what SWR reads, and parses like --switches
+    fn test_script_switches() {
+        // RXRB A ; SWR B ; TXR B ; HALT — the switches are set while RXRB waits.
+        let words: [u32; 4] = [0x5050, 0x3011, 0x5011, 0xF011];
+        let image = build_ddr_image(&words.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>());
+        let script = Script::parse("t", "switches 0b1010\nsend \"x\"\nexpect \"000000000000000A\"\nexpect halt").unwrap();
+        let mut target = EmulatorTarget::new(&image, 0x20, 1000);
+        assert_eq!(run_script(&script, &mut target, |_| {}), Ok(4));
+        assert_eq!(target.cpu().peripherals().switches(), 0b1010);
+        assert!(Script::parse("t", "switches 0xZZ").unwrap_err().starts_with("t:1: bad switch value"));
+    }
+}
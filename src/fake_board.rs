@@ -0,0 +1,360 @@
+//! Virtual board: the UART `.kbt` loader backed by the emulator.
+//!
+//! [`serve_board`] speaks the board's side of the serial protocol over any
+//! [`Transport`], so `-s`, `-m`, `-T` and `-L` can be exercised without
+//! hardware:
+//!
+//! 1. `S` (or a break, which reads as `0x00`) resets the loader; the `S` also
+//!    starts the `S…Z…X` frame.
+//! 2. On `X` the frame is decoded with `parse_kbt` and its checksum checked
+//!    with the FPGA formula (`calc_checksum`).
+//! 3. The board replies [`LOAD_ACK`] (or [`CHECKSUM_NAK`]) and runs the image
+//!    in `emulate::Cpu`, streaming UART output as it is produced.
+//! 4. On HALT it sends `0x00`, which `monitor_serial_port` takes as a halt.
+//!
+//! A reset received while a program runs abandons it and starts a new load;
+//! any other byte is queued as UART input for the program (`RXRB` / `RXRNB`).
+
+use crate::emulate::{Cpu, StopReason};
+use crate::kbt::{parse_kbt, KbtImage};
+use crate::messages::{MessageType, MsgList};
+use crate::transport::Transport;
+use std::io::{self, ErrorKind};
+use std::time::Duration;
+
+/// Acknowledgement sent after a frame with a good checksum.
+pub const LOAD_ACK: &str = "Load Complete OK\r\n";
+
+/// Reply to a frame whose checksum does not match.
+pub const CHECKSUM_NAK: &str = "Load Checksum Error\r\n";
+
+/// Reply to a frame that cannot be decoded.
+pub const FRAME_NAK: &str = "Load Frame Error\r\n";
+
+/// Instructions run between UART flushes and reset checks.
+const RUN_SLICE: u64 = 100_000;
+
+/// Pause between the acknowledgement and the first program output.  The host
+/// reads the acknowledgement with a single `read`, as it does from the real
+/// board, so UART output arriving in the same read would be lost.
+const ACK_SETTLE: Duration = Duration::from_millis(200);
+
+/// Read timeout while waiting for a load.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Read timeout for the reset check between run slices.
+const RUN_POLL_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Something the loader recognised in the incoming byte stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoaderEvent {
+    /// Break or `S`: the board resets.
+    Reset,
+    /// A complete frame (the checksum may still be wrong).
+    Loaded(KbtImage),
+    /// A complete frame that could not be decoded.
+    Rejected(String),
+}
+
+/// Byte-at-a-time `S…Z…X` frame assembler.
+#[derive(Debug, Default)]
+pub struct KbtLoader {
+    /// Frame collected since the last `S`, or `None` while waiting for one.
+    frame: Option<String>,
+}
+
+impl KbtLoader {
+    /// Feed one received byte.
+    pub fn feed(&mut self, byte: u8) -> Option<LoaderEvent> {
+        match byte {
+            0x00 => {
+                self.frame = None;
+                Some(LoaderEvent::Reset)
+            }
+            b'S' => {
+                self.frame = Some("S".to_owned());
+                Some(LoaderEvent::Reset)
+            }
+            _ if byte.is_ascii_whitespace() => None,
+            _ => {
+                let frame = self.frame.as_mut()?;
+                frame.push(char::from(byte));
+                if byte != b'X' {
+                    return None;
+                }
+                let text = self.frame.take().unwrap_or_default();
+                Some(parse_kbt(&text).map_or_else(LoaderEvent::Rejected, LoaderEvent::Loaded))
+            }
+        }
+    }
+}
+
+/// UART text from the emulator as the bytes the board would send.
+#[must_use]
+pub fn uart_bytes(uart: &str) -> Vec<u8> {
+    uart.chars().map(|ch| ch as u8).collect()
+}
+
+/// Serve one connection until the peer disconnects.
+///
+/// Progress (resets, loads, halts) is reported through `msg_list`; set it
+/// live to see it as it happens.  Returns `Ok` when the peer closes the
+/// connection and `Err` on any other transport error.
+pub fn serve_board(transport: &mut dyn Transport, max_instructions: u64, msg_list: &mut MsgList) -> io::Result<()> {
+    match serve_loop(transport, max_instructions, msg_list) {
+        Err(err) if is_disconnect(&err) => Ok(()),
+        result => result,
+    }
+}
+
+/// The loader loop behind [`serve_board`]; ends only with an error.
+fn serve_loop(transport: &mut dyn Transport, max_instructions: u64, msg_list: &mut MsgList) -> io::Result<()> {
+    let mut loader = KbtLoader::default();
+    let mut pending: Vec<u8> = Vec::new();
+    let mut buffer = [0_u8; 4096];
+    transport.set_timeout(IDLE_TIMEOUT)?;
+    loop {
+        if pending.is_empty() {
+            match transport.read(&mut buffer) {
+                Ok(count) => pending.extend_from_slice(&buffer[..count]),
+                Err(err) if err.kind() == ErrorKind::TimedOut => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        let bytes = std::mem::take(&mut pending);
+        for (index, byte) in bytes.iter().enumerate() {
+            match loader.feed(*byte) {
+                None | Some(LoaderEvent::Reset) => {}
+                Some(LoaderEvent::Rejected(err)) => {
+                    msg_list.push(format!("Rejected load: {err}"), None, None, MessageType::Warning);
+                    transport.write_all(FRAME_NAK.as_bytes())?;
+                }
+                Some(LoaderEvent::Loaded(image)) if !image.checksum_ok() => {
+                    msg_list.push(
+                        format!(
+                            "Rejected load: checksum 0x{:08X}, expected 0x{:08X}",
+                            image.checksum, image.computed_checksum
+                        ),
+                        None,
+                        None,
+                        MessageType::Warning,
+                    );
+                    transport.write_all(CHECKSUM_NAK.as_bytes())?;
+                }
+                Some(LoaderEvent::Loaded(image)) => {
+                    msg_list.push(
+                        format!("Loaded {} bytes, entry 0x{:08X}", image.code.len(), image.entry),
+                        None,
+                        None,
+                        MessageType::Information,
+                    );
+                    transport.write_all(LOAD_ACK.as_bytes())?;
+                    transport.flush()?;
+                    std::thread::sleep(ACK_SETTLE);
+                    // Anything after the frame belongs to the running program's reset check.
+                    let mut after: Vec<u8> = bytes[index + 1..].to_vec();
+                    pending = run_image(transport, &image, max_instructions, &mut after, msg_list)?;
+                    transport.set_timeout(IDLE_TIMEOUT)?;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// True for errors that mean the other end went away.
+fn is_disconnect(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::UnexpectedEof | ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+    )
+}
+
+/// Run a loaded image, streaming UART output, until it stops or a reset arrives.
+///
+/// Returns the received bytes from the reset onward (empty if none), so the
+/// caller's loader sees the `S` that starts the next frame.
+fn run_image(
+    transport: &mut dyn Transport,
+    image: &KbtImage,
+    max_instructions: u64,
+    received: &mut Vec<u8>,
+    msg_list: &mut MsgList,
+) -> io::Result<Vec<u8>> {
+    let mut cpu = Cpu::new(&image.memory_image(), image.entry);
+    let mut retired: u64 = 0;
+    let mut buffer = [0_u8; 4096];
+    transport.set_timeout(RUN_POLL_TIMEOUT)?;
+    loop {
+        if let Some(reset) = received.iter().position(|byte| *byte == b'S' || *byte == 0x00) {
+            msg_list.push(format!("Reset after {retired} instructions"), None, None, MessageType::Information);
+            return Ok(received.split_off(reset));
+        }
+        // Anything else is input for the program's UART.
+        cpu.push_uart_input(received);
+        received.clear();
+
+        let slice = RUN_SLICE.min(max_instructions - retired);
+        let result = cpu.run(slice, None);
+        retired += result.instructions;
+        transport.write_all(&uart_bytes(&result.uart))?;
+        match result.stop {
+            StopReason::InstructionCap if retired < max_instructions => {}
+            StopReason::WaitingForInput => {}
+            StopReason::Halt => {
+                transport.write_all(&[0x00])?;
+                transport.flush()?;
+                msg_list.push(format!("HALT after {retired} instructions"), None, None, MessageType::Information);
+                return Ok(Vec::new());
+            }
+            stop => {
+                transport.flush()?;
+                msg_list.push(
+                    format!("Stopped after {retired} instructions: {stop:?}"),
+                    None,
+                    None,
+                    MessageType::Warning,
+                );
+                return Ok(Vec::new());
+            }
+        }
+
+        match transport.read(&mut buffer) {
+            Ok(count) => received.extend_from_slice(&buffer[..count]),
+            Err(err) if err.kind() == ErrorKind::TimedOut => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+    use crate::files::LineType;
+    use crate::helper::create_bin_string;
+    use crate::serial::{monitor_serial_port, run_test_monitor, write_to_board_keep_port};
+    use crate::test_support::pass2_line;
+    use crate::transport::TcpTransport;
+    use std::io::Read as _;
+    use std::io::Write as _;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// `SETR A 0x41`, `TXR A`, `NEWLINE`, `HALT` as a kbt frame.
+    fn sample_kbt() -> String {
+        let pass2 = vec![
+            pass2_line(LineType::Start, "", 0x20),
+            pass2_line(LineType::Opcode, "0000080000000041", 0x20),
+            pass2_line(LineType::Opcode, "00005010", 0x28),
+            pass2_line(LineType::Opcode, "00005001", 0x2C),
+            pass2_line(LineType::Opcode, "0000F011", 0x30),
+        ];
+        create_bin_string(&pass2, &mut MsgList::new()).unwrap()
+    }
+
+    #[test]
+    // Test the loader resets on S or break and decodes a frame on X
+    fn test_kbt_loader() {
+        let kbt = sample_kbt();
+        let mut loader = KbtLoader::default();
+        assert_eq!(loader.feed(b'1'), None, "bytes before S are ignored");
+        assert_eq!(loader.feed(0x00), Some(LoaderEvent::Reset));
+        let mut events: Vec<LoaderEvent> = format!("S{kbt}\r\n").bytes().filter_map(|byte| loader.feed(byte)).collect();
+        assert_eq!(events.len(), 3, "reset S, frame S, frame");
+        let Some(LoaderEvent::Loaded(image)) = events.pop() else {
+            panic!("expected a loaded image");
+        };
+        assert!(image.checksum_ok());
+        assert_eq!(image.entry, 0x20);
+
+        let events: Vec<LoaderEvent> = "S0Z0X".bytes().filter_map(|byte| loader.feed(byte)).collect();
+        assert!(matches!(events.last(), Some(LoaderEvent::Rejected(_))));
+    }
+
+    #[test]
+    // Test load, UART streaming and the 0x00 halt marker through the real host-side flows
+    fn test_serve_board_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port_name = format!("tcp://{}", listener.local_addr().unwrap());
+        let board = thread::spawn(move || {
+            let mut msg_list = MsgList::new();
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut transport = TcpTransport::from_stream(stream).unwrap();
+                serve_board(&mut transport, 1_000, &mut msg_list).unwrap();
+            }
+            msg_list
+        });
+
+        let kbt = sample_kbt();
+        let mut msg_list = MsgList::new();
+        let port = write_to_board_keep_port(&kbt, &port_name, true, true, &mut msg_list).unwrap();
+        monitor_serial_port(port, &crate::serial::MonitorOptions::default(), &mut msg_list).unwrap();
+
+        let port = write_to_board_keep_port(&kbt, &port_name, false, false, &mut msg_list).unwrap();
+        let result = run_test_monitor(port, &["00000000".to_owned()], 5, &mut msg_list);
+        assert_eq!((result.passed, result.timed_out), (1, false));
+        assert!(msg_list.list.iter().any(|msg| msg.text.contains("Load Complete OK")));
+
+        let board_msgs = board.join().unwrap();
+        let texts: Vec<&str> = board_msgs.list.iter().map(|msg| msg.text.as_str()).collect();
+        assert_eq!(texts.iter().filter(|text| text.starts_with("Loaded 20 bytes")).count(), 2, "{texts:?}");
+        assert!(texts.iter().any(|text| text.starts_with("HALT after 4 instructions")), "{texts:?}");
+    }
+
+    #[test]
+    // Test an expect script sends input to the running program and sees its echo and halt
+    fn test_script_against_fake_board() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port_name = format!("tcp://{}", listener.local_addr().unwrap());
+        let board = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut transport = TcpTransport::from_stream(stream).unwrap();
+            serve_board(&mut transport, 1_000, &mut MsgList::new()).unwrap();
+        });
+
+        // RXRB A ; TXR A ; HALT
+        let pass2 = vec![
+            pass2_line(LineType::Start, "", 0x20),
+            pass2_line(LineType::Opcode, "00005050", 0x20),
+            pass2_line(LineType::Opcode, "00005010", 0x24),
+            pass2_line(LineType::Opcode, "0000F011", 0x28),
+        ];
+        let kbt = create_bin_string(&pass2, &mut MsgList::new()).unwrap();
+        let options = crate::serial::MonitorOptions {
+            script: Some(crate::expect::Script::parse("echo", "send \"Z\"\nexpect \"5A\" 5\nexpect halt 5").unwrap()),
+            ..crate::serial::MonitorOptions::default()
+        };
+        let mut msg_list = MsgList::new();
+        let port = write_to_board_keep_port(&kbt, &port_name, true, true, &mut msg_list).unwrap();
+        let outcome = monitor_serial_port(port, &options, &mut msg_list).unwrap();
+        assert_eq!(outcome, crate::serial::MonitorOutcome::ScriptPassed(3));
+        board.join().unwrap();
+    }
+
+    #[test]
+    // Test a corrupted frame is answered with the checksum error and not run
+    fn test_serve_board_checksum_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let board = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut transport = TcpTransport::from_stream(stream).unwrap();
+            let mut msg_list = MsgList::new();
+            serve_board(&mut transport, 1_000, &mut msg_list).unwrap();
+            msg_list
+        });
+
+        let bad = sample_kbt().replacen("41000000", "42000000", 1);
+        let mut client = TcpTransport::connect(&address).unwrap();
+        client.write_all(bad.as_bytes()).unwrap();
+        client.set_timeout(Duration::from_secs(2)).unwrap();
+        let mut reply = vec![0_u8; CHECKSUM_NAK.len()];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(reply, CHECKSUM_NAK.as_bytes());
+        drop(client);
+        let board_msgs = board.join().unwrap();
+        assert!(board_msgs.list.iter().any(|msg| msg.text.contains("Rejected load: checksum")));
+    }
+}
@@ -2,13 +2,14 @@ use crate::helper::strip_comments;
 use crate::macros::Macro;
 use crate::messages::{MessageType, MsgList};
 use crate::opcodes::{InputData, Opcode, Pass2};
+use std::fmt::Write as _;
 use std::io::Error;
 
 use std::ffi::OsStr;
 use std::{
     fs::File,
     io::{prelude::*, BufReader},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -304,6 +305,18 @@ fn output_opcodes_textmate(filename_stem: String, opcodes: &[Opcode], msg_list:
 ///
 /// Reads any given file by filename, adding the fill line by line into vector and returns None or Some(String). Manages included files.
 pub fn read_file_to_vector(filename: &str, msg_list: &mut MsgList, opened_files: &mut Vec<String>) -> Option<Vec<InputData>> {
+    read_file_with_includes(filename, &[], msg_list, opened_files)
+}
+
+/// Open text file and return as vector of strings, searching `include_paths` for `!include` files.
+///
+/// An `!include` is resolved against the including file's directory first, then each include path in order.
+pub fn read_file_with_includes(
+    filename: &str,
+    include_paths: &[PathBuf],
+    msg_list: &mut MsgList,
+    opened_files: &mut Vec<String>,
+) -> Option<Vec<InputData>> {
     let file_result = File::open(filename);
     if file_result.is_err() {
         msg_list.push(format!("Unable to open file {filename}"), None, None, MessageType::Error);
@@ -320,12 +333,74 @@ pub fn read_file_to_vector(filename: &str, msg_list: &mut MsgList, opened_files:
     }
 
     opened_files.push(filename.to_owned());
+    let lines = lines_to_vector(filename, BufReader::new(file).lines(), include_paths, msg_list, opened_files);
+    opened_files.pop();
+    lines
+}
+
+/// Split source text held in memory into a vector of lines, expanding `!include` files.
+///
+/// `name` stands in for the file name in messages and relative includes.
+pub fn read_text_to_vector(
+    name: &str,
+    text: &str,
+    include_paths: &[PathBuf],
+    msg_list: &mut MsgList,
+    opened_files: &mut Vec<String>,
+) -> Option<Vec<InputData>> {
+    opened_files.push(name.to_owned());
+    let lines = lines_to_vector(name, text.lines().map(|line| Ok(line.to_owned())), include_paths, msg_list, opened_files);
+    opened_files.pop();
+    lines
+}
+
+/// Return the path of an `!include` file.
+///
+/// Tries the directory of the including file, then each include path; falls back to the first.
+fn resolve_include(filename: &str, include_file: &str, include_paths: &[PathBuf]) -> String {
+    let parent = Path::new(filename).parent().unwrap_or_else(|| Path::new(""));
+    let local = parent.join(include_file);
+    if local.exists() {
+        return local.to_string_lossy().into_owned();
+    }
+    include_paths
+        .iter()
+        .map(|dir| dir.join(include_file))
+        .find(|path| path.exists())
+        .unwrap_or(local)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Return every path the `!include` files of `filename` are looked for at.
+///
+/// For each include, the including file's directory and then each include
+/// path, whether or not the file exists there yet.
+pub fn include_candidates(filename: &str, include_paths: &[PathBuf]) -> Vec<String> {
+    let Ok(text) = std::fs::read_to_string(filename) else {
+        return Vec::new();
+    };
+    let parent = Path::new(filename).parent().unwrap_or_else(|| Path::new(""));
+    text.lines()
+        .filter_map(get_include_filename)
+        .filter(|include_file| !include_file.is_empty())
+        .flat_map(|include_file| std::iter::once(parent.join(&include_file)).chain(include_paths.iter().map(move |dir| dir.join(&include_file))))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect()
+}
 
-    let buf = BufReader::new(file);
+/// Collect lines of one source into `InputData`, recursing into `!include` files.
+fn lines_to_vector(
+    filename: &str,
+    source_lines: impl Iterator<Item = Result<String, Error>>,
+    include_paths: &[PathBuf],
+    msg_list: &mut MsgList,
+    opened_files: &mut Vec<String>,
+) -> Option<Vec<InputData>> {
     let mut lines: Vec<InputData> = Vec::new();
 
     let mut line_number = 0;
-    for line in buf.lines() {
+    for line in source_lines {
         match line {
             Ok(line_contents) => {
                 line_number += 1;
@@ -341,11 +416,10 @@ pub fn read_file_to_vector(filename: &str, msg_list: &mut MsgList, opened_files:
                         return None;
                     }
 
-                    // Get the include file from the same directory as the previous file
-                    let parent = Path::new(filename).parent().unwrap_or_else(|| Path::new(""));
-                    let new_include_file = parent.join(include_file.unwrap_or_default()).to_string_lossy().into_owned();
+                    // Get the include file from the same directory as the previous file, or the include paths
+                    let new_include_file = resolve_include(filename, &include_file.unwrap_or_default(), include_paths);
 
-                    let include_lines = read_file_to_vector(&new_include_file, msg_list, opened_files);
+                    let include_lines = read_file_with_includes(&new_include_file, include_paths, msg_list, opened_files);
                     if include_lines.is_none() {
                         msg_list.push(
                             format!("Unable to open include file {new_include_file} in {filename}"),
@@ -377,7 +451,6 @@ pub fn read_file_to_vector(filename: &str, msg_list: &mut MsgList, opened_files:
             ),
         }
     }
-    opened_files.pop();
     Some(lines)
 }
 
@@ -456,20 +529,28 @@ pub fn write_binary_output_file(filename: &impl AsRef<Path>, output_string: &str
 /// Output the code details file to given filename.
 ///
 /// Writes all data to the detailed code file.
-pub fn write_code_output_file(filename: impl AsRef<Path> + Copy, pass2: &mut Vec<Pass2>, msg_list: &mut MsgList) -> Result<(), Error> {
+pub fn write_code_output_file(filename: impl AsRef<Path> + Copy, pass2: &mut [Pass2], msg_list: &mut MsgList) -> Result<(), Error> {
     let mut file = match File::create(filename) {
         Ok(file) => file,
         #[cfg(not(tarpaulin_include))] // Can't test error creating file
         Err(err) => return Err(err),
     };
 
-    let mut out_line = String::default();
     msg_list.push(
         format!("Writing code file to {}", filename.as_ref().display()),
         None,
         None,
         MessageType::Information,
     );
+    file.write_all(code_listing(pass2).as_bytes())
+}
+
+/// Return the code details listing as text.
+///
+/// Heap header words followed by one line per opcode, data word, label, comment or error.
+#[must_use]
+pub fn code_listing(pass2: &[Pass2]) -> String {
+    let mut listing = String::default();
     // Compute heap_start = first free byte after the program
     // program_counter is already a byte address; opcode.len()/8 = words, *4 = bytes → /2 total
     let heap_start: u32 = pass2
@@ -487,48 +568,37 @@ pub fn write_code_output_file(filename: impl AsRef<Path> + Copy, pass2: &mut Vec
         ("0000000000000000".to_owned(), "(reserved)"),
     ];
     for (i, (value, comment)) in heap_header.iter().enumerate() {
-        match file.write_all(format!("0x{:08X}: {value:<33} -- {comment}\n", i * 8).as_bytes()) {
-            Ok(()) => {}
-            #[cfg(not(tarpaulin_include))]
-            Err(err) => return Err(err),
-        }
+        let _ = writeln!(listing, "0x{:08X}: {value:<33} -- {comment}", i * 8);
     }
 
     for pass in pass2 {
-        out_line.clear();
         if pass.line_type == LineType::Opcode {
-            out_line = format!(
-                "0x{:08X}: {:<17} -- {}\n",
+            let _ = writeln!(
+                listing,
+                "0x{:08X}: {:<17} -- {}",
                 pass.program_counter,
                 format_opcodes(&pass.opcode),
                 pass.input_text_line
             );
         } else if pass.line_type == LineType::Data {
             for n in 0..pass.opcode.len() / 16 {
-                out_line.push_str(
-                    format!(
-                        "0x{:08X}: {:<32}  -- {}\n",
-                        pass.program_counter + n as u32 * 8,
-                        &mut pass.opcode.get(n * 16..n * 16 + 16).unwrap_or("                "),
-                        pass.input_text_line
-                    )
-                    .as_str(),
+                let _ = writeln!(
+                    listing,
+                    "0x{:08X}: {:<32}  -- {}",
+                    pass.program_counter + n as u32 * 8,
+                    pass.opcode.get(n * 16..n * 16 + 16).unwrap_or("                "),
+                    pass.input_text_line
                 );
             }
         } else if pass.line_type == LineType::Label {
-            out_line = format!("0x{:08X}:                   -- {}\n", pass.program_counter, pass.input_text_line);
+            let _ = writeln!(listing, "0x{:08X}:                   -- {}", pass.program_counter, pass.input_text_line);
         } else if pass.line_type == LineType::Error {
-            out_line = format!("Error                         -- {}\n", pass.input_text_line);
+            let _ = writeln!(listing, "Error                         -- {}", pass.input_text_line);
         } else {
-            out_line = format!("                              -- {}\n", pass.input_text_line);
-        }
-        match file.write_all(out_line.as_bytes()) {
-            Ok(()) => {}
-            #[cfg(not(tarpaulin_include))] // Can't test error writing to file
-            Err(err) => return Err(err),
+            let _ = writeln!(listing, "                              -- {}", pass.input_text_line);
         }
     }
-    Ok(())
+    listing
 }
 
 #[cfg(test)]
@@ -1024,6 +1094,26 @@ mod test {
         _ = tmp_dir.close();
     }
 
+    #[test]
+    // Test include candidates cover missing files in every search directory
+    fn test_include_candidates() {
+        let tmp_dir = TempDir::new().unwrap();
+        let include_paths = vec![tmp_dir.path().join("lib")];
+        let main_path = tmp_dir.path().join("main.kla");
+        fs::write(&main_path, "!include missing.kla // not written yet\nSETR A 1\n  !include\n").unwrap();
+        let main_name = main_path.to_str().unwrap();
+
+        let candidates = include_candidates(main_name, &include_paths);
+        assert_eq!(
+            candidates,
+            vec![
+                tmp_dir.path().join("missing.kla").to_string_lossy().into_owned(),
+                include_paths[0].join("missing.kla").to_string_lossy().into_owned()
+            ]
+        );
+        assert!(include_candidates(&format!("{main_name}.absent"), &[]).is_empty());
+    }
+
     #[test]
     fn test_write_binary_output_file() {
         let tmp_dir = TempDir::new().unwrap();
@@ -0,0 +1,702 @@
+//! GDB remote serial protocol stub for the emulator (`--emulate --gdb <port>`).
+//!
+//! One GDB connects over TCP and drives a [`Cpu`]: register and memory access,
+//! software breakpoints, single-step and continue.  Registers, in `g` packet
+//! order, all little-endian:
+//!
+//! | Number | Name          | Bits | Notes                                          |
+//! |--------|---------------|------|------------------------------------------------|
+//! | 0–15   | `r0`–`r15`    | 64   | A–P                                            |
+//! | 16     | `sp`          | 32   |                                                |
+//! | 17     | `pc`          | 32   |                                                |
+//! | 18     | `flags`       | 32   | bit 6 zero … bit 0 ult, as in the interrupt slot |
+//!
+//! GDB reads the same layout from the target description
+//! (`qXfer:features:read:target.xml`).  UART output is forwarded as console
+//! (`O`) packets while the program runs, and Ctrl+C in GDB stops it.
+
+use crate::emulate::{Cpu, Flags, StopReason};
+use crate::fake_board::uart_bytes;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind, Read as _, Write as _};
+use std::net::TcpStream;
+
+/// Number of registers GDB knows about.
+const REGISTER_COUNT: usize = 19;
+
+/// `sp` register number.
+const SP_REGISTER: usize = 16;
+
+/// `pc` register number.
+const PC_REGISTER: usize = 17;
+
+/// `flags` register number.
+const FLAGS_REGISTER: usize = 18;
+
+/// Instructions between checks for Ctrl+C and UART output while running.
+const POLL_INTERVAL: u64 = 10_000;
+
+/// Flag names from bit 0 up, as in the target description.
+const FLAG_BITS: [&str; 7] = ["ult", "less", "sign", "overflow", "carry", "equal", "zero"];
+
+/// The target description GDB reads with `qXfer:features:read:target.xml`.
+#[must_use]
+pub fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n  <feature name=\"org.klausscpu.core\">\n",
+    );
+    xml.push_str("    <flags id=\"klauss_flags\" size=\"4\">\n");
+    for (bit, name) in FLAG_BITS.iter().enumerate() {
+        let _ = writeln!(xml, "      <field name=\"{name}\" start=\"{bit}\" end=\"{bit}\"/>");
+    }
+    xml.push_str("    </flags>\n");
+    for number in 0..16 {
+        let _ = writeln!(xml, "    <reg name=\"r{number}\" bitsize=\"64\" type=\"int64\" regnum=\"{number}\"/>");
+    }
+    let _ = writeln!(xml, "    <reg name=\"sp\" bitsize=\"32\" type=\"data_ptr\" regnum=\"{SP_REGISTER}\"/>");
+    let _ = writeln!(xml, "    <reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"{PC_REGISTER}\"/>");
+    let _ = writeln!(
+        xml,
+        "    <reg name=\"flags\" bitsize=\"32\" type=\"klauss_flags\" regnum=\"{FLAGS_REGISTER}\"/>"
+    );
+    xml.push_str("  </feature>\n</target>\n");
+    xml
+}
+
+/// What the stub does with a packet.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Send this reply (empty: not supported).
+    Reply(String),
+    /// Run the program (`step`: one instruction), then send the stop reply.
+    Resume {
+        /// Execute a single instruction.
+        step: bool,
+    },
+    /// Reply `OK`, then stop acknowledging packets (`QStartNoAckMode`).
+    NoAck,
+    /// Reply `OK` and end the session (`D`).
+    Detach,
+    /// End the session without replying (`k`).
+    Kill,
+}
+
+/// Why a resumed program stopped.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// A single step finished.
+    Step,
+    /// A breakpoint was reached.
+    Breakpoint,
+    /// GDB sent Ctrl+C.
+    Interrupted,
+    /// The instruction budget ran out.
+    Budget,
+    /// The machine stopped by itself.
+    Machine(StopReason),
+}
+
+impl Stop {
+    /// The stop reply packet: `W00` when the program halts, otherwise a signal
+    /// chosen to read naturally in GDB (SIGTRAP for steps and breakpoints,
+    /// SIGILL for an invalid opcode, SIGTTIN when it needs UART input, …).
+    #[must_use]
+    pub fn reply(&self) -> String {
+        let signal = match self {
+            Self::Breakpoint => return "T05swbreak:;".to_owned(),
+            Self::Machine(StopReason::Halt) => return "W00".to_owned(),
+            Self::Step => 5,
+            Self::Interrupted | Self::Machine(StopReason::Interrupted) => 2,
+            Self::Budget | Self::Machine(StopReason::InstructionCap) => 24,
+            Self::Machine(StopReason::Trap) => 6,
+            Self::Machine(StopReason::InvalidOpcode(_)) => 4,
+            Self::Machine(StopReason::PcOutOfRange(_)) => 11,
+            Self::Machine(StopReason::WaitingForInput | StopReason::InputExhausted) => 21,
+        };
+        format!("S{signal:02x}")
+    }
+}
+
+/// The emulator side of a GDB session.
+pub struct GdbStub {
+    /// The machine being debugged.
+    cpu: Cpu,
+    /// Software breakpoint addresses.
+    breakpoints: Vec<u32>,
+    /// Most instructions one continue may run.
+    max_instructions: u64,
+    /// Reply to `?`: the last stop.
+    last_stop: String,
+}
+
+impl GdbStub {
+    /// A stub for `cpu`, stopped before its first instruction.
+    #[must_use]
+    pub fn new(cpu: Cpu, max_instructions: u64) -> Self {
+        Self {
+            cpu,
+            breakpoints: Vec::new(),
+            max_instructions,
+            last_stop: Stop::Step.reply(),
+        }
+    }
+
+    /// The machine being debugged.
+    #[must_use]
+    pub const fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// Serve one GDB connection until it detaches, kills or disconnects.
+    pub fn serve(&mut self, stream: TcpStream) -> io::Result<()> {
+        let mut connection = Connection::new(stream)?;
+        while let Some(packet) = connection.read_packet()? {
+            let Some(packet) = packet else {
+                continue; // Ctrl+C while already stopped
+            };
+            match self.command(&packet) {
+                Action::Reply(reply) => connection.send(&reply)?,
+                Action::Resume { step } => {
+                    let stop = self.resume(step, &mut connection)?;
+                    self.last_stop = stop.reply();
+                    connection.send(&self.last_stop)?;
+                }
+                Action::NoAck => {
+                    connection.send("OK")?;
+                    connection.no_ack = true;
+                }
+                Action::Detach => {
+                    connection.send("OK")?;
+                    break;
+                }
+                Action::Kill => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Decide what to do with one packet, carrying out everything but resuming.
+    pub fn command(&mut self, packet: &[u8]) -> Action {
+        // `X` carries binary data, so handle it before treating the packet as text.
+        if let Some(rest) = packet.strip_prefix(b"X") {
+            return Action::Reply(self.write_binary(rest));
+        }
+        let text = String::from_utf8_lossy(packet);
+        let reply = match text.as_ref() {
+            "?" => self.last_stop.clone(),
+            "g" => self.read_registers(),
+            "qAttached" => "1".to_owned(),
+            "qfThreadInfo" => "m1".to_owned(),
+            "qsThreadInfo" => "l".to_owned(),
+            "qC" => "QC1".to_owned(),
+            "vCont?" => "vCont;c;C;s;S".to_owned(),
+            "QStartNoAckMode" => return Action::NoAck,
+            "D" | "D;1" => return Action::Detach,
+            "k" | "vKill;1" => return Action::Kill,
+            _ => return self.command_with_arguments(&text),
+        };
+        Action::Reply(reply)
+    }
+
+    /// Packets that take arguments.
+    fn command_with_arguments(&mut self, text: &str) -> Action {
+        let reply = if text.starts_with("qSupported") {
+            "PacketSize=4000;qXfer:features:read+;swbreak+;QStartNoAckMode+".to_owned()
+        } else if let Some(rest) = text.strip_prefix("qXfer:features:read:target.xml:") {
+            read_chunk(&target_xml(), rest).unwrap_or_else(|| "E01".to_owned())
+        } else if let Some(rest) = text.strip_prefix("vCont;") {
+            // One thread, so the first action decides.
+            return match rest.chars().next() {
+                Some('c' | 'C') => Action::Resume { step: false },
+                Some('s' | 'S') => Action::Resume { step: true },
+                _ => Action::Reply("E01".to_owned()),
+            };
+        } else if let Some(rest) = text.strip_prefix(['c', 's']) {
+            // `c [addr]` / `s [addr]` resume at `addr` if given.
+            if !rest.is_empty() {
+                let Some(address) = parse_hex(rest) else {
+                    return Action::Reply("E01".to_owned());
+                };
+                self.cpu.set_pc(address as u32);
+            }
+            return Action::Resume { step: text.starts_with('s') };
+        } else if text.starts_with(['C', 'S']) {
+            // Signals mean nothing to the emulator: resume as `c` / `s`.
+            return Action::Resume { step: text.starts_with('S') };
+        } else if text.starts_with('H') || text.starts_with('T') {
+            "OK".to_owned()
+        } else if let Some(rest) = text.strip_prefix('G') {
+            self.write_registers(rest)
+        } else if let Some(rest) = text.strip_prefix('p') {
+            parse_hex(rest)
+                .and_then(|number| self.read_register(number as usize))
+                .unwrap_or_else(|| "E01".to_owned())
+        } else if let Some(rest) = text.strip_prefix('P') {
+            rest.split_once('=')
+                .and_then(|(number, value)| self.write_register(parse_hex(number)? as usize, value))
+                .map_or_else(|| "E01".to_owned(), |()| "OK".to_owned())
+        } else if let Some(rest) = text.strip_prefix('m') {
+            self.read_memory(rest).unwrap_or_else(|| "E01".to_owned())
+        } else if let Some(rest) = text.strip_prefix('M') {
+            self.write_memory(rest)
+        } else if let Some(rest) = text.strip_prefix(['Z', 'z']) {
+            self.breakpoint(text.starts_with('Z'), rest)
+        } else {
+            String::new()
+        };
+        Action::Reply(reply)
+    }
+
+    /// Run until a breakpoint, Ctrl+C, the budget or a machine stop; with
+    /// `step`, execute one instruction.
+    pub fn resume(&mut self, step: bool, connection: &mut Connection) -> io::Result<Stop> {
+        // A Ctrl+C that arrived while stopped is stale.
+        connection.interrupt = false;
+        let mut executed: u64 = 0;
+        let outcome = loop {
+            if let Some(reason) = self.cpu.step_instruction(None) {
+                break Stop::Machine(reason);
+            }
+            executed += 1;
+            if step {
+                break Stop::Step;
+            }
+            if self.breakpoints.contains(&self.cpu.pc()) {
+                break Stop::Breakpoint;
+            }
+            if executed >= self.max_instructions {
+                break Stop::Budget;
+            }
+            if executed.is_multiple_of(POLL_INTERVAL) {
+                self.forward_uart(connection)?;
+                if connection.interrupted()? {
+                    break Stop::Interrupted;
+                }
+            }
+        };
+        self.forward_uart(connection)?;
+        Ok(outcome)
+    }
+
+    /// Send UART output produced since the last call as a console packet.
+    fn forward_uart(&mut self, connection: &mut Connection) -> io::Result<()> {
+        let uart = self.cpu.take_uart();
+        if uart.is_empty() {
+            return Ok(());
+        }
+        connection.send(&format!("O{}", hex(&uart_bytes(&uart))))
+    }
+
+    /// Register `number`'s value as little-endian hex.
+    fn read_register(&self, number: usize) -> Option<String> {
+        let bytes = match number {
+            0..16 => self.cpu.reg(number).to_le_bytes().to_vec(),
+            SP_REGISTER => self.cpu.sp().to_le_bytes().to_vec(),
+            PC_REGISTER => self.cpu.pc().to_le_bytes().to_vec(),
+            FLAGS_REGISTER => u32::from(self.cpu.flags().bits()).to_le_bytes().to_vec(),
+            _ => return None,
+        };
+        Some(hex(&bytes))
+    }
+
+    /// Set register `number` from little-endian hex.
+    fn write_register(&mut self, number: usize, value: &str) -> Option<()> {
+        let bytes = unhex(value)?;
+        let mut raw = [0_u8; 8];
+        raw.get_mut(..bytes.len())?.copy_from_slice(&bytes);
+        let value = u64::from_le_bytes(raw);
+        match number {
+            0..16 => self.cpu.set_reg(number, value),
+            SP_REGISTER => self.cpu.set_sp(value as u32),
+            PC_REGISTER => self.cpu.set_pc(value as u32),
+            FLAGS_REGISTER => self.cpu.set_flags(Flags::from_bits(value as u8)),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// `g`: every register.
+    fn read_registers(&self) -> String {
+        (0..REGISTER_COUNT).filter_map(|number| self.read_register(number)).collect()
+    }
+
+    /// `G`: every register, in `g` order.
+    fn write_registers(&mut self, data: &str) -> String {
+        let mut offset = 0;
+        for number in 0..REGISTER_COUNT {
+            let width = if number < 16 { 16 } else { 8 };
+            let Some(value) = data.get(offset..offset + width) else {
+                return "E01".to_owned();
+            };
+            if self.write_register(number, value).is_none() {
+                return "E01".to_owned();
+            }
+            offset += width;
+        }
+        "OK".to_owned()
+    }
+
+    /// `m addr,len`.
+    fn read_memory(&self, arguments: &str) -> Option<String> {
+        let (address, length) = arguments.split_once(',')?;
+        let bytes = self.cpu.memory(u32::try_from(parse_hex(address)?).ok()?, parse_hex(length)? as usize)?;
+        Some(hex(bytes))
+    }
+
+    /// `M addr,len:hex`.
+    fn write_memory(&mut self, arguments: &str) -> String {
+        let written = arguments.split_once(':').and_then(|(target, data)| {
+            let (address, length) = target.split_once(',')?;
+            let bytes = unhex(data)?;
+            (parse_hex(length)? as usize == bytes.len()).then_some(())?;
+            Some(self.cpu.write_memory(u32::try_from(parse_hex(address)?).ok()?, &bytes))
+        });
+        if written == Some(true) { "OK" } else { "E01" }.to_owned()
+    }
+
+    /// `X addr,len:binary` (already unescaped).
+    fn write_binary(&mut self, arguments: &[u8]) -> String {
+        let written = arguments.iter().position(|&byte| byte == b':').and_then(|colon| {
+            let target = std::str::from_utf8(&arguments[..colon]).ok()?;
+            let (address, length) = target.split_once(',')?;
+            let bytes = &arguments[colon + 1..];
+            (parse_hex(length)? as usize == bytes.len()).then_some(())?;
+            Some(self.cpu.write_memory(u32::try_from(parse_hex(address)?).ok()?, bytes))
+        });
+        if written == Some(true) { "OK" } else { "E01" }.to_owned()
+    }
+
+    /// `Z0`/`z0` (and `Z1`/`z1`, the same thing here) `,addr,kind`.
+    fn breakpoint(&mut self, insert: bool, arguments: &str) -> String {
+        let mut fields = arguments.split(',');
+        let (Some("0" | "1"), Some(address)) = (fields.next(), fields.next()) else {
+            return String::new(); // watchpoints are not supported
+        };
+        let Some(address) = parse_hex(address).and_then(|address| u32::try_from(address).ok()) else {
+            return "E01".to_owned();
+        };
+        if insert {
+            if !self.breakpoints.contains(&address) {
+                self.breakpoints.push(address);
+            }
+        } else {
+            self.breakpoints.retain(|&b| b != address);
+        }
+        "OK".to_owned()
+    }
+}
+
+/// A GDB connection: packet framing, checksums and acknowledgements.
+pub struct Connection {
+    /// The socket.
+    stream: TcpStream,
+    /// Bytes read but not yet consumed.
+    pending: VecDeque<u8>,
+    /// True once `QStartNoAckMode` is agreed.
+    no_ack: bool,
+    /// Ctrl+C arrived while waiting for an acknowledgement.
+    interrupt: bool,
+}
+
+impl Connection {
+    /// Wrap an accepted socket.
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            pending: VecDeque::new(),
+            no_ack: false,
+            interrupt: false,
+        })
+    }
+
+    /// Next byte, or `None` once GDB disconnects.
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.pending.is_empty() {
+            let mut buffer = [0_u8; 4096];
+            let count = self.stream.read(&mut buffer)?;
+            if count == 0 {
+                return Ok(None);
+            }
+            self.pending.extend(&buffer[..count]);
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    /// Next packet's (unescaped) data; `Some(None)` for Ctrl+C, `None` once
+    /// GDB disconnects.  Corrupt packets are asked for again.
+    pub fn read_packet(&mut self) -> io::Result<Option<Option<Vec<u8>>>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(0x03) => return Ok(Some(None)),
+                Some(b'$') => {}
+                Some(_) => continue, // acks and noise
+            }
+            let mut data: Vec<u8> = Vec::new();
+            let mut sum: u8 = 0;
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => {
+                        sum = sum.wrapping_add(byte);
+                        data.push(byte);
+                    }
+                }
+            }
+            let (Some(high), Some(low)) = (self.read_byte()?, self.read_byte()?) else {
+                return Ok(None);
+            };
+            let checksum = std::str::from_utf8(&[high, low]).ok().and_then(|text| u8::from_str_radix(text, 16).ok());
+            if self.no_ack || checksum == Some(sum) {
+                if !self.no_ack {
+                    self.stream.write_all(b"+")?;
+                }
+                return Ok(Some(Some(unescape(&data))));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    /// Send one packet, resending until GDB acknowledges it.
+    pub fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut body: Vec<u8> = Vec::with_capacity(data.len());
+        for byte in data.bytes() {
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                body.extend_from_slice(&[b'}', byte ^ 0x20]);
+            } else {
+                body.push(byte);
+            }
+        }
+        let sum = body.iter().fold(0_u8, |sum, &byte| sum.wrapping_add(byte));
+        let packet = [b"$".as_slice(), &body, format!("#{sum:02x}").as_bytes()].concat();
+        loop {
+            self.stream.write_all(&packet)?;
+            if self.no_ack {
+                return Ok(());
+            }
+            loop {
+                match self.read_byte()? {
+                    None | Some(b'+') => return Ok(()),
+                    Some(b'-') => break,
+                    Some(0x03) => self.interrupt = true,
+                    Some(_) => {}
+                }
+            }
+        }
+    }
+
+    /// True if GDB sent Ctrl+C; does not wait.
+    pub fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0_u8; 256];
+        let result = loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => break Ok(()),
+                Ok(count) => self.pending.extend(&buffer[..count]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result?;
+        if let Some(index) = self.pending.iter().position(|&byte| byte == 0x03) {
+            self.pending.remove(index);
+            self.interrupt = true;
+        }
+        Ok(std::mem::take(&mut self.interrupt))
+    }
+}
+
+/// Undo the protocol's `}` escaping.
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'}' {
+            if let Some(&next) = bytes.next() {
+                result.push(next ^ 0x20);
+            }
+        } else {
+            result.push(byte);
+        }
+    }
+    result
+}
+
+/// Bytes as lowercase hex.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut text, byte| {
+        let _ = write!(text, "{byte:02x}");
+        text
+    })
+}
+
+/// Hex pairs as bytes.
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// A hex number.
+fn parse_hex(text: &str) -> Option<u64> {
+    u64::from_str_radix(text, 16).ok()
+}
+
+/// `offset,length` of `document` as a `qXfer` reply: `m` if more follows, `l` if not.
+fn read_chunk(document: &str, arguments: &str) -> Option<String> {
+    let (offset, length) = arguments.split_once(',')?;
+    let offset = (parse_hex(offset)? as usize).min(document.len());
+    let end = offset.saturating_add(parse_hex(length)? as usize).min(document.len());
+    let chunk = document.get(offset..end)?;
+    Some(format!("{}{chunk}", if end < document.len() { 'm' } else { 'l' }))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+    use crate::helper::build_ddr_image;
+    use std::net::TcpListener;
+
+    /// SETR A 0x41; TXR A; NEWLINE; HALT.
+    fn program() -> Vec<u8> {
+        [0x0000_0800_u32, 0x41, 0x0000_5010, 0x0000_5001, 0x0000_F011]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect()
+    }
+
+    fn stub() -> GdbStub {
+        GdbStub::new(Cpu::new(&build_ddr_image(&program()), 0x20), 1000)
+    }
+
+    fn reply(stub: &mut GdbStub, packet: &str) -> String {
+        match stub.command(packet.as_bytes()) {
+            Action::Reply(reply) => reply,
+            other => panic!("{packet}: {other:?}"),
+        }
+    }
+
+    #[test]
+    // Test register, memory and breakpoint packets
+    fn test_register_and_memory_packets() {
+        let mut stub = stub();
+        let registers = reply(&mut stub, "g");
+        assert_eq!(registers.len(), 16 * 16 + 3 * 8);
+        assert_eq!(&registers[16 * 16 + 8..16 * 16 + 16], "20000000", "pc");
+        assert_eq!(reply(&mut stub, "P1=efbeadde00000000"), "OK");
+        assert_eq!(stub.cpu().reg(1), 0xDEAD_BEEF);
+        assert_eq!(reply(&mut stub, "p1"), "efbeadde00000000");
+        assert_eq!(reply(&mut stub, "P12=11000000"), "OK");
+        assert_eq!(stub.cpu().flags(), Flags::from_bits(0x11));
+        assert!(stub.cpu().flags().carry && stub.cpu().flags().ult);
+        assert_eq!(reply(&mut stub, "p13"), "E01");
+
+        let mut all = reply(&mut stub, "g");
+        all.replace_range(16 * 16..16 * 16 + 8, "00100000");
+        assert_eq!(reply(&mut stub, &format!("G{all}")), "OK");
+        assert_eq!(stub.cpu().sp(), 0x1000);
+
+        assert_eq!(reply(&mut stub, "m20,8"), "0008000041000000");
+        assert_eq!(reply(&mut stub, "M100,2:abcd"), "OK");
+        assert_eq!(reply(&mut stub, "m100,3"), "abcd00");
+        assert_eq!(unescape(b"#}\x03}]"), b"##}");
+        assert_eq!(stub.command(b"X102,2:##"), Action::Reply("OK".to_owned()));
+        assert_eq!(reply(&mut stub, "m100,4"), "abcd2323");
+        assert_eq!(reply(&mut stub, "mffffffff,4"), "E01");
+
+        assert_eq!(reply(&mut stub, "Z0,2c,4"), "OK");
+        assert_eq!(reply(&mut stub, "Z2,100,8"), "", "watchpoints are not supported");
+        assert_eq!(stub.command(b"vCont;c"), Action::Resume { step: false });
+        assert_eq!(stub.command(b"s"), Action::Resume { step: true });
+        assert!(reply(&mut stub, "qSupported:swbreak+").contains("qXfer:features:read+"));
+        let xml = reply(&mut stub, "qXfer:features:read:target.xml:0,20");
+        assert!(xml.starts_with("m<?xml") && xml.len() == 0x21, "{xml}");
+        assert!(reply(&mut stub, "qXfer:features:read:target.xml:0,10000").starts_with('l'));
+        assert_eq!(reply(&mut stub, "vMustReplyEmpty"), "");
+    }
+
+    #[test]
+    // Test stop replies for machine stop reasons
+    fn test_stop_replies() {
+        assert_eq!(Stop::Machine(StopReason::Halt).reply(), "W00");
+        assert_eq!(Stop::Breakpoint.reply(), "T05swbreak:;");
+        assert_eq!(Stop::Step.reply(), "S05");
+        assert_eq!(Stop::Machine(StopReason::InvalidOpcode(7)).reply(), "S04");
+        assert_eq!(Stop::Machine(StopReason::PcOutOfRange(0)).reply(), "S0b");
+        assert_eq!(Stop::Budget.reply(), "S18");
+        assert_eq!(Stop::Machine(StopReason::InputExhausted).reply(), "S15");
+    }
+
+    /// Frame `data` as a packet.
+    fn frame(data: &str) -> Vec<u8> {
+        let sum = data.bytes().fold(0_u8, u8::wrapping_add);
+        format!("${data}#{sum:02x}").into_bytes()
+    }
+
+    /// Read one packet's data from the stub, acknowledging it.
+    fn receive(stream: &mut TcpStream) -> String {
+        let mut data = Vec::new();
+        let mut byte = [0_u8; 1];
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+        let mut checksum = [0_u8; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    #[test]
+    // Test a loopback session: step, breakpoint, continue with console output, halt
+    fn test_session_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stub = stub();
+            stub.serve(stream).unwrap();
+            stub.cpu().reg(0)
+        });
+        let mut client = TcpStream::connect(address).unwrap();
+        let send = |client: &mut TcpStream, packet: &str| {
+            client.write_all(&frame(packet)).unwrap();
+            let mut ack = [0_u8; 1];
+            client.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+            receive(client)
+        };
+        assert_eq!(send(&mut client, "?"), "S05");
+        assert_eq!(send(&mut client, "s"), "S05");
+        assert_eq!(send(&mut client, "p11"), "28000000", "pc after SETR");
+        assert_eq!(send(&mut client, "Z0,30,4"), "OK");
+
+        let console = send(&mut client, "c");
+        let text = String::from_utf8(unhex(console.strip_prefix('O').unwrap()).unwrap()).unwrap();
+        assert!(text.starts_with("0000000000000041"), "{text:?}");
+        assert_eq!(receive(&mut client), "T05swbreak:;");
+        assert_eq!(send(&mut client, "p11"), "30000000");
+        assert_eq!(send(&mut client, "z0,30,4"), "OK");
+        assert_eq!(send(&mut client, "vCont;c"), "W00");
+        assert_eq!(send(&mut client, "D"), "OK");
+        assert_eq!(server.join().unwrap(), 0x41);
+    }
+}
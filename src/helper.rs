@@ -215,6 +215,96 @@ pub fn build_ddr_image(binary_data: &[u8]) -> Vec<u8> {
     image
 }
 
+/// Read `$readmemh` text as `(hex digits per word, words)`.
+///
+/// Every data line must have the same width: 16 or 8 hex digits for 64- or
+/// 32-bit words, or 2 for one byte lane.  `@address` records (word addresses)
+/// move the write position; words they skip read as zero.  `_` separators and
+/// `//` comments are ignored.
+fn parse_mem_words(text: &str) -> Result<(usize, Vec<u64>), String> {
+    let mut width: Option<usize> = None;
+    let mut words: Vec<u64> = Vec::new();
+    let mut position: usize = 0;
+    for (index, line) in text.lines().enumerate() {
+        let word = strip_comments(line).replace('_', "");
+        if word.is_empty() {
+            continue;
+        }
+        if let Some(address) = word.strip_prefix('@') {
+            position = usize::from_str_radix(address, 16).map_err(|err| format!("line {}: bad address record \"{word}\": {err}", index + 1))?;
+            continue;
+        }
+        match width {
+            None if matches!(word.len(), 2 | 8 | 16) => width = Some(word.len()),
+            None => {
+                return Err(format!(
+                    "line {}: {}-digit words are not a --mem-out layout (expected 16, 8 or 2 hex digits)",
+                    index + 1,
+                    word.len()
+                ))
+            }
+            Some(width) if width != word.len() => {
+                return Err(format!("line {}: {}-digit word after {width}-digit words", index + 1, word.len()));
+            }
+            Some(_) => {}
+        }
+        let value = u64::from_str_radix(&word, 16).map_err(|err| format!("line {}: {err}", index + 1))?;
+        if words.len() <= position {
+            words.resize(position + 1, 0);
+        }
+        words[position] = value;
+        position += 1;
+    }
+    Ok((width.unwrap_or(16), words))
+}
+
+/// Read a `$readmemh` image written by `--mem-out` or `--format mem` back into DDR bytes.
+///
+/// Accepts 64- and 32-bit little-endian words, with or without `@address`
+/// records.  A byte-lane file holds only part of each word; read the whole
+/// set with [`parse_mem_lanes`].
+pub fn parse_mem_image(text: &str) -> Result<Vec<u8>, String> {
+    let (width, words) = parse_mem_words(text)?;
+    if width == 2 {
+        return Err("2-digit lines are one byte lane of a --mem-lanes image; read all its lane files together".to_owned());
+    }
+    Ok(words.iter().flat_map(|word| word.to_le_bytes()[..width / 2].to_vec()).collect())
+}
+
+/// Merge the byte-lane files of a `--mem-lanes` image, lane 0 (the least
+/// significant byte) first, back into DDR bytes.
+pub fn parse_mem_lanes(lanes: &[String]) -> Result<Vec<u8>, String> {
+    if lanes.len() != 4 && lanes.len() != 8 {
+        return Err(format!("{} byte lanes found, --mem-lanes writes 4 or 8", lanes.len()));
+    }
+    let mut lane_words: Vec<Vec<u64>> = Vec::with_capacity(lanes.len());
+    for (lane, text) in lanes.iter().enumerate() {
+        let (width, words) = parse_mem_words(text).map_err(|err| format!("lane {lane}: {err}"))?;
+        if width != 2 {
+            return Err(format!("lane {lane}: {width}-digit words, expected one byte (2 hex digits) per line"));
+        }
+        lane_words.push(words);
+    }
+    let depth = lane_words.iter().map(Vec::len).max().unwrap_or(0);
+    let mut image: Vec<u8> = Vec::with_capacity(depth * lanes.len());
+    for index in 0..depth {
+        image.extend(lane_words.iter().map(|words| words.get(index).copied().unwrap_or(0) as u8));
+    }
+    Ok(image)
+}
+
+/// Parse an `--entry-point` address, hex with a `0x` prefix or decimal.
+///
+/// Text that is not a valid `u32` gives the default load address,
+/// `HEAP_HEADER_WORDS * 8 = 0x20`.
+pub fn parse_entry_point(text: &str) -> u32 {
+    let parsed = match text.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("0x") => u32::from_str_radix(&text[2..], 16).ok(),
+        _ => text.parse::<u32>().ok(),
+    };
+    parsed.unwrap_or(HEAP_HEADER_WORDS * 8)
+}
+
 /// Disassemble a flat byte slice into a `Vec<Pass2>` for use with `write_code_output_file`.
 ///
 /// Each 4-byte chunk is read as a big-endian 32-bit word (matching the `KlaussCPU` LLVM ELF
@@ -313,6 +403,19 @@ pub fn data_as_bytes(line: &str) -> Option<String> {
         return Some(format!("{lo32:08X}{hi32:08X}"));
     }
 
+    // Handle .long VALUE directive — emit a single 32-bit word
+    if first_word == ".long" {
+        let value_str = words.next().unwrap_or("");
+        let value: Option<i64> = if value_str.len() >= 2 && value_str.get(0..2).is_some_and(|p| p.eq_ignore_ascii_case("0x")) {
+            i64::from_str_radix(&value_str[2..].replace('_', ""), 16).ok()
+        } else {
+            value_str.parse::<i64>().ok()
+        };
+        return value
+            .filter(|v| *v >= i64::from(i32::MIN) && *v <= 0xFFFF_FFFF_i64)
+            .map(|v| format!("{:08X}", v as u32));
+    }
+
     // Handle .space N directive — N bytes of zero, rounded up to 64-bit word boundary
     if first_word == ".space" {
         let count_str = words.next().unwrap_or("");
@@ -490,7 +593,7 @@ pub fn line_type(opcodes: &mut Vec<Opcode>, line: &str) -> LineType {
     // Check for C compiler directives
     let first_word = line.split_whitespace().next().unwrap_or("");
     match first_word {
-        ".word" | ".space" => return LineType::Data,
+        ".word" | ".long" | ".space" => return LineType::Data,
         ".text" | ".data" | ".rodata" | ".bss" | ".global" | ".globl" | ".extern" | ".comm" | ".lcomm" => return LineType::Comment,
         _ => {}
     }
@@ -1136,4 +1239,73 @@ mod tests {
         let opcodes = &mut Vec::<Opcode>::new();
         assert_eq!(line_type(opcodes, ".lcomm temp 8"), LineType::Comment);
     }
+
+    #[test]
+    // Test --entry-point parsing of hex, decimal and invalid text
+    fn test_parse_entry_point() {
+        assert_eq!(parse_entry_point("0x1000"), 0x1000);
+        assert_eq!(parse_entry_point("0XfF"), 0xFF);
+        assert_eq!(parse_entry_point("4096"), 4096);
+        assert_eq!(parse_entry_point("0xZZ"), 0x20);
+        assert_eq!(parse_entry_point("start"), 0x20);
+        assert_eq!(parse_entry_point("4294967296"), 0x20);
+        assert_eq!(parse_entry_point(""), 0x20);
+    }
+
+    #[test]
+    // Test a $readmemh image is read back as little-endian doublewords
+    fn test_parse_mem_image() {
+        let image = parse_mem_image("0000000000000028\n// comment\n\n0000_F011_0000_0800\n").unwrap();
+        assert_eq!(image.len(), 16);
+        assert_eq!(image[0], 0x28);
+        assert_eq!(&image[8..12], &[0x00, 0x08, 0x00, 0x00]);
+        assert!(parse_mem_image("1234\n").unwrap_err().contains("line 1"));
+        assert!(parse_mem_image("000000000000002G\n").is_err());
+        assert!(parse_mem_image("00000028\n0000000000000000\n")
+            .unwrap_err()
+            .contains("16-digit word after 8-digit"));
+        assert!(parse_mem_image("28\n00\n").unwrap_err().contains("byte lane"));
+        assert!(parse_mem_lanes(&vec!["28\n".to_owned(); 3]).unwrap_err().contains("3 byte lanes"));
+    }
+
+    #[test]
+    // Test every layout mem_files writes reads back to the same image
+    fn test_parse_mem_round_trip() {
+        use crate::image_format::{mem_files, MemLayout};
+        let mut image: Vec<u8> = (1..=24).collect();
+        image.resize(24 + 8 * 10, 0);
+        image.extend_from_slice(&[0x11, 0xF0, 0, 0, 0x42, 0, 0, 0]);
+        for word_bytes in [8, 4] {
+            for address_records in [false, true] {
+                for split_lanes in [false, true] {
+                    let layout = MemLayout {
+                        word_bytes,
+                        split_lanes,
+                        depth: Some(64),
+                        address_records,
+                    };
+                    let files: Vec<String> = mem_files(&image, &layout).unwrap().into_iter().map(|(_, text)| text).collect();
+                    let read = if split_lanes {
+                        parse_mem_lanes(&files).unwrap()
+                    } else {
+                        parse_mem_image(&files[0]).unwrap()
+                    };
+                    let mut expected = image.clone();
+                    if !address_records {
+                        expected.resize(64 * word_bytes, 0);
+                    }
+                    assert_eq!(read, expected, "{layout:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    // Test .long emits one 32-bit word and rejects out-of-range values
+    fn test_data_as_bytes_long() {
+        assert_eq!(data_as_bytes(".long 0x12"), Some("00000012".to_owned()));
+        assert_eq!(data_as_bytes(".long -1"), Some("FFFFFFFF".to_owned()));
+        assert_eq!(data_as_bytes(".long 0x1_0000_0000"), None);
+        assert_eq!(data_as_bytes(".long"), None);
+    }
 }
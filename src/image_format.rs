@@ -0,0 +1,341 @@
+//! Program image writers for `--format`.
+//!
+//! Every format is rendered from the same DDR image (`build_ddr_image`: heap
+//! header then program, addressed from 0), so BRAM initialisers, flash images
+//! and third-party loaders all see identical bytes.
+
+use std::fmt::Write as _;
+
+/// Data bytes per Intel HEX / S-record line.
+const RECORD_BYTES: usize = 16;
+
+/// An output image format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Intel HEX (`.hex`) with extended linear address and start records.
+    Ihex,
+    /// Motorola S-record (`.srec`) with 32-bit addresses.
+    Srec,
+    /// Raw bytes (`.bin`).
+    Bin,
+    /// `$readmemh` text (`.mem`) in the `--mem-*` layout.
+    Mem,
+    /// Xilinx coefficient file (`.coe`) of 64-bit words.
+    Coe,
+}
+
+impl ImageFormat {
+    /// Names accepted by `--format`.
+    pub const NAMES: [&'static str; 5] = ["ihex", "srec", "bin", "mem", "coe"];
+
+    /// Parse a `--format` name.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ihex" | "hex" => Some(Self::Ihex),
+            "srec" => Some(Self::Srec),
+            "bin" => Some(Self::Bin),
+            "mem" => Some(Self::Mem),
+            "coe" => Some(Self::Coe),
+            _ => None,
+        }
+    }
+
+    /// File extension, without the dot.
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Ihex => "hex",
+            Self::Srec => "srec",
+            Self::Bin => "bin",
+            Self::Mem => "mem",
+            Self::Coe => "coe",
+        }
+    }
+
+    /// Render a DDR image (loaded at address 0) with its entry PC.
+    ///
+    /// `None` for `.mem`, which [`mem_files`] lays out as one or more files.
+    #[must_use]
+    pub fn render(self, image: &[u8], entry: u32) -> Option<Vec<u8>> {
+        match self {
+            Self::Ihex => Some(ihex_text(image, entry).into_bytes()),
+            Self::Srec => Some(srec_text(image, entry).into_bytes()),
+            Self::Bin => Some(image.to_vec()),
+            Self::Mem => None,
+            Self::Coe => Some(coe_text(image).into_bytes()),
+        }
+    }
+}
+
+/// 64-bit little-endian doublewords of an image, zero-padding a partial last word.
+fn doublewords(image: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    image.chunks(8).map(|chunk| {
+        let mut bytes = [0_u8; 8];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        u64::from_le_bytes(bytes)
+    })
+}
+
+/// Layout of `$readmemh` output for BRAM / boot ROM initialisation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemLayout {
+    /// Bytes per memory word (line): 4 or 8.
+    pub word_bytes: usize,
+    /// Write one file per byte lane, each line holding one byte of the word.
+    pub split_lanes: bool,
+    /// Fixed ROM depth in words: the image is zero-padded to it and must fit.
+    pub depth: Option<usize>,
+    /// Write `@address` records (word addresses) and skip runs of zero words.
+    pub address_records: bool,
+}
+
+impl Default for MemLayout {
+    /// One 64-bit little-endian doubleword per line, as `boot_rom.v` expects.
+    fn default() -> Self {
+        Self {
+            word_bytes: 8,
+            split_lanes: false,
+            depth: None,
+            address_records: false,
+        }
+    }
+}
+
+/// Zero words in a row before `@address` records skip over them.
+const MIN_ZERO_RUN: usize = 8;
+
+/// Render an image as `$readmemh` files in the given layout.
+///
+/// Returns `(suffix, text)` pairs: a single file with an empty suffix, or one
+/// per byte lane with suffix `_lane<n>` (lane 0 holds the least significant
+/// byte).  With a fixed depth the image is padded with zero words, unless
+/// `@address` records are written, and an image larger than the ROM is an
+/// error.
+pub fn mem_files(image: &[u8], layout: &MemLayout) -> Result<Vec<(String, String)>, String> {
+    if layout.word_bytes != 4 && layout.word_bytes != 8 {
+        return Err(format!("memory word width must be 32 or 64 bits, not {}", layout.word_bytes * 8));
+    }
+    let mut words: Vec<u64> = image
+        .chunks(layout.word_bytes)
+        .map(|chunk| {
+            let mut bytes = [0_u8; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(bytes)
+        })
+        .collect();
+    if let Some(depth) = layout.depth {
+        if words.len() > depth {
+            return Err(format!(
+                "image of {} bytes needs {} {}-bit words but the ROM depth is {depth}",
+                image.len(),
+                words.len(),
+                layout.word_bytes * 8
+            ));
+        }
+        if !layout.address_records {
+            words.resize(depth, 0);
+        }
+    }
+
+    // Word indexes to write, with an address record wherever the sequence restarts.
+    let mut lines: Vec<(usize, bool)> = Vec::with_capacity(words.len());
+    let mut index = 0;
+    while index < words.len() {
+        let zero_run = words[index..].iter().take_while(|word| **word == 0).count();
+        if layout.address_records && zero_run >= MIN_ZERO_RUN {
+            index += zero_run;
+            continue;
+        }
+        let restart = layout.address_records && lines.last().is_none_or(|(last, _)| last + 1 != index);
+        lines.push((index, restart));
+        index += 1;
+    }
+
+    let byte_lanes: Vec<Option<usize>> = if layout.split_lanes {
+        (0..layout.word_bytes).map(Some).collect()
+    } else {
+        vec![None]
+    };
+    Ok(byte_lanes
+        .into_iter()
+        .map(|lane| {
+            let mut text = String::with_capacity(lines.len() * (layout.word_bytes * 2 + 1));
+            for (index, restart) in &lines {
+                if *restart {
+                    let _ = writeln!(text, "@{index:08X}");
+                }
+                let word = words[*index];
+                let _ = match lane {
+                    Some(lane) => writeln!(text, "{:02X}", (word >> (lane * 8)) & 0xFF),
+                    None => writeln!(text, "{word:0width$X}", width = layout.word_bytes * 2),
+                };
+            }
+            (lane.map_or_else(String::new, |lane| format!("_lane{lane}")), text)
+        })
+        .collect())
+}
+
+/// Insert a lane suffix from [`mem_files`] before the extension of `file_name`.
+#[must_use]
+pub fn suffixed_file_name(file_name: &str, suffix: &str) -> String {
+    match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !extension.contains('/') => format!("{stem}{suffix}.{extension}"),
+        _ => format!("{file_name}{suffix}"),
+    }
+}
+
+/// Xilinx `.coe` text: radix 16, one 64-bit doubleword per vector entry.
+#[must_use]
+pub fn coe_text(image: &[u8]) -> String {
+    let words: Vec<String> = doublewords(image).map(|dw| format!("{dw:016X}")).collect();
+    format!("memory_initialization_radix=16;\nmemory_initialization_vector=\n{};\n", words.join(",\n"))
+}
+
+/// One Intel HEX record with its two's-complement checksum.
+fn ihex_record(record_type: u8, address: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+    let checksum = bytes.iter().fold(0_u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg();
+    let mut line = String::from(":");
+    for byte in bytes.iter().chain(std::iter::once(&checksum)) {
+        let _ = write!(line, "{byte:02X}");
+    }
+    line.push('\n');
+    line
+}
+
+/// Intel HEX text: data records, type 04 whenever the upper 16 address bits change,
+/// a type 05 start address and the end-of-file record.
+#[must_use]
+pub fn ihex_text(image: &[u8], entry: u32) -> String {
+    let mut out = String::new();
+    let mut upper: u32 = 0;
+    for (index, chunk) in image.chunks(RECORD_BYTES).enumerate() {
+        let address = (index * RECORD_BYTES) as u32;
+        if address >> 16 != upper {
+            upper = address >> 16;
+            out.push_str(&ihex_record(0x04, 0, &(upper as u16).to_be_bytes()));
+        }
+        out.push_str(&ihex_record(0x00, address as u16, chunk));
+    }
+    out.push_str(&ihex_record(0x05, 0, &entry.to_be_bytes()));
+    out.push_str(&ihex_record(0x01, 0, &[]));
+    out
+}
+
+/// One S-record with its ones'-complement checksum.
+fn srec_record(record_type: char, address: &[u8], data: &[u8]) -> String {
+    let count = (address.len() + data.len() + 1) as u8;
+    let sum = address.iter().chain(data).fold(count, |sum, byte| sum.wrapping_add(*byte));
+    let mut line = format!("S{record_type}{count:02X}");
+    for byte in address.iter().chain(data) {
+        let _ = write!(line, "{byte:02X}");
+    }
+    let _ = writeln!(line, "{:02X}", !sum);
+    line
+}
+
+/// Motorola S-record text: S0 header, S3 data records, S5 count and S7 entry.
+#[must_use]
+pub fn srec_text(image: &[u8], entry: u32) -> String {
+    let mut out = srec_record('0', &[0, 0], b"klausscc");
+    let mut records: usize = 0;
+    for (index, chunk) in image.chunks(RECORD_BYTES).enumerate() {
+        out.push_str(&srec_record('3', &((index * RECORD_BYTES) as u32).to_be_bytes(), chunk));
+        records += 1;
+    }
+    if let Ok(count) = u16::try_from(records) {
+        out.push_str(&srec_record('5', &count.to_be_bytes(), &[]));
+    }
+    out.push_str(&srec_record('7', &entry.to_be_bytes(), &[]));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+
+    #[test]
+    // Test Intel HEX records, checksums and the extended address record at 64 KiB
+    fn test_ihex_text() {
+        let text = ihex_text(&[0x30, 0, 0, 0, 0x11, 0xF0, 0, 0], 0x20);
+        assert_eq!(text, ":080000003000000011F00000C7\n:0400000500000020D7\n:00000001FF\n");
+
+        let big = ihex_text(&vec![0_u8; 0x1_0010], 0x20);
+        assert!(big.contains(":020000040001F9\n:1000000000000000000000000000000000000000F0\n"), "{big}");
+    }
+
+    #[test]
+    // Test S-record header, data, count and termination records
+    fn test_srec_text() {
+        let text = srec_text(&[0x30, 0, 0, 0], 0x20);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "S00B00006B6C6175737363639B");
+        assert_eq!(lines[1], "S3090000000030000000C6");
+        assert_eq!(lines[2], "S5030001FB");
+        assert_eq!(lines[3], "S70500000020DA");
+    }
+
+    #[test]
+    // Test mem and coe text share the 64-bit little-endian word layout
+    fn test_mem_and_coe_text() {
+        let image = [0x30, 0, 0, 0, 0, 0, 0, 0, 0x11, 0xF0, 0, 0];
+        assert_eq!(
+            mem_files(&image, &MemLayout::default()).unwrap(),
+            vec![(String::new(), "0000000000000030\n000000000000F011\n".to_owned())]
+        );
+        assert_eq!(
+            coe_text(&image),
+            "memory_initialization_radix=16;\nmemory_initialization_vector=\n0000000000000030,\n000000000000F011;\n"
+        );
+        assert_eq!(ImageFormat::Bin.render(&image, 0x20), Some(image.to_vec()));
+        assert_eq!(ImageFormat::Mem.render(&image, 0x20), None);
+        assert_eq!(ImageFormat::from_name("IHEX"), Some(ImageFormat::Ihex));
+        assert_eq!(ImageFormat::from_name("elf"), None);
+    }
+
+    #[test]
+    // Test 32-bit lines, fixed depth padding and the overflow error
+    fn test_mem_files_width_and_depth() {
+        let layout = MemLayout {
+            word_bytes: 4,
+            depth: Some(4),
+            ..MemLayout::default()
+        };
+        let files = mem_files(&[0x30, 0, 0, 0, 0x11, 0xF0, 0, 0], &layout).unwrap();
+        assert_eq!(files, vec![(String::new(), "00000030\n0000F011\n00000000\n00000000\n".to_owned())]);
+        let err = mem_files(&[0_u8; 20], &layout).unwrap_err();
+        assert!(err.contains("ROM depth is 4"), "{err}");
+    }
+
+    #[test]
+    // Test byte-lane split files and @address records skipping zero runs
+    fn test_mem_files_lanes_and_addresses() {
+        let layout = MemLayout {
+            word_bytes: 4,
+            split_lanes: true,
+            ..MemLayout::default()
+        };
+        let files = mem_files(&[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88], &layout).unwrap();
+        assert_eq!(files.len(), 4);
+        assert_eq!(files[0], ("_lane0".to_owned(), "11\n55\n".to_owned()));
+        assert_eq!(files[3], ("_lane3".to_owned(), "44\n88\n".to_owned()));
+
+        let mut image = vec![1_u8, 0, 0, 0];
+        image.resize(4 * 12, 0);
+        image.extend_from_slice(&[2, 0, 0, 0]);
+        let layout = MemLayout {
+            word_bytes: 4,
+            depth: Some(64),
+            address_records: true,
+            ..MemLayout::default()
+        };
+        let files = mem_files(&image, &layout).unwrap();
+        assert_eq!(files[0].1, "@00000000\n00000001\n@0000000C\n00000002\n");
+        assert_eq!(suffixed_file_name("rom/boot.mem", "_lane1"), "rom/boot_lane1.mem");
+    }
+}
@@ -0,0 +1,174 @@
+//! Reader for the `.kbt` board wire format.
+//!
+//! `create_bin_string` writes `S`, the four heap-header doublewords, the
+//! program words and the entry PC as LE-encoded ASCII hex, then `Z`, the
+//! checksum word and `X`.  [`parse_kbt`] turns that back into bytes so a
+//! `.kbt` can be inspected, disassembled or emulated without its source.
+
+use crate::helper::{calc_checksum, HEAP_HEADER_WORDS};
+use crate::messages::MsgList;
+
+/// Hex characters per 32-bit wire word.
+const WORD_HEX_CHARS: usize = 8;
+
+/// A decoded `.kbt` image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KbtImage {
+    /// The heap-header doublewords; word 0 lo32 is `heap_start`.
+    pub header: Vec<u64>,
+    /// Program bytes, loaded from board address `HEAP_HEADER_WORDS * 8`.
+    pub code: Vec<u8>,
+    /// Entry PC sent after the program.
+    pub entry: u32,
+    /// Checksum sent after `Z` (natural value).
+    pub checksum: u32,
+    /// Checksum recomputed over the frame with `calc_checksum`.
+    pub computed_checksum: u32,
+}
+
+impl KbtImage {
+    /// First free byte after the program, from header word 0.
+    #[must_use]
+    pub fn heap_start(&self) -> u32 {
+        self.header.first().map_or(0, |word| *word as u32)
+    }
+
+    /// True when the transmitted checksum matches the recomputed one.
+    #[must_use]
+    pub const fn checksum_ok(&self) -> bool {
+        self.checksum == self.computed_checksum
+    }
+
+    /// Header and program as the bytes the board holds in DDR.
+    #[must_use]
+    pub fn memory_image(&self) -> Vec<u8> {
+        let mut image: Vec<u8> = self.header.iter().flat_map(|word| word.to_le_bytes()).collect();
+        image.extend_from_slice(&self.code);
+        image
+    }
+}
+
+/// Decode one 8-hex-char wire word (bytes in transmission order).
+fn wire_word_bytes(chunk: &str) -> Option<[u8; 4]> {
+    let mut bytes = [0_u8; 4];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(chunk.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Parse `.kbt` text.
+///
+/// Framing errors (missing `S`/`Z`/`X`, non-hex characters, a length that is
+/// not whole 32-bit words, or a frame too short for the header and entry) are
+/// returned as `Err`.  A checksum mismatch is not an error here; check
+/// [`KbtImage::checksum_ok`].
+pub fn parse_kbt(text: &str) -> Result<KbtImage, String> {
+    let text = text.trim();
+    let Some(rest) = text.strip_prefix('S') else {
+        return Err("missing 'S' start character".to_owned());
+    };
+    let Some(rest) = rest.strip_suffix('X') else {
+        return Err("missing 'X' stop character".to_owned());
+    };
+    let Some((body, checksum_hex)) = rest.split_once('Z') else {
+        return Err("missing 'Z' checksum delimiter".to_owned());
+    };
+    if let Some((offset, bad)) = body
+        .char_indices()
+        .chain(checksum_hex.char_indices())
+        .find(|(_, c)| !c.is_ascii_hexdigit())
+    {
+        return Err(format!("unexpected character {bad:?} in frame (offset {offset} after delimiter)"));
+    }
+    if !body.len().is_multiple_of(WORD_HEX_CHARS) {
+        return Err(format!(
+            "odd frame length: {} hex digits is not a whole number of 32-bit words",
+            body.len()
+        ));
+    }
+    if checksum_hex.len() != WORD_HEX_CHARS {
+        return Err(format!("checksum is {} hex digits, expected {WORD_HEX_CHARS}", checksum_hex.len()));
+    }
+
+    let header_chars = HEAP_HEADER_WORDS as usize * 16;
+    if body.len() < header_chars + WORD_HEX_CHARS {
+        return Err(format!(
+            "frame of {} hex digits is too short for the heap header and entry PC",
+            body.len()
+        ));
+    }
+
+    let mut bytes: Vec<u8> = Vec::with_capacity(body.len() / 2);
+    for i in (0..body.len()).step_by(WORD_HEX_CHARS) {
+        bytes.extend_from_slice(&wire_word_bytes(&body[i..i + WORD_HEX_CHARS]).unwrap_or_default());
+    }
+    let entry_bytes = bytes.split_off(bytes.len() - 4);
+    let code = bytes.split_off(header_chars / 2);
+    let header = bytes
+        .chunks(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap_or_default()))
+        .collect();
+
+    let checksum = u32::from_le_bytes(wire_word_bytes(checksum_hex).unwrap_or_default());
+    let computed = calc_checksum(&format!("S{body}"), &mut MsgList::new());
+    let computed_checksum = u32::from_le_bytes(wire_word_bytes(&computed).unwrap_or_default());
+
+    Ok(KbtImage {
+        header,
+        code,
+        entry: u32::from_le_bytes(entry_bytes.try_into().unwrap_or_default()),
+        checksum,
+        computed_checksum,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+    use crate::files::LineType;
+    use crate::helper::create_bin_string;
+    use crate::test_support::pass2_line;
+
+    fn sample_kbt() -> String {
+        let pass2 = vec![
+            pass2_line(LineType::Start, "", 0x20),
+            pass2_line(LineType::Opcode, "0000080000000041", 0x20),
+            pass2_line(LineType::Opcode, "0000F011", 0x28),
+        ];
+        create_bin_string(&pass2, &mut MsgList::new()).unwrap()
+    }
+
+    #[test]
+    // Test a kbt written by create_bin_string decodes back to its parts
+    fn test_parse_kbt_round_trip() {
+        let image = parse_kbt(&format!("{}\n", sample_kbt())).unwrap();
+        assert_eq!(image.header.len(), 4);
+        assert_eq!(image.heap_start(), 0x30);
+        assert_eq!(image.entry, 0x20);
+        assert_eq!(image.code, vec![0x00, 0x08, 0x00, 0x00, 0x41, 0x00, 0x00, 0x00, 0x11, 0xF0, 0x00, 0x00]);
+        assert!(image.checksum_ok());
+        assert_eq!(image.memory_image().len(), 0x20 + 12);
+    }
+
+    #[test]
+    // Test a corrupted word is reported as a checksum mismatch
+    fn test_parse_kbt_bad_checksum() {
+        let kbt = sample_kbt().replacen("41000000", "42000000", 1);
+        let image = parse_kbt(&kbt).unwrap();
+        assert!(!image.checksum_ok());
+    }
+
+    #[test]
+    // Test framing errors are rejected
+    fn test_parse_kbt_framing() {
+        let kbt = sample_kbt();
+        assert!(parse_kbt(&kbt[1..]).unwrap_err().contains("'S'"));
+        assert!(parse_kbt(&kbt[..kbt.len() - 1]).unwrap_err().contains("'X'"));
+        assert!(parse_kbt(&kbt.replace('Z', "")).unwrap_err().contains("'Z'"));
+        assert!(parse_kbt(&kbt.replacen("41", "4", 1)).unwrap_err().contains("odd frame length"));
+        assert!(parse_kbt(&kbt.replacen("41", "4G", 1)).unwrap_err().contains("unexpected character"));
+        assert!(parse_kbt("S00000000Z00000000X").unwrap_err().contains("too short"));
+    }
+}
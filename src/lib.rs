@@ -0,0 +1,99 @@
+//! Library interface for Klausscc.
+//!
+//! Build scripts and test harnesses can assemble through [`Assembler`] instead
+//! of shelling out to the `klausscc` binary:
+//!
+//! ```ignore
+//! use klausscc::{messages::MsgList, Assembler, Isa};
+//!
+//! let mut msg_list = MsgList::new();
+//! let isa = Isa::from_vh_file("opcode_select.vh", &mut msg_list).expect("opcode file");
+//! let assembly = Assembler::new(&isa).source_file("hello.kla").assemble();
+//! if let (Some(image), Some(entry)) = (&assembly.image, assembly.entry) {
+//!     let (result, _) = klausscc::emulate::emulate_image(image, entry, 1_000_000, false);
+//!     print!("{}", result.uart);
+//! }
+//! ```
+
+/// Module: assembler passes and the `Assembler` builder.
+pub mod assembler;
+/// Module for the emulator's instruction and data cache simulator.
+pub mod cache_sim;
+/// Module for the emulator's interactive debugger.
+pub mod debugger;
+/// Module to disassemble images back to re-assemblable source.
+pub mod disasm;
+/// Module to find netboot boards on the local network by UDP broadcast.
+pub mod discover;
+/// Module: independent ISA emulator (golden-model trace generator).
+pub mod emulate;
+/// Module for expect-style scripts driving interactive programs.
+pub mod expect;
+/// Module for the emulator-backed virtual board (`--fake-board`).
+pub mod fake_board;
+/// Module to manage file read and write.
+#[allow(
+    clippy::must_use_candidate,
+    clippy::unnecessary_trailing_comma,
+    reason = "pre-library code kept unchanged"
+)]
+pub mod files;
+/// Module for the emulator's GDB remote serial protocol stub.
+pub mod gdb_stub;
+/// Module of helper functions.
+#[allow(
+    clippy::must_use_candidate,
+    clippy::unnecessary_trailing_comma,
+    reason = "pre-library code kept unchanged"
+)]
+pub mod helper;
+/// Module to write program images as Intel HEX, S-record, raw binary, `.mem` or `.coe`.
+pub mod image_format;
+/// Module to read the `.kbt` board wire format.
+pub mod kbt;
+/// Module to manage labels.
+#[allow(clippy::must_use_candidate, reason = "pre-library code kept unchanged")]
+pub mod labels;
+/// Module to manage macros.
+#[allow(
+    clippy::must_use_candidate,
+    clippy::missing_panics_doc,
+    clippy::unnecessary_trailing_comma,
+    reason = "pre-library code kept unchanged"
+)]
+pub mod macros;
+/// Module for the `klauss.toml` project manifest.
+pub mod manifest;
+/// Module to manage messages.
+#[allow(clippy::must_use_candidate, reason = "pre-library code kept unchanged")]
+pub mod messages;
+/// Module for the emulator-backed board side of network boot (`--netboot-server`).
+pub mod netboot_server;
+/// Module to stream a flat DDR image to the board over TCP (network boot).
+pub mod netload;
+/// Module to manage opcodes.
+#[allow(clippy::must_use_candidate, reason = "pre-library code kept unchanged")]
+pub mod opcodes;
+/// Module for the emulator's board peripherals (LEDs, 7-seg, RGB, LCD, switches).
+pub mod peripherals;
+/// Module for the emulator's execution profiler.
+pub mod profiler;
+/// Module to write to serial and read response.
+#[allow(clippy::must_use_candidate, reason = "pre-library code kept unchanged")]
+pub mod serial;
+/// Module for the image size report and build comparison.
+pub mod size_report;
+/// Module to look up the label an address falls under (for emulator reports).
+pub mod symbols;
+/// Module of fixtures shared by the unit tests.
+#[cfg(test)]
+mod test_support;
+/// Module for the emulator's cycle-approximate timing model.
+pub mod timing;
+/// Module of board transports: serial, pty and TCP.
+pub mod transport;
+
+pub use assembler::{Assembler, Assembly, Isa, Symbol};
+
+/// Magic bytes at the start of every ELF file (`0x7F` `E` `L` `F`).
+pub const ELF_MAGIC: &[u8] = b"\x7fELF";
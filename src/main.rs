@@ -4,40 +4,30 @@
 mod cli;
 /// Module of subcommand handlers (the `run_*` entry points).
 mod commands;
-/// Module: independent ISA emulator (golden-model trace generator).
-mod emulate;
-/// Module to manage file read and write.
-mod files;
-/// Module of helper functions.
-mod helper;
-/// Module to manage labels.
-mod labels;
-/// Module to manage macros.
-mod macros;
-/// Module to manage messages.
-mod messages;
-/// Module to stream a flat DDR image to the board over TCP (network boot).
-mod netload;
-/// Module to manage opcodes.
-mod opcodes;
-/// Module to write to serial and read response.
-mod serial;
+/// Module for `--watch` re-assembly on file changes.
+mod watch;
 use chrono::{Local, NaiveTime};
-use cli::set_matches;
+use cli::{cli_value, mem_layout, monitor_options, net_load_options, project_manifest, set_matches, uart_input};
 use commands::{
-    run_elf2serial, run_emulate, run_emulate_elf, run_emulate_test, run_kbt_send, run_mem_out, run_netload, run_test_list, run_test_mode,
+    find_board, monitor_exit, run_disasm, run_discover, run_elf2serial, run_emulate, run_emulate_elf, run_emulate_test, run_fake_board, run_inspect,
+    run_kbt_send, run_mem_out, run_netboot_server, run_netload, run_round_trip_check, run_size_diff, run_test_list, run_test_mode, write_mem_image,
+    EmulateOptions,
 };
-use files::{filename_stem, read_file_to_vector, remove_block_comments, write_binary_output_file, write_code_output_file, LineType};
-use helper::{build_ddr_image, create_bin_string, data_as_bytes, is_valid_line, line_type, num_data_bytes, strip_comments, HEAP_HEADER_WORDS};
-use labels::{find_duplicate_label, get_labels, Label};
-use macros::{expand_embedded_macros, expand_macros};
-use messages::{print_messages, MessageType, MsgList};
-use netload::NETBOOT_DEFAULT_PORT;
-use opcodes::{add_arguments, add_registers, num_arguments, parse_vh_file, Opcode, Pass0, Pass1, Pass2};
-use serial::{monitor_serial, monitor_serial_port, write_to_board, write_to_board_keep_port, AUTO_SERIAL};
-
-/// Magic bytes at the start of every ELF file (`0x7F` `E` `L` `F`).
-pub(crate) const ELF_MAGIC: &[u8] = b"\x7fELF";
+use klausscc::assembler::build_flat_code;
+use klausscc::cache_sim::CacheConfig;
+use klausscc::emulate::{self, UartInput};
+use klausscc::files::{filename_stem, write_binary_output_file, write_code_output_file};
+use klausscc::helper::{build_ddr_image, create_bin_string, parse_entry_point};
+use klausscc::image_format::{ImageFormat, MemLayout};
+use klausscc::messages::{print_messages, MessageType, MsgList};
+use klausscc::netload::{console_port_name, CONSOLE_PORT_OFFSET, NETBOOT_DEFAULT_PORT};
+use klausscc::opcodes::Pass2;
+use klausscc::serial::{monitor_serial, monitor_serial_port, write_to_board, write_to_board_keep_port, AUTO_SERIAL};
+use klausscc::size_report::{format_size_report, size_report};
+use klausscc::{Assembler, Isa};
+use std::fs;
+use std::path::{Path, PathBuf};
+use watch::{run_watch, WatchOptions};
 
 /// Main function for Klausscc.
 ///
@@ -46,7 +36,7 @@ pub(crate) const ELF_MAGIC: &[u8] = b"\x7fELF";
 fn main() -> Result<(), i32> {
     use std::fs::remove_file;
 
-    use files::output_macros_opcodes_html;
+    use klausscc::files::output_macros_opcodes_html;
 
     let mut msg_list = MsgList::new();
     /* Stream messages as they happen rather than dumping them all at the end:
@@ -58,40 +48,115 @@ fn main() -> Result<(), i32> {
     let start_time: NaiveTime = Local::now().time();
 
     let matches = set_matches().get_matches();
-    let opcode_file_name: String = matches
-        .get_one::<String>("opcode_file")
-        .unwrap_or(&"opcode_select.vh".to_owned())
+    let Some(manifest) = project_manifest(&matches, &mut msg_list) else {
+        print_messages(&msg_list);
+        return Err(1);
+    };
+    let target = match matches.get_one::<String>("target") {
+        Some(name) => {
+            let Some(target) = manifest.target.get(name) else {
+                msg_list.push(format!("No target \"{name}\" in the project manifest"), None, None, MessageType::Error);
+                print_messages(&msg_list);
+                return Err(1);
+            };
+            Some(target)
+        }
+        None => None,
+    };
+    let suite = match matches.get_one::<String>("suite") {
+        Some(name) => {
+            let Some(suite) = manifest.suite.get(name) else {
+                msg_list.push(
+                    format!("No test suite \"{name}\" in the project manifest"),
+                    None,
+                    None,
+                    MessageType::Error,
+                );
+                print_messages(&msg_list);
+                return Err(1);
+            };
+            Some(suite)
+        }
+        None => None,
+    };
+
+    // Command-line options win; the manifest fills in anything not given.
+    let opcode_file: Option<String> = cli_value(&matches, "opcode_file").or_else(|| manifest.opcode.clone());
+    let opcode_file_name: String = opcode_file.clone().unwrap_or_else(|| "opcode_select.vh".to_owned()).replace(' ', "");
+    let input_file_name: String = cli_value(&matches, "input")
+        .or_else(|| target.map(|t| t.input.clone()))
+        .unwrap_or_default()
         .replace(' ', "");
-    let input_file_name: String = matches.get_one::<String>("input").unwrap_or(&String::default()).replace(' ', "");
-    let mut binary_file_name: String = matches
-        .get_one::<String>("bitcode")
-        .unwrap_or(&filename_stem(&input_file_name))
+    let mut binary_file_name: String = cli_value(&matches, "bitcode")
+        .or_else(|| target.and_then(|t| t.bitcode.clone()))
+        .unwrap_or_else(|| filename_stem(&input_file_name))
         .replace(' ', "");
     binary_file_name.push_str(".kbt");
-    let mut output_file_name: String = matches
-        .get_one::<String>("output")
-        .unwrap_or(&filename_stem(&input_file_name))
+    let mut output_file_name: String = cli_value(&matches, "output")
+        .or_else(|| target.and_then(|t| t.output.clone()))
+        .unwrap_or_else(|| filename_stem(&input_file_name))
         .replace(' ', "");
     output_file_name.push_str(".code");
-    let output_serial_port: String = matches.get_one::<String>("serial").unwrap_or(&String::default()).replace(' ', "");
+    // The manifest port stands in for auto-detection: bare `-s`, monitor-only and board test runs.
+    let default_serial_port: String = manifest.serial.clone().unwrap_or_else(|| AUTO_SERIAL.to_owned());
+    let output_serial_port: String = match matches.get_one::<String>("serial") {
+        Some(port) if port == AUTO_SERIAL => default_serial_port.clone(),
+        Some(port) => port.replace(' ', ""),
+        None => String::default(),
+    };
+    let include_paths: Vec<PathBuf> = matches
+        .get_many::<String>("include")
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .chain(manifest.include_paths())
+        .collect();
     let opcodes_flag = matches.get_flag("opcodes");
     let textmate_flag = matches.get_flag("textmate");
-    let monitor_flag = matches.get_flag("monitor");
+    // An expect script talks to the board through the monitor, so it implies -m.
+    let monitor_flag = matches.get_flag("monitor") || matches.contains_id("script");
     let test_flag = matches.get_flag("test");
     let no_break_flag = matches.get_flag("no_break");
-    let debug_flag = matches.get_flag("debug");
-    let test_timeout: u64 = matches
-        .get_one::<String>("test_timeout")
+    let monitor_options = monitor_options(&matches);
+    let test_timeout: u64 = cli_value(&matches, "test_timeout")
         .and_then(|timeout_str| timeout_str.parse().ok())
+        .or_else(|| suite.and_then(|s| s.timeout))
+        .or(manifest.test_timeout)
         .unwrap_or(10);
-    let test_list_file: String = matches.get_one::<String>("test_list").unwrap_or(&String::default()).replace(' ', "");
+    let test_list_file: String = cli_value(&matches, "test_list")
+        .or_else(|| suite.filter(|s| !s.emulate).map(|s| s.list.clone()))
+        .unwrap_or_default()
+        .replace(' ', "");
     let emulate_flag = matches.get_flag("emulate");
     let trace_file: Option<String> = matches.get_one::<String>("trace").cloned();
-    let emulate_test_file: Option<String> = matches.get_one::<String>("emulate_test").cloned();
-    let max_instructions: u64 = matches
-        .get_one::<String>("max_instructions")
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(emulate::DEFAULT_MAX_INSTRUCTIONS);
+    let emulate_test_file: Option<String> = cli_value(&matches, "emulate_test").or_else(|| suite.filter(|s| s.emulate).map(|s| s.list.clone()));
+    let max_instructions_arg: Option<u64> = matches.get_one::<String>("max_instructions").and_then(|s| s.parse().ok());
+    let max_instructions: u64 = max_instructions_arg.unwrap_or(emulate::DEFAULT_MAX_INSTRUCTIONS);
+    let watch_flag = matches.get_flag("watch");
+    let input = uart_input(&matches);
+    // Ctrl+C ends an interactive run, so it is not capped unless asked to be.
+    let emulate_max_instructions = match max_instructions_arg {
+        None if input == UartInput::Terminal => u64::MAX,
+        _ => max_instructions,
+    };
+    let emulate_options = EmulateOptions {
+        trace_file: trace_file.as_deref(),
+        script: monitor_options.script.as_ref(),
+        input,
+        max_instructions: emulate_max_instructions,
+        debug: emulate_flag && matches.get_flag("debug"),
+        opcodes: &[],
+        gdb: matches.get_one::<String>("gdb").map(String::as_str),
+        switches: matches.get_one::<u64>("switches").copied().unwrap_or(0),
+        panel: matches.get_flag("panel"),
+        peripheral_log: matches.get_one::<String>("peripheral_log").map(String::as_str),
+        timing: matches.get_one::<String>("timing").map(String::as_str),
+        clock_mhz: matches.get_one::<f64>("clock_mhz").copied(),
+        icache: matches.get_one::<CacheConfig>("icache").copied(),
+        dcache: matches.get_one::<CacheConfig>("dcache").copied(),
+        profile: matches.get_flag("profile"),
+        profile_folded: matches.get_one::<String>("profile_folded").map(String::as_str),
+    };
 
     // Classify the input file by extension (case-insensitive).  The file type is
     // determined from the name rather than from a mode-specific flag:
@@ -102,6 +167,17 @@ fn main() -> Result<(), i32> {
     let is_kbt_input = input_lower.ends_with(".kbt");
     let is_binary_input = !input_file_name.is_empty() && !input_lower.ends_with(".kla") && !is_kbt_input;
 
+    if watch_flag && (is_binary_input || is_kbt_input) {
+        msg_list.push(
+            "Watch mode (--watch) needs a .kla input or --emulate-test".to_owned(),
+            None,
+            None,
+            MessageType::Error,
+        );
+        print_messages(&msg_list);
+        return Err(1);
+    }
+
     // Monitor-only mode: `-m` on its own (optionally with `-s`) just opens the
     // serial monitor — no input file, no assembly, no opcode file needed.  With
     // no `-s` the first USB serial port is auto-detected.
@@ -114,51 +190,128 @@ fn main() -> Result<(), i32> {
         && !textmate_flag
     {
         let monitor_port = if output_serial_port.is_empty() {
-            AUTO_SERIAL
+            default_serial_port.as_str()
         } else {
             output_serial_port.as_str()
         };
-        if let Err(err) = monitor_serial(monitor_port, debug_flag, &mut msg_list) {
-            msg_list.push(format!("Serial monitor stopped: \"{err}\""), None, None, MessageType::Warning);
-        }
+        let result = monitor_exit(
+            monitor_serial(monitor_port, &monitor_options, &mut msg_list),
+            &monitor_options,
+            &mut msg_list,
+        );
         print_messages(&msg_list);
-        return Ok(());
+        return result;
     }
 
     // net-load mode: flatten an ELF (or take a flat binary) and stream it to the
     // board over TCP — no kbt, no UART. See NETBOOT_PLAN.md / netboot.c.
     if let Some(net_binary_path) = matches.get_one::<String>("net_load") {
-        let entry_addr: Option<u32> = matches.get_one::<String>("entry_point").map(|s| {
-            if s.len() >= 2 && s.get(..2).is_some_and(|p| p.eq_ignore_ascii_case("0x")) {
-                u32::from_str_radix(&s[2..], 16).unwrap_or(0x20)
-            } else {
-                s.parse::<u32>().unwrap_or(0x20)
-            }
-        });
-        let board_ip = matches.get_one::<String>("ip").cloned().unwrap_or_default();
-        let board_port: u16 = matches
-            .get_one::<String>("port")
+        let entry_addr: Option<u32> = matches.get_one::<String>("entry_point").map(|s| parse_entry_point(s));
+        let mut board_ip = cli_value(&matches, "ip").or_else(|| manifest.board.ip.clone()).unwrap_or_default();
+        let mut board_port: u16 = cli_value(&matches, "port")
             .and_then(|p| p.parse().ok())
+            .or(manifest.board.port)
             .unwrap_or(NETBOOT_DEFAULT_PORT);
-        let load_result = run_netload(net_binary_path, entry_addr, &board_ip, board_port, &mut msg_list, start_time);
+        let mut console_port: Option<u16> = matches.get_one::<u16>("console_port").copied();
+        // --board <name> or --ip auto: look the board up by discovery; an explicit --port still wins.
+        let board_name = cli_value(&matches, "board").or_else(|| manifest.board.name.clone().filter(|_| cli_value(&matches, "ip").is_none()));
+        if board_name.is_some() || board_ip == "auto" {
+            let discover_address = matches.get_one::<String>("discover_address").cloned().unwrap_or_default();
+            let Some(board) = find_board(&discover_address, board_name.as_deref(), &mut msg_list) else {
+                print_results(&msg_list, start_time);
+                return Err(1);
+            };
+            board_ip = board.ip.map(|ip| ip.to_string()).unwrap_or_default();
+            if cli_value(&matches, "port").is_none() {
+                board_port = board.port;
+            }
+            console_port = console_port.or(board.console);
+        }
+        let load_result = run_netload(
+            net_binary_path,
+            entry_addr,
+            &board_ip,
+            board_port,
+            &net_load_options(&matches),
+            &mut msg_list,
+            start_time,
+        );
 
         /* After a net-load, optionally monitor the board's UART (and forward
-         * keystrokes), exactly like -s -m.  The load itself is over TCP, so the
-         * monitor needs its own serial port: honour -s if given, otherwise
-         * auto-detect the first USB serial port (same as -s with no value). */
+         * keystrokes), exactly like -s -m.  With -s the monitor uses that port;
+         * with --console-port, or a board that announces a console, it connects
+         * to the board's network console; otherwise it uses the manifest port
+         * or the first USB serial port (same as -s with no value). */
         if load_result.is_ok() && monitor_flag {
-            let monitor_port = if output_serial_port.is_empty() {
-                AUTO_SERIAL
-            } else {
-                output_serial_port.as_str()
+            let monitor_port = match console_port {
+                _ if !output_serial_port.is_empty() => output_serial_port.clone(),
+                Some(console) => console_port_name(&board_ip, console),
+                None => default_serial_port.clone(),
             };
-            if let Err(err) = monitor_serial(monitor_port, debug_flag, &mut msg_list) {
-                msg_list.push(format!("Serial monitor stopped: \"{err}\""), None, None, MessageType::Warning);
-            }
+            let result = monitor_exit(
+                monitor_serial(&monitor_port, &monitor_options, &mut msg_list),
+                &monitor_options,
+                &mut msg_list,
+            );
+            print_messages(&msg_list);
+            return result;
         }
         return load_result;
     }
 
+    // fake-board mode: serve the UART loader from the emulator until interrupted.
+    if let Some(endpoint) = matches.get_one::<String>("fake_board") {
+        let result = run_fake_board(endpoint, max_instructions, &mut msg_list);
+        print_messages(&msg_list);
+        return result;
+    }
+
+    // netboot-server mode: accept KNET uploads and run them in the emulator until interrupted.
+    if let Some(address) = matches.get_one::<String>("netboot_server") {
+        // A bare --uart-tcp serves the console on the boards' usual port: the netboot port + 1.
+        let uart_address = matches.get_one::<String>("uart_tcp").map(|uart| {
+            if uart.is_empty() {
+                address.parse::<std::net::SocketAddr>().map_or_else(
+                    |_| uart.clone(),
+                    |mut console| {
+                        console.set_port(console.port().wrapping_add(CONSOLE_PORT_OFFSET));
+                        console.to_string()
+                    },
+                )
+            } else {
+                uart.clone()
+            }
+        });
+        let board_name = matches.get_one::<String>("board").map_or("emulator", String::as_str);
+        let discover_address = matches.get_one::<String>("discover_address").map_or("", String::as_str);
+        let result = run_netboot_server(
+            address,
+            uart_address.as_deref(),
+            (discover_address, board_name),
+            max_instructions,
+            &mut msg_list,
+        );
+        print_messages(&msg_list);
+        return result;
+    }
+
+    // discover mode: list the netboot boards on the local network.
+    if matches.get_flag("discover") {
+        let discover_address = matches.get_one::<String>("discover_address").map_or("", String::as_str);
+        return run_discover(discover_address, &mut msg_list, start_time);
+    }
+
+    // size-diff mode: compare two saved size reports.
+    if let Some(mut reports) = matches.get_many::<String>("size_diff") {
+        let (old_report, new_report) = (reports.next().cloned().unwrap_or_default(), reports.next().cloned().unwrap_or_default());
+        return run_size_diff(&old_report, &new_report, &mut msg_list, start_time);
+    }
+
+    // inspect mode: decode and validate a .kbt without touching the board.
+    if let Some(kbt_path) = matches.get_one::<String>("inspect") {
+        return run_inspect(kbt_path, &opcode_file_name, matches.get_flag("inspect_disasm"), &mut msg_list, start_time);
+    }
+
     // mem-out mode: flatten an ELF (or take a flat binary) and write a $readmemh
     // image for the resident boot ROM (boot_rom.v). See NETBOOT_PLAN.md Phase 2.
     if let Some(mem_binary_path) = matches.get_one::<String>("mem_out") {
@@ -167,29 +320,28 @@ fn main() -> Result<(), i32> {
             .get_one::<String>("mem_file")
             .cloned()
             .unwrap_or_else(|| format!("{mem_stem}.mem"));
-        return run_mem_out(mem_binary_path, &mem_file_name, &mut msg_list, start_time);
+        return run_mem_out(mem_binary_path, &mem_file_name, &mem_layout(&matches), &mut msg_list, start_time);
     }
 
     // ELF / flat binary input: convert directly to the board wire format and
     // optionally send it.  No opcode file is required — it is only used, if it
     // happens to exist, to emit a .code disassembly listing alongside the .kbt.
     if is_binary_input {
-        let entry_addr: Option<u32> = matches.get_one::<String>("entry_point").map(|s| {
-            if s.len() >= 2 && s.get(..2).is_some_and(|p| p.eq_ignore_ascii_case("0x")) {
-                u32::from_str_radix(&s[2..], 16).unwrap_or(0x20)
-            } else {
-                s.parse::<u32>().unwrap_or(0x20)
-            }
-        });
+        let entry_addr: Option<u32> = matches.get_one::<String>("entry_point").map(|s| parse_entry_point(s));
         if emulate_flag {
-            return run_emulate_elf(
-                &input_file_name,
-                entry_addr,
-                trace_file.as_deref(),
-                max_instructions,
-                &mut msg_list,
-                start_time,
-            );
+            // The debugger disassembles, and a timing table names opcodes, with the
+            // opcode file when there is one.
+            let wants_opcodes = emulate_options.debug || emulate_options.timing.is_some_and(|table| !table.is_empty());
+            let elf_isa = if wants_opcodes && Path::new(&opcode_file_name).exists() {
+                Isa::from_vh_file(&opcode_file_name, &mut msg_list)
+            } else {
+                None
+            };
+            let emulate_options = EmulateOptions {
+                opcodes: elf_isa.as_ref().map_or(&[], |isa| isa.opcodes.as_slice()),
+                ..emulate_options
+            };
+            return run_emulate_elf(&input_file_name, entry_addr, &emulate_options, &mut msg_list, start_time);
         }
         return run_elf2serial(
             &input_file_name,
@@ -198,7 +350,7 @@ fn main() -> Result<(), i32> {
             &output_serial_port,
             &binary_file_name,
             monitor_flag,
-            debug_flag,
+            &monitor_options,
             no_break_flag,
             &mut msg_list,
             start_time,
@@ -211,7 +363,7 @@ fn main() -> Result<(), i32> {
             &input_file_name,
             &output_serial_port,
             monitor_flag,
-            debug_flag,
+            &monitor_options,
             no_break_flag,
             &mut msg_list,
             start_time,
@@ -220,7 +372,7 @@ fn main() -> Result<(), i32> {
 
     // From here on the only remaining work needs the opcode file: assembling a
     // .kla file, or emitting the opcode/textmate JSON.  Require it explicitly.
-    if matches.get_one::<String>("opcode_file").is_none() {
+    if opcode_file.is_none() {
         msg_list.push(
             "An opcode file (-c/--opcode) is required to assemble a .kla file or output opcode/textmate JSON".to_owned(),
             None,
@@ -231,12 +383,32 @@ fn main() -> Result<(), i32> {
         return Err(1);
     }
 
-    // Parse the opcode file
-    let mut opened_files: Vec<String> = Vec::new(); // Used for recursive includes check
-    let vh_list = read_file_to_vector(&opcode_file_name, &mut msg_list, &mut opened_files);
-    let (opt_oplist, opt_macro_list) = parse_vh_file(vh_list.unwrap_or_default(), &mut msg_list);
+    // Watch mode: rebuild (and re-run) on every source change until Ctrl+C.
+    if watch_flag {
+        return run_watch(
+            &WatchOptions {
+                opcode_file_name: &opcode_file_name,
+                include_paths: &include_paths,
+                input_file_name: &input_file_name,
+                output_file_name: &output_file_name,
+                binary_file_name: &binary_file_name,
+                emulate: emulate_flag,
+                trace_file: trace_file.as_deref(),
+                uart_input: match &emulate_options.input {
+                    UartInput::Bytes(bytes) => bytes,
+                    UartInput::Stdin | UartInput::Terminal => &[],
+                },
+                emulate_test_path: emulate_test_file.as_deref(),
+                max_instructions,
+                serial_port: &output_serial_port,
+                send_break: !no_break_flag,
+            },
+            &mut msg_list,
+        );
+    }
 
-    if opt_macro_list.is_none() || opt_oplist.is_none() {
+    // Parse the opcode file
+    let Some(isa) = Isa::from_vh_file(&opcode_file_name, &mut msg_list) else {
         msg_list.push(
             format!("Error parsing opcode file {opcode_file_name} to macro and opcode lists"),
             None,
@@ -245,14 +417,12 @@ fn main() -> Result<(), i32> {
         );
         print_messages(&msg_list);
         return Err(1);
-    }
-    let oplist = opt_oplist.unwrap_or_else(|| [].to_vec());
-    let mut macro_list = expand_embedded_macros(opt_macro_list.unwrap_or_else(|| [].to_vec()), &mut msg_list);
+    };
 
     if let Err(result_err) = output_macros_opcodes_html(
         filename_stem(&opcode_file_name),
-        &oplist,
-        macro_list.clone(),
+        &isa.opcodes,
+        isa.macros.clone(),
         &mut msg_list,
         opcodes_flag,
         textmate_flag,
@@ -270,58 +440,65 @@ fn main() -> Result<(), i32> {
         return Ok(());
     }
 
+    // Disassembly mode and/or the opcode round-trip self-check.
+    if matches.get_flag("round_trip") {
+        let check_result = run_round_trip_check(&isa, &mut msg_list, start_time);
+        if check_result.is_err() || matches.get_one::<String>("disasm").is_none() {
+            return check_result;
+        }
+    }
+    if let Some(disasm_path) = matches.get_one::<String>("disasm") {
+        let entry_addr: Option<u32> = matches.get_one::<String>("entry_point").map(|s| parse_entry_point(s));
+        let disasm_out = matches
+            .get_one::<String>("disasm_out")
+            .cloned()
+            .unwrap_or_else(|| format!("{}_disasm.kla", filename_stem(disasm_path)));
+        return run_disasm(&isa, disasm_path, &disasm_out, entry_addr, &mut msg_list, start_time);
+    }
+
     // Emulator batch-verify mode: assemble + emulate each .kla and check UART.
     if let Some(test_path) = emulate_test_file {
-        return run_emulate_test(&oplist, &macro_list, &test_path, max_instructions, &mut msg_list, start_time);
+        return run_emulate_test(&isa, &include_paths, &test_path, max_instructions, &mut msg_list, start_time);
     }
 
     // Batch test list mode
     if !test_list_file.is_empty() {
+        let test_serial_port = if output_serial_port.is_empty() {
+            manifest.serial.clone().unwrap_or_default()
+        } else {
+            output_serial_port.clone()
+        };
         return run_test_list(
-            &oplist,
-            &macro_list,
+            &isa,
+            &include_paths,
             &test_list_file,
-            &output_serial_port,
+            &test_serial_port,
             test_timeout,
             !no_break_flag,
             &mut msg_list,
         );
     }
 
-    // Parse the input file
+    // Parse the input file and assemble it
     msg_list.push(format!("Input file is {input_file_name}"), None, None, MessageType::Information);
-    let mut opened_input_files: Vec<String> = Vec::new(); // Used for recursive includes check
-    let input_list_option = read_file_to_vector(&input_file_name, &mut msg_list, &mut opened_input_files);
-    if input_list_option.is_none() {
+    let assembly = Assembler::new(&isa)
+        .source_file(input_file_name.as_str())
+        .include_paths(&include_paths)
+        .assemble_into(&mut msg_list);
+    if assembly.pass2.is_empty() && !assembly.is_ok() {
         print_messages(&msg_list);
         return Err(1);
     }
-    let input_list = input_list_option.unwrap_or_else(|| [].to_vec());
-
-    let input_list = remove_block_comments(input_list, &mut msg_list);
-
-    // Pass 0 to add macros
-    let pass0 = expand_macros(&mut msg_list, input_list, &mut macro_list);
-
-    // Pass 1 to get line numbers and labels
-    let pass1: Vec<Pass1> = get_pass1(&mut msg_list, pass0, oplist.clone());
-    let mut labels = get_labels(&pass1, &mut msg_list);
-    find_duplicate_label(&mut labels, &mut msg_list);
-
-    // Pass 2 to get create output
-    let mut pass2 = get_pass2(&mut msg_list, pass1, oplist, labels);
+    let mut pass2 = assembly.pass2;
 
     // Emulator mode: build the flat DDR image from the assembled program and run
     // the golden-model. Additive — returns early, leaving normal modes untouched.
     if emulate_flag {
-        return run_emulate(
-            &pass2,
-            &input_file_name,
-            trace_file.as_deref(),
-            max_instructions,
-            &mut msg_list,
-            start_time,
-        );
+        let emulate_options = EmulateOptions {
+            opcodes: &isa.opcodes,
+            ..emulate_options
+        };
+        return run_emulate(&pass2, &input_file_name, &emulate_options, &mut msg_list, start_time);
     }
 
     if let Err(result_err) = write_code_output_file(&output_file_name, &mut pass2, &mut msg_list) {
@@ -335,6 +512,25 @@ fn main() -> Result<(), i32> {
         return Err(1);
     }
 
+    let image_formats: Vec<ImageFormat> = matches
+        .get_many::<String>("format")
+        .unwrap_or_default()
+        .filter_map(|name| ImageFormat::from_name(name))
+        .collect();
+    if !image_formats.is_empty() && msg_list.number_by_type(&MessageType::Error) == 0 {
+        write_image_formats(
+            &pass2,
+            &filename_stem(&binary_file_name),
+            &image_formats,
+            &mem_layout(&matches),
+            &mut msg_list,
+        );
+    }
+
+    if matches.get_flag("size_report") && msg_list.number_by_type(&MessageType::Error) == 0 {
+        write_size_report(&pass2, &format!("{}.size.json", filename_stem(&binary_file_name)), &mut msg_list);
+    }
+
     if msg_list.number_by_type(&MessageType::Error) == 0 {
         if let Some(bin_string) = create_bin_string(&pass2, &mut msg_list) {
             write_binary_file(&mut msg_list, &binary_file_name, &bin_string);
@@ -357,11 +553,13 @@ fn main() -> Result<(), i32> {
                         Ok(port) => {
                             msg_list.push("Wrote to serial port".to_owned(), None, None, MessageType::Information);
                             print_results(&msg_list, start_time);
-                            if let Err(err) = monitor_serial_port(port, debug_flag, &mut msg_list) {
-                                msg_list.push(format!("Serial monitor stopped: \"{err}\""), None, None, MessageType::Warning);
-                                print_messages(&msg_list);
-                            }
-                            return Ok(());
+                            let result = monitor_exit(
+                                monitor_serial_port(port, &monitor_options, &mut msg_list),
+                                &monitor_options,
+                                &mut msg_list,
+                            );
+                            print_messages(&msg_list);
+                            return result;
                         }
                         Err(err) => {
                             msg_list.push(format!("Failed to write to serial port, error \"{err}\""), None, None, MessageType::Error);
@@ -404,10 +602,13 @@ fn main() -> Result<(), i32> {
             print_messages(&msg_list);
             return Err(1);
         }
-        if let Err(err) = monitor_serial(&output_serial_port, debug_flag, &mut msg_list) {
-            msg_list.push(format!("Serial monitor stopped: \"{err}\""), None, None, MessageType::Warning);
-            print_messages(&msg_list);
-        }
+        let result = monitor_exit(
+            monitor_serial(&output_serial_port, &monitor_options, &mut msg_list),
+            &monitor_options,
+            &mut msg_list,
+        );
+        print_messages(&msg_list);
+        result?;
     }
 
     // Check test flag requires serial port
@@ -420,193 +621,6 @@ fn main() -> Result<(), i32> {
     Ok(())
 }
 
-/// Auto-upgrade `SETR R value` to `SETR64 R value` when the immediate doesn't fit in 32 bits.
-///
-/// Returns the (possibly rewritten) line string unchanged for all other mnemonics or
-/// when the value fits in the 32-bit range accepted by `convert_argument`
-/// (i.e. `i32::MIN ..= 0xFFFF_FFFF`).
-fn upgrade_setr_to_setr64(line: &str) -> String {
-    let stripped = strip_comments(line);
-    let mut words = stripped.split_whitespace();
-    if !words.next().unwrap_or("").eq_ignore_ascii_case("setr") {
-        return line.to_owned();
-    }
-    let _reg = words.next(); // skip register name
-    let Some(val_str) = words.next() else {
-        return line.to_owned();
-    };
-    // Parse as signed 64-bit so we handle negative decimal and full-width hex.
-    let val: i64 = if val_str.len() >= 2 && val_str.get(..2).is_some_and(|s| s.eq_ignore_ascii_case("0x")) {
-        let hex = &val_str[2..].replace('_', "");
-        u64::from_str_radix(hex, 16).map_or(0, |v| v as i64)
-    } else {
-        val_str.parse::<i64>().unwrap_or(0)
-    };
-    // Same bounds as convert_argument: [i32::MIN, 0xFFFF_FFFF] fits in 32 bits.
-    if val >= i64::from(i32::MIN) && val <= 0xFFFF_FFFF_i64 {
-        return line.to_owned();
-    }
-    // Upgrade: replace the leading "SETR" (any case) with "SETR64".
-    let trimmed = line.trim_start();
-    let leading_ws = &line[..line.len() - trimmed.len()];
-    // SAFETY: trimmed starts with "setr" (4 ASCII chars), confirmed by eq_ignore_ascii_case above.
-    format!("{leading_ws}SETR64{}", &trimmed[4..])
-}
-
-/// Returns pass1 from pass0.
-///
-/// Takes the macro expanded pass0 and returns vector of pass1, with the program counters.
-#[inline]
-pub fn get_pass1(msg_list: &mut MsgList, pass0: Vec<Pass0>, mut oplist: Vec<Opcode>) -> Vec<Pass1> {
-    let mut pass1: Vec<Pass1> = Vec::new();
-    let mut program_counter: u32 = HEAP_HEADER_WORDS * 8; // Byte address: 4 header words × 8 bytes each (64-bit words)
-    let mut data_pass0: Vec<Pass0> = Vec::new();
-    let mut in_data_section = false;
-
-    for pass in pass0 {
-        let stripped = strip_comments(&pass.input_text_line);
-        let first_word = stripped.split_whitespace().next().unwrap_or("").to_owned();
-
-        // Track section context for C compiler directives
-        match first_word.as_str() {
-            ".text" => {
-                in_data_section = false;
-            }
-            ".data" | ".rodata" | ".bss" => {
-                in_data_section = true;
-            }
-            _ => {}
-        }
-
-        // Expand .comm/.lcomm NAME SIZE into label + .space, defer to data section
-        if first_word == ".comm" || first_word == ".lcomm" {
-            let parts: Vec<&str> = stripped
-                .split(|c: char| c.is_whitespace() || c == ',')
-                .filter(|s| !s.is_empty())
-                .collect();
-            if parts.len() >= 3 {
-                let name = parts[1];
-                let size_str = parts[2];
-                let size: u32 = size_str.parse().unwrap_or(0);
-                data_pass0.push(Pass0 {
-                    input_text_line: format!("{name}:"),
-                    file_name: pass.file_name.clone(),
-                    line_counter: pass.line_counter,
-                });
-                if size > 0 {
-                    data_pass0.push(Pass0 {
-                        input_text_line: format!(".space {size}"),
-                        file_name: pass.file_name.clone(),
-                        line_counter: pass.line_counter,
-                    });
-                }
-            }
-            continue;
-        }
-
-        // Rewrite "SETR R val" → "SETR64 R val" before line_type/num_arguments when val > 32 bits.
-        let upgraded_line = upgrade_setr_to_setr64(&pass.input_text_line);
-        let lt = line_type(&mut oplist, &upgraded_line);
-
-        // Defer labels in data section to end of program with data
-        if in_data_section && lt == LineType::Label {
-            data_pass0.push(pass);
-            continue;
-        }
-
-        pass1.push(Pass1 {
-            input_text_line: upgraded_line.clone(),
-            file_name: pass.file_name.clone(),
-            line_counter: pass.line_counter,
-            program_counter,
-            line_type: lt.clone(),
-        });
-        if !is_valid_line(&mut oplist, strip_comments(&upgraded_line)) {
-            msg_list.push(
-                format!("Error {upgraded_line}"),
-                Some(pass.line_counter),
-                Some(pass.file_name.clone()),
-                MessageType::Error,
-            );
-        }
-        if lt == LineType::Opcode {
-            let num_args = num_arguments(&mut oplist, &strip_comments(&upgraded_line));
-            if let Some(arguments) = num_args {
-                program_counter += (arguments + 1) * 4; // Each word = 4 bytes
-            }
-        }
-
-        if lt == LineType::Data {
-            if in_data_section {
-                data_pass0.push(pass);
-                pass1.pop();
-            } else {
-                // Keep inline data when no explicit .data section is active.
-                // This preserves label semantics for C compiler output data blocks.
-                //
-                program_counter += num_data_bytes(&pass.input_text_line, msg_list, pass.line_counter, pass.file_name.clone()) / 2;
-            }
-        }
-    }
-    for data_pass in data_pass0 {
-        let lt = line_type(&mut oplist, &data_pass.input_text_line);
-        pass1.push(Pass1 {
-            input_text_line: data_pass.input_text_line.clone(),
-            file_name: data_pass.file_name.clone(),
-            line_counter: data_pass.line_counter,
-            program_counter,
-            line_type: lt.clone(),
-        });
-
-        if lt == LineType::Data {
-            program_counter += num_data_bytes(&data_pass.input_text_line, msg_list, data_pass.line_counter, data_pass.file_name) / 2;
-        }
-    }
-    pass1
-}
-
-/// Returns pass2 from pass1.
-///
-/// Pass1 with program counters and returns vector of pass2, with final values.
-#[inline]
-pub fn get_pass2(msg_list: &mut MsgList, pass1: Vec<Pass1>, mut oplist: Vec<Opcode>, mut labels: Vec<Label>) -> Vec<Pass2> {
-    let mut pass2: Vec<Pass2> = Vec::new();
-    for line in pass1 {
-        let new_opcode = if line.line_type == LineType::Opcode {
-            let mut opcode = add_registers(
-                &mut oplist,
-                &strip_comments(&line.input_text_line.clone()),
-                line.file_name.clone(),
-                msg_list,
-                line.line_counter,
-            );
-            opcode.push_str(&add_arguments(
-                &mut oplist,
-                &strip_comments(&line.input_text_line.clone()),
-                msg_list,
-                line.line_counter,
-                &line.file_name,
-                &mut labels,
-            ));
-            opcode
-        } else if line.line_type == LineType::Data {
-            data_as_bytes(line.input_text_line.as_str()).unwrap_or_default()
-        } else {
-            String::new()
-        };
-
-        pass2.push(Pass2 {
-            input_text_line: line.input_text_line,
-            file_name: line.file_name.clone(),
-            line_counter: line.line_counter,
-            program_counter: line.program_counter,
-            line_type: if new_opcode.contains("ERR") { LineType::Error } else { line.line_type },
-            opcode: new_opcode,
-        });
-    }
-    pass2
-}
-
 /// Prints results of assembly.
 ///
 /// Takes the message list and start time and prints the results to the users.
@@ -643,6 +657,54 @@ pub fn write_binary_file(msg_list: &mut MsgList, binary_file_name: &str, bin_str
     }
 }
 
+/// Write the assembled program in each requested `--format`, as `<stem>.<ext>`.
+///
+/// All formats are rendered from the same `build_ddr_image` image; `mem`
+/// follows the `--mem-*` layout options.
+#[cfg(not(tarpaulin_include))]
+fn write_image_formats(pass2: &[Pass2], stem: &str, formats: &[ImageFormat], layout: &MemLayout, msg_list: &mut MsgList) {
+    let Some((code, entry)) = build_flat_code(pass2) else {
+        msg_list.push(
+            "No _start entry point, not writing image files".to_owned(),
+            None,
+            None,
+            MessageType::Error,
+        );
+        return;
+    };
+    let image = build_ddr_image(&code);
+    for format in formats {
+        let file_name = format!("{stem}.{}", format.extension());
+        let Some(bytes) = format.render(&image, entry) else {
+            write_mem_image(&image, &file_name, layout, msg_list);
+            continue;
+        };
+        match fs::write(&file_name, bytes) {
+            Ok(()) => msg_list.push(format!("Writing image file to {file_name}"), None, None, MessageType::Information),
+            Err(e) => msg_list.push(format!("Unable to write image file {file_name}: {e}"), None, None, MessageType::Error),
+        }
+    }
+}
+
+/// Print the size report for an assembled program and save it as JSON.
+#[cfg(not(tarpaulin_include))]
+fn write_size_report(pass2: &[Pass2], json_file_name: &str, msg_list: &mut MsgList) {
+    let report = size_report(pass2);
+    print!("{}", format_size_report(&report));
+    match serde_json::to_string_pretty(&report)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(json_file_name, json).map_err(|e| e.to_string()))
+    {
+        Ok(()) => msg_list.push(format!("Writing size report to {json_file_name}"), None, None, MessageType::Information),
+        Err(e) => msg_list.push(
+            format!("Unable to write size report {json_file_name}: {e}"),
+            None,
+            None,
+            MessageType::Warning,
+        ),
+    }
+}
+
 /// Send machine code to device.
 ///
 /// Sends the resultant code on the serial device defined if no errors were found.
@@ -675,20 +737,16 @@ pub fn write_to_device(msg_list: &mut MsgList, bin_string: &str, output_serial_p
 /// Returns `Some(binary_string)` on success, `None` on assembly error.
 #[inline]
 #[cfg(not(tarpaulin_include))]
-pub fn assemble_file(input_file_name: &str, oplist: &[Opcode], macro_list: &[macros::Macro], msg_list: &mut MsgList) -> Option<String> {
+pub fn assemble_file(input_file_name: &str, isa: &Isa, include_paths: &[PathBuf], msg_list: &mut MsgList) -> Option<String> {
     msg_list.push(format!("Input file is {input_file_name}"), None, None, MessageType::Information);
-    let mut opened_input_files: Vec<String> = Vec::new();
-    let input_list_option = read_file_to_vector(input_file_name, msg_list, &mut opened_input_files);
-    input_list_option.as_ref()?;
-
-    let input_list = remove_block_comments(input_list_option.unwrap_or_else(|| [].to_vec()), msg_list);
-
-    let mut macro_list_clone = macro_list.to_vec();
-    let pass0 = expand_macros(msg_list, input_list, &mut macro_list_clone);
-    let pass1: Vec<Pass1> = get_pass1(msg_list, pass0, oplist.to_vec());
-    let mut labels = get_labels(&pass1, msg_list);
-    find_duplicate_label(&mut labels, msg_list);
-    let mut pass2 = get_pass2(msg_list, pass1, oplist.to_vec(), labels);
+    let assembly = Assembler::new(isa)
+        .source_file(input_file_name)
+        .include_paths(include_paths)
+        .assemble_into(msg_list);
+    if assembly.pass2.is_empty() && !assembly.is_ok() {
+        return None;
+    }
+    let mut pass2 = assembly.pass2;
 
     let output_file_name = format!("{}.code", filename_stem(&input_file_name.to_owned()));
     if let Err(result_err) = write_code_output_file(&output_file_name, &mut pass2, msg_list) {
@@ -708,358 +766,24 @@ pub fn assemble_file(input_file_name: &str, oplist: &[Opcode], macro_list: &[mac
     create_bin_string(&pass2, msg_list)
 }
 
-/// Build a flat little-endian code byte image from an assembled `Pass2` vector.
-///
-/// Each opcode/data entry's hex string is placed at its `program_counter`
-/// offset relative to the code base (`HEAP_HEADER_WORDS * 8`).  Opcode words
-/// are 8 hex chars (one 32-bit word) emitted little-endian so the emulator's
-/// `read32` reconstructs the natural value; data entries are byte sequences
-/// already in natural order and are emitted as 64-bit little-endian words.
-/// Returns `(code_bytes, entry_pc)` or `None` if there is no `_start`.
-pub(crate) fn build_flat_code(pass2: &[Pass2]) -> Option<(Vec<u8>, u32)> {
-    let code_base: u32 = HEAP_HEADER_WORDS * 8;
-    let mut code: Vec<u8> = Vec::new();
-    let mut entry: Option<u32> = None;
-
-    for line in pass2 {
-        if line.line_type == LineType::Start {
-            entry = Some(line.program_counter);
-            continue;
-        }
-        if line.opcode.is_empty() {
-            continue;
-        }
-        let offset = line.program_counter.saturating_sub(code_base) as usize;
-        // Convert the hex string into bytes: each 8-char (32-bit) group → LE bytes.
-        let hex = &line.opcode;
-        let mut bytes: Vec<u8> = Vec::with_capacity(hex.len() / 2);
-        let chunk: Vec<char> = hex.chars().collect();
-        let mut i = 0;
-        while i + 8 <= chunk.len() {
-            let s: String = chunk[i..i + 8].iter().collect();
-            if let Ok(w) = u32::from_str_radix(&s, 16) {
-                bytes.extend_from_slice(&w.to_le_bytes());
-            }
-            i += 8;
-        }
-        // Any trailing < 8-char group (test artefacts) — pad pairwise as raw bytes.
-        while i + 2 <= chunk.len() {
-            let s: String = chunk[i..i + 2].iter().collect();
-            if let Ok(b) = u8::from_str_radix(&s, 16) {
-                bytes.push(b);
-            }
-            i += 2;
-        }
-        if offset + bytes.len() > code.len() {
-            code.resize(offset + bytes.len(), 0);
-        }
-        code[offset..offset + bytes.len()].copy_from_slice(&bytes);
-    }
-
-    entry.map(|e| (code, e))
-}
-
 /// Assemble a `.kla` file into a flat DDR image + entry PC for the emulator.
 ///
-/// Runs the standard pass0→pass1→pass2 pipeline (same path the kbt/code output
-/// uses) and wraps the result with `build_ddr_image` (heap header + code).
+/// Runs the standard `Assembler` pipeline (same path the kbt/code output uses)
+/// and returns its `build_ddr_image` image (heap header + code).
 #[cfg(not(tarpaulin_include))]
-pub(crate) fn assemble_to_image(
-    input_file_name: &str,
-    oplist: &[Opcode],
-    macro_list: &[macros::Macro],
-    msg_list: &mut MsgList,
-) -> Option<(Vec<u8>, u32)> {
-    let mut opened_input_files: Vec<String> = Vec::new();
-    let input_list_option = read_file_to_vector(input_file_name, msg_list, &mut opened_input_files);
-    let input_list = remove_block_comments(input_list_option?, msg_list);
-    let mut macro_list_clone = macro_list.to_vec();
-    let pass0 = expand_macros(msg_list, input_list, &mut macro_list_clone);
-    let pass1: Vec<Pass1> = get_pass1(msg_list, pass0, oplist.to_vec());
-    let mut labels = get_labels(&pass1, msg_list);
-    find_duplicate_label(&mut labels, msg_list);
-    let pass2 = get_pass2(msg_list, pass1, oplist.to_vec(), labels);
-    if msg_list.number_by_type(&MessageType::Error) > 0 {
-        return None;
-    }
-    let (code, entry) = build_flat_code(&pass2)?;
-    Some((build_ddr_image(&code), entry))
+pub(crate) fn assemble_to_image(input_file_name: &str, isa: &Isa, include_paths: &[PathBuf], msg_list: &mut MsgList) -> Option<(Vec<u8>, u32)> {
+    let assembly = Assembler::new(isa)
+        .source_file(input_file_name)
+        .include_paths(include_paths)
+        .assemble_into(msg_list);
+    assembly.image.zip(assembly.entry)
 }
 
 #[cfg(test)]
 mod tests {
     #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
     use super::*;
-    use crate::helper::parse_expected_uart_values;
-
-    #[test]
-    // Test get_pass1 for correct vector returned, with correct program counters
-    fn test_get_pass1_1() {
-        let mut msg_list = MsgList::new();
-        let opcodes = &mut Vec::<Opcode>::new();
-        opcodes.push(Opcode {
-            text_name: String::from("PUSH"),
-            hex_code: String::from("0000001X"),
-            comment: String::new(),
-            variables: 0,
-            registers: 1,
-            section: String::new(),
-        });
-        opcodes.push(Opcode {
-            text_name: String::from("MOV"),
-            hex_code: String::from("00000020"),
-            comment: String::new(),
-            variables: 2,
-            registers: 0,
-            section: String::new(),
-        });
-        opcodes.push(Opcode {
-            text_name: String::from("RET"),
-            hex_code: String::from("00000030"),
-            comment: String::new(),
-            variables: 0,
-            registers: 0,
-            section: String::new(),
-        });
-
-        let pass0 = vec![
-            Pass0 {
-                input_text_line: "MOV A B".to_owned(),
-                file_name: String::new(),
-                line_counter: 1,
-            },
-            Pass0 {
-                input_text_line: "PUSH A".to_owned(),
-                file_name: String::new(),
-                line_counter: 2,
-            },
-            Pass0 {
-                input_text_line: "RET".to_owned(),
-                file_name: String::new(),
-                line_counter: 3,
-            },
-            Pass0 {
-                input_text_line: "#DATA1 0x2".to_owned(), // Should be moved to end
-                file_name: String::new(),
-                line_counter: 4,
-            },
-            Pass0 {
-                input_text_line: "RET".to_owned(),
-                file_name: String::new(),
-                line_counter: 5,
-            },
-            Pass0 {
-                input_text_line: "#DATA1 \"HELLO\"".to_owned(), // Should be moved to end
-                file_name: String::new(),
-                line_counter: 6,
-            },
-            Pass0 {
-                input_text_line: "RET".to_owned(),
-                file_name: String::new(),
-                line_counter: 7,
-            },
-        ];
-        let pass1 = get_pass1(&mut msg_list, pass0, opcodes.clone());
-        // Byte addressing: PC starts at 32 (4 header words × 8 bytes each in 64-bit).
-        // Each instruction word = 4 bytes (opcode encoding unchanged).
-        // Each 64-bit data word = 8 bytes (16 hex chars).
-        assert_eq!(pass1.first().unwrap_or_default().program_counter, 32); // MOV
-        assert_eq!(pass1.get(1).unwrap_or_default().program_counter, 44); // PUSH (MOV=3 words×4=12)
-        assert_eq!(pass1.get(2).unwrap_or_default().program_counter, 48); // RET  (+4)
-        assert_eq!(pass1.get(3).unwrap_or_default().program_counter, 52); // #DATA1 0x2 inline (+4)
-        assert_eq!(pass1.get(4).unwrap_or_default().program_counter, 68); // RET  (2 data words×8=16)
-        assert_eq!(pass1.get(5).unwrap_or_default().program_counter, 72); // #DATA1 "HELLO" inline (+4)
-        assert_eq!(pass1.get(6).unwrap_or_default().program_counter, 84); // RET  (string=12 bytes)
-    }
-
-    #[test]
-    // Test get_pass1 for correct vector returned, with correct program counters
-    fn test_get_pass1_2() {
-        let mut msg_list = MsgList::new();
-        let opcodes = &mut Vec::<Opcode>::new();
-        opcodes.push(Opcode {
-            text_name: String::from("PUSH"),
-            hex_code: String::from("0000001X"),
-            comment: String::new(),
-            variables: 0,
-            registers: 1,
-            section: String::new(),
-        });
-
-        let pass0 = vec![Pass0 {
-            input_text_line: "Test_not_code_line".to_owned(),
-            file_name: String::new(),
-            line_counter: 1,
-        }];
-        let _pass1 = get_pass1(&mut msg_list, pass0, opcodes.clone());
-        assert_eq!(msg_list.list.first().unwrap_or_default().text, "Error Test_not_code_line");
-    }
-
-    #[test]
-    // Test get_pass2 for correct vector returned, with correct opcodes, registers and variables
-    fn test_get_pass2_1() {
-        let mut msg_list = MsgList::new();
-        let labels = Vec::<Label>::new();
-        let opcodes = &mut Vec::<Opcode>::new();
-        opcodes.push(Opcode {
-            text_name: String::from("PUSH"),
-            hex_code: String::from("0000001X"),
-            comment: String::new(),
-            variables: 0,
-            registers: 1,
-            section: String::new(),
-        });
-        opcodes.push(Opcode {
-            text_name: String::from("MOVR"),
-            hex_code: String::from("0000007X"),
-            comment: String::new(),
-            variables: 1,
-            registers: 1,
-            section: String::new(),
-        });
-        opcodes.push(Opcode {
-            text_name: String::from("MOV"),
-            hex_code: String::from("00000020"),
-            comment: String::new(),
-            variables: 2,
-            registers: 0,
-            section: String::new(),
-        });
-        opcodes.push(Opcode {
-            text_name: String::from("RET"),
-            hex_code: String::from("00000030"),
-            comment: String::new(),
-            variables: 0,
-            registers: 0,
-            section: String::new(),
-        });
-        opcodes.push(Opcode {
-            text_name: String::from("DELAY"),
-            hex_code: String::from("00000040"),
-            comment: String::new(),
-            variables: 1,
-            registers: 0,
-            section: String::new(),
-        });
-
-        opcodes.push(Opcode {
-            text_name: String::from("DMOV"),
-            hex_code: String::from("00000AXX"),
-            comment: String::new(),
-            variables: 2,
-            registers: 2,
-            section: String::new(),
-        });
-
-        let pass2 = get_pass2(
-            &mut msg_list,
-            vec![
-                Pass1 {
-                    input_text_line: "MOV 0xEEEEEEEE 0xFFFFFFFF".to_owned(),
-                    file_name: String::from("test"),
-                    line_counter: 1,
-                    program_counter: 0,
-                    line_type: LineType::Opcode,
-                },
-                Pass1 {
-                    input_text_line: "DELAY 0x7".to_owned(),
-                    file_name: String::from("test"),
-                    line_counter: 1,
-                    program_counter: 1,
-                    line_type: LineType::Opcode,
-                },
-                Pass1 {
-                    input_text_line: "PUSH A".to_owned(),
-                    file_name: String::from("test"),
-                    line_counter: 2,
-                    program_counter: 3,
-                    line_type: LineType::Opcode,
-                },
-                Pass1 {
-                    input_text_line: "RET".to_owned(),
-                    file_name: String::from("test"),
-                    line_counter: 3,
-                    program_counter: 4,
-                    line_type: LineType::Opcode,
-                },
-                Pass1 {
-                    input_text_line: "RET".to_owned(),
-                    file_name: String::from("test"),
-                    line_counter: 3,
-                    program_counter: 5,
-                    line_type: LineType::Opcode,
-                },
-                Pass1 {
-                    input_text_line: "MOVR C 0xAAAA".to_owned(),
-                    file_name: String::from("test"),
-                    line_counter: 3,
-                    program_counter: 5,
-                    line_type: LineType::Opcode,
-                },
-                Pass1 {
-                    input_text_line: "DMOV D E 0xA 0xB".to_owned(),
-                    file_name: String::from("test"),
-                    line_counter: 3,
-                    program_counter: 5,
-                    line_type: LineType::Opcode,
-                },
-                Pass1 {
-                    input_text_line: "#DATA1 \"HELLO\"".to_owned(),
-                    file_name: String::from("test"),
-                    line_counter: 3,
-                    program_counter: 5,
-                    line_type: LineType::Data,
-                },
-                Pass1 {
-                    input_text_line: "xxx".to_owned(),
-                    file_name: String::from("test"),
-                    line_counter: 3,
-                    program_counter: 5,
-                    line_type: LineType::Error,
-                },
-            ],
-            opcodes.clone(),
-            labels,
-        );
-        assert_eq!(pass2.first().unwrap_or_default().opcode, "00000020EEEEEEEEFFFFFFFF");
-        assert_eq!(pass2.get(1).unwrap_or_default().opcode, "0000004000000007");
-        assert_eq!(pass2.get(2).unwrap_or_default().opcode, "00000010");
-        assert_eq!(pass2.get(3).unwrap_or_default().opcode, "00000030");
-        assert_eq!(pass2.get(4).unwrap_or_default().opcode, "00000030");
-        assert_eq!(pass2.get(5).unwrap_or_default().opcode, "000000720000AAAA");
-        assert_eq!(pass2.get(6).unwrap_or_default().opcode, "00000A340000000A0000000B");
-        assert_eq!(pass2.get(7).unwrap_or_default().opcode, "0000000248454C4C4F000000");
-        assert_eq!(pass2.get(8).unwrap_or_default().opcode, "");
-    }
-
-    #[test]
-    // Test get_pass2 for invalid opcode
-    fn test_get_pass2_2() {
-        let mut msg_list = MsgList::new();
-        let labels = Vec::<Label>::new();
-        let opcodes = &mut Vec::<Opcode>::new();
-        opcodes.push(Opcode {
-            text_name: String::from("PUSH"),
-            hex_code: String::from("0000001X"),
-            comment: String::new(),
-            variables: 0,
-            registers: 1,
-            section: String::new(),
-        });
-        let pass2 = get_pass2(
-            &mut msg_list,
-            vec![Pass1 {
-                input_text_line: "TEST".to_owned(),
-                file_name: String::from("test"),
-                line_counter: 1,
-                program_counter: 0,
-                line_type: LineType::Opcode,
-            }],
-            opcodes.clone(),
-            labels,
-        );
-        assert_eq!(pass2.first().unwrap_or_default().opcode, "ERR     ");
-    }
+    use klausscc::helper::parse_expected_uart_values;
 
     /// Golden-model validation: assemble + emulate every klatest `.kla` that has
     /// expected `// ` UART values and compare captured UART tokens in order.
@@ -1073,11 +797,7 @@ mod tests {
         use std::path::Path;
         let opcode_file = "src/klatest/opcode_select.vh";
         let mut msg_list = MsgList::new();
-        let mut opened: Vec<String> = Vec::new();
-        let vh = read_file_to_vector(opcode_file, &mut msg_list, &mut opened).expect("opcode file");
-        let (opt_ops, opt_macros) = parse_vh_file(vh, &mut msg_list);
-        let oplist = opt_ops.expect("opcodes");
-        let macro_list = expand_embedded_macros(opt_macros.expect("macros"), &mut msg_list);
+        let isa = Isa::from_vh_file(opcode_file, &mut msg_list).expect("opcode file");
 
         let dir = Path::new("src/klatest");
         let mut files: Vec<String> = std::fs::read_dir(dir)
@@ -1109,7 +829,7 @@ mod tests {
             }
             total += 1;
             let mut tm = MsgList::new();
-            let Some((image, entry)) = assemble_to_image(file, &oplist, &macro_list, &mut tm) else {
+            let Some((image, entry)) = assemble_to_image(file, &isa, &[], &mut tm) else {
                 let first_err = tm
                     .list
                     .iter()
@@ -0,0 +1,229 @@
+//! Project manifest (`klauss.toml`).
+//!
+//! A manifest holds the settings a project would otherwise repeat on every
+//! command line.  It is found by searching from the current directory upward,
+//! and its values only fill in options not given on the command line.  Keys
+//! mirror the long CLI option names:
+//!
+//! ```toml
+//! opcode = "../klacode/opcode_select.vh"
+//! include = ["lib", "../klacode/include"]
+//! serial = "/dev/ttyUSB0"
+//! test-timeout = 20
+//!
+//! [board]
+//! ip = "192.168.68.50"
+//! port = 5000
+//! # name = "lab-a7"       # or find the board by discovery, as --board
+//!
+//! [target.hello]
+//! input = "hello.kla"
+//! output = "build/hello"   # .code listing stem, as -o
+//! bitcode = "build/hello"  # .kbt image stem, as -b
+//!
+//! [suite.regression]
+//! list = "tests/all_tests.txt"
+//! emulate = true           # run with --emulate-test rather than on the board
+//! ```
+//!
+//! Relative paths are resolved against the directory holding the manifest.
+
+use crate::messages::{MessageType, MsgList};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File name searched for when discovering the manifest.
+pub const MANIFEST_FILE_NAME: &str = "klauss.toml";
+
+/// Settings read from `klauss.toml`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Manifest {
+    /// Opcode `.vh` file (as `-c/--opcode`).
+    pub opcode: Option<String>,
+    /// Directories searched for `!include` files.
+    pub include: Vec<String>,
+    /// Serial port used instead of auto-detection: bare `-s`, monitor-only and board test runs.
+    pub serial: Option<String>,
+    /// Test-mode UART capture timeout in seconds (as `--test-timeout`).
+    pub test_timeout: Option<u64>,
+    /// Board network settings for netboot.
+    pub board: Board,
+    /// Named build targets, selected with `--target`.
+    pub target: BTreeMap<String, Target>,
+    /// Named test suites, selected with `--suite`.
+    pub suite: BTreeMap<String, TestSuite>,
+}
+
+/// Board network address for `--net-load`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Board {
+    /// Board IP address or host name (as `--ip`).
+    pub ip: Option<String>,
+    /// Netboot TCP port (as `--port`).
+    pub port: Option<u16>,
+    /// Board name found by discovery instead of `ip` (as `--board`).
+    pub name: Option<String>,
+}
+
+/// One named build: an input and where its outputs go.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Target {
+    /// Input file (as `-i/--input`).
+    pub input: String,
+    /// Stem of the `.code` listing (as `-o/--output`).
+    pub output: Option<String>,
+    /// Stem of the `.kbt` image (as `-b/--bitcode`).
+    pub bitcode: Option<String>,
+}
+
+/// One named test suite.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TestSuite {
+    /// Test list file or directory of `.kla` files.
+    pub list: String,
+    /// Run in the emulator (`--emulate-test`) instead of on the board (`--test-list`).
+    pub emulate: bool,
+    /// Per-test UART timeout in seconds, overriding the manifest `test-timeout`.
+    pub timeout: Option<u64>,
+}
+
+impl Manifest {
+    /// Parse manifest text; relative paths are resolved against `base_dir`.
+    pub fn from_text(text: &str, base_dir: &Path) -> Result<Self, String> {
+        let mut manifest: Self = toml::from_str(text).map_err(|err| err.to_string())?;
+        manifest.resolve_paths(base_dir);
+        Ok(manifest)
+    }
+
+    /// Read and parse the manifest at `path`.
+    ///
+    /// Returns `None` (with the reason in `msg_list`) if it cannot be read or parsed.
+    pub fn load(path: &Path, msg_list: &mut MsgList) -> Option<Self> {
+        let file_name = path.to_string_lossy().into_owned();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => {
+                msg_list.push(format!("Unable to read manifest: {err}"), None, Some(file_name), MessageType::Error);
+                return None;
+            }
+        };
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        match Self::from_text(&text, base_dir) {
+            Ok(manifest) => {
+                msg_list.push("Using project manifest".to_owned(), None, Some(file_name), MessageType::Information);
+                Some(manifest)
+            }
+            Err(err) => {
+                msg_list.push(format!("Error in manifest: {}", err.trim()), None, Some(file_name), MessageType::Error);
+                None
+            }
+        }
+    }
+
+    /// Include directories as paths.
+    #[must_use]
+    pub fn include_paths(&self) -> Vec<PathBuf> {
+        self.include.iter().map(PathBuf::from).collect()
+    }
+
+    /// Make every relative path in the manifest relative to `base_dir` instead.
+    fn resolve_paths(&mut self, base_dir: &Path) {
+        let resolve = |path: &mut String| {
+            if !path.is_empty() && Path::new(path.as_str()).is_relative() {
+                *path = base_dir.join(path.as_str()).to_string_lossy().into_owned();
+            }
+        };
+        self.opcode.iter_mut().for_each(resolve);
+        self.include.iter_mut().for_each(resolve);
+        for target in self.target.values_mut() {
+            resolve(&mut target.input);
+            target.output.iter_mut().for_each(resolve);
+            target.bitcode.iter_mut().for_each(resolve);
+        }
+        for suite in self.suite.values_mut() {
+            resolve(&mut suite.list);
+        }
+    }
+}
+
+/// Find `klauss.toml` in `start_dir` or the nearest ancestor directory.
+#[must_use]
+pub fn find_manifest(start_dir: &Path) -> Option<PathBuf> {
+    start_dir.ancestors().map(|dir| dir.join(MANIFEST_FILE_NAME)).find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+
+    #[test]
+    // Test a full manifest parses and relative paths resolve against its directory
+    fn test_manifest_from_text() {
+        let text = r#"
+opcode = "../klacode/opcode_select.vh"
+include = ["lib", "/abs/include"]
+serial = "/dev/ttyUSB0"
+test-timeout = 20
+
+[board]
+ip = "192.168.68.50"
+port = 5001
+
+[target.hello]
+input = "hello.kla"
+bitcode = "build/hello"
+
+[suite.regression]
+list = "tests/all_tests.txt"
+emulate = true
+"#;
+        let manifest = Manifest::from_text(text, Path::new("/proj")).unwrap();
+        assert_eq!(manifest.opcode.as_deref(), Some("/proj/../klacode/opcode_select.vh"));
+        assert_eq!(manifest.include, vec!["/proj/lib".to_owned(), "/abs/include".to_owned()]);
+        assert_eq!(manifest.serial.as_deref(), Some("/dev/ttyUSB0"));
+        assert_eq!(manifest.test_timeout, Some(20));
+        assert_eq!(manifest.board.ip.as_deref(), Some("192.168.68.50"));
+        assert_eq!(manifest.board.port, Some(5001));
+        let target = &manifest.target["hello"];
+        assert_eq!(target.input, "/proj/hello.kla");
+        assert_eq!(target.output, None);
+        assert_eq!(target.bitcode.as_deref(), Some("/proj/build/hello"));
+        let suite = &manifest.suite["regression"];
+        assert_eq!(suite.list, "/proj/tests/all_tests.txt");
+        assert!(suite.emulate);
+        assert_eq!(suite.timeout, None);
+    }
+
+    #[test]
+    // Test an empty manifest gives defaults and unknown keys are rejected
+    fn test_manifest_defaults_and_unknown_keys() {
+        assert_eq!(Manifest::from_text("", Path::new("/proj")).unwrap(), Manifest::default());
+        let err = Manifest::from_text("opcodes = \"x.vh\"\n", Path::new("/proj")).unwrap_err();
+        assert!(err.contains("opcodes"), "{err}");
+    }
+
+    #[test]
+    // Test the manifest is found in the start directory or an ancestor
+    fn test_find_manifest() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let nested = dir.path().join("src").join("lib");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_manifest(&nested), None);
+
+        let manifest_path = dir.path().join(MANIFEST_FILE_NAME);
+        fs::write(&manifest_path, "serial = \"/dev/ttyUSB1\"\n").unwrap();
+        assert_eq!(find_manifest(&nested), Some(manifest_path.clone()));
+
+        let mut msg_list = MsgList::new();
+        let manifest = Manifest::load(&manifest_path, &mut msg_list).unwrap();
+        assert_eq!(manifest.serial.as_deref(), Some("/dev/ttyUSB1"));
+        assert_eq!(msg_list.number_by_type(&MessageType::Error), 0);
+    }
+}
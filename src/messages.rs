@@ -1,7 +1,7 @@
 use chrono::{Local, NaiveTime};
 use colored::{ColoredString, Colorize as _};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// Struct for message.
 pub struct Message {
     /// File name of file causing message if exists.
@@ -15,7 +15,7 @@ pub struct Message {
     /// Time of message.
     pub time: Option<NaiveTime>,
 }
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 /// Enum for message type.
 pub enum MessageType {
     /// Error message.
@@ -0,0 +1,614 @@
+//! Local stand-in for the board's netboot server (`netboot.c`).
+//!
+//! [`NetbootServer`] speaks the board side of the KNET protocol used by
+//! [`net_load`](crate::netload::net_load), both the v1 header + image + ack
+//! exchange and the v2 handshake with acknowledged chunks, then runs the
+//! image in `emulate::Cpu`.  The program's UART goes to stdout or to a TCP port that
+//! `-m -s tcp://host:port` can monitor, so `--net-load` and `--net-load -m`
+//! can be exercised without a board.  Keystrokes from that monitor become the
+//! program's UART input.
+//!
+//! A new upload while a program runs abandons it, as a board reset would.
+//! With [`NetbootServer::enable_discovery`] it also answers `--discover`
+//! queries, so board selection can be tested on loopback.
+
+use crate::discover::{answer_queries, BoardAnnouncement};
+use crate::emulate::{Cpu, StopReason, MEM_SIZE};
+use crate::fake_board::uart_bytes;
+use crate::helper::human_bytes;
+use crate::messages::{MessageType, MsgList};
+use crate::netload::{
+    encode_fields, image_checksum, read_fields, CAP_CHUNK_ACK, CAP_EMULATOR, NETBOOT_MAGIC, NETBOOT_V2_MAGIC, NETBOOT_VERSION, STATUS_BAD_CHUNK,
+    STATUS_BAD_MAGIC, STATUS_CHUNK_CHECKSUM, STATUS_OK, STATUS_TOO_LARGE,
+};
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::time::Duration;
+
+/// Instructions run between checks for a new upload or UART client.
+const RUN_SLICE: u64 = 100_000;
+
+/// How long an upload may stall before it is dropped (matches the client's ack wait).
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Sleep between polls while waiting for an upload.
+const IDLE_POLL: Duration = Duration::from_millis(10);
+
+/// MAC address announced by the emulator (locally administered).
+const EMULATOR_MAC: &str = "02:00:00:00:00:01";
+
+/// Largest v2 chunk accepted.
+const MAX_CHUNK: u32 = 1024 * 1024;
+
+/// UART bytes kept for a TCP client that has not connected yet.
+const UART_BACKLOG_LIMIT: usize = 1024 * 1024;
+
+/// An image received over KNET.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetbootImage {
+    /// DDR image bytes (heap header + program), loaded at address 0.
+    pub image: Vec<u8>,
+    /// Byte address of the first instruction.
+    pub entry: u32,
+}
+
+/// Send an 8-byte acknowledgement (`status` plus a checksum or offset).
+fn send_ack<S: Write>(stream: &mut S, status: u32, value: u32) -> io::Result<()> {
+    stream.write_all(&encode_fields(&[status, value]))?;
+    stream.flush()
+}
+
+/// Refuse an upload: acknowledge with `status` and return the reason as an error.
+fn refuse<S: Write>(stream: &mut S, status: u32, value: u32, reason: String) -> io::Result<NetbootImage> {
+    send_ack(stream, status, value)?;
+    Err(Error::new(ErrorKind::InvalidData, reason))
+}
+
+/// Receive one upload (v1 or v2) and acknowledge it.
+///
+/// A bad magic, an image too large for DDR or a malformed chunk is answered
+/// with a non-zero status and returned as an `InvalidData` error.
+pub fn receive_image<S: Read + Write>(stream: &mut S) -> io::Result<NetbootImage> {
+    let [magic, length, entry] = read_fields::<3, _>(stream)?;
+    match magic {
+        NETBOOT_MAGIC => receive_v1(stream, length, entry),
+        NETBOOT_V2_MAGIC => receive_v2(stream),
+        _ => refuse(stream, STATUS_BAD_MAGIC, 0, format!("bad magic 0x{magic:08X}")),
+    }
+}
+
+/// Refuse an image larger than DDR.
+fn too_large<S: Write>(stream: &mut S, length: u32) -> io::Result<NetbootImage> {
+    refuse(
+        stream,
+        STATUS_TOO_LARGE,
+        0,
+        format!("image of {} does not fit in DDR", human_bytes(length as usize)),
+    )
+}
+
+/// v1: the image follows the header; one acknowledgement.
+fn receive_v1<S: Read + Write>(stream: &mut S, length: u32, entry: u32) -> io::Result<NetbootImage> {
+    if length as usize > MEM_SIZE {
+        return too_large(stream, length);
+    }
+    let mut image = vec![0_u8; length as usize];
+    stream.read_exact(&mut image)?;
+    send_ack(stream, STATUS_OK, image_checksum(&image))?;
+    Ok(NetbootImage { image, entry })
+}
+
+/// v2: WELCOME, LOAD, acknowledged chunks, DONE.
+fn receive_v2<S: Read + Write>(stream: &mut S) -> io::Result<NetbootImage> {
+    stream.write_all(&encode_fields(&[
+        NETBOOT_V2_MAGIC,
+        NETBOOT_VERSION,
+        CAP_CHUNK_ACK | CAP_EMULATOR,
+        MAX_CHUNK,
+        MEM_SIZE as u32,
+    ]))?;
+    stream.flush()?;
+    let [length, entry, chunk_size, _checksum] = read_fields::<4, _>(stream)?;
+    if length as usize > MEM_SIZE {
+        return too_large(stream, length);
+    }
+    if chunk_size == 0 || chunk_size > MAX_CHUNK {
+        return refuse(stream, STATUS_BAD_CHUNK, 0, format!("chunk size {chunk_size} out of range"));
+    }
+    send_ack(stream, STATUS_OK, 0)?;
+
+    let mut image: Vec<u8> = Vec::with_capacity(length as usize);
+    while image.len() < length as usize {
+        let [offset, chunk_len, chunk_checksum] = read_fields::<3, _>(stream)?;
+        if offset as usize != image.len() || chunk_len == 0 || chunk_len > chunk_size || image.len() + chunk_len as usize > length as usize {
+            return refuse(
+                stream,
+                STATUS_BAD_CHUNK,
+                offset,
+                format!("unexpected chunk of {chunk_len} bytes at offset {offset}"),
+            );
+        }
+        let mut chunk = vec![0_u8; chunk_len as usize];
+        stream.read_exact(&mut chunk)?;
+        if image_checksum(&chunk) == chunk_checksum {
+            image.extend_from_slice(&chunk);
+            send_ack(stream, STATUS_OK, offset)?;
+        } else {
+            send_ack(stream, STATUS_CHUNK_CHECKSUM, offset)?;
+        }
+    }
+    send_ack(stream, STATUS_OK, image_checksum(&image))?;
+    Ok(NetbootImage { image, entry })
+}
+
+/// Where the emulated program's UART output goes.
+pub enum UartOutput {
+    /// Print it on stdout.
+    Stdout,
+    /// Serve it to one TCP client at a time, as the board's network console
+    /// does.  Bytes the client sends are read and fed to the emulator's UART
+    /// receiver.
+    Tcp {
+        /// Non-blocking listener for monitor connections.
+        listener: TcpListener,
+        /// The connected monitor, if any.
+        client: Option<TcpStream>,
+        /// Output produced while no monitor was connected.
+        backlog: Vec<u8>,
+    },
+}
+
+impl UartOutput {
+    /// Listen for UART monitors on `address` (`host:port`).
+    pub fn listen(address: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self::Tcp {
+            listener,
+            client: None,
+            backlog: Vec::new(),
+        })
+    }
+
+    /// Address the UART listener is bound to (`None` for stdout).
+    #[must_use]
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Stdout => None,
+            Self::Tcp { listener, .. } => listener.local_addr().ok(),
+        }
+    }
+
+    /// Accept a waiting monitor, replacing any current one, and hand it the backlog.
+    fn poll(&mut self) -> io::Result<()> {
+        let Self::Tcp { listener, client, backlog } = self else {
+            return Ok(());
+        };
+        // Drop a monitor that has hung up, so output waits in the backlog for the next one.
+        if client.as_ref().is_some_and(peer_closed) {
+            *client = None;
+        }
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                stream.set_nodelay(true)?;
+                *client = Some(stream);
+                let pending = std::mem::take(backlog);
+                self.write(&pending);
+                Ok(())
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Send UART bytes; a monitor that has gone away is dropped and the bytes kept.
+    fn write(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        match self {
+            Self::Stdout => {
+                let mut stdout = io::stdout();
+                let _ = stdout.write_all(bytes);
+                let _ = stdout.flush();
+            }
+            Self::Tcp { client, backlog, .. } => {
+                if let Some(stream) = client {
+                    if stream.write_all(bytes).is_ok() {
+                        return;
+                    }
+                    *client = None;
+                }
+                let room = UART_BACKLOG_LIMIT.saturating_sub(backlog.len());
+                backlog.extend_from_slice(&bytes[..bytes.len().min(room)]);
+            }
+        }
+    }
+
+    /// Keystrokes sent by the connected monitor, for the program's UART input.
+    fn input(&mut self) -> Vec<u8> {
+        let Self::Tcp { client: Some(stream), .. } = self else {
+            return Vec::new();
+        };
+        let mut input = Vec::new();
+        if stream.set_nonblocking(true).is_ok() {
+            let mut buffer = [0_u8; 256];
+            while let Ok(count @ 1..) = stream.read(&mut buffer) {
+                input.extend_from_slice(&buffer[..count]);
+            }
+            let _ = stream.set_nonblocking(false);
+        }
+        input
+    }
+
+    /// Forget output from a previous program.
+    fn reset(&mut self) {
+        if let Self::Tcp { backlog, .. } = self {
+            backlog.clear();
+        }
+    }
+
+    /// Mark a HALT: `0x00` on TCP, which `monitor_serial_port` takes as a halt.
+    fn halt(&mut self) {
+        if matches!(self, Self::Tcp { .. }) {
+            self.write(&[0x00]);
+        }
+    }
+}
+
+/// True if the other end of `stream` has closed it.
+fn peer_closed(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+    let closed = match stream.peek(&mut [0_u8; 1]) {
+        Ok(count) => count == 0,
+        Err(err) => err.kind() != ErrorKind::WouldBlock,
+    };
+    closed || stream.set_nonblocking(false).is_err()
+}
+
+/// The board side of KNET backed by the emulator.
+pub struct NetbootServer {
+    /// Non-blocking listener for uploads.
+    listener: TcpListener,
+    /// Where the program's UART output goes.
+    uart: UartOutput,
+    /// Instruction cap per program.
+    max_instructions: u64,
+    /// An upload that arrived while a program was running.
+    next: Option<TcpStream>,
+    /// Discovery responder and the announcement it sends.
+    discovery: Option<(UdpSocket, BoardAnnouncement)>,
+}
+
+impl NetbootServer {
+    /// Listen for uploads on `address` (`host:port`).
+    pub fn bind(address: &str, uart: UartOutput, max_instructions: u64) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            uart,
+            max_instructions,
+            next: None,
+            discovery: None,
+        })
+    }
+
+    /// Answer discovery queries on UDP `address` as a board called `name`.
+    pub fn enable_discovery(&mut self, address: &str, name: &str) -> io::Result<()> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        let board = BoardAnnouncement {
+            name: name.to_owned(),
+            mac: EMULATOR_MAC.to_owned(),
+            ip: None,
+            port: self.listener.local_addr()?.port(),
+            console: self.uart.local_addr().map(|address| address.port()),
+            firmware: format!("klausscc netboot-server {}", env!("CARGO_PKG_VERSION")),
+            isa: "emulator".to_owned(),
+        };
+        self.discovery = Some((socket, board));
+        Ok(())
+    }
+
+    /// Address the discovery responder is bound to, if enabled.
+    #[must_use]
+    pub fn discovery_addr(&self) -> Option<SocketAddr> {
+        self.discovery.as_ref().and_then(|(socket, _)| socket.local_addr().ok())
+    }
+
+    /// Serve UART monitors and discovery queries.
+    fn poll(&mut self) -> io::Result<()> {
+        self.uart.poll()?;
+        if let Some((socket, board)) = &self.discovery {
+            answer_queries(socket, board)?;
+        }
+        Ok(())
+    }
+
+    /// Address the upload listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Address the UART listener is bound to (`None` for stdout).
+    #[must_use]
+    pub fn uart_addr(&self) -> Option<SocketAddr> {
+        self.uart.local_addr()
+    }
+
+    /// Serve uploads until a listener fails.
+    #[cfg(not(tarpaulin_include))] // Runs until interrupted
+    pub fn serve(&mut self, msg_list: &mut MsgList) -> io::Result<()> {
+        loop {
+            self.serve_one(msg_list)?;
+        }
+    }
+
+    /// Wait for one upload, acknowledge it and run it until it stops or is replaced.
+    ///
+    /// A rejected or broken upload is reported as a warning, not an error.
+    pub fn serve_one(&mut self, msg_list: &mut MsgList) -> io::Result<()> {
+        let mut stream = self.next_upload()?;
+        let peer = stream.peer_addr().map_or_else(|_| "unknown peer".to_owned(), |peer| peer.to_string());
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(UPLOAD_TIMEOUT))?;
+        let received = receive_image(&mut stream);
+        drop(stream);
+        match received {
+            Ok(upload) => {
+                msg_list.push(
+                    format!(
+                        "Loaded {} from {peer}, entry 0x{:08X}, checksum 0x{:08X}",
+                        human_bytes(upload.image.len()),
+                        upload.entry,
+                        image_checksum(&upload.image)
+                    ),
+                    None,
+                    None,
+                    MessageType::Information,
+                );
+                self.run(&upload, msg_list)
+            }
+            Err(err) => {
+                msg_list.push(format!("Rejected upload from {peer}: {err}"), None, None, MessageType::Warning);
+                Ok(())
+            }
+        }
+    }
+
+    /// Block until an upload connects, serving UART monitors and discovery meanwhile.
+    fn next_upload(&mut self) -> io::Result<TcpStream> {
+        loop {
+            if let Some(stream) = self.next.take() {
+                return Ok(stream);
+            }
+            self.poll()?;
+            match self.listener.accept() {
+                Ok((stream, _)) => return Ok(stream),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => std::thread::sleep(IDLE_POLL),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Run an image in slices, streaming UART output, until it stops or a new upload arrives.
+    fn run(&mut self, upload: &NetbootImage, msg_list: &mut MsgList) -> io::Result<()> {
+        self.uart.reset();
+        let mut cpu = Cpu::new(&upload.image, upload.entry);
+        let mut retired: u64 = 0;
+        loop {
+            self.poll()?;
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    msg_list.push(
+                        format!("New upload, abandoning the running program after {retired} instructions"),
+                        None,
+                        None,
+                        MessageType::Information,
+                    );
+                    self.next = Some(stream);
+                    return Ok(());
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+
+            cpu.push_uart_input(&self.uart.input());
+            let result = cpu.run(RUN_SLICE.min(self.max_instructions - retired), None);
+            retired += result.instructions;
+            self.uart.write(&uart_bytes(&result.uart));
+            match result.stop {
+                StopReason::InstructionCap if retired < self.max_instructions => {}
+                StopReason::WaitingForInput => std::thread::sleep(IDLE_POLL),
+                StopReason::Halt => {
+                    self.uart.halt();
+                    msg_list.push(format!("HALT after {retired} instructions"), None, None, MessageType::Information);
+                    return Ok(());
+                }
+                stop => {
+                    msg_list.push(
+                        format!("Stopped after {retired} instructions: {stop:?}"),
+                        None,
+                        None,
+                        MessageType::Warning,
+                    );
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+    use crate::helper::build_ddr_image;
+    use crate::netload::{net_load, net_load_with, NetLoadOptions, NetbootProtocol};
+    use std::thread;
+
+    /// `SETR A 0x41`, `TXR A`, `NEWLINE`, `HALT` as a netboot DDR image.
+    fn sample_image() -> Vec<u8> {
+        let words: [u32; 5] = [0x0000_0800, 0x0000_0041, 0x0000_5010, 0x0000_5001, 0x0000_F011];
+        build_ddr_image(&words.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>())
+    }
+
+    #[test]
+    // Test net_load uploads to the server and the UART reaches a TCP monitor with the halt marker
+    fn test_net_load_to_server() {
+        let uart = UartOutput::listen("127.0.0.1:0").unwrap();
+        let mut server = NetbootServer::bind("127.0.0.1:0", uart, 1_000).unwrap();
+        let port = server.local_addr().unwrap().port();
+        let mut monitor = TcpStream::connect(server.uart_addr().unwrap()).unwrap();
+
+        let host = thread::spawn(move || {
+            let mut msg_list = MsgList::new();
+            net_load("127.0.0.1", port, &sample_image(), 0x20, &mut msg_list).unwrap();
+            msg_list
+        });
+        let mut msg_list = MsgList::new();
+        server.serve_one(&mut msg_list).unwrap();
+        let host_messages = host.join().unwrap();
+        assert_eq!(host_messages.number_by_type(&MessageType::Error), 0);
+        assert_eq!(msg_list.number_by_type(&MessageType::Warning), 0);
+
+        monitor.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut output = Vec::new();
+        let mut byte = [0_u8; 1];
+        while byte[0] != 0x00 || output.is_empty() {
+            monitor.read_exact(&mut byte).unwrap();
+            output.push(byte[0]);
+        }
+        assert_eq!(output, b"0000000000000041\n\r\x00");
+    }
+
+    #[test]
+    // Test a header without the KNET magic is refused with a non-zero status
+    fn test_receive_image_bad_magic() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let mut header = b"XNET".to_vec();
+            header.extend_from_slice(&[0; 8]);
+            stream.write_all(&header).unwrap();
+            let mut ack = [0_u8; 8];
+            stream.read_exact(&mut ack).unwrap();
+            ack
+        });
+        let (mut stream, _) = listener.accept().unwrap();
+        let err = receive_image(&mut stream).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let ack = client.join().unwrap();
+        assert_eq!(u32::from_le_bytes([ack[0], ack[1], ack[2], ack[3]]), STATUS_BAD_MAGIC);
+    }
+
+    #[test]
+    // Test a v1-only host still loads
+    fn test_v1_upload() {
+        let mut server = NetbootServer::bind("127.0.0.1:0", UartOutput::Stdout, 10).unwrap();
+        let port = server.local_addr().unwrap().port();
+        let host = thread::spawn(move || {
+            let options = NetLoadOptions {
+                protocol: NetbootProtocol::V1,
+                progress: false,
+                ..NetLoadOptions::default()
+            };
+            net_load_with("127.0.0.1", port, &[0; 64], 0x20, &options, &mut MsgList::new()).unwrap();
+        });
+        let mut msg_list = MsgList::new();
+        server.serve_one(&mut msg_list).unwrap();
+        host.join().unwrap();
+        assert!(msg_list.list[0].text.starts_with("Loaded 64 B"), "{}", msg_list.list[0].text);
+    }
+
+    #[test]
+    // Test a v2 chunk with a bad checksum is NAKed and accepted when resent
+    fn test_v2_chunk_resend() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let host = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(&encode_fields(&[NETBOOT_V2_MAGIC, NETBOOT_VERSION, 0])).unwrap();
+            let [magic, _, capabilities, _, _] = read_fields::<5, _>(&mut stream).unwrap();
+            assert_eq!(magic, NETBOOT_V2_MAGIC);
+            assert_ne!(capabilities & CAP_EMULATOR, 0);
+            let image = encode_fields(&[1, 2, 3, 4]);
+            stream.write_all(&encode_fields(&[16, 0x20, 8, image_checksum(&image)])).unwrap();
+            assert_eq!(read_fields::<2, _>(&mut stream).unwrap(), [STATUS_OK, 0]);
+
+            let mut acks = Vec::new();
+            for (offset, checksum) in [(0, 99), (0, 3), (8, 7)] {
+                let mut frame = encode_fields(&[offset, 8, checksum]);
+                frame.extend_from_slice(&image[offset as usize..offset as usize + 8]);
+                stream.write_all(&frame).unwrap();
+                acks.push(read_fields::<2, _>(&mut stream).unwrap());
+            }
+            assert_eq!(acks, [[STATUS_CHUNK_CHECKSUM, 0], [STATUS_OK, 0], [STATUS_OK, 8]]);
+            read_fields::<2, _>(&mut stream).unwrap()
+        });
+        let (mut stream, _) = listener.accept().unwrap();
+        let upload = receive_image(&mut stream).unwrap();
+        assert_eq!(upload.image, encode_fields(&[1, 2, 3, 4]));
+        assert_eq!(host.join().unwrap(), [STATUS_OK, 10]);
+    }
+
+    #[test]
+    // Test the server answers discovery with its name and netboot port
+    fn test_discovery() {
+        let mut server = NetbootServer::bind("127.0.0.1:0", UartOutput::Stdout, 10).unwrap();
+        server.enable_discovery("127.0.0.1:0", "sim").unwrap();
+        let discovery = server.discovery_addr().unwrap().to_string();
+        let port = server.local_addr().unwrap().port();
+        let host = thread::spawn(move || crate::discover::discover(&discovery, Duration::from_millis(300)).unwrap());
+        let deadline = std::time::Instant::now() + Duration::from_millis(300);
+        while std::time::Instant::now() < deadline {
+            server.poll().unwrap();
+            thread::sleep(Duration::from_millis(5));
+        }
+        let boards = host.join().unwrap();
+        assert_eq!(boards.len(), 1);
+        assert_eq!((boards[0].name.as_str(), boards[0].port, boards[0].console), ("sim", port, None));
+    }
+
+    #[test]
+    // Test output reaches a console that connects after the program ran, even if an earlier one hung up
+    fn test_console_reconnect() {
+        let uart = UartOutput::listen("127.0.0.1:0").unwrap();
+        let mut server = NetbootServer::bind("127.0.0.1:0", uart, 1_000).unwrap();
+        let port = server.local_addr().unwrap().port();
+        let console = server.uart_addr().unwrap();
+        drop(TcpStream::connect(console).unwrap());
+        server.poll().unwrap();
+
+        let host = thread::spawn(move || {
+            let options = NetLoadOptions {
+                progress: false,
+                ..NetLoadOptions::default()
+            };
+            net_load_with("127.0.0.1", port, &sample_image(), 0x20, &options, &mut MsgList::new()).unwrap();
+        });
+        thread::sleep(Duration::from_millis(50));
+        server.serve_one(&mut MsgList::new()).unwrap();
+        host.join().unwrap();
+
+        let port_name = format!("tcp://{console}");
+        let monitor = thread::spawn(move || {
+            let mut msg_list = MsgList::new();
+            let port = crate::serial::open_transport(&port_name, &mut msg_list).unwrap();
+            crate::serial::monitor_serial_port(port, &crate::serial::MonitorOptions::default(), &mut msg_list).unwrap();
+        });
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while !monitor.is_finished() && std::time::Instant::now() < deadline {
+            server.poll().unwrap();
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(monitor.is_finished(), "the monitor sees the halt marker from the backlog");
+        monitor.join().unwrap();
+    }
+}
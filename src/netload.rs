@@ -1,31 +1,164 @@
 //! Network program loader — stream a flat DDR image to the board over TCP.
 //!
 //! The board runs `netboot.c` (a resident lwIP program) listening on a TCP
-//! port.  We connect, send a 12-byte header (`magic`, `img_len`, `entry_pc`,
-//! all little-endian) followed by the raw DDR image bytes, then read an 8-byte
-//! acknowledgement (`status`, `checksum`).  TCP itself provides the ordering /
-//! loss / retransmit that the kbt-over-UART path never needed and the earlier
-//! hand-rolled UDP design would have had to implement.
+//! port.  TCP itself provides the ordering / loss / retransmit that the
+//! kbt-over-UART path never needed and the earlier hand-rolled UDP design
+//! would have had to implement.  Two protocol versions exist, all fields
+//! little-endian u32:
+//!
+//! **v1** — a 12-byte header (`magic` = `KNET`, `img_len`, `entry_pc`), the
+//! raw DDR image, then an 8-byte acknowledgement (`status`, `checksum`).
+//!
+//! **v2** — a versioned handshake and chunked transfer:
+//!
+//! | step    | direction     | fields                                                   |
+//! |---------|---------------|----------------------------------------------------------|
+//! | HELLO   | host → board  | `magic` = `KNT2`, `version`, `flags`                     |
+//! | WELCOME | board → host  | `magic` = `KNT2`, `version`, `capabilities`, `max_chunk`, `mem_size` |
+//! | LOAD    | host → board  | `img_len`, `entry_pc`, `chunk_size`, `image_checksum`    |
+//! |         | board → host  | `status`, 0                                              |
+//! | CHUNK   | host → board  | `offset`, `len`, `chunk_checksum`, then `len` bytes      |
+//! |         | board → host  | `status`, `offset` (resend on [`STATUS_CHUNK_CHECKSUM`]) |
+//! | DONE    | board → host  | `status`, `image_checksum`                               |
+//!
+//! HELLO is the size of a v1 header, so a v1 board reads it as a bad magic
+//! and answers with a non-zero status (or hangs up); the host then reconnects
+//! and falls back to v1.
 
 use crate::helper::human_bytes;
 use crate::messages::{MessageType, MsgList};
-use std::io::{Error, Read as _, Write as _};
-use std::net::TcpStream;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs as _};
 use std::time::Duration;
 
 /// Protocol magic — `b"KNET"` read as a little-endian u32 (matches the board's
 /// `NETBOOT_MAGIC`).
-const NETBOOT_MAGIC: u32 = 0x5445_4E4B;
+pub(crate) const NETBOOT_MAGIC: u32 = 0x5445_4E4B;
+
+/// v2 handshake magic — `b"KNT2"` read as a little-endian u32.
+pub(crate) const NETBOOT_V2_MAGIC: u32 = 0x3254_4E4B;
+
+/// Highest protocol version this host speaks.
+pub const NETBOOT_VERSION: u32 = 2;
 
 /// Default TCP port the board's netboot server listens on.
 pub const NETBOOT_DEFAULT_PORT: u16 = 5000;
 
+/// Boards with a network console (UART output and keystrokes over raw TCP)
+/// usually serve it on the netboot port plus this offset.
+pub const CONSOLE_PORT_OFFSET: u16 = 1;
+
+/// Acknowledgement status: accepted.
+pub const STATUS_OK: u32 = 0;
+
+/// Acknowledgement status: the header magic was not recognised.
+pub const STATUS_BAD_MAGIC: u32 = 1;
+
+/// Acknowledgement status: the image does not fit in board memory.
+pub const STATUS_TOO_LARGE: u32 = 2;
+
+/// Chunk acknowledgement status: checksum mismatch, send the chunk again.
+pub const STATUS_CHUNK_CHECKSUM: u32 = 3;
+
+/// Chunk acknowledgement status: unexpected offset or length; the load is abandoned.
+pub const STATUS_BAD_CHUNK: u32 = 4;
+
+/// WELCOME capability: the board acknowledges each chunk.
+pub const CAP_CHUNK_ACK: u32 = 1 << 0;
+
+/// WELCOME capability: the board is the emulator (`--netboot-server`), not hardware.
+pub const CAP_EMULATOR: u32 = 1 << 31;
+
+/// Times a chunk is resent after a checksum NAK before the load fails.
+const CHUNK_RESENDS: u32 = 3;
+
+/// Which protocol version `--net-load` uses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NetbootProtocol {
+    /// Try v2 and fall back to v1 if the board does not answer the handshake.
+    #[default]
+    Auto,
+    /// Only the v1 header + image + ack exchange.
+    V1,
+    /// Only v2; fail if the board does not support it.
+    V2,
+}
+
+impl NetbootProtocol {
+    /// Values accepted by `--net-protocol`.
+    pub const NAMES: [&'static str; 3] = ["auto", "1", "2"];
+
+    /// Parse a `--net-protocol` value.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(Self::Auto),
+            "1" => Some(Self::V1),
+            "2" => Some(Self::V2),
+            _ => None,
+        }
+    }
+}
+
+/// Settings for [`net_load_with`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetLoadOptions {
+    /// Protocol version to use.
+    pub protocol: NetbootProtocol,
+    /// How long to wait for the TCP connection.
+    pub connect_timeout: Duration,
+    /// How long to wait for each reply from the board.
+    pub ack_timeout: Duration,
+    /// Reconnect attempts after a transient failure.
+    pub retries: u32,
+    /// Largest chunk sent with v2 (the board may ask for less).
+    pub chunk_size: u32,
+    /// Draw a progress bar on stderr.
+    pub progress: bool,
+}
+
+impl Default for NetLoadOptions {
+    fn default() -> Self {
+        Self {
+            protocol: NetbootProtocol::Auto,
+            connect_timeout: Duration::from_secs(5),
+            ack_timeout: Duration::from_secs(15),
+            retries: 3,
+            chunk_size: 64 * 1024,
+            progress: true,
+        }
+    }
+}
+
+/// What the board reported in its v2 WELCOME.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoardInfo {
+    /// Protocol version the board speaks.
+    pub version: u32,
+    /// `CAP_*` flags.
+    pub capabilities: u32,
+    /// Largest chunk the board accepts.
+    pub max_chunk: u32,
+    /// Bytes of memory available for the image.
+    pub mem_size: u32,
+}
+
+/// `-s` style port name of a board's network console (`tcp://host:port`).
+#[must_use]
+pub fn console_port_name(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("tcp://[{host}]:{port}")
+    } else {
+        format!("tcp://{host}:{port}")
+    }
+}
+
 /// 32-bit additive checksum over little-endian 32-bit words.
 ///
 /// Must match the board's `image_checksum()` so the two ends agree the image
 /// arrived intact (belt-and-braces on top of TCP's own integrity).
 #[must_use]
-fn image_checksum(image: &[u8]) -> u32 {
+pub(crate) fn image_checksum(image: &[u8]) -> u32 {
     let mut sum: u32 = 0;
     let mut i = 0;
     while i + 4 <= image.len() {
@@ -35,53 +168,410 @@ fn image_checksum(image: &[u8]) -> u32 {
     sum
 }
 
-/// Connect to the board and stream the DDR image, then verify the reply.
-#[cfg(not(tarpaulin_include))] // Cannot test live TCP in tarpaulin
-pub fn net_load(ip: &str, port: u16, image: &[u8], entry_pc: u32, msg_list: &mut MsgList) -> Result<(), Error> {
-    let addr = format!("{ip}:{port}");
-    msg_list.push(format!("netboot: connecting to {addr}"), None, None, MessageType::Information);
+/// Little-endian u32 fields as wire bytes.
+pub(crate) fn encode_fields(fields: &[u32]) -> Vec<u8> {
+    fields.iter().flat_map(|field| field.to_le_bytes()).collect()
+}
 
-    let mut stream = TcpStream::connect(&addr)?;
-    let _ = stream.set_nodelay(true);
-    stream.set_read_timeout(Some(Duration::from_secs(15)))?;
+/// Read `N` little-endian u32 fields.
+pub(crate) fn read_fields<const N: usize, S: Read>(stream: &mut S) -> Result<[u32; N], Error> {
+    let mut fields = [0_u32; N];
+    for field in &mut fields {
+        let mut bytes = [0_u8; 4];
+        stream.read_exact(&mut bytes)?;
+        *field = u32::from_le_bytes(bytes);
+    }
+    Ok(fields)
+}
 
-    // 12-byte header: magic, img_len, entry_pc (all little-endian).
-    let mut hdr = Vec::with_capacity(12);
-    hdr.extend_from_slice(&NETBOOT_MAGIC.to_le_bytes());
-    hdr.extend_from_slice(&(image.len() as u32).to_le_bytes());
-    hdr.extend_from_slice(&entry_pc.to_le_bytes());
+/// True for failures worth a reconnect: the network, not the board, went wrong.
+fn is_transient(err: &Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::TimedOut
+            | ErrorKind::WouldBlock
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::Interrupted
+            | ErrorKind::InvalidData
+    )
+}
+
+/// A board refusal: not retried.
+fn rejected(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+/// Resolve a host name, IPv4 or IPv6 address (brackets optional).
+fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>, Error> {
+    let host = host.strip_prefix('[').and_then(|inner| inner.strip_suffix(']')).unwrap_or(host);
+    let addresses: Vec<SocketAddr> = (host, port).to_socket_addrs()?.collect();
+    if addresses.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, format!("{host} has no address")));
+    }
+    Ok(addresses)
+}
+
+/// Connect to the first address that answers.
+fn connect(addresses: &[SocketAddr], options: &NetLoadOptions) -> Result<TcpStream, Error> {
+    let mut last_err = Error::new(ErrorKind::NotFound, "no address to connect to");
+    for address in addresses {
+        match TcpStream::connect_timeout(address, options.connect_timeout) {
+            Ok(stream) => {
+                let _ = stream.set_nodelay(true);
+                stream.set_read_timeout(Some(options.ack_timeout))?;
+                stream.set_write_timeout(Some(options.ack_timeout))?;
+                return Ok(stream);
+            }
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// Progress bar in the style of the serial loader.
+struct Progress {
+    /// Draw at all.
+    enabled: bool,
+    /// Image size.
+    total: usize,
+    /// Last percentage drawn.
+    last_pct: usize,
+}
+
+impl Progress {
+    /// Start a bar for `total` bytes.
+    const fn new(enabled: bool, total: usize) -> Self {
+        Self {
+            enabled,
+            total,
+            last_pct: usize::MAX,
+        }
+    }
 
-    stream.write_all(&hdr)?;
-    stream.write_all(image)?;
+    /// Redraw if the percentage changed.
+    fn update(&mut self, done: usize) {
+        let pct = done.saturating_mul(100).checked_div(self.total).unwrap_or(100);
+        if self.enabled && pct != self.last_pct {
+            let line = format!("Sending to board: {pct}% ({} / {})", human_bytes(done), human_bytes(self.total));
+            eprint!("\r{line:<50}");
+            self.last_pct = pct;
+        }
+    }
+
+    /// End the bar's line.
+    fn finish(&self) {
+        if self.enabled && self.last_pct != usize::MAX {
+            eprintln!();
+        }
+    }
+}
+
+/// Send the v2 HELLO and read the WELCOME; `None` if the board only speaks v1.
+fn handshake(stream: &mut TcpStream) -> Result<Option<BoardInfo>, Error> {
+    stream.write_all(&encode_fields(&[NETBOOT_V2_MAGIC, NETBOOT_VERSION, 0]))?;
+    let [magic, version] = match read_fields::<2, _>(stream) {
+        Ok(fields) => fields,
+        // A v1 board may simply hang up on an unknown magic.
+        Err(err) if matches!(err.kind(), ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    if magic != NETBOOT_V2_MAGIC {
+        // An 8-byte v1 acknowledgement rejecting the "header".
+        return Ok(None);
+    }
+    let [capabilities, max_chunk, mem_size] = read_fields::<3, _>(stream)?;
+    Ok(Some(BoardInfo {
+        version,
+        capabilities,
+        max_chunk,
+        mem_size,
+    }))
+}
+
+/// v1: header, whole image, one acknowledgement.  Returns the board's checksum.
+fn load_v1(stream: &mut TcpStream, image: &[u8], entry_pc: u32, progress: &mut Progress) -> Result<u32, Error> {
+    stream.write_all(&encode_fields(&[NETBOOT_MAGIC, image.len() as u32, entry_pc]))?;
+    let mut sent = 0_usize;
+    for window in image.chunks(0x10000) {
+        stream.write_all(window)?;
+        sent += window.len();
+        progress.update(sent);
+    }
     stream.flush()?;
+    let [status, board_cks] = read_fields::<2, _>(stream)?;
+    if status != STATUS_OK {
+        return Err(rejected(format!("Board rejected image (status {status})")));
+    }
+    Ok(board_cks)
+}
 
-    let host_cks = image_checksum(image);
+/// v2: LOAD, acknowledged chunks, DONE.  Returns the board's checksum.
+fn load_v2(stream: &mut TcpStream, info: &BoardInfo, image: &[u8], entry_pc: u32, chunk_size: u32, progress: &mut Progress) -> Result<u32, Error> {
+    if image.len() > info.mem_size as usize {
+        return Err(rejected(format!(
+            "Image of {} does not fit in the board's {}",
+            human_bytes(image.len()),
+            human_bytes(info.mem_size as usize)
+        )));
+    }
+    // Whole words, so each chunk's checksum covers all of its bytes.
+    let chunk_size = (chunk_size.min(info.max_chunk) & !3).max(4);
+    stream.write_all(&encode_fields(&[image.len() as u32, entry_pc, chunk_size, image_checksum(image)]))?;
+    let [status, _] = read_fields::<2, _>(stream)?;
+    if status != STATUS_OK {
+        return Err(rejected(format!("Board rejected image (status {status})")));
+    }
 
-    // 8-byte acknowledgement: status, checksum (both little-endian).
-    let mut ack = [0_u8; 8];
-    stream.read_exact(&mut ack)?;
-    let status = u32::from_le_bytes([ack[0], ack[1], ack[2], ack[3]]);
-    let board_cks = u32::from_le_bytes([ack[4], ack[5], ack[6], ack[7]]);
+    let mut offset = 0_usize;
+    for chunk in image.chunks(chunk_size as usize) {
+        let mut resends = 0;
+        loop {
+            let mut frame = encode_fields(&[offset as u32, chunk.len() as u32, image_checksum(chunk)]);
+            frame.extend_from_slice(chunk);
+            stream.write_all(&frame)?;
+            let [status, acked] = read_fields::<2, _>(stream)?;
+            match status {
+                STATUS_OK if acked as usize == offset => break,
+                STATUS_CHUNK_CHECKSUM if resends < CHUNK_RESENDS => resends += 1,
+                STATUS_CHUNK_CHECKSUM => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Chunk at offset {offset} failed its checksum {} times", resends + 1),
+                    ))
+                }
+                _ => {
+                    return Err(rejected(format!(
+                        "Board rejected chunk at offset {offset} (status {status}, offset {acked})"
+                    )))
+                }
+            }
+        }
+        offset += chunk.len();
+        progress.update(offset);
+    }
 
-    if status != 0 {
-        msg_list.push(format!("Board rejected image (status {status})"), None, None, MessageType::Error);
-    } else if board_cks != host_cks {
-        msg_list.push(
-            format!("Checksum mismatch: host 0x{host_cks:08X}, board 0x{board_cks:08X}"),
-            None,
-            None,
-            MessageType::Error,
-        );
+    let [status, board_cks] = read_fields::<2, _>(stream)?;
+    if status != STATUS_OK {
+        return Err(rejected(format!("Board rejected image (status {status})")));
+    }
+    Ok(board_cks)
+}
+
+/// One connection's worth of loading; returns the protocol version used.
+fn load_once(addresses: &[SocketAddr], image: &[u8], entry_pc: u32, options: &NetLoadOptions, msg_list: &mut MsgList) -> Result<u32, Error> {
+    let mut stream = connect(addresses, options)?;
+    let mut progress = Progress::new(options.progress, image.len());
+    let info = if options.protocol == NetbootProtocol::V1 {
+        None
     } else {
-        msg_list.push(
-            format!(
-                "netboot OK: {}, entry 0x{entry_pc:08X}, checksum 0x{board_cks:08X}",
-                human_bytes(image.len())
-            ),
-            None,
-            None,
-            MessageType::Information,
-        );
-    }
-    Ok(())
+        let info = handshake(&mut stream)?;
+        if info.is_none() {
+            if options.protocol == NetbootProtocol::V2 {
+                return Err(rejected("Board does not support netboot protocol v2".to_owned()));
+            }
+            msg_list.push(
+                "netboot: board speaks protocol v1 only, falling back".to_owned(),
+                None,
+                None,
+                MessageType::Information,
+            );
+            // The v1 board is done with this connection after rejecting the HELLO.
+            stream = connect(addresses, options)?;
+        }
+        info
+    };
+
+    let (version, result) = match &info {
+        Some(info) => {
+            msg_list.push(
+                format!(
+                    "netboot: protocol v{}, capabilities 0x{:08X}, chunks up to {}, memory {}{}",
+                    info.version,
+                    info.capabilities,
+                    human_bytes(info.max_chunk as usize),
+                    human_bytes(info.mem_size as usize),
+                    if info.capabilities & CAP_EMULATOR == 0 { "" } else { " (emulator)" }
+                ),
+                None,
+                None,
+                MessageType::Information,
+            );
+            (2, load_v2(&mut stream, info, image, entry_pc, options.chunk_size, &mut progress))
+        }
+        None => (1, load_v1(&mut stream, image, entry_pc, &mut progress)),
+    };
+    progress.finish();
+
+    let host_cks = image_checksum(image);
+    let board_cks = result?;
+    if board_cks != host_cks {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Checksum mismatch: host 0x{host_cks:08X}, board 0x{board_cks:08X}"),
+        ));
+    }
+    Ok(version)
+}
+
+/// Connect to the board and stream the DDR image with default options.
+#[cfg(not(tarpaulin_include))] // Cannot test live TCP in tarpaulin
+pub fn net_load(ip: &str, port: u16, image: &[u8], entry_pc: u32, msg_list: &mut MsgList) -> Result<(), Error> {
+    net_load_with(ip, port, image, entry_pc, &NetLoadOptions::default(), msg_list)
+}
+
+/// Connect to the board, stream the DDR image and verify the reply.
+///
+/// `host` may be a name, an IPv4 address or an IPv6 address (with or without
+/// brackets).  Transient network failures reconnect and start again, up to
+/// `options.retries` times; a refusal by the board is returned at once.
+#[cfg(not(tarpaulin_include))] // Cannot test live TCP in tarpaulin
+pub fn net_load_with(host: &str, port: u16, image: &[u8], entry_pc: u32, options: &NetLoadOptions, msg_list: &mut MsgList) -> Result<(), Error> {
+    let addresses = resolve(host, port)?;
+    msg_list.push(format!("netboot: connecting to {}", addresses[0]), None, None, MessageType::Information);
+
+    let mut attempt = 0;
+    loop {
+        match load_once(&addresses, image, entry_pc, options, msg_list) {
+            Ok(version) => {
+                msg_list.push(
+                    format!(
+                        "netboot OK: {}, entry 0x{entry_pc:08X}, checksum 0x{:08X} (protocol v{version})",
+                        human_bytes(image.len()),
+                        image_checksum(image)
+                    ),
+                    None,
+                    None,
+                    MessageType::Information,
+                );
+                return Ok(());
+            }
+            Err(err) if attempt < options.retries && is_transient(&err) => {
+                attempt += 1;
+                msg_list.push(
+                    format!("netboot: {err}, retrying ({attempt}/{})", options.retries),
+                    None,
+                    None,
+                    MessageType::Warning,
+                );
+                std::thread::sleep(Duration::from_millis(500) * attempt);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn quiet() -> NetLoadOptions {
+        NetLoadOptions {
+            progress: false,
+            ack_timeout: Duration::from_secs(2),
+            ..NetLoadOptions::default()
+        }
+    }
+
+    #[test]
+    // Test the checksum sums little-endian words and ignores a trailing partial word
+    fn test_image_checksum() {
+        assert_eq!(image_checksum(&[1, 0, 0, 0, 2, 0, 0, 0, 9]), 3);
+        assert_eq!(image_checksum(&encode_fields(&[u32::MAX, 2])), 1);
+    }
+
+    #[test]
+    // Test console port names bracket IPv6 addresses
+    fn test_console_port_name() {
+        assert_eq!(console_port_name("192.168.68.50", 5001), "tcp://192.168.68.50:5001");
+        assert_eq!(console_port_name("fe80::1", 5001), "tcp://[fe80::1]:5001");
+        assert_eq!(console_port_name("[::1]", 5001), "tcp://[::1]:5001");
+    }
+
+    #[test]
+    // Test host names, IPv4 and bracketed IPv6 addresses resolve
+    fn test_resolve() {
+        assert_eq!(resolve("127.0.0.1", 5000).unwrap()[0].to_string(), "127.0.0.1:5000");
+        assert_eq!(resolve("[::1]", 5000).unwrap()[0].to_string(), "[::1]:5000");
+        assert_eq!(resolve("::1", 5000).unwrap()[0].to_string(), "[::1]:5000");
+        assert!(resolve("localhost", 5000).is_ok());
+    }
+
+    #[test]
+    // Test auto mode falls back to v1 when a v1 board rejects the HELLO
+    fn test_fallback_to_v1() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let board = thread::spawn(move || {
+            // First connection: a v1 board sees a bad magic.
+            let (mut stream, _) = listener.accept().unwrap();
+            let [magic, _, _] = read_fields::<3, _>(&mut stream).unwrap();
+            assert_eq!(magic, NETBOOT_V2_MAGIC);
+            stream.write_all(&encode_fields(&[STATUS_BAD_MAGIC, 0])).unwrap();
+            drop(stream);
+            // Second connection: the v1 load.
+            let (mut stream, _) = listener.accept().unwrap();
+            let [magic, length, entry] = read_fields::<3, _>(&mut stream).unwrap();
+            assert_eq!((magic, entry), (NETBOOT_MAGIC, 0x20));
+            let mut image = vec![0_u8; length as usize];
+            stream.read_exact(&mut image).unwrap();
+            stream.write_all(&encode_fields(&[STATUS_OK, image_checksum(&image)])).unwrap();
+        });
+        let mut msg_list = MsgList::new();
+        net_load_with("127.0.0.1", port, &[7; 64], 0x20, &quiet(), &mut msg_list).unwrap();
+        board.join().unwrap();
+        assert_eq!(msg_list.number_by_type(&MessageType::Warning), 0);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let board = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = read_fields::<3, _>(&mut stream).unwrap();
+            stream.write_all(&encode_fields(&[STATUS_BAD_MAGIC, 0])).unwrap();
+        });
+        let options = NetLoadOptions {
+            protocol: NetbootProtocol::V2,
+            ..quiet()
+        };
+        let err = net_load_with("127.0.0.1", port, &[7; 64], 0x20, &options, &mut MsgList::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput, "forced v2 is not retried");
+        board.join().unwrap();
+    }
+
+    #[test]
+    // Test a dropped connection is retried and a refused one gives up after the retry budget
+    fn test_retry_transient_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let board = thread::spawn(move || {
+            drop(listener.accept().unwrap());
+            let (mut stream, _) = listener.accept().unwrap();
+            let [_, length, _] = read_fields::<3, _>(&mut stream).unwrap();
+            let mut image = vec![0_u8; length as usize];
+            stream.read_exact(&mut image).unwrap();
+            stream.write_all(&encode_fields(&[STATUS_OK, image_checksum(&image)])).unwrap();
+        });
+        let options = NetLoadOptions {
+            protocol: NetbootProtocol::V1,
+            ..quiet()
+        };
+        let mut msg_list = MsgList::new();
+        net_load_with("127.0.0.1", port, &[1; 16], 0x20, &options, &mut msg_list).unwrap();
+        board.join().unwrap();
+        assert_eq!(msg_list.number_by_type(&MessageType::Warning), 1);
+
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let options = NetLoadOptions { retries: 1, ..options };
+        let mut msg_list = MsgList::new();
+        let err = net_load_with("127.0.0.1", port, &[1; 16], 0x20, &options, &mut msg_list).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        assert_eq!(msg_list.number_by_type(&MessageType::Warning), 1);
+    }
 }
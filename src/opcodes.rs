@@ -306,6 +306,23 @@ fn hex_nibble_to_reg(nibble: u32) -> &'static str {
     }
 }
 
+/// Mask and pattern of an opcode's `hex_code`: a word is that opcode when
+/// `word & mask == pattern`.  `'?'` characters are nibble wildcards.
+#[must_use]
+pub fn opcode_mask(hex_code: &str) -> (u32, u32) {
+    let mut mask: u32 = 0;
+    let mut pattern: u32 = 0;
+    for ch in hex_code.chars() {
+        mask <<= 4;
+        pattern <<= 4;
+        if ch != '?' {
+            mask |= 0xF_u32;
+            pattern |= ch.to_digit(16).unwrap_or(0);
+        }
+    }
+    (mask, pattern)
+}
+
 /// Disassemble a 32-bit instruction word.
 ///
 /// Searches `opcodes` for the first entry whose `hex_code` pattern matches `word`.
@@ -315,17 +332,7 @@ fn hex_nibble_to_reg(nibble: u32) -> &'static str {
 /// order they appear in source (reg1 at the highest of the occupied nibbles).
 pub fn disassemble_word(word: u32, opcodes: &[Opcode]) -> Option<(String, u32)> {
     for opcode in opcodes {
-        // Compute mask/pattern from hex_code on the fly — '?' = wildcard nibble.
-        let mut mask: u32 = 0;
-        let mut pattern: u32 = 0;
-        for ch in opcode.hex_code.chars() {
-            mask <<= 4;
-            pattern <<= 4;
-            if ch != '?' {
-                mask |= 0xF_u32;
-                pattern |= ch.to_digit(16).unwrap_or(0);
-            }
-        }
+        let (mask, pattern) = opcode_mask(&opcode.hex_code);
         if (word & mask) == pattern {
             let n = opcode.registers;
             let mut text = opcode.text_name.clone();
@@ -0,0 +1,406 @@
+//! Board peripheral state for the emulator.
+//!
+//! The board's LEDs, two seven-segment displays, two RGB LEDs, the 16×2 LCD
+//! and the switches are modelled here so emulated programs can be watched
+//! (a live front panel) and checked (`// Expected LEDs:` comments).  Every
+//! change is kept in a bounded history numbered by retired instruction.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Characters per LCD row.
+pub const LCD_COLUMNS: usize = 16;
+
+/// LCD rows.
+pub const LCD_ROWS: usize = 2;
+
+/// LEDs shown on the front panel (bits 7..0 of the LED register).
+pub const PANEL_LEDS: u32 = 8;
+
+/// Changes kept in the history; older ones are dropped.
+pub const HISTORY_LIMIT: usize = 10_000;
+
+/// DDRAM address of the first character of LCD row 2.
+const LCD_ROW2_ADDR: u8 = 0x40;
+
+/// One write to a board output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// LED register written.
+    Leds(u32),
+    /// Seven-segment display 1 written (`None`: blanked).
+    Seg1(Option<u32>),
+    /// Seven-segment display 2 written (`None`: blanked).
+    Seg2(Option<u32>),
+    /// RGB LED 1 written.
+    Rgb1(u32),
+    /// RGB LED 2 written.
+    Rgb2(u32),
+    /// LCD command byte.
+    LcdCommand(u8),
+    /// LCD data byte.
+    LcdData(u8),
+    /// LCD reset line driven.
+    LcdReset(bool),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Leds(value) => write!(f, "LEDs 0x{value:02X}"),
+            Self::Seg1(Some(value)) => write!(f, "7SEG1 0x{value:04X}"),
+            Self::Seg2(Some(value)) => write!(f, "7SEG2 0x{value:04X}"),
+            Self::Seg1(None) => write!(f, "7SEG1 blank"),
+            Self::Seg2(None) => write!(f, "7SEG2 blank"),
+            Self::Rgb1(value) => write!(f, "RGB1 0x{value:06X}"),
+            Self::Rgb2(value) => write!(f, "RGB2 0x{value:06X}"),
+            Self::LcdCommand(byte) => write!(f, "LCD command 0x{byte:02X}"),
+            Self::LcdData(byte) if byte.is_ascii_graphic() || byte == b' ' => write!(f, "LCD data 0x{byte:02X} '{}'", char::from(byte)),
+            Self::LcdData(byte) => write!(f, "LCD data 0x{byte:02X}"),
+            Self::LcdReset(level) => write!(f, "LCD reset {}", u8::from(level)),
+        }
+    }
+}
+
+/// An output that a test can check, named as in `// Expected <name>:` comments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    /// The LED register.
+    Leds,
+    /// Seven-segment display 1 (`7SEG` or `7SEG1`).
+    Seg1,
+    /// Seven-segment display 2.
+    Seg2,
+    /// RGB LED 1.
+    Rgb1,
+    /// RGB LED 2.
+    Rgb2,
+}
+
+impl Output {
+    /// Parse a comment name (`LEDs`, `7SEG`, `7SEG1`, `7SEG2`, `RGB1`, `RGB2`), any case.
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "LED" | "LEDS" => Some(Self::Leds),
+            "7SEG" | "7SEG1" => Some(Self::Seg1),
+            "7SEG2" => Some(Self::Seg2),
+            "RGB1" => Some(Self::Rgb1),
+            "RGB2" => Some(Self::Rgb2),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Leds => "LEDs",
+            Self::Seg1 => "7SEG1",
+            Self::Seg2 => "7SEG2",
+            Self::Rgb1 => "RGB1",
+            Self::Rgb2 => "RGB2",
+        })
+    }
+}
+
+/// State of the board peripherals.
+#[derive(Clone, Debug)]
+pub struct Peripherals {
+    /// LED register.
+    leds: u32,
+    /// Seven-segment display 1 (`None`: blank).
+    seg1: Option<u32>,
+    /// Seven-segment display 2 (`None`: blank).
+    seg2: Option<u32>,
+    /// RGB LED 1 (0xRRGGBB).
+    rgb1: u32,
+    /// RGB LED 2 (0xRRGGBB).
+    rgb2: u32,
+    /// Switch status word returned by SWR.
+    switches: u64,
+    /// LCD display RAM, one row per line.
+    lcd: [[u8; LCD_COLUMNS]; LCD_ROWS],
+    /// LCD DDRAM address of the next data byte.
+    lcd_cursor: u8,
+    /// Changes, oldest first, with the retired-instruction count at each.
+    history: VecDeque<(u64, Event)>,
+    /// Changes dropped from the front of the history.
+    dropped: u64,
+}
+
+impl Default for Peripherals {
+    fn default() -> Self {
+        Self {
+            leds: 0,
+            seg1: None,
+            seg2: None,
+            rgb1: 0,
+            rgb2: 0,
+            switches: 0,
+            lcd: [[b' '; LCD_COLUMNS]; LCD_ROWS],
+            lcd_cursor: 0,
+            history: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+}
+
+impl Peripherals {
+    /// All outputs off, the LCD blank and the switches at 0.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply a write made by the instruction retired as number `instruction`.
+    pub fn apply(&mut self, instruction: u64, event: Event) {
+        match event {
+            Event::Leds(value) => self.leds = value,
+            Event::Seg1(value) => self.seg1 = value,
+            Event::Seg2(value) => self.seg2 = value,
+            Event::Rgb1(value) => self.rgb1 = value,
+            Event::Rgb2(value) => self.rgb2 = value,
+            Event::LcdCommand(byte) => self.lcd_command(byte),
+            Event::LcdData(byte) => self.lcd_data(byte),
+            Event::LcdReset(level) => {
+                // The reset line is active low: holding it low clears the display.
+                if !level {
+                    self.lcd = [[b' '; LCD_COLUMNS]; LCD_ROWS];
+                    self.lcd_cursor = 0;
+                }
+            }
+        }
+        if self.history.len() == HISTORY_LIMIT {
+            self.history.pop_front();
+            self.dropped += 1;
+        }
+        self.history.push_back((instruction, event));
+    }
+
+    /// HD44780-style command: clear (0x01), home (0x02/0x03) and set DDRAM
+    /// address (0x80 | addr).  Mode, display and function-set commands do not
+    /// change what is shown and are ignored.
+    fn lcd_command(&mut self, byte: u8) {
+        match byte {
+            0x01 => {
+                self.lcd = [[b' '; LCD_COLUMNS]; LCD_ROWS];
+                self.lcd_cursor = 0;
+            }
+            0x02 | 0x03 => self.lcd_cursor = 0,
+            0x80..=0xFF => self.lcd_cursor = byte & 0x7F,
+            _ => {}
+        }
+    }
+
+    /// Write a character at the cursor and move it right.
+    fn lcd_data(&mut self, byte: u8) {
+        let row = usize::from(self.lcd_cursor >= LCD_ROW2_ADDR);
+        let column = usize::from(self.lcd_cursor & 0x3F);
+        if let Some(cell) = self.lcd[row].get_mut(column) {
+            *cell = byte;
+        }
+        self.lcd_cursor = self.lcd_cursor.wrapping_add(1) & 0x7F;
+    }
+
+    /// Set the switch status word that SWR reads.
+    pub const fn set_switches(&mut self, value: u64) {
+        self.switches = value;
+    }
+
+    /// Switch status word.
+    #[must_use]
+    pub const fn switches(&self) -> u64 {
+        self.switches
+    }
+
+    /// Current value of a checkable output (a blank display reads as 0).
+    #[must_use]
+    pub fn value(&self, output: Output) -> u32 {
+        match output {
+            Output::Leds => self.leds,
+            Output::Seg1 => self.seg1.unwrap_or(0),
+            Output::Seg2 => self.seg2.unwrap_or(0),
+            Output::Rgb1 => self.rgb1,
+            Output::Rgb2 => self.rgb2,
+        }
+    }
+
+    /// LCD text, one string per row (non-printable bytes shown as `?`).
+    #[must_use]
+    pub fn lcd_text(&self) -> Vec<String> {
+        self.lcd
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&byte| {
+                        if byte.is_ascii_graphic() || byte == b' ' {
+                            char::from(byte)
+                        } else {
+                            '?'
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Recorded changes, oldest first, each with its retired-instruction count.
+    #[must_use]
+    pub const fn history(&self) -> &VecDeque<(u64, Event)> {
+        &self.history
+    }
+
+    /// Changes no longer in [`Self::history`] because it reached [`HISTORY_LIMIT`].
+    #[must_use]
+    pub const fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Front panel as text lines: LED row, both 7-seg displays, RGB LEDs,
+    /// switches and the LCD in a frame.
+    #[must_use]
+    pub fn render(&self) -> Vec<String> {
+        let leds: String = (0..PANEL_LEDS)
+            .rev()
+            .map(|bit| if self.leds & (1 << bit) == 0 { '.' } else { '*' })
+            .collect();
+        let seg = |value: Option<u32>| value.map_or_else(|| "    ".to_owned(), |value| format!("{:04X}", value & 0xFFFF));
+        let lcd = self.lcd_text();
+        vec![
+            format!("LEDs  [{leds}]  0x{:02X}", self.leds),
+            format!("7SEG  [{}] [{}]", seg(self.seg1), seg(self.seg2)),
+            format!("RGB   #{:06X} #{:06X}", self.rgb1 & 0xFF_FFFF, self.rgb2 & 0xFF_FFFF),
+            format!("SW    0x{:X}", self.switches),
+            format!("LCD   +{}+", "-".repeat(LCD_COLUMNS)),
+            format!("      |{}|", lcd[0]),
+            format!("      |{}|", lcd[1]),
+            format!("      +{}+", "-".repeat(LCD_COLUMNS)),
+        ]
+    }
+
+    /// Compare against expected final values; one message per mismatch.
+    #[must_use]
+    pub fn check(&self, expected: &[(Output, u32)]) -> Vec<String> {
+        expected
+            .iter()
+            .filter(|&&(output, value)| self.value(output) != value)
+            .map(|&(output, value)| format!("{output} expected 0x{value:02X}, got 0x{:02X}", self.value(output)))
+            .collect()
+    }
+}
+
+/// Parse a switch word: `0x` hex, `0b` binary or decimal.
+pub fn parse_switches(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let parsed = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else if let Some(binary) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        u64::from_str_radix(binary, 2)
+    } else {
+        text.parse()
+    };
+    parsed.map_err(|_| format!("bad switch value \"{text}\" (expected 0x hex, 0b binary or decimal)"))
+}
+
+/// Parse expected final peripheral values from source comments.
+///
+/// Matches `// Expected <name>: 0xNN …` where the name is `LEDs`, `7SEG`,
+/// `7SEG1`, `7SEG2`, `RGB1` or `RGB2`, optionally followed by `at end`.
+/// Anything after the value (`= all pass`) is ignored.
+#[must_use]
+pub fn parse_expected_peripherals(lines: &[String]) -> Vec<(Output, u32)> {
+    let mut expected = Vec::new();
+    for line in lines {
+        let Some(body) = line.trim().strip_prefix("//").map(str::trim) else {
+            continue;
+        };
+        let Some((name, value)) = body.strip_prefix("Expected ").and_then(|rest| rest.split_once(':')) else {
+            continue;
+        };
+        let name = name.trim();
+        let name = name.strip_suffix(" at end").unwrap_or(name).trim();
+        let Some(output) = Output::from_name(name) else {
+            continue;
+        };
+        let token = value.split_whitespace().next().unwrap_or("");
+        let parsed = match token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => token.parse().ok(),
+        };
+        if let Some(value) = parsed {
+            expected.push((output, value));
+        }
+    }
+    expected
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+
+    #[test]
+    // Test outputs, the LCD model and the history
+    fn test_apply_and_render() {
+        let mut panel = Peripherals::new();
+        panel.apply(1, Event::Leds(0xA5));
+        panel.apply(2, Event::Seg1(Some(0x14)));
+        panel.apply(3, Event::LcdCommand(0x01));
+        for (index, byte) in b"Hi".iter().enumerate() {
+            panel.apply(4 + index as u64, Event::LcdData(*byte));
+        }
+        panel.apply(6, Event::LcdCommand(0xC0 + 3));
+        panel.apply(7, Event::LcdData(b'!'));
+        assert_eq!(panel.value(Output::Leds), 0xA5);
+        assert_eq!(panel.value(Output::Seg1), 0x14);
+        assert_eq!(panel.value(Output::Seg2), 0);
+        assert_eq!(panel.lcd_text(), vec!["Hi              ".to_owned(), "   !            ".to_owned()]);
+        let lines = panel.render();
+        assert_eq!(lines[0], "LEDs  [*.*..*.*]  0xA5");
+        assert_eq!(lines[1], "7SEG  [0014] [    ]");
+        assert_eq!(lines[5], "      |Hi              |");
+        assert_eq!(panel.history().len(), 7);
+        assert_eq!(panel.history()[1], (2, Event::Seg1(Some(0x14))));
+        assert_eq!(panel.history()[4].1.to_string(), "LCD data 0x69 'i'");
+
+        panel.apply(8, Event::LcdReset(false));
+        assert_eq!(panel.lcd_text()[0], " ".repeat(LCD_COLUMNS));
+        for index in 0..HISTORY_LIMIT as u64 {
+            panel.apply(9 + index, Event::Leds(0));
+        }
+        assert_eq!(panel.history().len(), HISTORY_LIMIT);
+        assert_eq!(panel.dropped(), 8);
+    }
+
+    #[test]
+    // Test parsing expected values and checking them
+    fn test_expected_peripherals() {
+        let lines: Vec<String> = [
+            "// Expected UART output:",
+            "//   00000001  (one)",
+            "// Expected 7SEG: 0x14",
+            "// Expected LEDs at end: 0xFF = all pass",
+            "// Expected RGB2: 255",
+            "// Expected 7SEG: later",
+            "SETR A 1 // Expected LEDs: 0x01",
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+        let expected = parse_expected_peripherals(&lines);
+        assert_eq!(expected, vec![(Output::Seg1, 0x14), (Output::Leds, 0xFF), (Output::Rgb2, 255)]);
+
+        let mut panel = Peripherals::new();
+        panel.apply(1, Event::Seg1(Some(0x14)));
+        panel.apply(2, Event::Leds(0x0F));
+        assert_eq!(
+            panel.check(&expected),
+            vec!["LEDs expected 0xFF, got 0x0F".to_owned(), "RGB2 expected 0xFF, got 0x00".to_owned()]
+        );
+
+        assert_eq!(parse_switches("0x1F"), Ok(0x1F));
+        assert_eq!(parse_switches("0b101"), Ok(5));
+        assert_eq!(parse_switches(" 12 "), Ok(12));
+        assert!(parse_switches("0xZZ").is_err());
+    }
+}
@@ -0,0 +1,329 @@
+//! Execution profiler for the emulator.
+//!
+//! A [`Profiler`] counts retired instructions per program counter and keeps a
+//! shadow call stack: a taken `CALL*` / `CALLR` (or an interrupt dispatch)
+//! pushes a frame for its target, `RET` / `IRET` pops back to the frame whose
+//! return address it reads.  Instructions are also counted per distinct stack,
+//! which gives three reports once addresses are named from a [`SymbolMap`]:
+//! a flat profile by label, a call graph by function (frame entry), and folded
+//! stacks (`main;print;putc 42`) for flame graph tools.
+
+use crate::symbols::{SymbolMap, NO_LABEL};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+
+/// One shadow call stack entry.
+#[derive(Clone, Copy, Debug)]
+struct Frame {
+    /// Address the frame was entered at.
+    entry: u32,
+    /// Stack pointer after the return address was pushed (`u32::MAX` for the root).
+    sp: u32,
+}
+
+/// Self and inclusive instruction counts of one function.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FunctionCounts {
+    /// Instructions retired in the function itself.
+    pub own: u64,
+    /// Instructions retired in the function and everything it called.
+    pub total: u64,
+    /// Times the function was called.
+    pub calls: u64,
+}
+
+/// Instruction counts per PC and per call stack.
+#[derive(Clone, Debug)]
+pub struct Profiler {
+    /// Retired instructions by program counter.
+    counts: HashMap<u32, u64>,
+    /// Shadow call stack; the first frame is the program entry.
+    stack: Vec<Frame>,
+    /// Retired instructions by call stack (frame entries, outermost first).
+    stacks: HashMap<Vec<u32>, u64>,
+    /// Instructions retired since the stack last changed (not yet in `stacks`).
+    current: u64,
+    /// Calls by `(caller entry, callee entry)`.
+    calls: HashMap<(u32, u32), u64>,
+    /// Returns with no frame to pop.
+    unmatched_returns: u64,
+}
+
+impl Profiler {
+    /// An empty profile of a program entered at `entry`.
+    #[must_use]
+    pub fn new(entry: u32) -> Self {
+        Self {
+            counts: HashMap::new(),
+            stack: vec![Frame { entry, sp: u32::MAX }],
+            stacks: HashMap::new(),
+            current: 0,
+            calls: HashMap::new(),
+            unmatched_returns: 0,
+        }
+    }
+
+    /// Count one retired instruction at `pc`.
+    pub fn record(&mut self, pc: u32) {
+        *self.counts.entry(pc).or_default() += 1;
+        self.current += 1;
+    }
+
+    /// A call to `target` pushed its return address at `sp`.
+    pub fn call(&mut self, target: u32, sp: u32) {
+        self.flush();
+        let caller = self.stack.last().map_or(target, |frame| frame.entry);
+        *self.calls.entry((caller, target)).or_default() += 1;
+        self.stack.push(Frame { entry: target, sp });
+    }
+
+    /// A return popped its address from `sp`: leave that frame and any
+    /// deeper ones it skipped (e.g. a longjmp-style stack reset).
+    pub fn ret(&mut self, sp: u32) {
+        self.flush();
+        let keep = self.stack.iter().skip(1).position(|frame| frame.sp <= sp).map(|index| index + 1);
+        match keep {
+            Some(len) => self.stack.truncate(len),
+            None => self.unmatched_returns += 1,
+        }
+    }
+
+    /// Move the instructions counted since the stack last changed into `stacks`.
+    fn flush(&mut self) {
+        if self.current > 0 {
+            let key = self.stack.iter().map(|frame| frame.entry).collect();
+            *self.stacks.entry(key).or_default() += std::mem::take(&mut self.current);
+        }
+    }
+
+    /// Retired instructions counted.
+    #[must_use]
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// Returns that had no frame to pop (hand-built stacks, or a RET used as a jump).
+    #[must_use]
+    pub const fn unmatched_returns(&self) -> u64 {
+        self.unmatched_returns
+    }
+
+    /// Instruction counts per call stack, including the current one.
+    fn all_stacks(&self) -> HashMap<Vec<u32>, u64> {
+        let mut stacks = self.stacks.clone();
+        if self.current > 0 {
+            *stacks.entry(self.stack.iter().map(|frame| frame.entry).collect()).or_default() += self.current;
+        }
+        stacks
+    }
+
+    /// Instruction and call counts by the label each PC falls under, most
+    /// instructions first: `(label, instructions, calls)`.
+    #[must_use]
+    pub fn by_label<'a>(&self, symbols: &'a SymbolMap) -> Vec<(&'a str, u64, u64)> {
+        let mut labels: HashMap<Option<u32>, (&str, u64, u64)> = HashMap::new();
+        for (pc, count) in &self.counts {
+            let (name, start) = symbols.enclosing(*pc).map_or((NO_LABEL, None), |(name, start)| (name, Some(start)));
+            labels.entry(start).or_insert((name, 0, 0)).1 += count;
+        }
+        for (&(_, callee), count) in &self.calls {
+            if let Some(label) = symbols.enclosing(callee).and_then(|(_, start)| labels.get_mut(&Some(start))) {
+                label.2 += count;
+            }
+        }
+        let mut labels: Vec<(&str, u64, u64)> = labels.into_values().collect();
+        labels.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        labels
+    }
+
+    /// Self, inclusive and call counts by function (frame entry address).
+    #[must_use]
+    pub fn by_function(&self) -> HashMap<u32, FunctionCounts> {
+        let mut functions: HashMap<u32, FunctionCounts> = HashMap::new();
+        for (stack, count) in self.all_stacks() {
+            let mut seen = Vec::with_capacity(stack.len());
+            for entry in &stack {
+                // A recursive function's instructions count once towards its total.
+                if !seen.contains(entry) {
+                    seen.push(*entry);
+                    functions.entry(*entry).or_default().total += count;
+                }
+            }
+            if let Some(leaf) = stack.last() {
+                functions.entry(*leaf).or_default().own += count;
+            }
+        }
+        for (&(_, callee), count) in &self.calls {
+            functions.entry(callee).or_default().calls += count;
+        }
+        functions
+    }
+
+    /// Flat profile: instructions by label, at most `limit` rows.
+    #[must_use]
+    pub fn flat_report(&self, symbols: &SymbolMap, limit: usize) -> String {
+        let total = self.total();
+        let labels = self.by_label(symbols);
+        let mut text = format!("Flat profile: {total} instructions in {} labels\n", labels.len());
+        if labels.is_empty() {
+            return text;
+        }
+        let _ = writeln!(
+            text,
+            "  {:<24} {:>12} {:>8} {:>8} {:>8}",
+            "label", "instructions", "%", "cumul %", "calls"
+        );
+        let mut cumulative = 0;
+        for (label, count, calls) in labels.iter().take(limit) {
+            cumulative += count;
+            let _ = writeln!(
+                text,
+                "  {label:<24} {count:>12} {:>8.2} {:>8.2} {calls:>8}",
+                percent(*count, total),
+                percent(cumulative, total)
+            );
+        }
+        if labels.len() > limit {
+            let _ = writeln!(text, "  … {} more labels", labels.len() - limit);
+        }
+        text
+    }
+
+    /// Call graph: each function's self and inclusive counts with its callers
+    /// and callees, largest inclusive count first, at most `limit` functions.
+    #[must_use]
+    pub fn call_graph_report(&self, symbols: &SymbolMap, limit: usize) -> String {
+        let total = self.total();
+        let mut functions: Vec<(u32, FunctionCounts)> = self.by_function().into_iter().collect();
+        functions.sort_by(|(a_entry, a), (b_entry, b)| b.total.cmp(&a.total).then(b.own.cmp(&a.own)).then(a_entry.cmp(b_entry)));
+        let mut text = format!("Call graph: {} functions", functions.len());
+        if self.unmatched_returns > 0 {
+            let _ = write!(text, ", {} unmatched returns", self.unmatched_returns);
+        }
+        text.push('\n');
+        for (entry, counts) in functions.iter().take(limit) {
+            let _ = writeln!(
+                text,
+                "  {:<24} total {:>12} ({:>6.2}%)  self {:>12} ({:>6.2}%)  calls {}",
+                frame_name(symbols, *entry),
+                counts.total,
+                percent(counts.total, total),
+                counts.own,
+                percent(counts.own, total),
+                counts.calls
+            );
+            let mut edges: Vec<(&str, String, u64)> = self
+                .calls
+                .iter()
+                .filter_map(|(&(caller, callee), &count)| {
+                    if callee == *entry {
+                        Some(("<-", frame_name(symbols, caller), count))
+                    } else if caller == *entry {
+                        Some(("->", frame_name(symbols, callee), count))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            edges.sort_by(|a, b| b.0.cmp(a.0).then(b.2.cmp(&a.2)).then(a.1.cmp(&b.1)));
+            for (arrow, name, count) in edges {
+                let _ = writeln!(text, "      {arrow} {name:<24} {count:>10} calls");
+            }
+        }
+        if functions.len() > limit {
+            let _ = writeln!(text, "  … {} more functions", functions.len() - limit);
+        }
+        text
+    }
+
+    /// Folded stacks, one `outer;inner count` line per distinct stack, sorted.
+    #[must_use]
+    pub fn folded(&self, symbols: &SymbolMap) -> String {
+        let mut lines: BTreeMap<String, u64> = BTreeMap::new();
+        for (stack, count) in self.all_stacks() {
+            let names: Vec<String> = stack.iter().map(|entry| frame_name(symbols, *entry)).collect();
+            *lines.entry(names.join(";")).or_default() += count;
+        }
+        lines.iter().fold(String::new(), |mut text, (stack, count)| {
+            let _ = writeln!(text, "{stack} {count}");
+            text
+        })
+    }
+}
+
+/// Name of a frame entered at `entry`: its label, `label+0xN` inside one, else the address.
+fn frame_name(symbols: &SymbolMap, entry: u32) -> String {
+    match symbols.enclosing(entry) {
+        Some((name, start)) if start == entry => name.to_owned(),
+        Some((name, start)) => format!("{name}+0x{:x}", entry - start),
+        None => format!("0x{entry:08x}"),
+    }
+}
+
+/// `part` as a percentage of `whole` (0 for an empty profile).
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+
+    /// `main` at 0x20 calls `work` at 0x40 twice; `work` calls `leaf` at 0x60 once.
+    fn sample() -> (Profiler, SymbolMap) {
+        let symbols = SymbolMap::from_symbols(vec![(0x20, "main".to_owned()), (0x40, "work".to_owned()), (0x60, "leaf".to_owned())]);
+        let mut profiler = Profiler::new(0x20);
+        profiler.record(0x20);
+        profiler.call(0x40, 0x1000);
+        profiler.record(0x40);
+        profiler.record(0x44);
+        profiler.call(0x60, 0x0FF8);
+        profiler.record(0x60);
+        profiler.ret(0x0FF8);
+        profiler.record(0x48);
+        profiler.ret(0x1000);
+        profiler.record(0x24);
+        profiler.call(0x40, 0x1000);
+        profiler.record(0x40);
+        profiler.ret(0x1000);
+        profiler.record(0x28);
+        (profiler, symbols)
+    }
+
+    #[test]
+    // Test the shadow stack gives per-label, per-function and per-stack counts
+    fn test_profile_counts() {
+        let (profiler, symbols) = sample();
+        assert_eq!(profiler.total(), 8);
+        assert_eq!(profiler.by_label(&symbols), vec![("work", 4, 2), ("main", 3, 0), ("leaf", 1, 1)]);
+        let functions = profiler.by_function();
+        assert_eq!(functions[&0x20], FunctionCounts { own: 3, total: 8, calls: 0 });
+        assert_eq!(functions[&0x40], FunctionCounts { own: 4, total: 5, calls: 2 });
+        assert_eq!(functions[&0x60], FunctionCounts { own: 1, total: 1, calls: 1 });
+        assert_eq!(profiler.folded(&symbols), "main 3\nmain;work 4\nmain;work;leaf 1\n");
+        let graph = profiler.call_graph_report(&symbols, 10);
+        assert!(graph.contains("<- main"), "{graph}");
+        assert!(graph.contains("-> leaf"), "{graph}");
+        assert!(profiler.flat_report(&symbols, 1).contains("… 2 more labels"));
+    }
+
+    #[test]
+    // Test a return that skips frames unwinds them, and one with no frame is counted
+    fn test_profile_unwind() {
+        let symbols = SymbolMap::default();
+        let mut profiler = Profiler::new(0x20);
+        profiler.call(0x40, 0x1000);
+        profiler.call(0x60, 0x0FF8);
+        profiler.record(0x60);
+        profiler.ret(0x1000);
+        profiler.record(0x24);
+        profiler.ret(0x1000);
+        assert_eq!(profiler.unmatched_returns(), 1);
+        assert_eq!(profiler.folded(&symbols), "0x00000020 1\n0x00000020;0x00000040;0x00000060 1\n");
+    }
+}
@@ -1,8 +1,13 @@
+use crate::expect::{run_script, Script, ScriptTarget, TargetEvent, TransportTarget};
 use crate::helper::{human_bytes, trim_newline};
 use crate::messages::{MessageType, MsgList};
+use crate::transport::{PtyTransport, SerialTransport, TcpTransport, Transport, TransportSpec, BOARD_BAUD};
+use regex::Regex;
 use serialport::{SerialPort, SerialPortType, UsbPortInfo};
 use std::fmt::Write as _;
+use std::fs::File;
 use std::io::{self, Error, Read as _, Write as _};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
@@ -94,7 +99,7 @@ fn return_port(port_name: &str, msg_list: &mut MsgList) -> Result<Box<dyn Serial
         }
     }
 
-    let port_result = serialport::new(local_port_name.clone(), 3_000_000)
+    let port_result = serialport::new(local_port_name.clone(), BOARD_BAUD)
         .timeout(Duration::from_millis(100))
         .open();
     if let Err(err) = port_result {
@@ -165,6 +170,23 @@ fn return_port(port_name: &str, msg_list: &mut MsgList) -> Result<Box<dyn Serial
     Ok(port)
 }
 
+/// Open the transport named by `port_name` (see [`TransportSpec`]).
+///
+/// Plain names and `serial:` open a USB serial port as before, including
+/// automatic selection for `AUTO_SERIAL`; `pty:` and `tcp://` reach a local
+/// fake or a network bridge.
+#[cfg(not(tarpaulin_include))] // Cannot test opening serial hardware in tarpaulin
+pub fn open_transport(port_name: &str, msg_list: &mut MsgList) -> Result<Box<dyn Transport>, Error> {
+    let opened: Result<Box<dyn Transport>, Error> = match TransportSpec::parse(port_name) {
+        TransportSpec::Serial(device) => return Ok(Box::new(SerialTransport::new(return_port(&device, msg_list)?)?)),
+        TransportSpec::Pty(path) => PtyTransport::open(&path).map(|pty| Box::new(pty) as Box<dyn Transport>),
+        TransportSpec::Tcp(address) => TcpTransport::connect(&address).map(|tcp| Box::new(tcp) as Box<dyn Transport>),
+    };
+    opened.inspect_err(|err| {
+        msg_list.push(format!("Error opening {port_name} error \"{err}\""), None, None, MessageType::Error);
+    })
+}
+
 /// Output the code details file to given serial port, keeping the port open.
 ///
 /// Will send the program to the serial port, wait for the response, and return the open port.
@@ -175,22 +197,21 @@ pub fn write_to_board_keep_port(
     send_break: bool,
     skip_load_response: bool,
     msg_list: &mut MsgList,
-) -> Result<Box<dyn SerialPort>, Error> {
-    use serialport::{DataBits, FlowControl, Parity, StopBits};
-
+) -> Result<Box<dyn Transport>, Error> {
     let mut read_buffer = [0; 1024];
-    let mut port = return_port(port_name, msg_list)?;
+    let mut port = open_transport(port_name, msg_list)?;
 
-    port.set_stop_bits(StopBits::One)?;
-    port.set_data_bits(DataBits::Eight)?;
-    port.set_parity(Parity::None)?;
-    port.set_flow_control(FlowControl::None)?;
-
-    if send_break {
-        port.set_break()?;
-        thread::sleep(Duration::from_millis(100));
-        port.clear_break()?;
+    if send_break && port.supports_break() {
+        port.send_break(Duration::from_millis(100))?;
     } else {
+        if send_break {
+            msg_list.push(
+                format!("{} cannot send a UART break, resetting the board with 'S'", port.name()),
+                None,
+                None,
+                MessageType::Information,
+            );
+        }
         port.write_all(b"S")?;
     }
 
@@ -310,29 +331,187 @@ fn print_bytes(data: &[u8], debug: bool) {
     }
 }
 
-/// Monitor serial port for incoming UART data from the FPGA board.
-///
-/// Continuously reads from the serial port and prints received data to stdout.
-/// Runs until the user presses Ctrl+C, then closes the port cleanly.
-#[cfg(not(tarpaulin_include))] // Cannot test serial monitoring in tarpaulin
-pub fn monitor_serial(port_name: &str, debug: bool, msg_list: &mut MsgList) -> Result<(), Error> {
-    let port = return_port(port_name, msg_list)?;
-    monitor_serial_port(port, debug, msg_list)
+/// Options for [`monitor_serial_port`].
+#[derive(Clone, Debug, Default)]
+pub struct MonitorOptions {
+    /// Print each received byte as hex alongside normal output.
+    pub debug: bool,
+    /// Prefix each line with the host time.
+    pub timestamps: bool,
+    /// Tee the decoded output to this file and the raw bytes to `<file>.raw`.
+    pub log: Option<PathBuf>,
+    /// Stop with success when the output matches.
+    pub until: Option<Regex>,
+    /// Stop with failure when the output matches.
+    pub fail_on: Option<Regex>,
+    /// Stop after this long.
+    pub timeout: Option<Duration>,
+    /// Drive the program with this expect script instead of forwarding keystrokes.
+    pub script: Option<Script>,
 }
 
-/// Monitor an already-open serial port for incoming UART data.
-///
-/// Used after `write_to_board_keep_port` to continue reading from the same port
-/// that was used to upload the program, ensuring no UART output is missed.
-#[cfg(not(tarpaulin_include))] // Cannot test serial monitoring in tarpaulin
-pub fn monitor_serial_port(mut port: Box<dyn SerialPort>, debug: bool, msg_list: &mut MsgList) -> Result<(), Error> {
-    port.set_timeout(Duration::from_millis(500))?;
+/// Why the monitor stopped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MonitorOutcome {
+    /// The board sent the `0x00` halt marker.
+    Halted,
+    /// Ctrl+C.
+    Stopped,
+    /// A line matched `--until`.
+    Matched(String),
+    /// A line matched `--fail-on`.
+    Failed(String),
+    /// `--monitor-timeout` expired.
+    TimedOut,
+    /// The `--script` ran to the end (number of steps).
+    ScriptPassed(usize),
+    /// A `--script` step failed.
+    ScriptFailed(String),
+}
 
-    let running = Arc::new(AtomicBool::new(true));
-    let running_stdin = Arc::clone(&running);
+impl MonitorOutcome {
+    /// True if the run counts as a pass: `--until` matched, or nothing was
+    /// required and `--fail-on` did not match.
+    #[must_use]
+    pub fn is_success(&self, options: &MonitorOptions) -> bool {
+        match self {
+            Self::Matched(_) | Self::ScriptPassed(_) => true,
+            Self::Failed(_) | Self::ScriptFailed(_) => false,
+            Self::Halted | Self::Stopped | Self::TimedOut => options.until.is_none(),
+        }
+    }
+}
+
+/// Line tracking, timestamps, log files and pattern checks for one monitor run.
+struct MonitorSession<'opts> {
+    /// The run's options.
+    options: &'opts MonitorOptions,
+    /// Text of the current line so far (without `\r`).
+    line: String,
+    /// Next printable character starts a line (gets a timestamp).
+    at_line_start: bool,
+    /// Start of a UTF-8 character whose remaining bytes are still to come.
+    partial: Vec<u8>,
+    /// Decoded-text log.
+    log: Option<File>,
+    /// Raw-byte log.
+    raw_log: Option<File>,
+}
+
+impl<'opts> MonitorSession<'opts> {
+    /// Start a session, creating the log files if asked for.
+    fn open(options: &'opts MonitorOptions) -> io::Result<Self> {
+        let (log, raw_log) = match &options.log {
+            Some(path) => {
+                let mut raw_path = path.clone().into_os_string();
+                raw_path.push(".raw");
+                (Some(File::create(path)?), Some(File::create(raw_path)?))
+            }
+            None => (None, None),
+        };
+        Ok(Self {
+            options,
+            line: String::new(),
+            at_line_start: true,
+            partial: Vec::new(),
+            log,
+            raw_log,
+        })
+    }
+
+    /// Decode `data` after the bytes held back from the previous read, holding
+    /// back a UTF-8 character cut off at its end until the next read.
+    fn decode(&mut self, data: &[u8]) -> String {
+        let mut bytes = std::mem::take(&mut self.partial);
+        bytes.extend_from_slice(data);
+        let cut = (1..=bytes.len().min(3))
+            .find(|&len| std::str::from_utf8(&bytes[bytes.len() - len..]).is_err_and(|err| err.valid_up_to() == 0 && err.error_len().is_none()));
+        if let Some(len) = cut {
+            self.partial = bytes.split_off(bytes.len() - len);
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
 
-    // Raw mode sends each keystroke immediately without buffering and without
-    // the OS intercepting Ctrl+C as a signal, so we handle 0x03 manually below.
+    /// Insert a timestamp before the first character of each line of `decoded`.
+    ///
+    /// The board ends lines with `\n\r`, so a `\r` at the start of a line is
+    /// passed through before the timestamp rather than overwriting it.
+    fn decorate(&mut self, decoded: &str) -> String {
+        let mut text = String::with_capacity(decoded.len());
+        for ch in decoded.chars() {
+            match ch {
+                '\n' => {
+                    self.at_line_start = true;
+                    text.push(ch);
+                }
+                '\r' => text.push(ch),
+                _ => {
+                    if self.at_line_start && self.options.timestamps {
+                        let _ = write!(text, "[{}] ", chrono::Local::now().format("%H:%M:%S%.3f"));
+                    }
+                    self.at_line_start = false;
+                    text.push(ch);
+                }
+            }
+        }
+        text
+    }
+
+    /// Check a line (or the partial line so far) against `--fail-on` and `--until`.
+    fn check(&self, line: &str) -> Option<MonitorOutcome> {
+        if self.options.fail_on.as_ref().is_some_and(|pattern| pattern.is_match(line)) {
+            return Some(MonitorOutcome::Failed(line.to_owned()));
+        }
+        if self.options.until.as_ref().is_some_and(|pattern| pattern.is_match(line)) {
+            return Some(MonitorOutcome::Matched(line.to_owned()));
+        }
+        None
+    }
+
+    /// Display and log received bytes; returns an outcome if a pattern matched.
+    fn feed(&mut self, data: &[u8]) -> Option<MonitorOutcome> {
+        if data.is_empty() {
+            return None;
+        }
+        let decoded = self.decode(data);
+        let text = self.decorate(&decoded);
+        if self.options.debug {
+            print_bytes(data, true);
+        } else {
+            eprint!("{text}");
+        }
+        io::stderr().flush().unwrap_or(());
+        if let Some(raw_log) = &mut self.raw_log {
+            let _ = raw_log.write_all(data);
+        }
+        if let Some(log) = &mut self.log {
+            let _ = log.write_all(text.replace('\r', "").as_bytes());
+        }
+
+        for ch in decoded.chars() {
+            match ch {
+                '\n' => {
+                    let line = std::mem::take(&mut self.line);
+                    if let Some(outcome) = self.check(&line) {
+                        return Some(outcome);
+                    }
+                }
+                '\r' => {}
+                _ => self.line.push(ch),
+            }
+        }
+        // Prompts do not end in a newline, so the partial line counts too.
+        self.check(&self.line)
+    }
+}
+
+/// Put the terminal in raw mode and discard keystrokes typed before now.
+///
+/// Raw mode sends each keystroke immediately without buffering and without
+/// the OS intercepting Ctrl+C as a signal, so [`forward_keystrokes`] handles
+/// 0x03 itself.  Undo with `crossterm::terminal::disable_raw_mode`.
+#[cfg(not(tarpaulin_include))] // Needs a terminal
+pub fn start_raw_terminal(msg_list: &mut MsgList) {
     if let Err(err) = crossterm::terminal::enable_raw_mode() {
         msg_list.push(format!("Failed to enable raw terminal mode: \"{err}\""), None, None, MessageType::Warning);
     }
@@ -350,37 +529,78 @@ pub fn monitor_serial_port(mut port: Box<dyn SerialPort>, debug: bool, msg_list:
     while crossterm::event::poll(Duration::ZERO).unwrap_or(false) {
         drop(crossterm::event::read());
     }
+}
 
-    // Clone the port so the stdin thread can write while the main loop reads.
-    match port.try_clone().map_err(|e| Error::other(e.to_string())) {
-        Ok(mut write_port) => {
-            thread::spawn(move || {
-                use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
-                while running_stdin.load(Ordering::Relaxed) {
-                    // Poll with a short timeout so we can check `running` regularly.
-                    if let Ok(true) = event::poll(Duration::from_millis(100)) {
-                        if let Ok(Event::Key(KeyEvent { code, modifiers, .. })) = event::read() {
-                            let byte: Option<u8> = match code {
-                                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
-                                    running_stdin.store(false, Ordering::Relaxed);
-                                    break;
-                                }
-                                KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => {
-                                    // Convert Ctrl+<letter> to its control code (e.g. Ctrl+Z -> 0x1A)
-                                    c.to_ascii_lowercase().try_into().ok().map(|b: u8| b & 0x1F)
-                                }
-                                KeyCode::Char(c) => c.encode_utf8(&mut [0_u8; 4]).bytes().next(),
-                                KeyCode::Enter => Some(b'\r'),
-                                KeyCode::Backspace => Some(0x08),
-                                _ => None,
-                            };
-                            if let Some(b) = byte {
-                                drop(write_port.write_all(&[b]));
-                            }
+/// Send each keystroke to `sink` as the byte a terminal would, on a new thread.
+///
+/// Ctrl+C is not forwarded: it clears `running` and ends the thread, as does
+/// anyone else clearing `running`.
+#[cfg(not(tarpaulin_include))] // Needs a terminal
+pub fn forward_keystrokes(running: Arc<AtomicBool>, mut sink: impl FnMut(u8) + Send + 'static) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+        while running.load(Ordering::Relaxed) {
+            // Poll with a short timeout so we can check `running` regularly.
+            if let Ok(true) = event::poll(Duration::from_millis(100)) {
+                if let Ok(Event::Key(KeyEvent { code, modifiers, .. })) = event::read() {
+                    let byte: Option<u8> = match code {
+                        KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                            running.store(false, Ordering::Relaxed);
+                            break;
+                        }
+                        KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => {
+                            // Convert Ctrl+<letter> to its control code (e.g. Ctrl+Z -> 0x1A)
+                            c.to_ascii_lowercase().try_into().ok().map(|b: u8| b & 0x1F)
                         }
+                        KeyCode::Char(c) => c.encode_utf8(&mut [0_u8; 4]).bytes().next(),
+                        KeyCode::Enter => Some(b'\r'),
+                        KeyCode::Backspace => Some(0x08),
+                        _ => None,
+                    };
+                    if let Some(b) = byte {
+                        sink(b);
                     }
                 }
-            });
+            }
+        }
+    })
+}
+
+/// Monitor serial port for incoming UART data from the FPGA board.
+///
+/// Continuously reads from the serial port and prints received data to stdout.
+/// Runs until the user presses Ctrl+C, the board halts, a `--until` /
+/// `--fail-on` pattern matches or the timeout expires, then closes the port
+/// cleanly.
+#[cfg(not(tarpaulin_include))] // Cannot test serial monitoring in tarpaulin
+pub fn monitor_serial(port_name: &str, options: &MonitorOptions, msg_list: &mut MsgList) -> Result<MonitorOutcome, Error> {
+    let port = open_transport(port_name, msg_list)?;
+    monitor_serial_port(port, options, msg_list)
+}
+
+/// Monitor an already-open serial port for incoming UART data.
+///
+/// Used after `write_to_board_keep_port` to continue reading from the same port
+/// that was used to upload the program, ensuring no UART output is missed.
+#[cfg(not(tarpaulin_include))] // Cannot test serial monitoring in tarpaulin
+pub fn monitor_serial_port(mut port: Box<dyn Transport>, options: &MonitorOptions, msg_list: &mut MsgList) -> Result<MonitorOutcome, Error> {
+    if let Some(script) = &options.script {
+        return run_script_on_port(port, script, options);
+    }
+    let mut session = MonitorSession::open(options)?;
+    // Short reads so the timeout is checked promptly.
+    port.set_timeout(Duration::from_millis(if options.timeout.is_some() { 100 } else { 500 }))?;
+    let started = Instant::now();
+
+    let running = Arc::new(AtomicBool::new(true));
+    let running_stdin = Arc::clone(&running);
+
+    start_raw_terminal(msg_list);
+
+    // Clone the port so the stdin thread can write while the main loop reads.
+    match port.try_clone() {
+        Ok(mut write_port) => {
+            forward_keystrokes(running_stdin, move |byte| drop(write_port.write_all(&[byte])));
         }
         Err(err) => {
             msg_list.push(
@@ -395,40 +615,122 @@ pub fn monitor_serial_port(mut port: Box<dyn SerialPort>, debug: bool, msg_list:
     eprintln!("Monitoring serial port (Ctrl+C to stop)...\r");
 
     let mut read_buf = [0_u8; 1024];
-    let mut halt_received = false;
+    let mut outcome = MonitorOutcome::Stopped;
 
     'monitor: while running.load(Ordering::Relaxed) {
+        if options.timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            outcome = MonitorOutcome::TimedOut;
+            break;
+        }
         match port.read(&mut read_buf[..]) {
             Ok(bytes_read) => {
                 let data = read_buf.get(..bytes_read).unwrap_or(&[]);
                 // A UART break arrives as 0x00 (NUL).  Since the CPU only ever
                 // sends ASCII text, 0x00 cannot appear in normal output.
-                if let Some(pos) = data.iter().position(|&b| b == 0x00) {
-                    print_bytes(&data[..pos], debug);
-                    io::stderr().flush().unwrap_or(());
-                    halt_received = true;
+                let halt = data.iter().position(|&b| b == 0x00);
+                if let Some(matched) = session.feed(&data[..halt.unwrap_or(data.len())]) {
+                    outcome = matched;
+                    break 'monitor;
+                }
+                if halt.is_some() {
+                    outcome = MonitorOutcome::Halted;
                     break 'monitor;
                 }
-                print_bytes(data, debug);
-                io::stderr().flush().unwrap_or(());
             }
             Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
             Err(err) => {
                 msg_list.push(format!("Serial monitor error: \"{err}\""), None, None, MessageType::Error);
+                running.store(false, Ordering::Relaxed);
                 drop(crossterm::terminal::disable_raw_mode());
                 return Err(err);
             }
         }
     }
 
+    // Stop the input-forwarding thread so it drops its handle on the port.
+    running.store(false, Ordering::Relaxed);
     let _ = crossterm::terminal::disable_raw_mode();
-    if halt_received {
-        eprintln!("\r\nCPU halted.");
-    } else {
-        eprintln!("\r\nSerial monitor stopped.");
+    match &outcome {
+        MonitorOutcome::Halted => eprintln!("\r\nCPU halted."),
+        MonitorOutcome::Stopped => eprintln!("\r\nSerial monitor stopped."),
+        MonitorOutcome::Matched(line) => eprintln!("\r\nMatched --until: \"{line}\""),
+        MonitorOutcome::Failed(line) => eprintln!("\r\nMatched --fail-on: \"{line}\""),
+        MonitorOutcome::TimedOut => eprintln!("\r\nSerial monitor timed out after {:.1}s.", started.elapsed().as_secs_f64()),
+        MonitorOutcome::ScriptPassed(_) | MonitorOutcome::ScriptFailed(_) => {}
     }
     drop(port);
-    Ok(())
+    Ok(outcome)
+}
+
+/// Run an expect script against the program behind `port`, showing and logging its output.
+///
+/// `--until` and `--fail-on` are checked on the output as it arrives; a match
+/// ends the script with that outcome.  `--monitor-timeout` fails the script.
+#[cfg(not(tarpaulin_include))] // Cannot test serial hardware in tarpaulin
+fn run_script_on_port(port: Box<dyn Transport>, script: &Script, options: &MonitorOptions) -> Result<MonitorOutcome, Error> {
+    let mut target = MonitoredTarget {
+        target: TransportTarget::new(port),
+        session: MonitorSession::open(options)?,
+        deadline: options.timeout.map(|timeout| Instant::now() + timeout),
+        outcome: None,
+    };
+    eprintln!("Running script {} ({} steps)...", script.name, script.steps.len());
+    let result = run_script(script, &mut target, |_| {});
+    Ok(match (target.outcome, result) {
+        (Some(outcome), _) => {
+            match &outcome {
+                MonitorOutcome::Matched(line) => eprintln!("\r\nMatched --until: \"{line}\""),
+                MonitorOutcome::Failed(line) => eprintln!("\r\nMatched --fail-on: \"{line}\""),
+                _ => {}
+            }
+            outcome
+        }
+        (None, Ok(steps)) => {
+            eprintln!("\r\nScript passed.");
+            MonitorOutcome::ScriptPassed(steps)
+        }
+        (None, Err(failure)) => {
+            eprintln!("\r\nScript failed.");
+            MonitorOutcome::ScriptFailed(failure.describe(script))
+        }
+    })
+}
+
+/// A board under a script, with its output shown, logged and matched by the monitor.
+struct MonitoredTarget<'opts> {
+    /// The board.
+    target: TransportTarget,
+    /// Display, log and `--until` / `--fail-on` matching.
+    session: MonitorSession<'opts>,
+    /// When `--monitor-timeout` expires.
+    deadline: Option<Instant>,
+    /// The `--until` or `--fail-on` match that ended the script.
+    outcome: Option<MonitorOutcome>,
+}
+
+impl ScriptTarget for MonitoredTarget<'_> {
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.target.send(data)
+    }
+
+    fn receive(&mut self, wait: Duration) -> io::Result<TargetEvent> {
+        if self.outcome.is_some() {
+            return Ok(TargetEvent::Stopped("monitor pattern matched".to_owned()));
+        }
+        let wait = match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => return Ok(TargetEvent::Stopped("--monitor-timeout expired".to_owned())),
+            Some(deadline) => wait.min(deadline.saturating_duration_since(Instant::now())),
+            None => wait,
+        };
+        let event = self.target.receive(wait)?;
+        if let TargetEvent::Output(data) = &event {
+            self.outcome = self.session.feed(data);
+            if let Some(outcome) = &self.outcome {
+                return Ok(TargetEvent::Stopped(format!("{outcome:?}")));
+            }
+        }
+        Ok(event)
+    }
 }
 
 /// Result of a test verification run.
@@ -462,7 +764,7 @@ fn extract_hex_value(line: &str) -> Option<String> {
 /// Reads UART output from the FPGA board and compares each hex line against the
 /// expected values in order. Stops when all expected values are matched or timeout.
 #[cfg(not(tarpaulin_include))] // Cannot test serial hardware in tarpaulin
-pub fn run_test_monitor(mut port: Box<dyn SerialPort>, expected_values: &[String], timeout_secs: u64, msg_list: &mut MsgList) -> TestResult {
+pub fn run_test_monitor(mut port: Box<dyn Transport>, expected_values: &[String], timeout_secs: u64, msg_list: &mut MsgList) -> TestResult {
     let start = Instant::now();
     let timeout = Duration::from_secs(timeout_secs);
 
@@ -559,6 +861,119 @@ mod tests {
     #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
     use super::*;
 
+    #[test]
+    // Test lines are split across reads, timestamps skip the board's leading \r and --until matches
+    fn test_monitor_session_until() {
+        let options = MonitorOptions {
+            timestamps: true,
+            until: Some(Regex::new("^PASS").unwrap()),
+            ..MonitorOptions::default()
+        };
+        let mut session = MonitorSession::open(&options).unwrap();
+        let text = session.decorate("ab\n\rcd");
+        assert!(text.starts_with('['), "{text}");
+        assert!(text.contains("ab\n\r["), "{text}");
+        assert!(session.feed(b"PA").is_none());
+        assert_eq!(session.feed(b"SS 3\n\r"), Some(MonitorOutcome::Matched("PASS 3".to_owned())));
+    }
+
+    #[test]
+    // Test --fail-on wins over --until and partial lines (prompts) are matched
+    fn test_monitor_session_fail_on() {
+        let options = MonitorOptions {
+            until: Some(Regex::new("done").unwrap()),
+            fail_on: Some(Regex::new("FAIL|panic").unwrap()),
+            ..MonitorOptions::default()
+        };
+        let mut session = MonitorSession::open(&options).unwrap();
+        assert!(session.feed(b"ok\n\r").is_none());
+        assert_eq!(session.feed(b"done but FAIL"), Some(MonitorOutcome::Failed("done but FAIL".to_owned())));
+        assert!(!MonitorOutcome::Failed(String::new()).is_success(&options));
+        assert!(!MonitorOutcome::Halted.is_success(&options), "--until was required");
+        assert!(MonitorOutcome::Halted.is_success(&MonitorOptions::default()));
+        assert!(!MonitorOutcome::TimedOut.is_success(&options));
+    }
+
+    #[test]
+    // Test --log writes decoded text and <log>.raw the exact bytes
+    fn test_monitor_session_log() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("monitor.log");
+        let options = MonitorOptions {
+            log: Some(path.clone()),
+            ..MonitorOptions::default()
+        };
+        let mut session = MonitorSession::open(&options).unwrap();
+        let _ = session.feed(b"hello\n\rworld");
+        drop(session);
+        let mut raw_path = path.clone().into_os_string();
+        raw_path.push(".raw");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello\nworld");
+        assert_eq!(std::fs::read(&raw_path).unwrap(), b"hello\n\rworld");
+    }
+
+    #[test]
+    // Test a UTF-8 character split across two reads is decoded whole, for matching and the log
+    fn test_monitor_session_split_utf8() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("monitor.log");
+        let options = MonitorOptions {
+            log: Some(path.clone()),
+            until: Some(Regex::new("^café$").unwrap()),
+            ..MonitorOptions::default()
+        };
+        let mut session = MonitorSession::open(&options).unwrap();
+        assert_eq!(session.feed(b"caf\xC3"), None);
+        assert_eq!(session.feed(b"\xA9\n\r"), Some(MonitorOutcome::Matched("café".to_owned())));
+        assert_eq!(session.feed(b"\xFFok\n"), None, "an invalid byte is not held back");
+        drop(session);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "café\n\u{FFFD}ok\n");
+    }
+
+    /// A script run against a TCP "board" that sends `output` and then stays connected.
+    fn script_against_output(output: &'static [u8], options: &MonitorOptions) -> MonitorOutcome {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let board = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(output).unwrap();
+            // Hold the connection until the monitor hangs up.
+            let _ = stream.read(&mut [0_u8; 16]);
+        });
+        let port = TcpTransport::from_stream(std::net::TcpStream::connect(address).unwrap()).unwrap();
+        let script = Script::parse("t", "expect \"never\" 5").unwrap();
+        let outcome = run_script_on_port(Box::new(port), &script, options).unwrap();
+        board.join().unwrap();
+        outcome
+    }
+
+    #[test]
+    // Test --fail-on and --until end a script, and --monitor-timeout fails it
+    fn test_script_monitor_conditions() {
+        let options = MonitorOptions {
+            fail_on: Some(Regex::new("^FAIL").unwrap()),
+            ..MonitorOptions::default()
+        };
+        assert_eq!(
+            script_against_output(b"boot\nFAIL 3\n", &options),
+            MonitorOutcome::Failed("FAIL 3".to_owned())
+        );
+        let options = MonitorOptions {
+            until: Some(Regex::new("^PASS$").unwrap()),
+            ..MonitorOptions::default()
+        };
+        assert_eq!(script_against_output(b"PASS\n", &options), MonitorOutcome::Matched("PASS".to_owned()));
+        let options = MonitorOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..MonitorOptions::default()
+        };
+        let outcome = script_against_output(b"", &options);
+        assert!(
+            matches!(&outcome, MonitorOutcome::ScriptFailed(reason) if reason.contains("--monitor-timeout")),
+            "{outcome:?}"
+        );
+    }
+
     #[test]
     fn test_extract_hex_value_valid() {
         assert_eq!(extract_hex_value("00000080"), Some("00000080".to_owned()));
@@ -578,4 +993,33 @@ mod tests {
     fn test_extract_hex_value_lowercase_rejected() {
         assert_eq!(extract_hex_value("0000abcd"), None);
     }
+
+    #[test]
+    // Test the load and test-monitor flows run unchanged against a TCP fake board
+    fn test_load_and_test_monitor_over_tcp() {
+        use std::io::{BufRead as _, BufReader};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port_name = format!("tcp://{}", listener.local_addr().unwrap());
+        let fake_board = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut frame = Vec::new();
+            reader.read_until(b'X', &mut frame).unwrap();
+            let mut writer = stream;
+            writer.write_all(b"Complete\r\n").unwrap();
+            thread::sleep(Duration::from_millis(300));
+            writer.write_all(b"0000002A\n00000007\n").unwrap();
+            frame
+        });
+
+        let mut msg_list = MsgList::new();
+        let port = write_to_board_keep_port("S0000Z0000X", &port_name, true, false, &mut msg_list).unwrap();
+        let result = run_test_monitor(port, &["0000002A".to_owned(), "00000008".to_owned()], 5, &mut msg_list);
+        assert_eq!(fake_board.join().unwrap(), b"SS0000Z0000X".to_vec());
+        assert_eq!((result.passed, result.failed, result.timed_out), (1, 1, false));
+        assert!(msg_list.list.iter().any(|msg| msg.text.contains("resetting the board with 'S'")));
+        assert!(msg_list.list.iter().any(|msg| msg.text.contains("\"Complete\"")));
+    }
 }
@@ -0,0 +1,297 @@
+//! Image size breakdown (`--size-report`) and build-to-build comparison.
+//!
+//! Every byte emitted in pass 2 is charged to the nearest preceding code label
+//! (a function) or `#DATA` name, so `.word`/`.space` objects in the data
+//! section are charged to the label that names them.  Bytes before the first
+//! symbol go to `(start)`.  The report is saved as JSON next to the `.kbt` so
+//! two builds can be compared with `--size-diff`.
+
+use crate::files::LineType;
+use crate::helper::data_name_from_string;
+use crate::labels::label_name_from_string;
+use crate::opcodes::Pass2;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// Name used for bytes emitted before the first label.
+const START_OBJECT: &str = "(start)";
+
+/// Bytes attributed to one function or data object.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct SizeEntry {
+    /// Label name without `:`, or `#DATA` name.
+    pub name: String,
+    /// Source file defining the symbol (after `!include` resolution).
+    pub file_name: String,
+    /// Byte address of the symbol.
+    pub address: u32,
+    /// Instruction bytes.
+    pub code_bytes: u32,
+    /// `#DATA`, `.word`, `.long` and `.space` bytes.
+    pub data_bytes: u32,
+}
+
+impl SizeEntry {
+    /// Total bytes of code and data.
+    #[must_use]
+    pub const fn total(&self) -> u32 {
+        self.code_bytes + self.data_bytes
+    }
+
+    /// True when the object holds only data.
+    #[must_use]
+    pub const fn is_data(&self) -> bool {
+        self.code_bytes == 0 && self.data_bytes > 0
+    }
+}
+
+/// Size breakdown of one build, as saved to JSON.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct SizeReport {
+    /// Program bytes after the heap header.
+    pub total_bytes: u32,
+    /// Objects, largest first.
+    pub objects: Vec<SizeEntry>,
+}
+
+/// Change in size of one symbol between two builds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeDelta {
+    /// Symbol name.
+    pub name: String,
+    /// Size in the old build, `None` if the symbol is new.
+    pub old: Option<u32>,
+    /// Size in the new build, `None` if the symbol was removed.
+    pub new: Option<u32>,
+}
+
+impl SizeDelta {
+    /// Growth in bytes (negative for shrinkage).
+    #[must_use]
+    pub fn change(&self) -> i64 {
+        i64::from(self.new.unwrap_or(0)) - i64::from(self.old.unwrap_or(0))
+    }
+}
+
+/// Attribute every byte in `pass2` to a label or `#DATA` object.
+#[must_use]
+pub fn size_report(pass2: &[Pass2]) -> SizeReport {
+    let mut objects: Vec<SizeEntry> = Vec::new();
+    for line in pass2 {
+        let symbol = match line.line_type {
+            LineType::Label => label_name_from_string(&line.input_text_line).map(|name| name.trim_end_matches(':').to_owned()),
+            LineType::Data => data_name_from_string(&line.input_text_line),
+            _ => None,
+        };
+        if let Some(name) = symbol {
+            objects.push(SizeEntry {
+                name,
+                file_name: line.file_name.clone(),
+                address: line.program_counter,
+                ..SizeEntry::default()
+            });
+        }
+
+        let bytes = (line.opcode.len() / 2) as u32;
+        if bytes == 0 {
+            continue;
+        }
+        if objects.is_empty() {
+            objects.push(SizeEntry {
+                name: START_OBJECT.to_owned(),
+                file_name: line.file_name.clone(),
+                address: line.program_counter,
+                ..SizeEntry::default()
+            });
+        }
+        if let Some(object) = objects.last_mut() {
+            if line.line_type == LineType::Data {
+                object.data_bytes += bytes;
+            } else {
+                object.code_bytes += bytes;
+            }
+        }
+    }
+
+    // Several labels at one address (e.g. `_start` plus `main:`) leave empty
+    // objects; keep only symbols that own bytes.
+    objects.retain(|object| object.total() > 0);
+    objects.sort_by(|a, b| b.total().cmp(&a.total()).then_with(|| a.address.cmp(&b.address)));
+    SizeReport {
+        total_bytes: objects.iter().map(SizeEntry::total).sum(),
+        objects,
+    }
+}
+
+/// Render a report as a table, followed by per-file totals.
+#[must_use]
+pub fn format_size_report(report: &SizeReport) -> String {
+    let name_width = report.objects.iter().map(|o| o.name.len()).max().unwrap_or(0).max(6);
+    let mut text = format!(
+        "{:>8} {:>6}  {:<4}  {:<name_width$}  {:<10}  file\n",
+        "bytes", "%", "kind", "symbol", "address"
+    );
+    let percent = |bytes: u32| {
+        if report.total_bytes == 0 {
+            0.0
+        } else {
+            f64::from(bytes) * 100.0 / f64::from(report.total_bytes)
+        }
+    };
+    for object in &report.objects {
+        let _ = writeln!(
+            text,
+            "{:>8} {:>5.1}%  {:<4}  {:<name_width$}  0x{:08X}  {}",
+            object.total(),
+            percent(object.total()),
+            if object.is_data() { "data" } else { "code" },
+            object.name,
+            object.address,
+            object.file_name
+        );
+    }
+
+    let mut by_file: BTreeMap<&str, u32> = BTreeMap::new();
+    for object in &report.objects {
+        *by_file.entry(object.file_name.as_str()).or_default() += object.total();
+    }
+    let mut files: Vec<(&str, u32)> = by_file.into_iter().collect();
+    files.sort_by_key(|(_, bytes)| std::cmp::Reverse(*bytes));
+    text.push('\n');
+    for (file_name, bytes) in files {
+        let _ = writeln!(text, "{bytes:>8} {:>5.1}%  {file_name}", percent(bytes));
+    }
+    let _ = writeln!(text, "{:>8} bytes total", report.total_bytes);
+    text
+}
+
+/// Per-symbol changes from `old` to `new`, largest growth first; unchanged symbols are left out.
+#[must_use]
+pub fn diff_size_reports(old: &SizeReport, new: &SizeReport) -> Vec<SizeDelta> {
+    let mut sizes: BTreeMap<&str, (Option<u32>, Option<u32>)> = BTreeMap::new();
+    for object in &old.objects {
+        sizes.entry(object.name.as_str()).or_default().0 = Some(object.total());
+    }
+    for object in &new.objects {
+        sizes.entry(object.name.as_str()).or_default().1 = Some(object.total());
+    }
+    let mut deltas: Vec<SizeDelta> = sizes
+        .into_iter()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(name, (old, new))| SizeDelta {
+            name: name.to_owned(),
+            old,
+            new,
+        })
+        .collect();
+    deltas.sort_by(|a, b| b.change().cmp(&a.change()).then_with(|| a.name.cmp(&b.name)));
+    deltas
+}
+
+/// Render a diff as a table with the overall change.
+#[must_use]
+pub fn format_size_diff(old: &SizeReport, new: &SizeReport) -> String {
+    let deltas = diff_size_reports(old, new);
+    let size = |value: Option<u32>| value.map_or_else(|| "-".to_owned(), |v| v.to_string());
+    let mut text = format!("{:>8} {:>8} {:>8}  symbol\n", "old", "new", "change");
+    for delta in &deltas {
+        let _ = writeln!(
+            text,
+            "{:>8} {:>8} {:>+8}  {}",
+            size(delta.old),
+            size(delta.new),
+            delta.change(),
+            delta.name
+        );
+    }
+    let total_change = i64::from(new.total_bytes) - i64::from(old.total_bytes);
+    let _ = writeln!(
+        text,
+        "{:>8} {:>8} {total_change:>+8}  total ({} symbol{} changed)",
+        old.total_bytes,
+        new.total_bytes,
+        deltas.len(),
+        if deltas.len() == 1 { "" } else { "s" }
+    );
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+
+    fn line(line_type: LineType, text: &str, opcode: &str, program_counter: u32, file_name: &str) -> Pass2 {
+        Pass2 {
+            file_name: file_name.to_owned(),
+            input_text_line: text.to_owned(),
+            line_counter: 1,
+            line_type,
+            opcode: opcode.to_owned(),
+            program_counter,
+        }
+    }
+
+    fn sample_pass2() -> Vec<Pass2> {
+        vec![
+            line(LineType::Opcode, "JMP main:", "0000100000000030", 0x20, "main.kla"),
+            line(LineType::Label, "print:", "", 0x28, "lib.kla"),
+            line(LineType::Opcode, "TXR A", "00005010", 0x28, "lib.kla"),
+            line(LineType::Opcode, "RET", "00001012", 0x2C, "lib.kla"),
+            line(LineType::Start, "_start", "", 0x30, "main.kla"),
+            line(LineType::Label, "main:", "", 0x30, "main.kla"),
+            line(LineType::Opcode, "HALT", "0000F011", 0x30, "main.kla"),
+            line(LineType::Data, "#MSG \"Hi\"", "0000000248690000", 0x34, "main.kla"),
+            line(LineType::Label, "buffer:", "", 0x3C, "main.kla"),
+            line(LineType::Data, ".space 16", "00000000000000000000000000000000", 0x3C, "main.kla"),
+        ]
+    }
+
+    #[test]
+    // Test bytes are charged to the preceding label or data name and sorted by size
+    fn test_size_report() {
+        let report = size_report(&sample_pass2());
+        assert_eq!(report.total_bytes, 8 + 8 + 4 + 8 + 16);
+        let names: Vec<&str> = report.objects.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names, vec!["buffer", "(start)", "print", "#MSG", "main"]);
+        let print = &report.objects[2];
+        assert_eq!((print.code_bytes, print.data_bytes, print.file_name.as_str()), (8, 0, "lib.kla"));
+        assert!(report.objects[0].is_data());
+        assert!(report.objects[3].is_data());
+
+        let text = format_size_report(&report);
+        assert!(text.contains("lib.kla"), "{text}");
+        assert!(text.contains("44 bytes total"), "{text}");
+    }
+
+    #[test]
+    // Test the report survives a JSON round trip and diffs show growth per symbol
+    fn test_size_diff() {
+        let old = size_report(&sample_pass2());
+        let json = serde_json::to_string(&old).unwrap();
+        assert_eq!(serde_json::from_str::<SizeReport>(&json).unwrap(), old);
+
+        let mut pass2 = sample_pass2();
+        pass2.insert(3, line(LineType::Opcode, "TXR B", "00005011", 0x2C, "lib.kla"));
+        pass2.truncate(pass2.len() - 2);
+        let new = size_report(&pass2);
+        let deltas = diff_size_reports(&old, &new);
+        assert_eq!(
+            deltas,
+            vec![
+                SizeDelta {
+                    name: "print".to_owned(),
+                    old: Some(8),
+                    new: Some(12),
+                },
+                SizeDelta {
+                    name: "buffer".to_owned(),
+                    old: Some(16),
+                    new: None,
+                },
+            ]
+        );
+        assert!(format_size_diff(&old, &new).contains("-12  total (2 symbols changed)"));
+    }
+}
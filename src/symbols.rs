@@ -0,0 +1,111 @@
+//! Address-to-label lookup for emulator reports.
+//!
+//! Cache statistics and profiles attribute program counters to the label (or
+//! ELF function symbol) they fall under: the nearest one at or below the
+//! address.  Labels come from the assembler's `Pass2` list; ELF symbols are
+//! moved from their link addresses to where the image is loaded in DDR.
+
+use crate::files::LineType;
+use crate::opcodes::Pass2;
+
+/// Name reports use for addresses below every label.
+pub const NO_LABEL: &str = "(no label)";
+
+/// Labels sorted by address.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolMap {
+    /// `(address, name)`, sorted by address.
+    symbols: Vec<(u32, String)>,
+}
+
+impl SymbolMap {
+    /// Labels (and `_start`) from an assembled program.
+    #[must_use]
+    pub fn from_pass2(pass2: &[Pass2]) -> Self {
+        let symbols = pass2
+            .iter()
+            .filter(|line| matches!(line.line_type, LineType::Label | LineType::Start))
+            .filter_map(|line| {
+                let name = line.input_text_line.split_whitespace().next()?;
+                Some((line.program_counter, name.trim_end_matches(':').to_owned()))
+            })
+            .collect();
+        Self::from_symbols(symbols)
+    }
+
+    /// Function and label symbols of an ELF file, moved from link address
+    /// `elf_base` to load address `load_base`.  Not an ELF, or no symbols: empty.
+    #[must_use]
+    pub fn from_elf(data: &[u8], elf_base: u64, load_base: u32) -> Self {
+        use object::{Object as _, ObjectSymbol as _, SymbolKind};
+        let Ok(file) = object::File::parse(data) else {
+            return Self::default();
+        };
+        let symbols = file
+            .symbols()
+            .filter(|symbol| matches!(symbol.kind(), SymbolKind::Text | SymbolKind::Label) && symbol.is_definition())
+            .filter_map(|symbol| {
+                let name = symbol.name().ok().filter(|name| !name.is_empty() && !name.starts_with('$'))?;
+                let address = u32::try_from(symbol.address().checked_sub(elf_base)?).ok()?.checked_add(load_base)?;
+                Some((address, name.to_owned()))
+            })
+            .collect();
+        Self::from_symbols(symbols)
+    }
+
+    /// A map of `(address, name)` pairs in any order.
+    #[must_use]
+    pub fn from_symbols(mut symbols: Vec<(u32, String)>) -> Self {
+        symbols.sort();
+        symbols.dedup_by_key(|(address, _)| *address);
+        Self { symbols }
+    }
+
+    /// True if there are no labels.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// `(address, name)` pairs in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.symbols.iter().map(|(address, name)| (*address, name.as_str()))
+    }
+
+    /// The label `address` falls under and its start address.
+    #[must_use]
+    pub fn enclosing(&self, address: u32) -> Option<(&str, u32)> {
+        let index = self.symbols.partition_point(|(start, _)| *start <= address);
+        let (start, name) = self.symbols.get(index.checked_sub(1)?)?;
+        Some((name, *start))
+    }
+
+    /// Name of the label `address` falls under, or [`NO_LABEL`].
+    #[must_use]
+    pub fn name(&self, address: u32) -> &str {
+        self.enclosing(address).map_or(NO_LABEL, |(name, _)| name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+    use crate::test_support::corpus_isa;
+    use crate::Assembler;
+
+    #[test]
+    // Test labels from an assembled program, and lookup below, at and between them
+    fn test_from_pass2() {
+        let isa = corpus_isa();
+        let output = Assembler::new(&isa)
+            .source_text("test.kla", "_start\nNOP\nloop:\nNOP\nJMP loop:\n")
+            .assemble();
+        let symbols = SymbolMap::from_pass2(&output.pass2);
+        assert_eq!(symbols.enclosing(0x20), Some(("_start", 0x20)));
+        assert_eq!(symbols.name(0x24), "loop");
+        assert_eq!(symbols.enclosing(0x2C), Some(("loop", 0x24)));
+        assert_eq!(symbols.name(0x10), NO_LABEL);
+        assert!(SymbolMap::from_elf(b"not an elf", 0, 0x20).is_empty());
+    }
+}
@@ -0,0 +1,26 @@
+//! Fixtures shared by the unit tests.
+
+#![allow(clippy::expect_used, reason = "test fixtures may expect")]
+
+use crate::files::LineType;
+use crate::messages::MsgList;
+use crate::opcodes::Pass2;
+use crate::Isa;
+
+/// The opcode table shipped with the klatest corpus.
+pub fn corpus_isa() -> Isa {
+    let path = format!("{}/src/klatest/opcode_select.vh", env!("CARGO_MANIFEST_DIR"));
+    Isa::from_vh_file(&path, &mut MsgList::new()).expect("opcode file")
+}
+
+/// A `Pass2` line with just a type, opcode and program counter.
+pub fn pass2_line(line_type: LineType, opcode: &str, program_counter: u32) -> Pass2 {
+    Pass2 {
+        file_name: String::new(),
+        input_text_line: String::new(),
+        line_counter: 0,
+        line_type,
+        opcode: opcode.to_owned(),
+        program_counter,
+    }
+}
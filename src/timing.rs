@@ -0,0 +1,229 @@
+//! Cycle-approximate timing model for the emulator.
+//!
+//! The emulator itself is cycle-agnostic; this model estimates what a run
+//! would take on the board.  Each retired instruction costs its entry in a
+//! cycle table (by mnemonic, else by `.vh` section, else the default), plus
+//! the DDR latency for every data read or write it makes, plus the count of a
+//! `DELAYV` / `DELAYR` spin.  A table file sets any of these:
+//!
+//! ```text
+//! # KlaussCPU timing
+//! clock_mhz   = 50        # clock for the wall-clock estimate
+//! default     = 4         # cycles for an instruction not listed
+//! ddr_latency = 20        # extra cycles per DDR data access
+//! MULRR       = 34        # by mnemonic
+//! section Board I/O = 6   # every opcode under a `/// Board I/O` heading
+//! ```
+//!
+//! The built-in figures are placeholders until measured against the RTL.
+
+use crate::opcodes::{opcode_mask, Opcode};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Cycles for an instruction the table does not list.
+pub const DEFAULT_CYCLES: u64 = 4;
+
+/// Extra cycles per DDR data access.
+pub const DEFAULT_DDR_LATENCY: u64 = 20;
+
+/// Clock frequency for the wall-clock estimate (MHz).
+pub const DEFAULT_CLOCK_MHZ: f64 = 50.0;
+
+/// Cycle costs for a run and the clock that turns them into time.
+#[derive(Clone, Debug)]
+pub struct TimingModel {
+    /// `(mask, pattern, cycles)`: mnemonic entries first, then section entries.
+    rules: Vec<(u32, u32, u64)>,
+    /// Cycles for an instruction no rule matches.
+    default_cycles: u64,
+    /// Extra cycles per DDR data access.
+    ddr_latency: u64,
+    /// Clock frequency (MHz).
+    clock_mhz: f64,
+    /// Cycles already looked up, by instruction word.
+    memo: HashMap<u32, u64>,
+}
+
+impl Default for TimingModel {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            default_cycles: DEFAULT_CYCLES,
+            ddr_latency: DEFAULT_DDR_LATENCY,
+            clock_mhz: DEFAULT_CLOCK_MHZ,
+            memo: HashMap::new(),
+        }
+    }
+}
+
+impl TimingModel {
+    /// The built-in model: every instruction [`DEFAULT_CYCLES`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read and parse a timing table; mnemonics and sections are looked up in `opcodes`.
+    pub fn load(path: &Path, opcodes: &[Opcode]) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("Cannot read timing table {}: {err}", path.display()))?;
+        Self::parse(&path.display().to_string(), &text, opcodes)
+    }
+
+    /// Parse timing table text; `name` is used in error messages.
+    pub fn parse(name: &str, text: &str, opcodes: &[Opcode]) -> Result<Self, String> {
+        let mut model = Self::new();
+        let mut mnemonic_rules = Vec::new();
+        let mut section_rules = Vec::new();
+        for (index, raw) in text.lines().enumerate() {
+            let content = raw.split('#').next().unwrap_or("").trim();
+            if content.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("{name}:{}: {message}", index + 1);
+            let (key, value) = content
+                .rsplit_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| error(format!("expected \"name = value\", got \"{content}\"")))?;
+            if key.eq_ignore_ascii_case("clock_mhz") {
+                model.clock_mhz = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|mhz| mhz.is_finite() && *mhz > 0.0)
+                    .ok_or_else(|| error(format!("bad clock \"{value}\"")))?;
+                continue;
+            }
+            let cycles: u64 = value.parse().map_err(|_| error(format!("bad cycle count \"{value}\"")))?;
+            if key.eq_ignore_ascii_case("default") {
+                model.default_cycles = cycles;
+            } else if key.eq_ignore_ascii_case("ddr_latency") {
+                model.ddr_latency = cycles;
+            } else if let Some(section) = key.strip_prefix("section ") {
+                let section = section.trim();
+                let before = section_rules.len();
+                for opcode in opcodes.iter().filter(|opcode| opcode.section.eq_ignore_ascii_case(section)) {
+                    let (mask, pattern) = opcode_mask(&opcode.hex_code);
+                    section_rules.push((mask, pattern, cycles));
+                }
+                if section_rules.len() == before {
+                    return Err(error(format!("no opcodes in section \"{section}\"")));
+                }
+            } else {
+                let opcode = opcodes
+                    .iter()
+                    .find(|opcode| opcode.text_name.eq_ignore_ascii_case(key))
+                    .ok_or_else(|| error(format!("unknown opcode \"{key}\"")))?;
+                let (mask, pattern) = opcode_mask(&opcode.hex_code);
+                mnemonic_rules.push((mask, pattern, cycles));
+            }
+        }
+        model.rules = mnemonic_rules;
+        model.rules.append(&mut section_rules);
+        Ok(model)
+    }
+
+    /// Override the clock frequency (MHz).
+    pub const fn set_clock_mhz(&mut self, mhz: f64) {
+        self.clock_mhz = mhz;
+    }
+
+    /// Clock frequency (MHz).
+    #[must_use]
+    pub const fn clock_mhz(&self) -> f64 {
+        self.clock_mhz
+    }
+
+    /// Extra cycles per DDR data access.
+    #[must_use]
+    pub const fn ddr_latency(&self) -> u64 {
+        self.ddr_latency
+    }
+
+    /// Table cycles for the instruction `word` (without DDR or DELAY cycles).
+    pub fn instruction_cycles(&mut self, word: u32) -> u64 {
+        if let Some(&cycles) = self.memo.get(&word) {
+            return cycles;
+        }
+        let cycles = self
+            .rules
+            .iter()
+            .find(|&&(mask, pattern, _)| word & mask == pattern)
+            .map_or(self.default_cycles, |&(_, _, cycles)| cycles);
+        self.memo.insert(word, cycles);
+        cycles
+    }
+
+    /// Wall-clock seconds `cycles` take at the model's clock.
+    #[must_use]
+    pub fn seconds(&self, cycles: u64) -> f64 {
+        cycles as f64 / (self.clock_mhz * 1e6)
+    }
+
+    /// One line for a run summary: cycles and estimated time.
+    #[must_use]
+    pub fn describe(&self, cycles: u64) -> String {
+        let seconds = self.seconds(cycles);
+        let time = if seconds >= 1.0 {
+            format!("{seconds:.3} s")
+        } else if seconds >= 1e-3 {
+            format!("{:.3} ms", seconds * 1e3)
+        } else {
+            format!("{:.3} µs", seconds * 1e6)
+        };
+        format!("{cycles} cycles, about {time} at {} MHz", self.clock_mhz)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+
+    /// Two opcodes in one section and one in another.
+    fn opcodes() -> Vec<Opcode> {
+        let opcode = |name: &str, hex: &str, section: &str| Opcode {
+            comment: String::new(),
+            hex_code: hex.to_owned(),
+            registers: 1,
+            section: section.to_owned(),
+            text_name: name.to_owned(),
+            variables: 0,
+        };
+        vec![
+            opcode("LEDR", "0000300?", "Board I/O"),
+            opcode("SWR", "0000301?", "Board I/O"),
+            opcode("INCR", "0000084?", "Arithmetic"),
+        ]
+    }
+
+    #[test]
+    // Test mnemonic entries win over section entries, which win over the default
+    fn test_parse_table() {
+        let text = "# timing\nclock_mhz = 100\ndefault = 3\nddr_latency = 9\nsection board i/o = 6   # all I/O\nswr = 8\n";
+        let mut model = TimingModel::parse("t", text, &opcodes()).unwrap();
+        assert_eq!(model.instruction_cycles(0x3012), 8, "SWR by mnemonic");
+        assert_eq!(model.instruction_cycles(0x3003), 6, "LEDR by section");
+        assert_eq!(model.instruction_cycles(0x0841), 3, "INCR by default");
+        assert_eq!(model.ddr_latency(), 9);
+        assert_eq!(model.describe(250), "250 cycles, about 2.500 µs at 100 MHz");
+        model.set_clock_mhz(0.001);
+        assert_eq!(model.describe(2_500), "2500 cycles, about 2.500 s at 0.001 MHz");
+    }
+
+    #[test]
+    // Test table errors name the file and line
+    fn test_parse_errors() {
+        assert_eq!(
+            TimingModel::parse("t", "\nFOO = 1", &opcodes()).unwrap_err(),
+            "t:2: unknown opcode \"FOO\""
+        );
+        assert!(TimingModel::parse("t", "section Nothing = 1", &opcodes())
+            .unwrap_err()
+            .contains("no opcodes"));
+        assert!(TimingModel::parse("t", "LEDR 1", &opcodes()).unwrap_err().contains("name = value"));
+        assert!(TimingModel::parse("t", "LEDR = fast", &opcodes())
+            .unwrap_err()
+            .contains("bad cycle count"));
+        assert!(TimingModel::parse("t", "clock_mhz = 0", &opcodes()).unwrap_err().contains("bad clock"));
+    }
+}
@@ -0,0 +1,337 @@
+//! Byte transports to the board's UART loader.
+//!
+//! The load, monitor and test flows talk to a [`Transport`] rather than a
+//! concrete serial port, so they run unchanged against a real board on a USB
+//! serial adapter, a local fake on a Unix pty, or a raw TCP bridge (ser2net
+//! style).  The `-s` port name selects one by prefix:
+//!
+//! - `tcp://host:port` — raw TCP socket
+//! - `pty:/dev/pts/5` — pseudo-terminal, no baud rate or break
+//! - `serial:/dev/ttyUSB0` or a plain device name — USB serial at 3 Mbaud
+
+use serialport::SerialPort;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// Baud rate of the board's UART loader.
+pub const BOARD_BAUD: u32 = 3_000_000;
+
+/// A bidirectional byte stream to the board.
+///
+/// Reads time out with `ErrorKind::TimedOut` after the configured timeout, as
+/// `serialport` does, so callers can poll without special cases.
+pub trait Transport: Read + Write + Send {
+    /// Human-readable endpoint, for messages.
+    fn name(&self) -> String;
+
+    /// Set how long a read waits for data before timing out.
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()>;
+
+    /// True if [`Transport::send_break`] reaches the other end.
+    fn supports_break(&self) -> bool {
+        false
+    }
+
+    /// Hold a UART break condition for `duration`.
+    fn send_break(&mut self, duration: Duration) -> io::Result<()> {
+        let _ = duration;
+        Err(Error::new(ErrorKind::Unsupported, format!("{} cannot send a UART break", self.name())))
+    }
+
+    /// Bytes written but not yet sent, where the transport can tell (else 0).
+    fn bytes_to_write(&self) -> io::Result<u32> {
+        Ok(0)
+    }
+
+    /// A second handle on the same stream, for a writer thread.
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>>;
+}
+
+/// Where a port name points.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransportSpec {
+    /// USB serial device path.
+    Serial(String),
+    /// Pseudo-terminal path.
+    Pty(String),
+    /// `host:port` of a raw TCP bridge.
+    Tcp(String),
+}
+
+impl TransportSpec {
+    /// Parse a `-s` port name.
+    #[must_use]
+    pub fn parse(port_name: &str) -> Self {
+        if let Some(address) = port_name.strip_prefix("tcp://") {
+            Self::Tcp(address.trim_end_matches('/').to_owned())
+        } else if let Some(path) = port_name.strip_prefix("pty:") {
+            Self::Pty(path.to_owned())
+        } else {
+            Self::Serial(port_name.strip_prefix("serial:").unwrap_or(port_name).to_owned())
+        }
+    }
+}
+
+/// A USB serial port (8N1, no flow control).
+pub struct SerialTransport {
+    /// The open port.
+    port: Box<dyn SerialPort>,
+}
+
+impl SerialTransport {
+    /// Wrap an open port and set the board's line settings.
+    pub fn new(mut port: Box<dyn SerialPort>) -> io::Result<Self> {
+        use serialport::{DataBits, FlowControl, Parity, StopBits};
+        port.set_stop_bits(StopBits::One)?;
+        port.set_data_bits(DataBits::Eight)?;
+        port.set_parity(Parity::None)?;
+        port.set_flow_control(FlowControl::None)?;
+        Ok(Self { port })
+    }
+}
+
+impl Read for SerialTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for SerialTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl Transport for SerialTransport {
+    fn name(&self) -> String {
+        self.port.name().unwrap_or_default()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        Ok(self.port.set_timeout(timeout)?)
+    }
+
+    fn supports_break(&self) -> bool {
+        true
+    }
+
+    fn send_break(&mut self, duration: Duration) -> io::Result<()> {
+        self.port.set_break()?;
+        std::thread::sleep(duration);
+        Ok(self.port.clear_break()?)
+    }
+
+    fn bytes_to_write(&self) -> io::Result<u32> {
+        Ok(self.port.bytes_to_write()?)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(Self {
+            port: self.port.try_clone()?,
+        }))
+    }
+}
+
+/// A pseudo-terminal in raw mode; read timeouts use the termios `VTIME` timer.
+pub struct PtyTransport {
+    /// Pty path, for messages.
+    path: String,
+    /// The open terminal.
+    file: File,
+}
+
+impl PtyTransport {
+    /// Open a pty and put it in raw mode with a 100 ms read timeout.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut transport = Self { path: path.to_owned(), file };
+        transport.set_timeout(Duration::from_millis(100))?;
+        Ok(transport)
+    }
+}
+
+impl Read for PtyTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // With VMIN = 0 a read that returns nothing has timed out.
+        match self.file.read(buf)? {
+            0 if !buf.is_empty() => Err(Error::new(ErrorKind::TimedOut, "pty read timed out")),
+            count => Ok(count),
+        }
+    }
+}
+
+impl Write for PtyTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Transport for PtyTransport {
+    fn name(&self) -> String {
+        format!("pty:{}", self.path)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg, SpecialCharacterIndices};
+        let mut termios = tcgetattr(&self.file).map_err(Error::from)?;
+        cfmakeraw(&mut termios);
+        // VTIME counts tenths of a second, up to 25.5 s.
+        let tenths = timeout.as_millis().div_ceil(100).clamp(1, 255) as u8;
+        termios.control_chars[SpecialCharacterIndices::VMIN as usize] = 0;
+        termios.control_chars[SpecialCharacterIndices::VTIME as usize] = tenths;
+        tcsetattr(&self.file, SetArg::TCSANOW, &termios).map_err(Error::from)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(Self {
+            path: self.path.clone(),
+            file: self.file.try_clone()?,
+        }))
+    }
+}
+
+/// A raw TCP connection to a serial bridge or fake board.
+pub struct TcpTransport {
+    /// `host:port` as given, for messages.
+    address: String,
+    /// The connected socket.
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    /// Connect to `host:port` (host names and IPv6 `[addr]:port` are resolved).
+    pub fn connect(address: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        let mut transport = Self::from_stream(stream)?;
+        address.clone_into(&mut transport.address);
+        Ok(transport)
+    }
+
+    /// Wrap an accepted or already-connected socket.
+    pub fn from_stream(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(Duration::from_millis(100)))?;
+        Ok(Self {
+            address: stream.peer_addr()?.to_string(),
+            stream,
+        })
+    }
+}
+
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stream.read(buf) {
+            Ok(0) if !buf.is_empty() => Err(Error::new(ErrorKind::UnexpectedEof, format!("{} closed the connection", self.address))),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Err(Error::new(ErrorKind::TimedOut, err)),
+            result => result,
+        }
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for TcpTransport {
+    fn name(&self) -> String {
+        format!("tcp://{}", self.address)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(Self {
+            address: self.address.clone(),
+            stream: self.stream.try_clone()?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    // Test port names select the transport by prefix
+    fn test_transport_spec_parse() {
+        assert_eq!(
+            TransportSpec::parse("tcp://localhost:5000"),
+            TransportSpec::Tcp("localhost:5000".to_owned())
+        );
+        assert_eq!(TransportSpec::parse("tcp://[::1]:5000/"), TransportSpec::Tcp("[::1]:5000".to_owned()));
+        assert_eq!(TransportSpec::parse("pty:/dev/pts/5"), TransportSpec::Pty("/dev/pts/5".to_owned()));
+        assert_eq!(
+            TransportSpec::parse("serial:/dev/ttyUSB0"),
+            TransportSpec::Serial("/dev/ttyUSB0".to_owned())
+        );
+        assert_eq!(TransportSpec::parse("/dev/ttyUSB1"), TransportSpec::Serial("/dev/ttyUSB1".to_owned()));
+    }
+
+    #[test]
+    // Test TCP reads time out, echo data and report a closed peer
+    fn test_tcp_transport() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let peer = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0_u8; 5];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(&buf).unwrap();
+        });
+
+        let mut transport = TcpTransport::connect(&address).unwrap();
+        transport.set_timeout(Duration::from_millis(20)).unwrap();
+        let mut buf = [0_u8; 16];
+        assert_eq!(transport.read(&mut buf).unwrap_err().kind(), ErrorKind::TimedOut);
+        assert!(!transport.supports_break());
+        assert_eq!(transport.send_break(Duration::ZERO).unwrap_err().kind(), ErrorKind::Unsupported);
+
+        transport.try_clone().unwrap().write_all(b"hello").unwrap();
+        transport.set_timeout(Duration::from_secs(2)).unwrap();
+        let mut echoed = [0_u8; 5];
+        transport.read_exact(&mut echoed).unwrap();
+        assert_eq!(&echoed, b"hello");
+        peer.join().unwrap();
+        assert_eq!(transport.read(&mut buf).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(transport.name(), format!("tcp://{address}"));
+    }
+
+    #[test]
+    // Test a pty slave reads what the master writes and times out when idle
+    fn test_pty_transport() {
+        let (mut master, slave) = serialport::TTYPort::pair().unwrap();
+        let slave_name = slave.name().unwrap();
+        let mut transport = PtyTransport::open(&slave_name).unwrap();
+        let mut buf = [0_u8; 16];
+        assert_eq!(transport.read(&mut buf).unwrap_err().kind(), ErrorKind::TimedOut);
+
+        master.write_all(b"S\n").unwrap();
+        let count = transport.read(&mut buf).unwrap();
+        assert_eq!(&buf[..count], b"S\n");
+        transport.write_all(b"ok").unwrap();
+        master.set_timeout(Duration::from_secs(1)).unwrap();
+        let mut reply = [0_u8; 2];
+        master.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"ok");
+    }
+}
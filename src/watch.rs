@@ -0,0 +1,283 @@
+//! Watch mode (`--watch`): re-assemble whenever a source file changes.
+//!
+//! The input `.kla`, every file it `!include`s and the opcode `.vh` are polled
+//! for modification-time changes.  Each rebuild writes the usual `.code`/`.kbt`
+//! outputs and can re-run the emulator, the emulator test list or a board load.
+//! Only diagnostics that were not raised by the previous build are printed, so
+//! the terminal shows what an edit fixed or broke rather than the whole log.
+
+use crate::commands::{emulate_test_files, run_emulate, run_emulate_test_with, EmulateOptions};
+use crate::{write_binary_file, write_to_device};
+use chrono::{Local, NaiveTime};
+use klausscc::emulate::UartInput;
+use klausscc::files::{include_candidates, write_code_output_file};
+use klausscc::helper::create_bin_string;
+use klausscc::messages::{print_messages, Message, MessageType, MsgList};
+use klausscc::{Assembler, Isa};
+use std::fs;
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
+
+/// How often the watched files are polled for changes.
+const WATCH_POLL_MS: u64 = 300;
+
+/// Settling time after a change is seen, so an editor's multi-step save is
+/// picked up as one rebuild.
+const WATCH_SETTLE_MS: u64 = 100;
+
+/// What to watch and what to do after each rebuild.
+pub(crate) struct WatchOptions<'a> {
+    /// Opcode `.vh` file (re-parsed on every rebuild).
+    pub opcode_file_name: &'a str,
+    /// Extra `!include` search directories.
+    pub include_paths: &'a [PathBuf],
+    /// Top-level `.kla` input, empty when only `--emulate-test` is watched.
+    pub input_file_name: &'a str,
+    /// `.code` listing written for the input.
+    pub output_file_name: &'a str,
+    /// `.kbt` image written for the input.
+    pub binary_file_name: &'a str,
+    /// Re-run the emulator on the input after each successful build.
+    pub emulate: bool,
+    /// Trace file for the emulator run.
+    pub trace_file: Option<&'a str>,
+    /// UART input for the emulator run.
+    pub uart_input: &'a [u8],
+    /// Re-run `--emulate-test` over this path after each rebuild.
+    pub emulate_test_path: Option<&'a str>,
+    /// Instruction cap for emulator runs.
+    pub max_instructions: u64,
+    /// Serial port to load the board after each successful build, empty for none.
+    pub serial_port: &'a str,
+    /// Send a UART break (rather than 'S') before loading the board.
+    pub send_break: bool,
+}
+
+/// File modification times, `None` where the file is missing.
+type Snapshot = Vec<(String, Option<SystemTime>)>;
+
+/// Rebuild on every change to the watched files until interrupted (Ctrl+C).
+#[cfg(not(tarpaulin_include))] // Runs until interrupted
+pub(crate) fn run_watch(options: &WatchOptions<'_>, msg_list: &mut MsgList) -> Result<(), i32> {
+    msg_list.push("Watching for changes (Ctrl+C to stop)".to_owned(), None, None, MessageType::Information);
+    print_messages(msg_list);
+
+    let mut previous: Vec<Message> = Vec::new();
+    loop {
+        let start_time = Local::now().time();
+        let mut build_msgs = MsgList::new();
+        let watched = rebuild(options, &mut build_msgs, start_time);
+        report_diagnostics(&previous, &build_msgs.list);
+        previous = build_msgs.list;
+
+        let snapshot = take_snapshot(&watched);
+        while take_snapshot(&watched) == snapshot {
+            sleep(Duration::from_millis(WATCH_POLL_MS));
+        }
+        sleep(Duration::from_millis(WATCH_SETTLE_MS));
+        eprintln!("{} Change detected, rebuilding", Local::now().time().format("%H:%M:%S%.3f"));
+    }
+}
+
+/// Run one build and its follow-up actions, returning the files to watch.
+///
+/// Assembly diagnostics, and the warnings and errors of emulator runs, go to
+/// `build_msgs` for filtering; emulator and board output is printed in full as
+/// it happens.
+#[cfg(not(tarpaulin_include))] // Writes files, runs the emulator and talks to the board
+fn rebuild(options: &WatchOptions<'_>, build_msgs: &mut MsgList, start_time: NaiveTime) -> Vec<String> {
+    let mut watched = vec![options.opcode_file_name.to_owned()];
+    let Some(isa) = Isa::from_vh_file(options.opcode_file_name, build_msgs) else {
+        build_msgs.push(
+            format!("Error parsing opcode file {} to macro and opcode lists", options.opcode_file_name),
+            None,
+            None,
+            MessageType::Error,
+        );
+        if !options.input_file_name.is_empty() {
+            watched.push(options.input_file_name.to_owned());
+        }
+        return watched;
+    };
+
+    if !options.input_file_name.is_empty() {
+        let assembly = Assembler::new(&isa)
+            .source_file(options.input_file_name)
+            .include_paths(options.include_paths)
+            .assemble_into(build_msgs);
+        watched.extend(source_and_include_files(&assembly.source_files, options.include_paths));
+        let mut pass2 = assembly.pass2;
+        if !pass2.is_empty() {
+            if let Err(result_err) = write_code_output_file(options.output_file_name, &mut pass2, build_msgs) {
+                build_msgs.push(
+                    format!("Unable to write to code file {}, error {result_err}", options.output_file_name),
+                    None,
+                    None,
+                    MessageType::Error,
+                );
+            }
+        }
+        if build_msgs.number_by_type(&MessageType::Error) == 0 {
+            if let Some(bin_string) = create_bin_string(&pass2, build_msgs) {
+                write_binary_file(build_msgs, options.binary_file_name, &bin_string);
+                if options.emulate {
+                    let emulate_options = EmulateOptions {
+                        trace_file: options.trace_file,
+                        input: UartInput::Bytes(options.uart_input.to_vec()),
+                        max_instructions: options.max_instructions,
+                        ..Default::default()
+                    };
+                    let mut emulate_msgs = MsgList::new();
+                    let _ = run_emulate(&pass2, options.input_file_name, &emulate_options, &mut emulate_msgs, start_time);
+                    keep_diagnostics(build_msgs, emulate_msgs);
+                }
+                if !options.serial_port.is_empty() {
+                    let mut load_msgs = MsgList::new();
+                    write_to_device(&mut load_msgs, &bin_string, options.serial_port, options.send_break);
+                    print_messages(&load_msgs);
+                }
+            }
+        }
+    }
+
+    if let Some(test_path) = options.emulate_test_path {
+        // Each test is assembled once, for the run and for its list of included files.
+        let mut test_msgs = MsgList::new();
+        watched.push(test_path.to_owned());
+        watched.extend(emulate_test_files(test_path, &mut test_msgs));
+        let _ = run_emulate_test_with(test_path, options.max_instructions, &mut test_msgs, start_time, |file, file_msgs| {
+            let assembly = Assembler::new(&isa)
+                .source_file(file)
+                .include_paths(options.include_paths)
+                .assemble_into(file_msgs);
+            watched.extend(source_and_include_files(&assembly.source_files, options.include_paths));
+            assembly.image.zip(assembly.entry)
+        });
+        keep_diagnostics(build_msgs, test_msgs);
+    }
+
+    watched.sort();
+    watched.dedup();
+    watched
+}
+
+/// The files an assembly read, and every place their `!include`s are looked
+/// for, so that an include missing at this build is picked up once created.
+fn source_and_include_files(source_files: &[String], include_paths: &[PathBuf]) -> Vec<String> {
+    source_files
+        .iter()
+        .cloned()
+        .chain(source_files.iter().flat_map(|file| include_candidates(file, include_paths)))
+        .collect()
+}
+
+/// Add the warnings and errors of an emulator run to the build's diagnostics,
+/// so they are counted and compared with the previous build's.
+fn keep_diagnostics(build_msgs: &mut MsgList, run_msgs: MsgList) {
+    build_msgs
+        .list
+        .extend(run_msgs.list.into_iter().filter(|msg| msg.level != MessageType::Information));
+}
+
+/// Print the diagnostics not raised by the previous build and a one-line summary.
+#[cfg(not(tarpaulin_include))] // Cannot test printing in tarpaulin
+fn report_diagnostics(previous: &[Message], current: &[Message]) {
+    let fresh = MsgList {
+        list: new_diagnostics(previous, current),
+        live: false,
+    };
+    print_messages(&fresh);
+    let resolved = new_diagnostics(current, previous).len();
+    let errors = current.iter().filter(|msg| msg.level == MessageType::Error).count();
+    let warnings = current.iter().filter(|msg| msg.level == MessageType::Warning).count();
+    eprintln!(
+        "Build finished with {errors} error{} and {warnings} warning{} ({} new, {resolved} resolved); watching for changes",
+        if errors == 1 { "" } else { "s" },
+        if warnings == 1 { "" } else { "s" },
+        fresh.list.len()
+    );
+}
+
+/// Warnings and errors in `current` that did not appear in `previous`.
+///
+/// Messages are compared on level, file, line and text (not time).
+fn new_diagnostics(previous: &[Message], current: &[Message]) -> Vec<Message> {
+    current
+        .iter()
+        .filter(|msg| msg.level != MessageType::Information)
+        .filter(|msg| !previous.iter().any(|old| same_diagnostic(old, msg)))
+        .cloned()
+        .collect()
+}
+
+/// True when two messages report the same thing at the same place.
+fn same_diagnostic(first: &Message, second: &Message) -> bool {
+    first.level == second.level && first.file_name == second.file_name && first.line_number == second.line_number && first.text == second.text
+}
+
+/// Record the modification time of each watched file.
+fn take_snapshot(files: &[String]) -> Snapshot {
+    files
+        .iter()
+        .map(|file| (file.clone(), fs::metadata(file).and_then(|meta| meta.modified()).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, reason = "tests may unwrap/expect")]
+    use super::*;
+
+    fn message(text: &str, line_number: Option<u32>, level: MessageType) -> Message {
+        let mut msg_list = MsgList::new();
+        msg_list.push(text.to_owned(), line_number, Some("main.kla".to_owned()), level);
+        msg_list.list.remove(0)
+    }
+
+    #[test]
+    // Test only diagnostics absent from the previous build are reported
+    fn test_new_diagnostics() {
+        let previous = vec![message("Unknown opcode", Some(3), MessageType::Error)];
+        let current = vec![
+            message("Unknown opcode", Some(3), MessageType::Error),
+            message("Unknown opcode", Some(7), MessageType::Error),
+            message("Unused label", Some(9), MessageType::Warning),
+            message("Writing code file", None, MessageType::Information),
+        ];
+        let fresh = new_diagnostics(&previous, &current);
+        assert_eq!(fresh.len(), 2);
+        assert_eq!(fresh[0].line_number, Some(7));
+        assert_eq!(fresh[1].level, MessageType::Warning);
+        assert_eq!(new_diagnostics(&current, &previous).len(), 0);
+    }
+
+    #[test]
+    // Test an emulator run's warnings and errors join the build diagnostics, its information does not
+    fn test_keep_diagnostics() {
+        let mut build_msgs = MsgList::new();
+        let mut run_msgs = MsgList::new();
+        run_msgs.list.push(message("Emulating main.kla", None, MessageType::Information));
+        run_msgs.list.push(message("Failed to write trace file", None, MessageType::Error));
+        keep_diagnostics(&mut build_msgs, run_msgs);
+        assert_eq!(build_msgs.list.len(), 1);
+        assert_eq!(build_msgs.number_by_type(&MessageType::Error), 1);
+    }
+
+    #[test]
+    // Test a snapshot changes when a file is created or rewritten
+    fn test_take_snapshot() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("main.kla").to_string_lossy().into_owned();
+        let missing = take_snapshot(std::slice::from_ref(&path));
+        assert_eq!(missing, vec![(path.clone(), None)]);
+
+        fs::write(&path, "HALT\n").unwrap();
+        let created = take_snapshot(std::slice::from_ref(&path));
+        assert_ne!(created, missing);
+
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(5)).unwrap();
+        assert_ne!(take_snapshot(&[path]), created);
+    }
+}